@@ -0,0 +1,39 @@
+use std::{io::BufWriter, path::Path};
+
+use fs_err as fs;
+use rbx_dom_weak::{InstanceBuilder, WeakDom};
+use rbx_types::{CFrame, Matrix3, Variant, Vector3};
+
+/// Builds a place with nothing in it but an anchored baseplate, like the one
+/// Studio creates for a new place. Studio adds the other services when it
+/// opens the place.
+pub fn new_place() -> WeakDom {
+    let mut place = WeakDom::new(InstanceBuilder::new("DataModel"));
+
+    let workspace = place.insert(place.root_ref(), InstanceBuilder::new("Workspace"));
+    place.insert(
+        workspace,
+        InstanceBuilder::new("Part")
+            .with_name("Baseplate")
+            .with_property("Anchored", Variant::Bool(true))
+            .with_property("Locked", Variant::Bool(true))
+            .with_property("Size", Variant::Vector3(Vector3::new(2048.0, 16.0, 2048.0)))
+            .with_property(
+                "CFrame",
+                Variant::CFrame(CFrame::new(
+                    Vector3::new(0.0, -8.0, 0.0),
+                    Matrix3::identity(),
+                )),
+            ),
+    );
+
+    place
+}
+
+/// Writes a place as a binary place file.
+pub fn write_place(place: &WeakDom, path: &Path) -> Result<(), anyhow::Error> {
+    let output = BufWriter::new(fs::File::create(path)?);
+    rbx_binary::to_writer(output, place, place.root().children())?;
+
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Context};
+
+/// Limits on the size of the place after the script runs, given on the
+/// command line like `--budget instances=50000,parts=20000`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Budget {
+    /// The most instances the whole DataModel may contain.
+    pub instances: Option<u64>,
+
+    /// The most BaseParts the whole DataModel may contain.
+    pub parts: Option<u64>,
+}
+
+impl Budget {
+    /// Formats the budget as a Lua table for the plugin.
+    pub fn to_lua(self) -> String {
+        let mut fields = Vec::new();
+
+        if let Some(instances) = self.instances {
+            fields.push(format!("instances = {}", instances));
+        }
+
+        if let Some(parts) = self.parts {
+            fields.push(format!("parts = {}", parts));
+        }
+
+        format!("{{ {} }}", fields.join(", "))
+    }
+}
+
+impl FromStr for Budget {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut budget = Budget::default();
+
+        for setting in value.split(',').filter(|setting| !setting.is_empty()) {
+            let (key, value) = match setting.find('=') {
+                Some(index) => (&setting[..index], &setting[index + 1..]),
+                None => bail!("Invalid --budget setting {:?}, expected key=value", setting),
+            };
+
+            let limit = value
+                .parse()
+                .with_context(|| format!("Invalid limit for {}: {:?}", key, value))?;
+
+            match key {
+                "instances" => budget.instances = Some(limit),
+                "parts" => budget.parts = Some(limit),
+                _ => bail!(
+                    "Unknown --budget setting {:?}, expected instances or parts",
+                    key
+                ),
+            }
+        }
+
+        Ok(budget)
+    }
+}
@@ -0,0 +1,259 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use fs_err as fs;
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+
+use crate::ignore::IgnoreRules;
+
+/// Names of the instances that run-in-roblox puts next to the injected script,
+/// which bundled modules can't use.
+const RESERVED_NAMES: &[&str] = &["Main", "rir", "Probe", "Warmup", "Mounts"];
+
+/// A local module pulled in by `--bundle`, or from a folder given with
+/// `--script`, and placed next to the injected script.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BundledModule {
+    pub name: String,
+
+    /// The module's source, or `None` for a Folder that only holds other
+    /// modules.
+    pub source: Option<String>,
+
+    /// Modules inside this one, from a folder given with `--script`.
+    pub children: Vec<BundledModule>,
+}
+
+impl BundledModule {
+    /// This module followed by every module inside it.
+    pub fn descendants(&self) -> Vec<&BundledModule> {
+        let mut modules = vec![self];
+
+        for child in &self.children {
+            modules.extend(child.descendants());
+        }
+
+        modules
+    }
+}
+
+/// Finds the local modules that a script requires, directly or through other
+/// local modules, so they can be injected alongside it.
+///
+/// Both `require(script.Parent.Name)` and string requires like
+/// `require("./name")` are supported. String requires are rewritten into
+/// instance requires, since that's all Studio understands. Returns the
+/// rewritten script and the modules it needs.
+pub fn bundle(
+    script_path: &Path,
+    source: &str,
+) -> Result<(String, Vec<BundledModule>), anyhow::Error> {
+    let mut bundler = Bundler {
+        instance_require: Regex::new(
+            r#"require\(\s*script\.Parent(?:\.([A-Za-z_][A-Za-z0-9_]*)|\[\s*["']([^"']+)["']\s*\])\s*\)"#,
+        )
+        .unwrap(),
+        string_require: Regex::new(r#"require\(\s*["']([^"']+)["']\s*\)"#).unwrap(),
+        modules: Vec::new(),
+        paths_by_name: HashMap::new(),
+    };
+
+    let base_dir = script_path.parent().unwrap_or_else(|| Path::new(""));
+    let main_source = bundler.visit(base_dir, source)?;
+
+    Ok((main_source, bundler.modules))
+}
+
+struct Bundler {
+    instance_require: Regex,
+    string_require: Regex,
+    modules: Vec<BundledModule>,
+    paths_by_name: HashMap<String, PathBuf>,
+}
+
+impl Bundler {
+    /// Pulls in every module required by `source` and returns the source with
+    /// string requires rewritten.
+    fn visit(&mut self, base_dir: &Path, source: &str) -> Result<String, anyhow::Error> {
+        let mut instance_requires = Vec::new();
+        for captures in self.instance_require.captures_iter(source) {
+            let name = captures.get(1).or_else(|| captures.get(2)).unwrap();
+            instance_requires.push(name.as_str().to_owned());
+        }
+
+        for name in instance_requires {
+            if let Some(path) = find_module(&base_dir.join(&name)) {
+                self.add(name, path)?;
+            } else {
+                log::debug!("Not bundling {}, no matching file was found", name);
+            }
+        }
+
+        let mut string_requires = Vec::new();
+        for captures in self.string_require.captures_iter(source) {
+            string_requires.push(captures[1].to_owned());
+        }
+
+        let mut names_by_require = HashMap::new();
+        for require_path in string_requires {
+            let path = find_module(&base_dir.join(&require_path))
+                .with_context(|| format!("Could not find module {:?} to bundle", require_path))?;
+            let name = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .with_context(|| format!("Module path had invalid Unicode: {}", path.display()))?
+                .to_owned();
+
+            self.add(name.clone(), path)?;
+            names_by_require.insert(require_path, name);
+        }
+
+        let rewritten = self
+            .string_require
+            .replace_all(source, |captures: &Captures| {
+                format!(
+                    "require(script.Parent[{:?}])",
+                    names_by_require[&captures[1]]
+                )
+            });
+
+        Ok(rewritten.into_owned())
+    }
+
+    fn add(&mut self, name: String, path: PathBuf) -> Result<(), anyhow::Error> {
+        if RESERVED_NAMES.contains(&name.as_str()) {
+            bail!(
+                "Module {} can't be bundled because its name is reserved: {}",
+                name,
+                path.display()
+            );
+        }
+
+        if let Some(existing) = self.paths_by_name.get(&name) {
+            if *existing != path {
+                bail!(
+                    "Two bundled modules are both named {}: {} and {}",
+                    name,
+                    existing.display(),
+                    path.display()
+                );
+            }
+
+            return Ok(());
+        }
+
+        self.paths_by_name.insert(name.clone(), path.clone());
+
+        let source = fs::read_to_string(&path)?;
+        let base_dir = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+
+        // Reserve our slot before visiting so that the order of modules is
+        // stable and cycles between modules terminate.
+        let index = self.modules.len();
+        self.modules.push(BundledModule {
+            name,
+            source: None,
+            children: Vec::new(),
+        });
+
+        self.modules[index].source = Some(self.visit(&base_dir, &source)?);
+
+        Ok(())
+    }
+}
+
+/// Finds the file for a module path that may or may not have an extension.
+fn find_module(path: &Path) -> Option<PathBuf> {
+    if path.is_file() {
+        return Some(path.to_path_buf());
+    }
+
+    ["lua", "luau"]
+        .iter()
+        .map(|extension| path.with_extension(extension))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Reads a folder given with `--script`, returning the source of its
+/// `init.lua` to run and the rest of the folder as modules to put next to it,
+/// so that the script can `require(script.Parent.Name)` them. Folders inside
+/// it follow Rojo's conventions: one with an `init.lua` becomes a module with
+/// the folder's other files inside it, and one without becomes a Folder.
+pub fn bundle_dir(dir: &Path) -> Result<(String, Vec<BundledModule>), anyhow::Error> {
+    let init_path = find_module(&dir.join("init"))
+        .with_context(|| format!("{} has no init.lua to run", dir.display()))?;
+    let source = fs::read_to_string(&init_path)?;
+
+    let modules = load_dir_modules(dir, &IgnoreRules::for_dir(dir))?;
+
+    for module in &modules {
+        if RESERVED_NAMES.contains(&module.name.as_str()) {
+            bail!(
+                "{} can't be used as a script because {} has a reserved name",
+                dir.display(),
+                module.name
+            );
+        }
+    }
+
+    Ok((source, modules))
+}
+
+/// Turns everything in a folder except its `init.lua` into modules.
+fn load_dir_modules(dir: &Path, rules: &IgnoreRules) -> Result<Vec<BundledModule>, anyhow::Error> {
+    let rules = rules.with_dir(dir);
+
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+
+    let mut modules = Vec::new();
+
+    for entry in entries {
+        let is_dir = entry.is_dir();
+
+        if rules.is_ignored(&entry, is_dir) {
+            continue;
+        }
+
+        let file_name = entry
+            .file_name()
+            .and_then(|name| name.to_str())
+            .with_context(|| format!("Path had invalid Unicode: {}", entry.display()))?;
+
+        if is_dir {
+            let source = match find_module(&entry.join("init")) {
+                Some(init_path) => Some(fs::read_to_string(init_path)?),
+                None => None,
+            };
+
+            modules.push(BundledModule {
+                name: file_name.to_owned(),
+                source,
+                children: load_dir_modules(&entry, &rules)?,
+            });
+            continue;
+        }
+
+        let name = match file_name
+            .strip_suffix(".lua")
+            .or_else(|| file_name.strip_suffix(".luau"))
+        {
+            Some(name) if name != "init" => name,
+            _ => continue,
+        };
+
+        modules.push(BundledModule {
+            name: name.to_owned(),
+            source: Some(fs::read_to_string(&entry)?),
+            children: Vec::new(),
+        });
+    }
+
+    Ok(modules)
+}
@@ -0,0 +1,56 @@
+use std::{str::FromStr, time::Duration};
+
+use anyhow::{bail, Context};
+
+use crate::duration::parse_duration;
+
+/// Faults to inject into HttpService requests made by the script, given on
+/// the command line like `--chaos-http latency=200ms,fail-rate=0.1`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChaosHttp {
+    /// Extra time each request takes before it's sent.
+    pub latency: Duration,
+
+    /// The fraction of requests, from 0 to 1, that fail without being sent.
+    pub fail_rate: f64,
+}
+
+impl FromStr for ChaosHttp {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut chaos = ChaosHttp {
+            latency: Duration::from_secs(0),
+            fail_rate: 0.0,
+        };
+
+        for setting in value.split(',').filter(|setting| !setting.is_empty()) {
+            let (key, value) = match setting.find('=') {
+                Some(index) => (&setting[..index], &setting[index + 1..]),
+                None => bail!(
+                    "Invalid --chaos-http setting {:?}, expected key=value",
+                    setting
+                ),
+            };
+
+            match key {
+                "latency" => chaos.latency = parse_duration(value)?,
+                "fail-rate" => {
+                    chaos.fail_rate = value
+                        .parse()
+                        .with_context(|| format!("Invalid fail-rate {:?}", value))?;
+
+                    if !(0.0..=1.0).contains(&chaos.fail_rate) {
+                        bail!("fail-rate must be between 0 and 1, got {}", value);
+                    }
+                }
+                _ => bail!(
+                    "Unknown --chaos-http setting {:?}, expected latency or fail-rate",
+                    key
+                ),
+            }
+        }
+
+        Ok(chaos)
+    }
+}
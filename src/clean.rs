@@ -0,0 +1,230 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::{self, Command},
+    time::{Duration, SystemTime},
+};
+
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+
+/// Prefix given to every temporary directory that run-in-roblox creates.
+pub const TEMP_DIR_PREFIX: &str = "run-in-roblox-";
+
+/// Name of the marker file written into each run's temporary directory.
+const SESSION_MARKER_NAME: &str = "run-in-roblox-session.json";
+
+/// Temporary directories without a marker are only removed once they're this
+/// old, since a run that just started might not have written one yet.
+const UNMARKED_DIR_MAX_AGE: Duration = Duration::from_secs(10 * 60);
+
+/// Describes a run so that `run-in-roblox clean` can tell whether it's still
+/// active and what it left behind if it isn't.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionMarker {
+    pub host_pid: u32,
+    pub studio_pid: Option<u32>,
+    pub plugin_path: Option<PathBuf>,
+}
+
+impl SessionMarker {
+    pub fn for_current_process() -> Self {
+        SessionMarker {
+            host_pid: process::id(),
+            studio_pid: None,
+            plugin_path: None,
+        }
+    }
+
+    pub fn write(&self, temp_dir: &Path) -> Result<(), anyhow::Error> {
+        let contents = serde_json::to_string(self)?;
+        fs::write(temp_dir.join(SESSION_MARKER_NAME), contents)?;
+
+        Ok(())
+    }
+
+    fn read(temp_dir: &Path) -> Option<Self> {
+        let contents = fs::read(temp_dir.join(SESSION_MARKER_NAME)).ok()?;
+        serde_json::from_slice(&contents).ok()
+    }
+}
+
+/// Removes leftovers from runs that didn't shut down cleanly and returns a
+/// description of everything that was removed.
+pub fn clean(plugins_paths: &[PathBuf]) -> Vec<String> {
+    let mut removed = Vec::new();
+    let mut active_plugins = Vec::new();
+
+    let temp_root = env::temp_dir();
+    let entries = match fs::read_dir(&temp_root) {
+        Ok(entries) => entries.flatten().collect(),
+        Err(_) => Vec::new(),
+    };
+
+    for entry in entries {
+        let path = entry.path();
+        let is_ours = entry
+            .file_name()
+            .to_str()
+            .map(|name| name.starts_with(TEMP_DIR_PREFIX))
+            .unwrap_or(false);
+
+        if !is_ours || !path.is_dir() {
+            continue;
+        }
+
+        match SessionMarker::read(&path) {
+            Some(marker) => {
+                if process_exists(marker.host_pid) {
+                    if let Some(plugin_path) = marker.plugin_path {
+                        active_plugins.push(plugin_path);
+                    }
+
+                    continue;
+                }
+
+                if let Some(studio_pid) = marker.studio_pid {
+                    if is_studio_process(studio_pid) && kill_process(studio_pid) {
+                        removed.push(format!("Roblox Studio process {}", studio_pid));
+                    }
+                }
+            }
+            None => {
+                let age = fs::metadata(&path)
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+                    .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+
+                if age.map(|age| age < UNMARKED_DIR_MAX_AGE).unwrap_or(true) {
+                    continue;
+                }
+            }
+        }
+
+        match fs::remove_dir_all(&path) {
+            Ok(()) => removed.push(format!("temporary directory {}", path.display())),
+            Err(err) => log::warn!("{}", err),
+        }
+    }
+
+    for plugins_path in plugins_paths {
+        for path in plugin_files(plugins_path) {
+            if active_plugins.contains(&path) {
+                continue;
+            }
+
+            match fs::remove_file(&path) {
+                Ok(()) => removed.push(format!("plugin {}", path.display())),
+                Err(err) => log::warn!("{}", err),
+            }
+        }
+    }
+
+    removed
+}
+
+/// The plugins that runs have put in a plugins folder, whether or not the runs
+/// are still going.
+pub fn plugin_files(plugins_path: &Path) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(plugins_path) {
+        Ok(entries) => entries.flatten(),
+        Err(_) => return Vec::new(),
+    };
+
+    entries
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .map(|name| name.starts_with("run_in_roblox-") && name.ends_with(".rbxmx"))
+                .unwrap_or(false)
+        })
+        .map(|entry| entry.path())
+        .collect()
+}
+
+/// The temporary directories that belong to the run-in-roblox process with
+/// the given ID, which should all be gone once it exits.
+pub fn temp_dirs_of(host_pid: u32) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(env::temp_dir()) {
+        Ok(entries) => entries.flatten(),
+        Err(_) => return Vec::new(),
+    };
+
+    entries
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let is_ours = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| name.starts_with(TEMP_DIR_PREFIX))
+                .unwrap_or(false);
+
+            is_ours
+                && path.is_dir()
+                && matches!(SessionMarker::read(path), Some(marker) if marker.host_pid == host_pid)
+        })
+        .collect()
+}
+
+#[cfg(windows)]
+fn process_image_name(pid: u32) -> Option<String> {
+    let output = Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/FO", "CSV", "/NH"])
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let first_field = stdout.trim().split(',').next()?.trim_matches('"');
+
+    if first_field.is_empty() || first_field.starts_with("INFO:") {
+        None
+    } else {
+        Some(first_field.to_owned())
+    }
+}
+
+#[cfg(not(windows))]
+fn process_image_name(pid: u32) -> Option<String> {
+    let output = Command::new("ps")
+        .args(["-p", &pid.to_string(), "-o", "comm="])
+        .output()
+        .ok()?;
+    let name = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+
+    if name.is_empty() {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+/// Tells whether a process with the given ID is currently running.
+pub fn process_exists(pid: u32) -> bool {
+    process_image_name(pid).is_some()
+}
+
+/// Guards against killing an unrelated process that happens to have been
+/// given a recycled process ID.
+fn is_studio_process(pid: u32) -> bool {
+    process_image_name(pid)
+        .map(|name| name.contains("Roblox"))
+        .unwrap_or(false)
+}
+
+#[cfg(windows)]
+fn kill_process(pid: u32) -> bool {
+    Command::new("taskkill")
+        .args(["/PID", &pid.to_string(), "/F"])
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+#[cfg(not(windows))]
+fn kill_process(pid: u32) -> bool {
+    Command::new("kill")
+        .args(["-9", &pid.to_string()])
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
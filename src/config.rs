@@ -0,0 +1,574 @@
+use std::{
+    env,
+    ffi::OsString,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use fs_err as fs;
+use serde_json::Value;
+
+/// The config file that's read from the current directory when there is one
+/// and `--config` isn't given.
+pub const CONFIG_FILE_NAME: &str = "run-in-roblox.json";
+
+/// Environment variables starting with this set options, like
+/// `RUN_IN_ROBLOX_TIMEOUT=2m` for `--timeout 2m`.
+pub const ENV_PREFIX: &str = "RUN_IN_ROBLOX_";
+
+/// Options that only make sense on the command line.
+const COMMAND_LINE_ONLY: &[&str] = &["config", "show-config", "detach", "help", "version"];
+
+/// Whether a command line option is a flag or takes a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+    Flag,
+    Value,
+}
+
+/// What `layer` needs to know about the options it layers. Options are named
+/// like their long flags without the leading dashes.
+pub trait OptionSpec {
+    /// Whether the option is a flag or takes a value, or `None` if there's no
+    /// such option.
+    fn kind_of(&self, name: &str) -> Option<ArgKind>;
+
+    /// The name of the option with the given short flag, like `verbose` for
+    /// `v`.
+    fn long_name(&self, short: char) -> Option<&'static str>;
+
+    /// Whether the two options can't be given together.
+    fn conflicts(&self, name: &str, other: &str) -> bool;
+
+    /// Whether the option can be given with a subcommand, like `--plain`.
+    fn is_global(&self, name: &str) -> bool;
+}
+
+/// Where the value of an option came from. Later sources override earlier
+/// ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    File(PathBuf),
+    Env(String),
+    CommandLine,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::File(path) => write!(formatter, "{}", path.display()),
+            Source::Env(name) => write!(formatter, "{}", name),
+            Source::CommandLine => write!(formatter, "command line"),
+        }
+    }
+}
+
+/// The value of one option, named like its flag without the leading dashes.
+#[derive(Debug, Clone)]
+pub struct Setting {
+    pub name: String,
+
+    /// For flags, whether the flag is set.
+    pub enabled: bool,
+
+    /// For options that take values, each value given, in order.
+    pub values: Vec<String>,
+
+    pub source: Source,
+}
+
+impl Setting {
+    /// Formats the setting's value like it would be written in a config file.
+    pub fn value_text(&self) -> String {
+        if self.values.is_empty() {
+            self.enabled.to_string()
+        } else {
+            self.values.join(", ")
+        }
+    }
+}
+
+/// The options to parse once the config file and environment variables have
+/// been layered under the command line, along with where each one came from.
+#[derive(Debug)]
+pub struct Layered {
+    pub args: Vec<OsString>,
+    pub settings: Vec<Setting>,
+
+    /// Problems that didn't stop the options from being layered, to be logged
+    /// once logging is set up.
+    pub warnings: Vec<String>,
+}
+
+/// Layers the options set in the config file and environment variables under
+/// those given on the command line, so that an option given on the command
+/// line overrides the same option set by an environment variable, which
+/// overrides the config file. Options that take several values are replaced
+/// as a whole, and options that conflict with one set by a later source are
+/// dropped, like a config file's `verbose` when `--quiet` is given.
+///
+/// With a subcommand, only global options, like `lang`, are layered, since
+/// the rest only apply to runs.
+pub fn layer(args: Vec<OsString>, spec: &impl OptionSpec) -> Result<Layered, anyhow::Error> {
+    let (command_line, subcommand) = command_line_settings(&args[1..], spec);
+
+    let config_path = match command_line.iter().find(|setting| setting.name == "config") {
+        Some(setting) => setting.values.first().map(PathBuf::from),
+        None => Some(PathBuf::from(CONFIG_FILE_NAME)).filter(|path| path.is_file()),
+    };
+
+    let mut settings = Vec::new();
+
+    if let Some(config_path) = &config_path {
+        for setting in file_settings(config_path, spec)? {
+            override_setting(&mut settings, setting, spec);
+        }
+    }
+
+    let mut warnings = Vec::new();
+
+    for setting in env_settings(spec, &mut warnings)? {
+        override_setting(&mut settings, setting, spec);
+    }
+
+    if subcommand.is_some() {
+        settings.retain(|setting| spec.is_global(&setting.name));
+    }
+
+    for given in &command_line {
+        drop_conflicts(&mut settings, given, spec);
+    }
+
+    // Options given on the command line are passed on as they are, after the
+    // ones from other sources that they don't override.
+    let mut layered_args = vec![args[0].clone()];
+
+    for setting in &settings {
+        if command_line.iter().any(|given| given.name == setting.name) {
+            continue;
+        }
+
+        let flag = format!("--{}", setting.name);
+
+        if setting.values.is_empty() {
+            if setting.enabled {
+                layered_args.push(flag.into());
+            }
+        } else {
+            for value in &setting.values {
+                layered_args.push(flag.clone().into());
+                layered_args.push(value.into());
+            }
+        }
+    }
+
+    layered_args.extend(args[1..].iter().cloned());
+
+    for setting in command_line {
+        override_setting(&mut settings, setting, spec);
+    }
+
+    Ok(Layered {
+        args: layered_args,
+        settings,
+        warnings,
+    })
+}
+
+fn override_setting(settings: &mut Vec<Setting>, setting: Setting, spec: &impl OptionSpec) {
+    settings.retain(|existing| existing.name != setting.name);
+    drop_conflicts(settings, &setting, spec);
+    settings.push(setting);
+}
+
+/// Drops the settings that can't be given along with `setting`.
+fn drop_conflicts(settings: &mut Vec<Setting>, setting: &Setting, spec: &impl OptionSpec) {
+    if !setting.enabled {
+        return;
+    }
+
+    settings.retain(|existing| {
+        !existing.enabled
+            || existing.name == setting.name
+            || !spec.conflicts(&existing.name, &setting.name)
+    });
+}
+
+/// Finds the options given on the command line, including short flags like
+/// `-v` or `-vq`, which are named after their long flags, along with the
+/// subcommand if one was given. Values are only needed for reporting where
+/// options came from, so anything that isn't an option we know about is
+/// skipped, as are a subcommand's own options.
+fn command_line_settings(
+    args: &[OsString],
+    spec: &impl OptionSpec,
+) -> (Vec<Setting>, Option<String>) {
+    let mut settings: Vec<Setting> = Vec::new();
+    let mut subcommand = None;
+    let mut args = args.iter().map(|arg| arg.to_string_lossy());
+
+    while let Some(arg) = args.next() {
+        let mut given = Vec::new();
+
+        // Our own options don't take positional arguments, so the first one
+        // is the subcommand.
+        if !arg.starts_with('-') && subcommand.is_none() {
+            subcommand = Some(arg.into_owned());
+            continue;
+        }
+
+        if let Some(option) = arg.strip_prefix("--") {
+            if option.is_empty() {
+                // Everything after `--` is a value.
+                break;
+            }
+
+            let (name, inline_value) = match option.find('=') {
+                Some(index) => (&option[..index], Some(option[index + 1..].to_owned())),
+                None => (option, None),
+            };
+
+            given.push((name.to_owned(), inline_value));
+        } else if let Some(shorts) = arg.strip_prefix('-') {
+            for (index, short) in shorts.char_indices() {
+                let name = match spec.long_name(short) {
+                    Some(name) => name,
+                    None => break,
+                };
+
+                // Like `-p50312`, the rest of the argument is the value of a
+                // short flag that takes one.
+                if spec.kind_of(name) == Some(ArgKind::Value) {
+                    let rest = &shorts[index + short.len_utf8()..];
+                    given.push((
+                        name.to_owned(),
+                        Some(rest.to_owned()).filter(|rest| !rest.is_empty()),
+                    ));
+                    break;
+                }
+
+                given.push((name.to_owned(), None));
+            }
+        }
+
+        for (name, inline_value) in given {
+            let kind = match spec.kind_of(&name) {
+                Some(kind) => kind,
+                None => continue,
+            };
+
+            let value = match kind {
+                ArgKind::Flag => None,
+                ArgKind::Value => inline_value.or_else(|| args.next().map(|arg| arg.into_owned())),
+            };
+
+            if subcommand.is_some() && !spec.is_global(&name) {
+                continue;
+            }
+
+            match settings.iter_mut().find(|setting| setting.name == name) {
+                Some(setting) => setting.values.extend(value),
+                None => settings.push(Setting {
+                    name,
+                    enabled: true,
+                    values: value.into_iter().collect(),
+                    source: Source::CommandLine,
+                }),
+            }
+        }
+    }
+
+    (settings, subcommand)
+}
+
+/// Reads the options set in a config file, which holds a JSON object like
+/// `{ "place": "MyPlace.rbxlx", "timeout": "2m", "mount": ["src/=..."] }`.
+fn file_settings(path: &Path, spec: &impl OptionSpec) -> Result<Vec<Setting>, anyhow::Error> {
+    let contents = fs::read_to_string(path)?;
+    let object: serde_json::Map<String, Value> = serde_json::from_str(&contents)
+        .with_context(|| format!("{} should hold a JSON object", path.display()))?;
+
+    let mut settings = Vec::new();
+
+    for (name, value) in object {
+        let kind = match spec.kind_of(&name) {
+            Some(_) if COMMAND_LINE_ONLY.contains(&name.as_str()) => {
+                bail!("{} can't set {:?}", path.display(), name)
+            }
+            Some(kind) => kind,
+            None => bail!("{} sets unknown option {:?}", path.display(), name),
+        };
+
+        let (enabled, values) = match (kind, &value) {
+            (ArgKind::Flag, Value::Bool(enabled)) => (*enabled, Vec::new()),
+            (ArgKind::Value, Value::Array(items)) => {
+                let values = items.iter().map(value_string).collect::<Option<Vec<_>>>();
+
+                match values {
+                    Some(values) if !values.is_empty() => (true, values),
+                    _ => bail!(
+                        "{} should set {:?} to a string, a number, or a non-empty array of them",
+                        path.display(),
+                        name
+                    ),
+                }
+            }
+            (ArgKind::Value, value) => match value_string(value) {
+                Some(value) => (true, vec![value]),
+                None => bail!(
+                    "{} should set {:?} to a string, a number, or an array of them",
+                    path.display(),
+                    name
+                ),
+            },
+            (ArgKind::Flag, _) => {
+                bail!("{} should set {:?} to true or false", path.display(), name)
+            }
+        };
+
+        settings.push(Setting {
+            name,
+            enabled,
+            values,
+            source: Source::File(path.to_path_buf()),
+        });
+    }
+
+    Ok(settings)
+}
+
+fn value_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(value) => Some(value.clone()),
+        Value::Number(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// Reads the options set with environment variables, like
+/// `RUN_IN_ROBLOX_KEEP_OPEN=true` for `--keep-open`.
+fn env_settings(
+    spec: &impl OptionSpec,
+    warnings: &mut Vec<String>,
+) -> Result<Vec<Setting>, anyhow::Error> {
+    let mut settings = Vec::new();
+
+    let mut vars: Vec<(String, String)> = env::vars()
+        .filter(|(var, _)| var.starts_with(ENV_PREFIX))
+        .collect();
+    vars.sort();
+
+    for (var, value) in vars {
+        let name = var[ENV_PREFIX.len()..].to_lowercase().replace('_', "-");
+
+        let kind = match spec.kind_of(&name) {
+            Some(_) if COMMAND_LINE_ONLY.contains(&name.as_str()) => {
+                bail!("--{} can't be set with an environment variable", name)
+            }
+            Some(kind) => kind,
+            None => {
+                warnings.push(format!("Ignoring {}, which doesn't match an option", var));
+                continue;
+            }
+        };
+
+        let (enabled, values) = match kind {
+            ArgKind::Flag => match value.to_lowercase().as_str() {
+                "1" | "true" | "yes" => (true, Vec::new()),
+                "" | "0" | "false" | "no" => (false, Vec::new()),
+                _ => bail!("{} should be true or false, not {:?}", var, value),
+            },
+            ArgKind::Value => (true, vec![value]),
+        };
+
+        settings.push(Setting {
+            name,
+            enabled,
+            values,
+            source: Source::Env(var),
+        });
+    }
+
+    Ok(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    /// `layer` reads every RUN_IN_ROBLOX_ variable, so tests that set them
+    /// take turns.
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    struct TestSpec;
+
+    impl OptionSpec for TestSpec {
+        fn kind_of(&self, name: &str) -> Option<ArgKind> {
+            match name {
+                "config" | "place" | "timeout" | "mount" | "lang" => Some(ArgKind::Value),
+                "show-config" | "detach" | "plain" | "verbose" | "quiet" => Some(ArgKind::Flag),
+                _ => None,
+            }
+        }
+
+        fn long_name(&self, short: char) -> Option<&'static str> {
+            match short {
+                'v' => Some("verbose"),
+                'q' => Some("quiet"),
+                _ => None,
+            }
+        }
+
+        fn conflicts(&self, name: &str, other: &str) -> bool {
+            matches!((name, other), ("verbose", "quiet") | ("quiet", "verbose"))
+        }
+
+        fn is_global(&self, name: &str) -> bool {
+            matches!(name, "config" | "lang" | "plain" | "verbose" | "quiet")
+        }
+    }
+
+    /// Layers `args` over the config file holding `config` and the given
+    /// environment variables.
+    fn layer_with(
+        args: &[&str],
+        config: &str,
+        vars: &[(&str, &str)],
+    ) -> Result<Layered, anyhow::Error> {
+        let _lock = ENV_LOCK
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join(CONFIG_FILE_NAME);
+        fs::write(&config_path, config).unwrap();
+
+        let args = ["run-in-roblox", "--config", config_path.to_str().unwrap()]
+            .iter()
+            .chain(args)
+            .map(OsString::from)
+            .collect();
+
+        for (var, value) in vars {
+            env::set_var(var, value);
+        }
+
+        let layered = layer(args, &TestSpec);
+
+        for (var, _) in vars {
+            env::remove_var(var);
+        }
+
+        layered
+    }
+
+    fn setting<'a>(layered: &'a Layered, name: &str) -> Option<&'a Setting> {
+        layered.settings.iter().find(|setting| setting.name == name)
+    }
+
+    fn args_after_config(layered: &Layered) -> Vec<String> {
+        let args: Vec<String> = layered
+            .args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let config_index = args.iter().position(|arg| arg == "--config").unwrap();
+
+        args[..config_index]
+            .iter()
+            .chain(&args[config_index + 2..])
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn later_sources_win() {
+        let layered = layer_with(
+            &["--mount", "c=ReplicatedStorage"],
+            r#"{ "place": "file.rbxlx", "timeout": "1m", "mount": ["a=Workspace", "b=Workspace"] }"#,
+            &[("RUN_IN_ROBLOX_TIMEOUT", "2m")],
+        )
+        .unwrap();
+
+        let place = setting(&layered, "place").unwrap();
+        assert_eq!(place.values, ["file.rbxlx"]);
+        assert!(matches!(place.source, Source::File(_)));
+
+        let timeout = setting(&layered, "timeout").unwrap();
+        assert_eq!(timeout.values, ["2m"]);
+        assert_eq!(
+            timeout.source,
+            Source::Env("RUN_IN_ROBLOX_TIMEOUT".to_owned())
+        );
+
+        let mount = setting(&layered, "mount").unwrap();
+        assert_eq!(mount.values, ["c=ReplicatedStorage"]);
+        assert_eq!(mount.source, Source::CommandLine);
+
+        let args = args_after_config(&layered);
+        assert!(args.contains(&"2m".to_owned()));
+        assert!(!args.contains(&"1m".to_owned()));
+        assert!(!args.contains(&"a=Workspace".to_owned()));
+        assert!(args.ends_with(&["--mount".to_owned(), "c=ReplicatedStorage".to_owned()]));
+    }
+
+    #[test]
+    fn conflicting_settings_are_dropped() {
+        let layered = layer_with(
+            &["-q"],
+            r#"{ "verbose": true }"#,
+            &[("RUN_IN_ROBLOX_PLAIN", "1")],
+        )
+        .unwrap();
+
+        assert!(setting(&layered, "verbose").is_none());
+        assert_eq!(
+            setting(&layered, "quiet").unwrap().source,
+            Source::CommandLine
+        );
+        assert_eq!(
+            args_after_config(&layered),
+            ["run-in-roblox", "--plain", "-q"]
+        );
+    }
+
+    #[test]
+    fn detach_is_command_line_only() {
+        assert!(layer_with(&[], r#"{ "detach": true }"#, &[]).is_err());
+        assert!(layer_with(&[], "{}", &[("RUN_IN_ROBLOX_DETACH", "true")]).is_err());
+        assert!(layer_with(&["--detach"], "{}", &[]).is_ok());
+    }
+
+    #[test]
+    fn subcommands_only_get_global_settings() {
+        let layered = layer_with(
+            &["status", "--plain", "--timeout", "1m"],
+            r#"{ "lang": "es", "timeout": "2m" }"#,
+            &[("RUN_IN_ROBLOX_PLACE", "env.rbxlx")],
+        )
+        .unwrap();
+
+        assert_eq!(setting(&layered, "lang").unwrap().values, ["es"]);
+        assert_eq!(
+            setting(&layered, "plain").unwrap().source,
+            Source::CommandLine
+        );
+        assert!(setting(&layered, "timeout").is_none());
+        assert!(setting(&layered, "place").is_none());
+        assert_eq!(
+            args_after_config(&layered),
+            [
+                "run-in-roblox",
+                "--lang",
+                "es",
+                "status",
+                "--plain",
+                "--timeout",
+                "1m"
+            ]
+        );
+    }
+}
@@ -0,0 +1,69 @@
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use fs_err as fs;
+
+/// Folders that Roblox Studio or the operating system write crash dumps to,
+/// along with a prefix that identifies files that came from Studio.
+fn crash_dump_locations() -> Vec<(PathBuf, &'static str)> {
+    let mut locations = Vec::new();
+
+    if let Some(local_data) = dirs::data_local_dir() {
+        locations.push((local_data.join("Roblox").join("logs").join("crashes"), ""));
+        locations.push((local_data.join("CrashDumps"), "RobloxStudio"));
+    }
+
+    if let Some(home) = dirs::home_dir() {
+        locations.push((
+            home.join("Library").join("Logs").join("DiagnosticReports"),
+            "RobloxStudio",
+        ));
+    }
+
+    locations
+}
+
+/// Copies crash dumps written since `since` into `destination`, returning the
+/// paths of the copies.
+pub fn collect(since: SystemTime, destination: &Path) -> Vec<PathBuf> {
+    let mut collected = Vec::new();
+
+    for (location, prefix) in crash_dump_locations() {
+        let entries = match fs::read_dir(&location) {
+            Ok(entries) => entries.flatten(),
+            Err(_) => continue,
+        };
+
+        for entry in entries {
+            let file_name = entry.file_name();
+            let matches_prefix = file_name
+                .to_str()
+                .map(|name| name.starts_with(prefix))
+                .unwrap_or(false);
+
+            let is_new = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .map(|modified| modified >= since)
+                .unwrap_or(false);
+
+            if !matches_prefix || !is_new || !entry.path().is_file() {
+                continue;
+            }
+
+            let copy_path = destination.join(&file_name);
+
+            let copied =
+                fs::create_dir_all(destination).and_then(|_| fs::copy(entry.path(), &copy_path));
+
+            match copied {
+                Ok(_) => collected.push(copy_path),
+                Err(err) => log::warn!("Could not copy crash dump: {}", err),
+            }
+        }
+    }
+
+    collected
+}
@@ -0,0 +1,288 @@
+use std::{
+    env,
+    ffi::OsString,
+    fmt,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context};
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+
+use crate::{clean, report};
+
+/// How often `wait` checks whether a detached run has finished.
+const WAIT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often `follow` checks a detached run's log for new output.
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The file in a detached run's folder that describes it.
+const SESSION_FILE_NAME: &str = "session.json";
+
+/// A run started with --detach, which keeps going in the background after the
+/// terminal that started it closes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DetachedRun {
+    pub id: String,
+    pub pid: u32,
+
+    /// When the run started, in seconds since the Unix epoch.
+    pub started_at: u64,
+
+    /// The options the run was started with.
+    pub args: Vec<String>,
+
+    /// Where the run's output is written.
+    pub log_path: PathBuf,
+
+    /// The --report the run writes, which tells whether it has finished.
+    pub report_path: PathBuf,
+}
+
+/// How a detached run is doing, as told by its report and whether its process
+/// is still around.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunState {
+    Running,
+    Finished {
+        exit_code: i32,
+    },
+
+    /// The process is gone without finalizing its report, because it crashed
+    /// or was killed.
+    Stopped,
+}
+
+impl fmt::Display for RunState {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RunState::Running => write!(formatter, "running"),
+            RunState::Finished { exit_code: 0 } => write!(formatter, "passed"),
+            RunState::Finished { exit_code } => {
+                write!(formatter, "failed with exit code {}", exit_code)
+            }
+            RunState::Stopped => write!(formatter, "stopped without finishing"),
+        }
+    }
+}
+
+impl DetachedRun {
+    pub fn started_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.started_at)
+    }
+
+    pub fn state(&self) -> RunState {
+        // The run might be part way through rewriting its report, so a report
+        // that can't be read counts as not finalized.
+        let report: Option<serde_json::Value> = fs::read(&self.report_path)
+            .ok()
+            .and_then(|contents| serde_json::from_slice(&contents).ok());
+
+        if let Some(report) = &report {
+            if report["finalized"] == true {
+                let exit_code = report["exitCode"].as_i64().unwrap_or(2);
+
+                return RunState::Finished {
+                    exit_code: exit_code as i32,
+                };
+            }
+        }
+
+        if clean::process_exists(self.pid) {
+            RunState::Running
+        } else {
+            RunState::Stopped
+        }
+    }
+
+    /// Waits for the run to finish, returning how it ended.
+    pub fn wait(&self) -> RunState {
+        loop {
+            match self.state() {
+                RunState::Running => thread::sleep(WAIT_POLL_INTERVAL),
+                state => return state,
+            }
+        }
+    }
+
+    /// Copies the run's output to `output`, starting with everything it has
+    /// written so far, then as it's written until the run finishes, returning
+    /// how it ended.
+    pub fn follow(&self, mut output: impl Write) -> Result<RunState, anyhow::Error> {
+        let mut log = fs::File::open(&self.log_path)?;
+
+        loop {
+            // The state is checked before reading, so that output written
+            // right before the run finished is still copied.
+            let state = self.state();
+
+            io::copy(&mut log, &mut output)?;
+            output.flush()?;
+
+            if state != RunState::Running {
+                return Ok(state);
+            }
+
+            thread::sleep(FOLLOW_POLL_INTERVAL);
+        }
+    }
+}
+
+/// Starts run-in-roblox again in the background with `args`, which shouldn't
+/// include --detach, and returns the run it started. The run's output goes to
+/// a log file, and it writes a report even if --report isn't given, so that
+/// `status` and `wait` can tell how it went.
+pub fn spawn(mut args: Vec<OsString>) -> Result<DetachedRun, anyhow::Error> {
+    let id = format!("{:08x}", rand::random::<u32>());
+    let dir = run_dir(&id)?;
+    fs::create_dir_all(&dir)?;
+
+    let report_path = match given_report_path(&args) {
+        Some(report_path) => env::current_dir()?.join(report_path),
+        None => {
+            let report_path = dir.join("report.json");
+            args.push("--report".into());
+            args.push(report_path.clone().into());
+            report_path
+        }
+    };
+
+    let log_path = dir.join("log.txt");
+    let log = fs::File::create(&log_path)?.into_parts().0;
+    let log_for_errors = log.try_clone()?;
+
+    let mut command = Command::new(env::current_exe()?);
+    command
+        .args(&args)
+        .stdin(Stdio::null())
+        .stdout(log)
+        .stderr(log_for_errors)
+        .env("NO_COLOR", "1");
+    detach_command(&mut command);
+
+    let child = command
+        .spawn()
+        .context("Could not start run-in-roblox in the background")?;
+
+    let started_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let run = DetachedRun {
+        id,
+        pid: child.id(),
+        started_at,
+        args: args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect(),
+        log_path,
+        report_path,
+    };
+
+    report::write_atomic(
+        &dir.join(SESSION_FILE_NAME),
+        &serde_json::to_vec_pretty(&run)?,
+    )?;
+
+    Ok(run)
+}
+
+/// Finds the detached run with the given ID.
+pub fn load(id: &str) -> Result<DetachedRun, anyhow::Error> {
+    let path = run_dir(id)?.join(SESSION_FILE_NAME);
+
+    let contents = match fs::read(&path) {
+        Ok(contents) => contents,
+        Err(_) => bail!("There's no detached run with the ID {:?}", id),
+    };
+
+    serde_json::from_slice(&contents).with_context(|| format!("Could not read {}", path.display()))
+}
+
+/// Lists the detached runs on this machine, oldest first.
+pub fn list() -> Result<Vec<DetachedRun>, anyhow::Error> {
+    let dir = runs_dir()?;
+
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut runs = Vec::new();
+
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path().join(SESSION_FILE_NAME);
+
+        let run = fs::read(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_slice::<DetachedRun>(&contents).ok());
+
+        match run {
+            Some(run) => runs.push(run),
+            None => log::debug!("Skipping unreadable detached run {}", path.display()),
+        }
+    }
+
+    runs.sort_by_key(|run| run.started_at);
+    Ok(runs)
+}
+
+/// Finds the --report path given in `args`, if there is one.
+fn given_report_path(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let arg = arg.to_string_lossy();
+
+        if arg == "--report" {
+            return args.next().map(PathBuf::from);
+        }
+
+        if let Some(path) = arg.strip_prefix("--report=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+
+    None
+}
+
+fn runs_dir() -> Result<PathBuf, anyhow::Error> {
+    dirs::cache_dir()
+        .map(|dir| dir.join("run-in-roblox").join("detached"))
+        .context("Could not find a cache folder to keep detached runs in")
+}
+
+fn run_dir(id: &str) -> Result<PathBuf, anyhow::Error> {
+    if id.is_empty() || Path::new(id).components().count() != 1 {
+        bail!("{:?} isn't a detached run ID", id);
+    }
+
+    Ok(runs_dir()?.join(id))
+}
+
+/// Keeps the background process from being stopped along with the terminal
+/// that started it.
+#[cfg(windows)]
+fn detach_command(command: &mut Command) {
+    use std::os::windows::process::CommandExt;
+
+    const DETACHED_PROCESS: u32 = 0x0000_0008;
+    const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+
+    command.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+}
+
+#[cfg(not(windows))]
+fn detach_command(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+
+    // The terminal only hangs up on processes in its own process groups.
+    command.process_group(0);
+}
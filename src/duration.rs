@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+use anyhow::anyhow;
+
+/// Parses a duration given on the command line or in configuration, like
+/// `90s`, `2m30s`, or `1h`. Plain numbers are treated as seconds.
+pub fn parse_duration(value: &str) -> Result<Duration, anyhow::Error> {
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+
+    humantime::parse_duration(value).map_err(|err| anyhow!("Invalid duration {:?}: {}", value, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_numbers_are_seconds() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+    }
+
+    #[test]
+    fn units_combine() {
+        assert_eq!(parse_duration("2m30s").unwrap(), Duration::from_secs(150));
+        assert_eq!(parse_duration("1h").unwrap(), Duration::from_secs(3600));
+        assert_eq!(parse_duration("100ms").unwrap(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn invalid_durations_are_rejected() {
+        for value in ["", "-5", "-5s", "5x"] {
+            assert!(parse_duration(value).is_err(), "{:?}", value);
+        }
+
+        assert_eq!(
+            parse_duration("5x").unwrap_err().to_string(),
+            "Invalid duration \"5x\": unknown time unit \"x\", supported units: ns, us, ms, \
+             sec, min, hours, days, weeks, months, years (and few variations)"
+        );
+    }
+}
@@ -0,0 +1,266 @@
+use std::{path::PathBuf, sync::mpsc, time::Duration};
+
+use anyhow::{anyhow, bail, Context};
+use tempfile::TempDir;
+
+use crate::{
+    clean::{SessionMarker, TEMP_DIR_PREFIX},
+    lune_runner::{self, LuneRunner},
+    message_receiver::{CommandQueue, HostCommand, RobloxMessage},
+    mock_runner::MockRunner,
+    output::FormatVersion,
+    place_runner::{self, PlaceRunner},
+    plugin::{Capture, InjectMethod, Isolation, StudioMode},
+    port_registry::{self, PortClaim},
+    runner::{self, Backend, RunnerEvent, ScriptRunner},
+    severity::SeverityMap,
+    studio_cache,
+};
+
+/// What a [`Runner`] runs, and where.
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    /// The source of the script to run.
+    pub script: String,
+
+    pub backend: Backend,
+
+    /// The place to run the script in. Studio is given a copy, so the place
+    /// itself is never changed. An empty baseplate is used if there isn't
+    /// one.
+    pub place_path: Option<PathBuf>,
+
+    /// Where Roblox Studio is installed, found automatically if not given.
+    pub studio_app_path: Option<PathBuf>,
+
+    /// Where Roblox Studio loads plugins from, found automatically if not
+    /// given.
+    pub studio_plugins_path: Option<PathBuf>,
+
+    /// The port run-in-roblox listens on for the plugin. If not given, a free
+    /// port is claimed the same way the `run-in-roblox` command does, so runs
+    /// started at the same time don't collide.
+    pub port: Option<u16>,
+
+    /// How long the script can run before the run fails.
+    pub timeout: Option<Duration>,
+
+    /// Variables the script can read from `RIR_ENV`, by name.
+    pub env: Vec<(String, String)>,
+}
+
+impl RunOptions {
+    /// Runs `script` in Roblox Studio, in an empty baseplate.
+    pub fn new(script: impl Into<String>) -> Self {
+        RunOptions {
+            script: script.into(),
+            backend: Backend::Studio,
+            place_path: None,
+            studio_app_path: None,
+            studio_plugins_path: None,
+            port: None,
+            timeout: None,
+            env: Vec::new(),
+        }
+    }
+}
+
+/// Runs a script from another program, the same way the `run-in-roblox`
+/// command does, without parsing its output.
+///
+/// ```no_run
+/// use run_in_roblox::{OutputLevel, RobloxMessage, RunOptions, Runner};
+///
+/// let mut messages = Runner::new(RunOptions::new("print('Hello!')")).run()?;
+///
+/// for message in &mut messages {
+///     if let RobloxMessage::Output { level: OutputLevel::Error, body, .. } = message {
+///         eprintln!("{}", body);
+///     }
+/// }
+///
+/// messages.finish()?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct Runner {
+    options: RunOptions,
+}
+
+impl Runner {
+    pub fn new(options: RunOptions) -> Self {
+        Runner { options }
+    }
+
+    /// Starts the script, returning the messages it produces as they arrive.
+    /// Fails if the run couldn't be set up; if the run fails after it starts,
+    /// the messages stop early and `Messages::finish` returns the error.
+    pub fn run(self) -> Result<Messages, anyhow::Error> {
+        let options = self.options;
+
+        if options.place_path.is_some() && options.backend != Backend::Studio {
+            bail!("A place can only be used with the studio backend");
+        }
+
+        let temp_dir = tempfile::Builder::new()
+            .prefix(TEMP_DIR_PREFIX)
+            .tempdir()
+            .context("Could not create a temporary directory for the run")?;
+        SessionMarker::for_current_process().write(temp_dir.path())?;
+
+        let commands = CommandQueue::new();
+        let mut port_claim = None;
+
+        let script_runner: Box<dyn ScriptRunner> = match options.backend {
+            Backend::Studio => {
+                let place_path =
+                    place_runner::copy_place(options.place_path.as_deref(), temp_dir.path())?;
+
+                let (studio_app_path, studio_plugins_path) =
+                    match (options.studio_app_path, options.studio_plugins_path) {
+                        (Some(app_path), Some(plugins_path)) => (app_path, plugins_path),
+                        (app_path, plugins_path) => {
+                            let studio_install = studio_cache::locate(true)
+                                .context("Could not find Roblox Studio")?;
+
+                            (
+                                app_path.unwrap_or(studio_install.application_path),
+                                plugins_path.unwrap_or(studio_install.plugins_path),
+                            )
+                        }
+                    };
+
+                let port = match options.port {
+                    Some(port) => port,
+                    None => port_claim
+                        .insert(port_registry::claim(place_runner::DEFAULT_PORT)?)
+                        .port(),
+                };
+
+                Box::new(PlaceRunner {
+                    port,
+                    place_path,
+                    place_source: options.place_path,
+                    studio_app_path,
+                    studio_plugins_path,
+                    inject_method: InjectMethod::PluginsFolder,
+                    server_id: place_runner::new_server_id(),
+                    lua_scripts: vec![options.script],
+                    parallel_scripts: 1,
+                    isolation: Isolation::None,
+                    modules: Vec::new(),
+                    script_models: Vec::new(),
+                    mounts: Vec::new(),
+                    models: Vec::new(),
+                    mode: StudioMode::Edit,
+                    capture: Capture::All,
+                    wait_for_done: false,
+                    compare_security: false,
+                    chaos_http: None,
+                    forbid_network: false,
+                    read_only: false,
+                    budget: None,
+                    probe: None,
+                    probe_interval: Duration::from_secs(5),
+                    warmup: None,
+                    duration: None,
+                    flush_interval: Duration::from_millis(100),
+                    env: options.env,
+                    timeout: options.timeout,
+                    unresponsive_timeout: Duration::from_secs(30),
+                    validate_output: false,
+                    severity: SeverityMap::default(),
+                    format_version: FormatVersion::latest(),
+                    artifacts_dir: None,
+                    out_dir: None,
+                    read_dirs: Vec::new(),
+                    commands: commands.clone(),
+                    repl: false,
+                    snapshot: None,
+                    inspect: false,
+                })
+            }
+            Backend::Lune => Box::new(LuneRunner {
+                script_path: temp_dir.path().join(lune_runner::SCRIPT_FILE_NAME),
+                lua_script: options.script,
+                timeout: options.timeout,
+                env: options.env,
+            }),
+            Backend::Mock => Box::new(MockRunner {
+                lua_script: options.script,
+            }),
+        };
+
+        Ok(Messages {
+            events: runner::spawn(script_runner),
+            outcome: None,
+            commands,
+            _port_claim: port_claim,
+            _temp_dir: temp_dir,
+        })
+    }
+}
+
+/// The messages from a script started by [`Runner::run`], in the order they
+/// were produced.
+pub struct Messages {
+    events: mpsc::Receiver<RunnerEvent>,
+    outcome: Option<Result<(), anyhow::Error>>,
+    commands: CommandQueue,
+
+    // The port claimed for the run, if one wasn't given, which is given back
+    // when this is dropped.
+    _port_claim: Option<PortClaim>,
+
+    // Dropped last, once Studio has been closed.
+    _temp_dir: TempDir,
+}
+
+impl Messages {
+    /// Sends a command to the running script, which handles it with
+    /// `rir.onCommand()`. `HostCommand::Cancel` ends the run early. Only the
+    /// studio backend receives commands; other backends ignore them.
+    pub fn send_command(&self, command: HostCommand) {
+        self.commands.push(command);
+    }
+
+    /// A handle for sending commands from another thread while this one reads
+    /// messages, like cancelling the run when a user asks to.
+    pub fn commands(&self) -> CommandQueue {
+        self.commands.clone()
+    }
+
+    /// Waits for the run to end, skipping any messages that haven't been read
+    /// yet, and returns how it went.
+    pub fn finish(mut self) -> Result<(), anyhow::Error> {
+        while self.next().is_some() {}
+
+        self.outcome.take().unwrap()
+    }
+}
+
+impl Iterator for Messages {
+    type Item = RobloxMessage;
+
+    fn next(&mut self) -> Option<RobloxMessage> {
+        if self.outcome.is_some() {
+            return None;
+        }
+
+        loop {
+            let outcome = match self.events.recv() {
+                Ok(RunnerEvent::Message(message)) => return Some(message.message),
+                Ok(RunnerEvent::Lifecycle { .. }) => continue,
+
+                // Studio is closed as soon as the run is over.
+                Ok(RunnerEvent::Finished(_process)) => Ok(()),
+                Ok(RunnerEvent::Failed(err)) => Err(err),
+                Err(_) => Err(anyhow!(
+                    "The runner stopped without reporting how the run ended"
+                )),
+            };
+
+            self.outcome = Some(outcome);
+            return None;
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use fs_err as fs;
+
+use crate::baseplate;
+
+/// The files of the example project, by their path within it.
+static FILES: &[(&str, &str)] = &[
+    ("README.md", include_str!("examples/README.md")),
+    (
+        "run-in-roblox.json",
+        include_str!("examples/run-in-roblox.json"),
+    ),
+    (
+        "scripts/hello.lua",
+        include_str!("examples/scripts/hello.lua"),
+    ),
+    (
+        "scripts/checks.lua",
+        include_str!("examples/scripts/checks.lua"),
+    ),
+    (
+        "scripts/greeting.lua",
+        include_str!("examples/scripts/greeting.lua"),
+    ),
+    (
+        "scripts/bundled.lua",
+        include_str!("examples/scripts/bundled.lua"),
+    ),
+    (
+        "scripts/run-specs.lua",
+        include_str!("examples/scripts/run-specs.lua"),
+    ),
+    (
+        "specs/math.spec.lua",
+        include_str!("examples/specs/math.spec.lua"),
+    ),
+];
+
+/// The name of the example place, which is generated rather than stored.
+pub const PLACE_NAME: &str = "place.rbxl";
+
+/// The example scripts that work on the mock backend, along with the options
+/// they're run with. Everything else needs Roblox Studio.
+pub static MOCK_RUNS: &[(&str, &[&str])] = &[
+    ("scripts/hello.lua", &[]),
+    ("scripts/checks.lua", &[]),
+    ("scripts/bundled.lua", &["--bundle"]),
+];
+
+/// Whether the example project has already been written into `dir`.
+pub fn is_written(dir: &Path) -> bool {
+    dir.join(PLACE_NAME).is_file() && FILES.iter().all(|(path, _)| dir.join(path).is_file())
+}
+
+/// Writes the example project into `dir`, returning the paths of the files
+/// it wrote. Fails without writing anything if any of them already exist.
+pub fn write(dir: &Path) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let mut paths: Vec<PathBuf> = FILES.iter().map(|(path, _)| dir.join(path)).collect();
+    paths.push(dir.join(PLACE_NAME));
+
+    let existing: Vec<String> = paths
+        .iter()
+        .filter(|path| path.exists())
+        .map(|path| format!("  {}", path.display()))
+        .collect();
+
+    if !existing.is_empty() {
+        bail!(
+            "Not writing the examples, since these files already exist:\n{}",
+            existing.join("\n")
+        );
+    }
+
+    for (path, contents) in FILES {
+        let path = dir.join(path);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(&path, contents)?;
+    }
+
+    baseplate::write_place(&baseplate::new_place(), &dir.join(PLACE_NAME))?;
+
+    Ok(paths)
+}
@@ -0,0 +1,10 @@
+-- Requires a module next to it, which --bundle includes in the run. The
+-- run-in-roblox.json in this folder turns --bundle on.
+--
+--   run-in-roblox --backend mock --script scripts/bundled.lua
+--   run-in-roblox --place place.rbxl --script scripts/bundled.lua
+
+local greeting = require(script.Parent.greeting)
+
+print(greeting.greet("bundled module"))
+rir.pass("bundled module loaded")
@@ -0,0 +1,23 @@
+-- Records checks, which are counted in a summary at the end of the run. Any
+-- failing check fails the run.
+--
+--   run-in-roblox --backend mock --script scripts/checks.lua
+--   run-in-roblox --place place.rbxl --script scripts/checks.lua
+
+-- Raises an error listing everything about the baseplate that doesn't match.
+rir.expect_tree(workspace.Baseplate, {
+	class = "Part",
+	properties = { Anchored = true },
+})
+rir.pass("baseplate is anchored")
+
+-- rir.fail takes a reason, which is printed along with the check's name.
+local function check(name, passed, reason)
+	if passed then
+		rir.pass(name)
+	else
+		rir.fail(name, reason)
+	end
+end
+
+check("baseplate is big enough", workspace.Baseplate.Size.X >= 512, "the baseplate is too small")
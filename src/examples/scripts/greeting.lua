@@ -0,0 +1,9 @@
+-- A module required by bundled.lua.
+
+local greeting = {}
+
+function greeting.greet(name)
+	return "Hello, " .. name .. "!"
+end
+
+return greeting
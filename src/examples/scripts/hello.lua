@@ -0,0 +1,7 @@
+-- Prints output at each level. Only errors fail the run.
+--
+--   run-in-roblox --backend mock --script scripts/hello.lua
+--   run-in-roblox --place place.rbxl --script scripts/hello.lua
+
+print("Hello from run-in-roblox!")
+warn("Warnings are printed, but don't fail the run")
@@ -0,0 +1,15 @@
+-- Runs the TestEZ specs in specs/. This needs Roblox Studio, with TestEZ and
+-- the specs mounted into the place:
+--
+--   run-in-roblox --place place.rbxl --script scripts/run-specs.lua \
+--     --mount path/to/TestEZ/src=ReplicatedStorage.TestEZ \
+--     --mount specs/=ReplicatedStorage.Specs
+
+local ReplicatedStorage = game:GetService("ReplicatedStorage")
+
+local TestEZ = require(ReplicatedStorage.TestEZ)
+local results = TestEZ.TestBootstrap:run({ ReplicatedStorage.Specs })
+
+if results.failureCount > 0 then
+	error(string.format("%d spec(s) failed", results.failureCount))
+end
@@ -0,0 +1,12 @@
+return function()
+	describe("math.clamp", function()
+		it("keeps numbers within the range", function()
+			expect(math.clamp(5, 0, 3)).to.equal(3)
+			expect(math.clamp(-1, 0, 3)).to.equal(0)
+		end)
+
+		it("leaves numbers in the range alone", function()
+			expect(math.clamp(2, 0, 3)).to.equal(2)
+		end)
+	end)
+end
@@ -0,0 +1,281 @@
+use std::path::{Path, PathBuf};
+
+use fs_err as fs;
+use regex::Regex;
+
+/// Files that list paths to skip when discovering scripts on disk. The second
+/// one lets projects skip files for run-in-roblox only.
+const IGNORE_FILE_NAMES: &[&str] = &[".gitignore", ".rirignore"];
+
+/// Ignore rules gathered from `.gitignore` and `.rirignore` files, supporting
+/// the commonly used parts of gitignore's syntax: `*`, `?`, `**`, leading `/`
+/// to anchor a pattern, trailing `/` to only match folders, and `!` to
+/// re-include a path.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    base: PathBuf,
+    pattern: Regex,
+    negated: bool,
+    dir_only: bool,
+}
+
+impl IgnoreRules {
+    /// Collects the rules that apply inside `dir`, including those from ignore
+    /// files in its ancestors up to the root of its Git repository.
+    pub fn for_dir(dir: &Path) -> IgnoreRules {
+        // A relative path's ancestors stop at the working directory, which
+        // can be inside the repository.
+        let dir = resolve(dir);
+        let mut ancestors = Vec::new();
+
+        for ancestor in dir.ancestors().skip(1) {
+            ancestors.push(ancestor);
+
+            if ancestor.join(".git").exists() {
+                break;
+            }
+        }
+
+        let mut rules = IgnoreRules::default();
+
+        for ancestor in ancestors.into_iter().rev() {
+            rules = rules.with_dir(ancestor);
+        }
+
+        rules.with_dir(&dir)
+    }
+
+    /// Returns these rules combined with the ones from ignore files directly
+    /// inside `dir`.
+    pub fn with_dir(&self, dir: &Path) -> IgnoreRules {
+        let mut rules = self.clone();
+        let dir = resolve(dir);
+
+        for file_name in IGNORE_FILE_NAMES {
+            if let Ok(contents) = fs::read_to_string(dir.join(file_name)) {
+                rules
+                    .rules
+                    .extend(contents.lines().filter_map(|line| Rule::parse(&dir, line)));
+            }
+        }
+
+        rules
+    }
+
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if path.file_name().map(|name| name == ".git").unwrap_or(false) {
+            return true;
+        }
+
+        let path = resolve(path);
+        let mut ignored = false;
+
+        for rule in &self.rules {
+            if rule.dir_only && !is_dir {
+                continue;
+            }
+
+            let relative = match path.strip_prefix(&rule.base) {
+                Ok(relative) => relative,
+                Err(_) => continue,
+            };
+
+            let relative = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+
+            if rule.pattern.is_match(&relative) {
+                ignored = !rule.negated;
+            }
+        }
+
+        ignored
+    }
+}
+
+/// Makes `path` absolute, with links in the folders leading to it resolved,
+/// so that it can be compared with the folders rules came from. Paths that
+/// can't be resolved are left as they are.
+fn resolve(path: &Path) -> PathBuf {
+    let (parent, name) = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => (parent, name),
+        _ => return path.canonicalize().unwrap_or_else(|_| path.to_path_buf()),
+    };
+
+    let parent = if parent.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        parent
+    };
+
+    match parent.canonicalize() {
+        Ok(parent) => parent.join(name),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+impl Rule {
+    fn parse(base: &Path, line: &str) -> Option<Rule> {
+        let line = line.trim_end();
+
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+
+        // Patterns containing a slash are relative to the ignore file's
+        // folder. Others can match a file or folder name at any depth.
+        let anchored = line.contains('/');
+        let line = line.trim_start_matches('/');
+
+        let mut pattern = String::from(if anchored { "^" } else { "^(?:.*/)?" });
+        let mut chars = line.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        pattern.push_str("(?:.*/)?");
+                    } else {
+                        pattern.push_str(".*");
+                    }
+                }
+                '*' => pattern.push_str("[^/]*"),
+                '?' => pattern.push_str("[^/]"),
+                c => pattern.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+
+        pattern.push('$');
+
+        Some(Rule {
+            base: base.to_path_buf(),
+            pattern: Regex::new(&pattern).ok()?,
+            negated,
+            dir_only,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    fn rules(lines: &str) -> IgnoreRules {
+        IgnoreRules {
+            rules: lines
+                .lines()
+                .filter_map(|line| Rule::parse(Path::new("/project"), line))
+                .collect(),
+        }
+    }
+
+    fn ignores_file(rules: &IgnoreRules, path: &str) -> bool {
+        rules.is_ignored(&Path::new("/project").join(path), false)
+    }
+
+    #[test]
+    fn star_matches_within_a_name() {
+        let rules = rules("*.lua");
+
+        assert!(ignores_file(&rules, "init.lua"));
+        assert!(ignores_file(&rules, "src/deep/init.lua"));
+        assert!(!ignores_file(&rules, "init.luau"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_character() {
+        let rules = rules("test?.lua");
+
+        assert!(ignores_file(&rules, "test1.lua"));
+        assert!(!ignores_file(&rules, "test10.lua"));
+        assert!(!ignores_file(&rules, "test.lua"));
+    }
+
+    #[test]
+    fn leading_double_star_matches_any_folder() {
+        let rules = rules("**/fixtures");
+
+        assert!(ignores_file(&rules, "fixtures"));
+        assert!(ignores_file(&rules, "src/tests/fixtures"));
+        assert!(!ignores_file(&rules, "fixtures2"));
+    }
+
+    #[test]
+    fn trailing_double_star_matches_everything_inside() {
+        let rules = rules("build/**");
+
+        assert!(ignores_file(&rules, "build/out.lua"));
+        assert!(ignores_file(&rules, "build/deep/out.lua"));
+        assert!(!ignores_file(&rules, "src/build/out.lua"));
+    }
+
+    #[test]
+    fn leading_slash_anchors() {
+        let rules = rules("/out");
+
+        assert!(ignores_file(&rules, "out"));
+        assert!(!ignores_file(&rules, "src/out"));
+    }
+
+    #[test]
+    fn trailing_slash_only_matches_folders() {
+        let rules = rules("logs/");
+
+        assert!(rules.is_ignored(Path::new("/project/src/logs"), true));
+        assert!(!rules.is_ignored(Path::new("/project/src/logs"), false));
+    }
+
+    #[test]
+    fn negation_overrides_earlier_rules() {
+        let kept = rules("# comment\n\n*.lua\n!keep.lua");
+        assert!(ignores_file(&kept, "other.lua"));
+        assert!(!ignores_file(&kept, "keep.lua"));
+        assert!(!ignores_file(&kept, "# comment"));
+
+        let ignored_again = rules("!keep.lua\n*.lua");
+        assert!(ignores_file(&ignored_again, "keep.lua"));
+    }
+
+    #[test]
+    fn relative_dir_reads_rules_above_working_directory() {
+        let repo = tempfile::tempdir().unwrap();
+        fs::create_dir(repo.path().join(".git")).unwrap();
+        fs::write(repo.path().join(".gitignore"), "*.tmp\n").unwrap();
+        fs::create_dir_all(repo.path().join("sub/src")).unwrap();
+        fs::write(repo.path().join("sub/src/a.tmp"), "").unwrap();
+        fs::write(repo.path().join("sub/src/a.lua"), "").unwrap();
+
+        let working_dir = env::current_dir().unwrap();
+        env::set_current_dir(repo.path().join("sub")).unwrap();
+        let rules = IgnoreRules::for_dir(Path::new("src"));
+        let ignored = (
+            rules.is_ignored(Path::new("src/a.tmp"), false),
+            rules.is_ignored(Path::new("src/a.lua"), false),
+        );
+        env::set_current_dir(working_dir).unwrap();
+
+        assert_eq!(ignored, (true, false));
+    }
+}
@@ -0,0 +1,370 @@
+use std::{env, fmt, str::FromStr};
+
+use anyhow::bail;
+
+use crate::triage::FailureCategory;
+
+/// A language for run-in-roblox's own messages, chosen with `--lang` or
+/// detected from the environment. Output from scripts and from Roblox Studio
+/// is passed through as it is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Lang {
+    #[default]
+    English,
+    Spanish,
+    Portuguese,
+}
+
+impl FromStr for Lang {
+    type Err = anyhow::Error;
+
+    /// Accepts language codes like "es", as well as locales like "pt_BR.UTF-8".
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let code = value
+            .split(['_', '-', '.'])
+            .next()
+            .unwrap_or("")
+            .to_ascii_lowercase();
+
+        match code.as_str() {
+            "en" | "c" | "posix" => Ok(Lang::English),
+            "es" => Ok(Lang::Spanish),
+            "pt" => Ok(Lang::Portuguese),
+            _ => bail!("Invalid value for --lang, expected \"en\", \"es\", or \"pt\""),
+        }
+    }
+}
+
+/// A message in run-in-roblox's catalog. Placeholders are written as `{}` and
+/// filled in order by `Lang::format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Message {
+    ScriptRequired,
+    StudioNotFound,
+    StillRunning,
+    Quarantined,
+    ChecksSummary,
+    QuarantinedFailures,
+    TriageHeader,
+    TriageNoFailures,
+    Category(FailureCategory),
+    LeftOpen,
+    MountInserted,
+    WhenDone,
+    InspectPrompt,
+    InspectKeepOpen,
+    InspectSave,
+    InspectSavePath,
+    InspectDump,
+    InspectDumpRoot,
+    InspectDumpPath,
+    InspectSaved,
+    InspectRerun,
+    InspectClose,
+    InspectInvalid,
+    NothingToClean,
+    Removed,
+    SmokeRunning,
+    SmokePassed,
+    SmokeFailed,
+    StudioRelaunched,
+    ScriptHeader,
+    ConfigDefaults,
+    ExamplesWritten,
+    ExamplesPassed,
+    ExamplesFailed,
+    CachedPass,
+    WatchWaiting,
+    WatchChanged,
+    WatchReloading,
+    Detached,
+    DetachedStatus,
+    NoDetachedRuns,
+    CommandSent,
+    ReplStarting,
+}
+
+impl Lang {
+    /// Picks the language from the usual locale environment variables, falling
+    /// back to English for locales we don't have messages for.
+    pub fn detect() -> Self {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_default()
+    }
+
+    pub fn text(self, message: Message) -> &'static str {
+        match message {
+            Message::ScriptRequired => self.pick(
+                "A script must be given with --script",
+                "Hay que indicar un script con --script",
+                "É preciso indicar um script com --script",
+            ),
+            Message::StudioNotFound => self.pick(
+                "Could not locate a Roblox Studio installation.",
+                "No se encontró ninguna instalación de Roblox Studio.",
+                "Não foi possível encontrar uma instalação do Roblox Studio.",
+            ),
+            Message::StillRunning => self.pick(
+                "[run-in-roblox] Still running, no output for {}",
+                "[run-in-roblox] Sigue en ejecución, sin salida desde hace {}",
+                "[run-in-roblox] Ainda em execução, sem saída há {}",
+            ),
+            Message::Quarantined => self.pick(
+                "{} (quarantined)",
+                "{} (en cuarentena)",
+                "{} (em quarentena)",
+            ),
+            Message::ChecksSummary => self.pick(
+                "{} passed, {} failed",
+                "{} superadas, {} fallidas",
+                "{} aprovadas, {} reprovadas",
+            ),
+            Message::QuarantinedFailures => self.pick(
+                "{} quarantined failure(s), not counted against the run:",
+                "{} fallo(s) en cuarentena, que no cuentan para el resultado:",
+                "{} falha(s) em quarentena, que não contam para o resultado:",
+            ),
+            Message::TriageHeader => self.pick("Triage:", "Clasificación:", "Classificação:"),
+            Message::TriageNoFailures => self.pick(
+                "Triage: no failures",
+                "Clasificación: sin fallos",
+                "Classificação: nenhuma falha",
+            ),
+            Message::Category(category) => match category {
+                FailureCategory::ScriptError => self.pick(
+                    "script error",
+                    "error del script",
+                    "erro do script",
+                ),
+                FailureCategory::EngineCrash => self.pick(
+                    "engine crash",
+                    "cierre inesperado del motor",
+                    "falha do motor",
+                ),
+                FailureCategory::Timeout => {
+                    self.pick("timeout", "tiempo agotado", "tempo esgotado")
+                }
+                FailureCategory::Infra => self.pick(
+                    "infra/handshake",
+                    "infraestructura/conexión",
+                    "infraestrutura/conexão",
+                ),
+                FailureCategory::OutputRule => self.pick(
+                    "output rule violation",
+                    "infracción de las reglas de salida",
+                    "violação das regras de saída",
+                ),
+            },
+            Message::LeftOpen => self.pick(
+                "Roblox Studio was left open with {}",
+                "Roblox Studio sigue abierto con {}",
+                "O Roblox Studio continua aberto com {}",
+            ),
+            Message::MountInserted => self.pick(
+                "{} was inserted into the place at {}",
+                "{} se insertó en el lugar en {}",
+                "{} foi inserido no lugar em {}",
+            ),
+            Message::WhenDone => self.pick(
+                "When you're done, close Roblox Studio and delete {}, or run `run-in-roblox clean`.",
+                "Cuando termines, cierra Roblox Studio y borra {}, o ejecuta `run-in-roblox clean`.",
+                "Quando terminar, feche o Roblox Studio e apague {}, ou execute `run-in-roblox clean`.",
+            ),
+            Message::InspectPrompt => self.pick(
+                "The run failed. What would you like to do?",
+                "La ejecución falló. ¿Qué quieres hacer?",
+                "A execução falhou. O que você quer fazer?",
+            ),
+            Message::InspectKeepOpen => self.pick(
+                "  [k] Keep Roblox Studio open to inspect the place",
+                "  [k] Mantener Roblox Studio abierto para inspeccionar el lugar",
+                "  [k] Manter o Roblox Studio aberto para inspecionar o lugar",
+            ),
+            Message::InspectSave => self.pick(
+                "  [s] Save the place as it is now",
+                "  [s] Guardar el lugar tal como está ahora",
+                "  [s] Salvar o lugar como está agora",
+            ),
+            Message::InspectSavePath => self.pick(
+                "Save the place to (.rbxlx or .rbxl): ",
+                "Guardar el lugar en (.rbxlx o .rbxl): ",
+                "Salvar o lugar em (.rbxlx ou .rbxl): ",
+            ),
+            Message::InspectDump => self.pick(
+                "  [d] Dump an instance and its descendants to a model file",
+                "  [d] Volcar una instancia y sus descendientes a un archivo de modelo",
+                "  [d] Despejar uma instância e seus descendentes em um arquivo de modelo",
+            ),
+            Message::InspectDumpRoot => self.pick(
+                "Instance to dump (for example Workspace.Map): ",
+                "Instancia a volcar (por ejemplo Workspace.Map): ",
+                "Instância a despejar (por exemplo Workspace.Map): ",
+            ),
+            Message::InspectDumpPath => self.pick(
+                "Save it to (.rbxmx or .rbxm): ",
+                "Guardarla en (.rbxmx o .rbxm): ",
+                "Salvá-la em (.rbxmx ou .rbxm): ",
+            ),
+            Message::InspectSaved => self.pick(
+                "Saved {} instances from {} to {}",
+                "Se guardaron {} instancias de {} en {}",
+                "{} instâncias de {} foram salvas em {}",
+            ),
+            Message::InspectRerun => self.pick(
+                "  [r] Run the script again",
+                "  [r] Volver a ejecutar el script",
+                "  [r] Executar o script novamente",
+            ),
+            Message::InspectClose => self.pick(
+                "  [q] Close Roblox Studio and exit",
+                "  [q] Cerrar Roblox Studio y salir",
+                "  [q] Fechar o Roblox Studio e sair",
+            ),
+            Message::InspectInvalid => self.pick(
+                "Please enter k, s, d, r, or q.",
+                "Escribe k, s, d, r o q.",
+                "Digite k, s, d, r ou q.",
+            ),
+            Message::NothingToClean => self.pick(
+                "Nothing to clean up.",
+                "No hay nada que limpiar.",
+                "Não há nada para limpar.",
+            ),
+            Message::Removed => self.pick("Removed {}", "Se eliminó {}", "Removido {}"),
+            Message::SmokeRunning => self.pick(
+                "Running a smoke test script with the {} backend...",
+                "Ejecutando un script de prueba con el backend {}...",
+                "Executando um script de teste com o backend {}...",
+            ),
+            Message::SmokePassed => self.pick(
+                "Smoke test passed: the script ran and its output arrived intact.",
+                "Prueba superada: el script se ejecutó y su salida llegó intacta.",
+                "Teste aprovado: o script foi executado e sua saída chegou intacta.",
+            ),
+            Message::SmokeFailed => self.pick(
+                "Smoke test failed:",
+                "La prueba falló:",
+                "O teste falhou:",
+            ),
+            Message::StudioRelaunched => self.pick(
+                "Roblox Studio couldn't open the place at first, so it was copied again and \
+                 Studio was relaunched.",
+                "Roblox Studio no pudo abrir el lugar al principio, así que se copió de nuevo \
+                 y se volvió a iniciar Studio.",
+                "O Roblox Studio não conseguiu abrir o lugar no início, então ele foi copiado \
+                 de novo e o Studio foi reiniciado.",
+            ),
+            Message::ScriptHeader => self.pick(
+                "Running {} ({} of {})",
+                "Ejecutando {} ({} de {})",
+                "Executando {} ({} de {})",
+            ),
+            Message::ConfigDefaults => self.pick(
+                "Options that aren't listed use their defaults.",
+                "Las opciones que no aparecen usan sus valores predeterminados.",
+                "As opções que não aparecem usam seus valores padrão.",
+            ),
+            Message::ExamplesWritten => self.pick(
+                "Wrote {} example files to {}",
+                "Se escribieron {} archivos de ejemplo en {}",
+                "Foram escritos {} arquivos de exemplo em {}",
+            ),
+            Message::ExamplesPassed => self.pick(
+                "All {} examples passed.",
+                "Los {} ejemplos pasaron.",
+                "Todos os {} exemplos passaram.",
+            ),
+            Message::ExamplesFailed => self.pick(
+                "{} of {} examples failed:",
+                "{} de {} ejemplos fallaron:",
+                "{} de {} exemplos falharam:",
+            ),
+            Message::CachedPass => self.pick(
+                "Skipped, since nothing has changed since this run passed at {}.",
+                "Se omitió, ya que nada ha cambiado desde que esta ejecución pasó el {}.",
+                "Ignorada, pois nada mudou desde que esta execução passou em {}.",
+            ),
+            Message::WatchWaiting => self.pick(
+                "Watching for changes. Press Ctrl+C to stop.",
+                "Esperando cambios. Presiona Ctrl+C para detenerlo.",
+                "Aguardando mudanças. Pressione Ctrl+C para parar.",
+            ),
+            Message::WatchChanged => self.pick(
+                "{} changed, running again.",
+                "{} cambió, ejecutando de nuevo.",
+                "{} mudou, executando novamente.",
+            ),
+            Message::WatchReloading => self.pick(
+                "{} changed, reloading it in Roblox Studio.",
+                "{} cambió, recargándolo en Roblox Studio.",
+                "{} mudou, recarregando no Roblox Studio.",
+            ),
+            Message::Detached => self.pick(
+                "Started run {} in the background, writing its output to {}. Check on it with \
+                 `run-in-roblox status {}`, wait for it with `run-in-roblox wait {}`, or follow \
+                 its output with `run-in-roblox attach {}`.",
+                "Se inició la ejecución {} en segundo plano, escribiendo su salida en {}. \
+                 Consulta su estado con `run-in-roblox status {}`, espérala con \
+                 `run-in-roblox wait {}`, o sigue su salida con `run-in-roblox attach {}`.",
+                "A execução {} foi iniciada em segundo plano, escrevendo sua saída em {}. \
+                 Verifique o estado com `run-in-roblox status {}`, aguarde com \
+                 `run-in-roblox wait {}`, ou acompanhe sua saída com `run-in-roblox attach {}`.",
+            ),
+            Message::DetachedStatus => self.pick(
+                "{}: {}, started at {}. Output: {}",
+                "{}: {}, iniciada el {}. Salida: {}",
+                "{}: {}, iniciada em {}. Saída: {}",
+            ),
+            Message::NoDetachedRuns => self.pick(
+                "There are no runs started with --detach.",
+                "No hay ejecuciones iniciadas con --detach.",
+                "Não há execuções iniciadas com --detach.",
+            ),
+            Message::CommandSent => self.pick(
+                "Sent {} to the run on port {}.",
+                "Se envió {} a la ejecución en el puerto {}.",
+                "{} foi enviado para a execução na porta {}.",
+            ),
+            Message::ReplStarting => self.pick(
+                "Starting Roblox Studio. Once it's ready, type Luau to run it, or press Ctrl+D \
+                 to quit.",
+                "Iniciando Roblox Studio. Cuando esté listo, escribe Luau para ejecutarlo, o \
+                 pulsa Ctrl+D para salir.",
+                "Iniciando o Roblox Studio. Quando estiver pronto, digite Luau para executá-lo, \
+                 ou pressione Ctrl+D para sair.",
+            ),
+        }
+    }
+
+    /// Fills in the placeholders of a message with `args`, in order.
+    pub fn format(self, message: Message, args: &[&dyn fmt::Display]) -> String {
+        let mut pieces = self.text(message).split("{}");
+        let mut output = pieces.next().unwrap_or("").to_owned();
+
+        for (index, piece) in pieces.enumerate() {
+            if let Some(arg) = args.get(index) {
+                output.push_str(&arg.to_string());
+            }
+            output.push_str(piece);
+        }
+
+        output
+    }
+
+    fn pick(
+        self,
+        english: &'static str,
+        spanish: &'static str,
+        portuguese: &'static str,
+    ) -> &'static str {
+        match self {
+            Lang::English => english,
+            Lang::Spanish => spanish,
+            Lang::Portuguese => portuguese,
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! run-in-roblox runs scripts inside Roblox Studio and reports what they
+//! print, along with any errors, checks, and metrics.
+//!
+//! The `run-in-roblox` command line tool is built on this library. Programs
+//! that want to run scripts without shelling out to it can use [`Runner`],
+//! which hands back the messages from the script as they arrive.
+
+pub mod baseplate;
+pub mod budget;
+pub mod bundle;
+pub mod chaos;
+pub mod clean;
+pub mod config;
+pub mod crash_dumps;
+pub mod detach;
+pub mod duration;
+pub mod embed;
+pub mod examples;
+pub mod ignore;
+pub mod lang;
+pub mod lune_runner;
+pub mod message_receiver;
+pub mod metrics;
+pub mod mock_runner;
+pub mod model;
+pub mod mount;
+pub mod output;
+pub mod place_runner;
+pub mod plugin;
+pub mod port_registry;
+pub mod preprocess;
+pub mod project;
+pub mod quarantine;
+pub mod report;
+pub mod result_cache;
+pub mod runner;
+pub mod schema;
+pub mod script_env;
+pub mod self_test;
+pub mod sessions;
+pub mod severity;
+pub mod smoke;
+pub mod snapshot;
+pub mod source_map;
+pub mod studio_cache;
+pub mod studio_settings;
+pub mod syntax;
+pub mod transcript;
+pub mod triage;
+pub mod typecheck;
+pub mod usage;
+pub mod verify_paths;
+pub mod watch;
+pub mod websocket;
+
+pub use crate::{
+    embed::{Messages, RunOptions, Runner},
+    message_receiver::{CommandQueue, HostCommand, OutputLevel, PlayContext, RobloxMessage},
+    runner::Backend,
+    sessions::{Session, SessionManager},
+};
@@ -0,0 +1,151 @@
+use std::{
+    io::{BufRead, BufReader, Read},
+    path::PathBuf,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use fs_err as fs;
+
+use crate::{
+    message_receiver::{OutputLevel, RobloxMessage},
+    place_runner::KillOnDrop,
+    runner::{LifecycleEvent, MessageSender, ScriptRunner, TimedMessage},
+    script_env,
+    triage::{FailureCategory, RunFailure},
+};
+
+/// The name of the file in the temporary directory that scripts are written
+/// to for Lune to run.
+pub const SCRIPT_FILE_NAME: &str = "script.luau";
+
+/// How often we check whether Lune has exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Runs scripts with Lune, a standalone Luau runtime. There's no `game` or
+/// `rir` global, so this only suits scripts that don't need the engine.
+///
+/// Lines Lune writes to stdout are reported as prints and lines written to
+/// stderr as errors.
+pub struct LuneRunner {
+    /// Where to write the script so that Lune can run it.
+    pub script_path: PathBuf,
+    pub lua_script: String,
+    pub timeout: Option<Duration>,
+
+    /// Variables given with `--env`, which the script sees as `RIR_ENV`.
+    pub env: Vec<(String, String)>,
+}
+
+impl ScriptRunner for LuneRunner {
+    fn run(&self, sender: MessageSender) -> Result<Option<KillOnDrop>, anyhow::Error> {
+        // Kept on the script's first line, so that line numbers in errors
+        // still match the file it came from.
+        let source = format!(
+            "local RIR_ENV = {}; {}",
+            script_env::to_lua(&self.env),
+            self.lua_script
+        );
+        fs::write(&self.script_path, source)?;
+
+        let mut child = Command::new("lune")
+            .arg("run")
+            .arg(&self.script_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Could not start Lune. Is it installed and on your PATH?")?;
+        sender.lifecycle(LifecycleEvent::ScriptStarted)?;
+
+        let readers = vec![
+            forward_lines(child.stdout.take(), OutputLevel::Print, sender.clone()),
+            forward_lines(child.stderr.take(), OutputLevel::Error, sender.clone()),
+        ];
+
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+
+            if let Some(deadline) = deadline {
+                if Instant::now() >= deadline {
+                    let _ignored = child.kill();
+                    bail!(RunFailure::new(
+                        FailureCategory::Timeout,
+                        "Timeout reached while waiting for the script to finish",
+                    ));
+                }
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        };
+
+        for reader in readers {
+            let _ignored = reader.join();
+        }
+
+        sender.lifecycle(LifecycleEvent::ScriptFinished)?;
+
+        if !status.success() {
+            sender.send(TimedMessage {
+                at: Instant::now(),
+                script: None,
+                context: None,
+                message: RobloxMessage::Output {
+                    level: OutputLevel::Error,
+                    body: format!("Lune exited unsuccessfully ({})", status),
+                    source: None,
+                    line: None,
+                    from_script: None,
+                    history: false,
+                },
+            })?;
+        }
+
+        Ok(None)
+    }
+}
+
+/// Reports each line read from one of Lune's output streams as output.
+fn forward_lines<R: Read + Send + 'static>(
+    stream: Option<R>,
+    level: OutputLevel,
+    sender: MessageSender,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let stream = match stream {
+            Some(stream) => stream,
+            None => return,
+        };
+
+        for line in BufReader::new(stream).lines() {
+            let body = match line {
+                Ok(body) => body,
+                Err(_) => break,
+            };
+
+            let message = TimedMessage {
+                at: Instant::now(),
+                script: None,
+                context: None,
+                message: RobloxMessage::Output {
+                    level,
+                    body,
+                    source: None,
+                    line: None,
+                    from_script: Some(true),
+                    history: false,
+                },
+            };
+
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    })
+}
@@ -1,70 +1,2211 @@
-mod message_receiver;
-mod place_runner;
-mod plugin;
-
-use std::{path::PathBuf, process, sync::mpsc, thread};
+use std::{
+    env,
+    io::{self, BufRead, Read, Write},
+    iter,
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+    sync::{mpsc, OnceLock},
+    thread,
+    time::{Duration, Instant},
+};
 
 use anyhow::{anyhow, bail, Context};
-use colored::Colorize;
+use colored::{ColoredString, Colorize};
 use fs_err as fs;
-use structopt::StructOpt;
-use tempfile::tempdir;
-
-use roblox_install::RobloxStudio;
+use regex::Regex;
+use serde::Serialize;
+use structopt::{
+    clap::{self, AppSettings, ArgGroup, ErrorKind},
+    StructOpt,
+};
 
-use crate::{
-    message_receiver::{OutputLevel, RobloxMessage},
-    place_runner::PlaceRunner,
+use run_in_roblox::{
+    budget::Budget,
+    bundle::BundledModule,
+    chaos::ChaosHttp,
+    clean::{SessionMarker, TEMP_DIR_PREFIX},
+    config::{self, ArgKind, OptionSpec, Setting},
+    detach::{self, RunState},
+    duration::parse_duration,
+    lang::{Lang, Message},
+    lune_runner::LuneRunner,
+    message_receiver::{self, CommandQueue, HostCommand, OutputLevel, RobloxMessage},
+    metrics::MetricSeries,
+    mock_runner::MockRunner,
+    model::{self, ModelInsert, ScriptModel},
+    mount::Mount,
+    output::{
+        FormatVersion, JsonLifecycle, JsonMessage, JsonSummary, NewlineStyle, OutputFormat,
+        SkipReason, SkippedScript,
+    },
+    place_runner::{KillOnDrop, PlaceRunner},
+    plugin::{Capture, InjectMethod, Isolation, StudioMode, RIR_TYPINGS},
+    port_registry::PortClaim,
+    quarantine::Quarantine,
+    result_cache::{CacheKey, CachedPass},
+    runner::{Backend, LifecycleEvent, RunnerEvent, ScriptRunner, TimedMessage},
+    script_env::EnvVar,
+    self_test::{CaseStatus, SelfTestOptions},
+    severity::SeverityMap,
+    snapshot::{SnapshotFormat, SnapshotOptions},
+    source_map::SourceMap,
+    transcript::{EntryKind, TranscriptLogger},
+    triage::{FailureCategory, RunFailure, Triage},
+    usage::{MetaTag, UsageReport},
 };
+use run_in_roblox::{
+    bundle, clean, examples, lune_runner, output, place_runner, plugin, port_registry, preprocess,
+    project, report, result_cache, runner, schema, script_env, self_test, smoke, studio_cache,
+    syntax, transcript, triage, typecheck, verify_paths, watch,
+};
+
+/// The --script path that means the script should be read from stdin.
+const STDIN_PATH: &str = "-";
+
+/// The script read from stdin, kept for reruns since stdin can only be read
+/// once.
+static STDIN_SCRIPT: OnceLock<String> = OnceLock::new();
+
+/// Where prompts are read from once stdin has been used up by the script.
+#[cfg(windows)]
+const TERMINAL_PATH: &str = "CONIN$";
+#[cfg(not(windows))]
+const TERMINAL_PATH: &str = "/dev/tty";
+
+/// What a script read from stdin is called in output.
+const STDIN_NAME: &str = "<stdin>";
+
+/// How often the --probe script runs when --probe-interval isn't given.
+const DEFAULT_PROBE_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    group = ArgGroup::with_name("place-source"),
+    group = ArgGroup::with_name("network-faults"),
+    group = ArgGroup::with_name("verbosity"),
+    group = ArgGroup::with_name("session")
+)]
+struct Options {
+    #[structopt(subcommand)]
+    command: Option<Subcommand>,
+
+    /// A JSON file to read options from, like `{ "timeout": "2m" }`. Without
+    /// this, run-in-roblox.json in the current directory is read if there is
+    /// one.
+    ///
+    /// Options can also be set with environment variables named like
+    /// RUN_IN_ROBLOX_TIMEOUT. Options given on the command line override
+    /// environment variables, which override the config file.
+    // Read by `config::layer` before the options are parsed.
+    #[structopt(long("config"), global = true)]
+    _config_path: Option<PathBuf>,
+
+    /// Print the options that are set, along with where each one was set, and
+    /// exit.
+    #[structopt(long("show-config"))]
+    show_config: bool,
+
+    /// A path to the place file to open in Roblox Studio. If not specified, an
+    /// empty place file is used.
+    #[structopt(long("place"), group("place-source"))]
+    place_path: Option<PathBuf>,
+
+    /// A path to a Rojo project, like default.project.json, to build the place
+    /// from instead of giving one with --place. Needs `rojo` on the PATH.
+    #[structopt(long("project"), group("place-source"))]
+    project_path: Option<PathBuf>,
+
+    /// A sourcemap written by `rojo sourcemap`, used to point errors in the
+    /// place's scripts, like `ReplicatedStorage.Lib.Foo:42`, at the files they
+    /// were built from, like `src/Lib/Foo.lua:42`.
+    #[structopt(long("sourcemap"))]
+    sourcemap_path: Option<PathBuf>,
+
+    /// A path to the script to run in Roblox Studio, or "-" to read it from
+    /// stdin. A folder runs its init.lua, with the rest of the folder next to
+    /// it as modules, and an .rbxm or .rbxmx model runs the ModuleScript in
+    /// it, keeping the instances inside it.
+    ///
+    /// The script will be run at plugin-level security. Can be given more
+    /// than once to run several scripts one after another in the same session
+    /// of Studio, which saves starting Studio for each of them.
+    #[structopt(long("script"), number_of_values = 1)]
+    script_paths: Vec<PathBuf>,
+
+    /// How many of the scripts given with --script to run at once, each on
+    /// its own thread in the same session of Studio.
+    ///
+    /// Output from scripts run in parallel is labeled with the script that
+    /// printed it. Can't be used with `--wait-for done`, since rir.done()
+    /// can't tell which script called it.
+    #[structopt(long("parallel-scripts"), default_value = "1")]
+    parallel_scripts: usize,
+
+    /// What's put back between the scripts given with --script, so that one
+    /// script can't change what the next one sees, either "none", "globals",
+    /// or "reload".
+    ///
+    /// "globals" puts back what was in `_G` and `shared`. "reload" also puts
+    /// back the contents of the services that are saved with the place, like
+    /// Workspace and ReplicatedStorage, as they were before the first script
+    /// ran. Can't be used with --parallel-scripts.
+    #[structopt(long("isolation"), default_value = "none")]
+    isolation: Isolation,
+
+    /// Include local modules required by the script, either with
+    /// `require(script.Parent.Name)` or `require("./name")`, as ModuleScripts
+    /// next to it.
+    #[structopt(long("bundle"))]
+    bundle: bool,
+
+    /// A command to pass each script through before it's run, like a
+    /// minifier or a tool that turns newer syntax into what Studio supports.
+    /// It's run with the system shell, given the script on stdin, and should
+    /// write the script to run to stdout. Modules from --bundle and from
+    /// folders given with --script are passed through it too.
+    #[structopt(long("preprocess"))]
+    preprocess: Option<String>,
+
+    /// Don't check the scripts for syntax errors before starting Studio. The
+    /// check only catches common mistakes, like a missing `end`, so this is
+    /// only needed if it rejects a script that Studio would run.
+    #[structopt(long("no-syntax-check"))]
+    no_syntax_check: bool,
+
+    /// Type check the scripts and their modules with luau-analyze before
+    /// starting Studio, failing the run if there are type errors.
+    #[structopt(long("typecheck"))]
+    typecheck: bool,
+
+    /// The type checker to use for --typecheck instead of luau-analyze, like
+    /// "luau-lsp analyze --definitions=globalTypes.d.luau". It's given the
+    /// files to check after its own arguments, and should exit with an error
+    /// if they have type errors.
+    #[structopt(long("typecheck-command"), requires("typecheck"))]
+    typecheck_command: Option<String>,
+
+    /// Insert a folder of Lua files into the place before running the script,
+    /// given as `<path>=<instance path>`, like `src/=ReplicatedStorage.MyLib`.
+    ///
+    /// Files are turned into instances using Rojo's conventions: `init.lua`
+    /// turns its folder into a ModuleScript, and `.server.lua` and
+    /// `.client.lua` files become Scripts and LocalScripts. Can be given more
+    /// than once.
+    #[structopt(long("mount"), number_of_values = 1)]
+    mounts: Vec<Mount>,
+
+    /// Insert a model file into the place before Studio opens it, given as
+    /// `<path>@<instance path>`, like `lib.rbxm@ReplicatedStorage`.
+    ///
+    /// The instances at the top of the model become children of the instance
+    /// path, which is created with Folders if the place doesn't have it. Can
+    /// be given more than once.
+    #[structopt(long("model"), number_of_values = 1)]
+    models: Vec<ModelInsert>,
+
+    /// Give the script an environment variable, which it can read from the
+    /// `RIR_ENV` table. Given as `NAME` to pass on one of ours, which is left
+    /// out if it isn't set, or as `NAME=value`. Can be given more than once.
+    ///
+    /// Values are written into the plugin that Studio loads, so they can be
+    /// read by anything with access to the Studio plugins folder while the
+    /// script runs.
+    #[structopt(long("env"), number_of_values = 1)]
+    env: Vec<EnvVar>,
+
+    /// Before starting Roblox Studio, check that the services and instances
+    /// the script refers to, like `workspace.Map.Spawn` or
+    /// `game:GetService("ServerStorage"):WaitForChild("Assets")`, exist in
+    /// the place, and fail with a list of the ones that don't. Needs --place
+    /// or --project.
+    #[structopt(long("verify-paths"), requires("place-source"))]
+    verify_paths: bool,
+
+    /// Where to run the script: "studio", "lune", or "mock".
+    ///
+    /// "lune" runs the script with the Lune standalone Luau runtime, which must
+    /// be on your PATH, for scripts that don't need the engine. "mock" doesn't
+    /// run anything and reports the output and checks written in the script,
+    /// which is useful for trying out run-in-roblox's own options offline.
+    #[structopt(long("backend"), default_value = "studio")]
+    backend: Backend,
+
+    /// A path to the Roblox Studio executable to run.
+    #[structopt(long("app"))]
+    studio_app_path: Option<PathBuf>,
+
+    /// A path to the Roblox Studio plugins folder to use.
+    #[structopt(long("plugins"))]
+    studio_plugins_path: Option<PathBuf>,
+
+    /// How the generated plugin is given to Roblox Studio: "plugins-folder",
+    /// "local-plugin", or "command-line".
+    ///
+    /// Only "plugins-folder", which writes it to the Studio plugins folder, is
+    /// supported by current releases of Studio.
+    #[structopt(long("inject-method"), default_value = "plugins-folder")]
+    inject_method: InjectMethod,
+
+    /// The local port to listen on for the plugin, for setups that only allow
+    /// some ports, like containers or machines with strict firewall rules.
+    /// The run fails if the port is taken. By default, the first free port
+    /// from 50312 onwards is used.
+    #[structopt(long("port"))]
+    port: Option<u16>,
+
+    /// Which mode of Studio to run the script in: "edit", "run", or "play".
+    ///
+    /// "run" simulates the place like pressing Run, and "play" runs the script
+    /// on the server of a play test with a player, like pressing Play. Physics,
+    /// the place's own scripts, and LocalScripts only run in those modes.
+    #[structopt(long("mode"), default_value = "edit")]
+    mode: StudioMode,
+
+    /// Which output from Studio to show: "all" or "script".
+    ///
+    /// "all" shows everything Studio logs, like warnings from the engine and
+    /// other plugins, including what it logged before the scripts started.
+    /// "script" only shows output from the scripts themselves. Either way,
+    /// `--output json` marks whether each line came from the scripts.
+    #[structopt(long("capture"), default_value = "all")]
+    capture: Capture,
+
+    /// What signals that the script has finished running, either "return" or
+    /// "done".
+    ///
+    /// With "done", the run continues after the script returns until it calls
+    /// `rir.done()`, which is useful for scripts that do asynchronous work.
+    #[structopt(long("wait-for"), default_value = "return")]
+    wait_for: WaitFor,
+
+    /// Run the script a second time as a normal Script in Run mode and fail if
+    /// it raises errors at only one of the two security levels.
+    #[structopt(long("compare-security"))]
+    compare_security: bool,
+
+    /// Inject faults into HttpService requests made by the script, given as
+    /// comma-separated settings like "latency=200ms,fail-rate=0.1".
+    #[structopt(long("chaos-http"), group("network-faults"))]
+    chaos_http: Option<ChaosHttp>,
+
+    /// Fail the run if the script uses HttpService, DataStoreService,
+    /// MemoryStoreService, or MessagingService, reporting where it happened.
+    #[structopt(long("forbid-network"), group("network-faults"))]
+    forbid_network: bool,
+
+    /// Fail the run if the script changes the place in any way, listing the
+    /// instances it added, removed, or changed. Used for analysis scripts
+    /// that must not have side effects.
+    #[structopt(long("read-only"), conflicts_with("compare-security"))]
+    read_only: bool,
+
+    /// Fail the run if the place is larger than allowed after the script runs,
+    /// given as comma-separated limits like "instances=50000,parts=20000".
+    #[structopt(long("budget"))]
+    budget: Option<Budget>,
+
+    /// Write the place as the script left it to this file once the script
+    /// finishes, as a .rbxlx or .rbxl place, or a .rbxmx or .rbxm model with
+    /// --snapshot-root. Properties that can't be read by plugins, like
+    /// terrain, are left out.
+    #[structopt(long("snapshot"))]
+    snapshot_path: Option<PathBuf>,
+
+    /// The instance to take the --snapshot of, given as a path like
+    /// "Workspace.Model". Defaults to the services that are saved with the
+    /// place.
+    #[structopt(long("snapshot-root"), requires("snapshot-path"))]
+    snapshot_root: Option<String>,
+
+    /// A path to a script to run repeatedly while the main script runs. It
+    /// should return a table of numbers, like `{ memory = ... }`, which are
+    /// summarized at the end of the run.
+    #[structopt(long("probe"))]
+    probe_path: Option<PathBuf>,
+
+    /// How often to run the --probe script. Defaults to every 5 seconds.
+    #[structopt(
+        long("probe-interval"),
+        requires("probe-path"),
+        parse(try_from_str = parse_duration)
+    )]
+    probe_interval: Option<Duration>,
+
+    /// A path to a script to run before the main script, for work like
+    /// preloading assets that shouldn't be measured. Its output is labeled
+    /// "[warmup]", and it isn't counted by --probe, --budget, or --duration.
+    #[structopt(long("warmup"))]
+    warmup_path: Option<PathBuf>,
+
+    /// End the run after this long instead of when the script returns, like
+    /// "10m". The run fails if any errors were reported or checks failed in
+    /// that time. Used for soak tests, usually together with --probe.
+    #[structopt(
+        long("duration"),
+        conflicts_with("wait-for"),
+        parse(try_from_str = parse_duration)
+    )]
+    duration: Option<Duration>,
+
+    /// How often Studio sends queued output to run-in-roblox. Shorter
+    /// intervals deliver output sooner, longer ones send fewer, larger
+    /// batches. Scripts can also send output right away with `rir.flush()`.
+    #[structopt(
+        long("flush-interval"),
+        default_value = "100ms",
+        parse(try_from_str = parse_duration)
+    )]
+    flush_interval: Duration,
+
+    /// How long to wait for the script to finish once Roblox Studio has
+    /// started running it, like "90s" or "2m30s". Plain numbers are seconds.
+    #[structopt(long("timeout"), parse(try_from_str = parse_duration))]
+    timeout: Option<Duration>,
+
+    /// How long Roblox Studio can go without contacting run-in-roblox before
+    /// the run fails, which happens when the script is paused at a breakpoint
+    /// or blocks without yielding. Accepts the same durations as --timeout.
+    #[structopt(
+        long("unresponsive-timeout"),
+        default_value = "30s",
+        parse(try_from_str = parse_duration)
+    )]
+    unresponsive_timeout: Duration,
+
+    /// Print a line to stderr whenever the script hasn't produced any output
+    /// for this long, for CI systems that kill quiet jobs. Accepts the same
+    /// durations as --timeout.
+    #[structopt(long("keepalive"), parse(try_from_str = parse_duration))]
+    keepalive: Option<Duration>,
+
+    /// A directory to write Roblox Studio's own stdout, stderr, and exit
+    /// status to, to copy crash dumps into if Studio crashes during the run,
+    /// and to save files from `rir.artifact()` in.
+    #[structopt(long("artifacts-dir"))]
+    artifacts_dir: Option<PathBuf>,
+
+    /// A directory for the script to write files into with
+    /// `rir.writeFile(path, contents)`, like a coverage report. Scripts can't
+    /// write anywhere outside of it.
+    #[structopt(long("out-dir"))]
+    out_dir: Option<PathBuf>,
+
+    /// A folder the script can read files from with `rir.readFile(path)`,
+    /// like fixtures or golden files. Paths are relative to where
+    /// run-in-roblox is run, and files outside of these folders can't be
+    /// read. Can be given more than once.
+    #[structopt(long("read-dir"), number_of_values = 1)]
+    read_dirs: Vec<PathBuf>,
+
+    /// Print a summary that sorts failures into categories like script errors,
+    /// timeouts, and Studio crashes, and use a separate exit code for each:
+    /// 1 for script errors, 3 for Studio crashes, 4 for timeouts, 5 for
+    /// problems starting Studio or connecting to it, and 6 for protocol
+    /// violations.
+    #[structopt(long("triage"))]
+    triage: bool,
+
+    /// A file listing known-flaky scripts and checks. They still run, but
+    /// their failures are reported as warnings and don't fail the run.
+    ///
+    /// The file contains string arrays named `scripts` and `checks`, like
+    /// `scripts = ["tests/flaky.lua"]`. Script paths are relative to the file.
+    #[structopt(long("quarantine"))]
+    quarantine_path: Option<PathBuf>,
+
+    /// A file that changes the level Roblox Studio's output is reported at,
+    /// like turning a noisy engine warning into info, or a deprecation notice
+    /// into an error that fails the run.
+    ///
+    /// The file contains arrays of regular expressions named after levels,
+    /// like `info = ['^Infinite yield possible']`. Some asset loading
+    /// warnings are reported as info even without this.
+    #[structopt(long("severity"))]
+    severity_path: Option<PathBuf>,
+
+    /// Write the wall time and Roblox Studio CPU time the run used, along with
+    /// the --meta tags and a summary of --probe metrics, to this path as JSON.
+    /// See `run-in-roblox schema usage`.
+    #[structopt(long("usage"))]
+    usage_path: Option<PathBuf>,
+
+    /// Tag the run for the --usage report, like `project=lobby`. Can be given
+    /// more than once.
+    #[structopt(long("meta"), number_of_values = 1, requires("usage-path"))]
+    meta: Vec<MetaTag>,
+
+    /// Record everything that happens during the run to this path, including
+    /// run-in-roblox's own debug logs, every message from the plugin exactly as
+    /// it was received, and Roblox Studio starting and stopping. Read it with
+    /// `run-in-roblox inspect-transcript`. See `run-in-roblox schema
+    /// transcript`.
+    #[structopt(long("transcript"))]
+    transcript_path: Option<PathBuf>,
+
+    /// Write how the run went to this path as JSON, including the exit code,
+    /// the Studio version, and check results. The report is written when the
+    /// run starts and replaced in one step as it goes, and is only marked as
+    /// finalized once run-in-roblox is about to exit, so a report that isn't
+    /// finalized means the run crashed or was killed. See `run-in-roblox
+    /// schema report`.
+    #[structopt(long("report"))]
+    report_path: Option<PathBuf>,
+
+    /// Print output without colors, one line at a time, with each line of
+    /// script output tagged with its level like `[ERROR]`. For screen readers
+    /// and log collectors that don't understand terminal formatting.
+    #[structopt(long("plain"), global = true)]
+    plain: bool,
+
+    /// Prefix each line of output with the time since the run started, like
+    /// `[+12.345s]`. Times come from Studio's clock, lined up with ours, so
+    /// they reflect when output was produced rather than when it arrived.
+    #[structopt(long("timestamps"))]
+    timestamps: bool,
+
+    /// Prefix each line of output with where it came from when the plugin
+    /// can tell, like `[ReplicatedStorage.Foo:12]`. Output from the scripts
+    /// themselves points at their files.
+    #[structopt(long("locations"))]
+    locations: bool,
+
+    /// Only show output matching this regex, like `--grep "^\[Test\]"`. Can
+    /// be given more than once to show output matching any of them. Hidden
+    /// errors still fail the run.
+    #[structopt(long("grep"), number_of_values = 1)]
+    grep: Vec<Regex>,
+
+    /// Hide output matching this regex, even if it matches --grep. Can be
+    /// given more than once. Hidden errors still fail the run.
+    #[structopt(long("exclude"), number_of_values = 1)]
+    exclude: Vec<Regex>,
+
+    /// Show info output, like Studio's own messages, and output from
+    /// `rir.debug()`, along with run-in-roblox's logs of each step of the run.
+    /// Given twice, like `-vv`, output from `rir.trace()` is shown too.
+    #[structopt(
+        short("v"),
+        long("verbose"),
+        parse(from_occurrences),
+        group("verbosity"),
+        global = true
+    )]
+    verbose: u8,
+
+    /// Only show errors, along with failed checks and the summary at the end.
+    #[structopt(short("q"), long("quiet"), group("verbosity"), global = true)]
+    quiet: bool,
+
+    /// Leave Roblox Studio open with the place after the script finishes so
+    /// that the state it left behind can be inspected.
+    #[structopt(long("keep-open"), group("session"), conflicts_with("on-failure"))]
+    keep_open: bool,
+
+    /// Run the script again whenever it changes, along with the place,
+    /// mounted folders, models, and other files the run uses, until
+    /// run-in-roblox is stopped. With --bundle, the folders of the scripts are
+    /// watched too.
+    #[structopt(long("watch"), group("session"))]
+    watch: bool,
+
+    /// With --watch, keep Roblox Studio open between runs. When only the
+    /// scripts or the modules they bundle change, they're swapped into the
+    /// open place and run again, which is much quicker than starting over.
+    /// Changes to anything else still start a new session.
+    #[structopt(long("hot"), requires("watch"))]
+    hot: bool,
+
+    /// Start the run in the background and exit straight away, printing an ID
+    /// for `run-in-roblox status` and `run-in-roblox wait`. The run keeps
+    /// going after the terminal is closed, writing its output to a log file.
+    #[structopt(long("detach"), group("session"))]
+    detach: bool,
+
+    /// What to do when the run fails, either "exit" or "inspect".
+    ///
+    /// With "inspect", Roblox Studio is kept open while you choose whether to
+    /// keep it open, save the place or an instance in it to a file, run the
+    /// script again, or close it. A script read from stdin is reused when it's
+    /// run again.
+    #[structopt(long("on-failure"), default_value = "exit")]
+    on_failure: OnFailure,
+
+    /// Check every message received from Roblox Studio against the protocol's
+    /// JSON Schema and fail the run if one doesn't match. See `run-in-roblox
+    /// schema`.
+    #[structopt(long("validate-output"))]
+    validate_output: bool,
+
+    /// The version of run-in-roblox's machine-readable formats to use.
+    /// Defaults to the latest version.
+    #[structopt(long("format-version"), default_value)]
+    format_version: FormatVersion,
+
+    /// Look for Roblox Studio again instead of using where it was found by
+    /// previous runs. The cache is refreshed whenever Studio is updated, so
+    /// this is only needed if Studio was moved.
+    #[structopt(long("no-cache"), global = true)]
+    no_cache: bool,
+
+    /// Skip the run if it passed before with the same scripts, place, mounts,
+    /// models, and options. Runs that pass are recorded in run-in-roblox's cache
+    /// folder for next time, and skipped runs are reported as cached passes.
+    #[structopt(long("cache-results"))]
+    cache_results: bool,
+
+    /// Run even if --cache-results finds a recorded pass, recording it again
+    /// if the run passes.
+    #[structopt(long("force"), requires("cache-results"))]
+    force: bool,
+
+    /// The language for run-in-roblox's own messages: "en", "es", or "pt".
+    /// Defaults to the language of the system's locale, or English.
+    #[structopt(long("lang"), global = true)]
+    lang: Option<Lang>,
+
+    /// Normalize line endings in the run's output to either "lf" or "crlf",
+    /// both within script messages and at the end of every printed line,
+    /// including summaries and JSON. Lone carriage returns are treated as line
+    /// endings too.
+    ///
+    /// Output is always written as UTF-8.
+    #[structopt(long("newline"))]
+    newline: Option<NewlineStyle>,
+
+    /// How to print script output: "text" for people to read, or "json" for
+    /// one JSON object per line for CI systems to parse, ending with a summary.
+    /// "ndjson" also prints a line for each step of the run, like Roblox Studio
+    /// launching and the script starting. See `run-in-roblox schema output`.
+    /// "github" prints text, with warnings and errors as GitHub Actions
+    /// annotations on the script lines they came from.
+    #[structopt(long("output"), default_value = "text")]
+    output: OutputFormat,
+}
+
+impl Options {
+    fn lang(&self) -> Lang {
+        self.lang.unwrap_or_else(Lang::detect)
+    }
+
+    /// Whether output at this level is shown. Info, debug, and trace output
+    /// is hidden unless --verbose is given, and everything but errors is
+    /// hidden with --quiet.
+    fn shows_level(&self, level: OutputLevel) -> bool {
+        match level {
+            OutputLevel::Trace => self.verbose >= 2,
+            OutputLevel::Debug | OutputLevel::Info => self.verbose >= 1,
+            OutputLevel::Print | OutputLevel::Warning => !self.quiet,
+            OutputLevel::Error => true,
+        }
+    }
+
+    /// Whether output with this body is shown, given --grep and --exclude.
+    fn shows_body(&self, body: &str) -> bool {
+        let matches_grep = self.grep.is_empty() || self.grep.iter().any(|grep| grep.is_match(body));
+
+        matches_grep && !self.exclude.iter().any(|exclude| exclude.is_match(body))
+    }
+}
+
+#[derive(Debug, StructOpt)]
+enum Subcommand {
+    /// Remove plugins, temporary places, and Roblox Studio processes left
+    /// behind by runs that didn't shut down cleanly.
+    Clean {
+        /// An additional Roblox Studio plugins folder to clean.
+        #[structopt(long("plugins"))]
+        studio_plugins_path: Option<PathBuf>,
+    },
+
+    /// Print the JSON Schema for one of run-in-roblox's machine-readable
+    /// formats, or list them if no name is given.
+    Schema {
+        /// The name of the schema to print.
+        name: Option<String>,
+
+        /// The version of the format to print the schema for. Defaults to the
+        /// latest version.
+        #[structopt(long("format-version"), default_value)]
+        format_version: FormatVersion,
+    },
+
+    /// Run a small generated script in a generated place and check that its
+    /// output arrives intact, to confirm that run-in-roblox and Roblox Studio
+    /// work on this machine.
+    Smoke {
+        /// Where to run the smoke test: "studio", "lune", or "mock".
+        #[structopt(long("backend"), default_value = "studio")]
+        backend: Backend,
+
+        /// A path to the Roblox Studio executable to run.
+        #[structopt(long("app"))]
+        studio_app_path: Option<PathBuf>,
+
+        /// A path to the Roblox Studio plugins folder to use.
+        #[structopt(long("plugins"))]
+        studio_plugins_path: Option<PathBuf>,
+
+        /// How long to wait for the smoke test to finish.
+        #[structopt(
+            long("timeout"),
+            default_value = "2m",
+            parse(try_from_str = parse_duration)
+        )]
+        timeout: Duration,
+    },
+
+    /// Run run-in-roblox end to end against a Roblox Studio install, covering
+    /// launching, the plugin handshake, output levels, exit codes, timeouts,
+    /// and cleanup, and print a conformance report as JSON. For checking new
+    /// Studio releases before rolling them out.
+    #[structopt(setting = AppSettings::Hidden)]
+    SelfTest {
+        /// Where to run the cases: "studio", "lune", or "mock". Cases that
+        /// only make sense in Studio are skipped on other backends.
+        #[structopt(long("backend"), default_value = "studio")]
+        backend: Backend,
+
+        /// A path to the Roblox Studio executable to run.
+        #[structopt(long("app"))]
+        studio_app_path: Option<PathBuf>,
+
+        /// A path to the Roblox Studio plugins folder to use.
+        #[structopt(long("plugins"))]
+        studio_plugins_path: Option<PathBuf>,
+
+        /// How long each case can take.
+        #[structopt(
+            long("timeout"),
+            default_value = "5m",
+            parse(try_from_str = parse_duration)
+        )]
+        timeout: Duration,
+
+        /// Write the report to this path instead of printing it. See
+        /// `run-in-roblox schema self-test`.
+        #[structopt(long("out"))]
+        out_path: Option<PathBuf>,
+    },
+
+    /// Write a small example project into a folder, with scripts that show off
+    /// what run-in-roblox can do, and optionally run them.
+    Examples {
+        /// The folder to write the examples to.
+        dir: PathBuf,
+
+        /// Run the examples that don't need Roblox Studio on the mock backend,
+        /// writing them first if they aren't in the folder yet.
+        #[structopt(long("run"))]
+        run: bool,
+    },
+
+    /// Print a transcript written with --transcript in a readable form.
+    InspectTranscript {
+        /// The transcript to print.
+        path: PathBuf,
+
+        /// Only print entries of this kind: "diagnostic", "protocol",
+        /// "lifecycle", or "output".
+        #[structopt(long("kind"))]
+        kind: Option<transcript::EntryKind>,
+    },
+
+    /// Print how runs started with --detach are doing, or just the one with
+    /// the given ID.
+    Status {
+        /// The ID printed when the run was started.
+        id: Option<String>,
+    },
+
+    /// Wait for a run started with --detach to finish, exiting with its exit
+    /// code.
+    Wait {
+        /// The ID printed when the run was started.
+        id: String,
+    },
+
+    /// Print the output of a run started with --detach, from the start and
+    /// then as it arrives, until it finishes, exiting with its exit code. Runs
+    /// can be attached to any number of times, like after the terminal
+    /// following one was closed.
+    Attach {
+        /// The ID printed when the run was started.
+        id: String,
+    },
+
+    /// Send a command to a running script, which handles it with
+    /// `rir.onCommand`. The "cancel" command ends the run early.
+    Send {
+        /// The name of the command.
+        name: String,
+
+        /// JSON to pass to the script's handler for the command.
+        payload: Option<String>,
+
+        /// Send the command to the run started with --detach with this ID.
+        #[structopt(long("run"), conflicts_with("port"))]
+        run_id: Option<String>,
+
+        /// Send the command to the run talking to its plugin on this port.
+        /// Defaults to the only run going on this machine.
+        #[structopt(long("port"))]
+        port: Option<u16>,
+    },
+
+    /// Open Roblox Studio and run each line typed on stdin in it, printing
+    /// what it outputs and the values of expressions.
+    Repl {
+        /// A path to the place file to open in Roblox Studio. If not
+        /// specified, an empty place file is used.
+        #[structopt(long("place"))]
+        place_path: Option<PathBuf>,
+
+        /// A script to run before the first line, to set things up. Can be
+        /// given more than once.
+        #[structopt(long("script"), number_of_values = 1)]
+        script_paths: Vec<PathBuf>,
+
+        /// Insert a folder of Lua files into the place, given as
+        /// `<path>=<instance path>`, like `src/=ReplicatedStorage.MyLib`.
+        #[structopt(long("mount"), number_of_values = 1)]
+        mounts: Vec<Mount>,
+
+        /// Give the lines an environment variable, which they can read from
+        /// the `RIR_ENV` table, as `NAME` or `NAME=value`.
+        #[structopt(long("env"), number_of_values = 1)]
+        env: Vec<EnvVar>,
+
+        /// A path to the Roblox Studio executable to run.
+        #[structopt(long("app"))]
+        studio_app_path: Option<PathBuf>,
+
+        /// A path to the Roblox Studio plugins folder.
+        #[structopt(long("plugins"))]
+        studio_plugins_path: Option<PathBuf>,
+    },
+
+    /// Print Luau type definitions for the `rir` global, for editors to use
+    /// for autocomplete and type checking.
+    Typings {
+        /// Write the definitions to this path instead of printing them, like
+        /// `rir.d.luau`.
+        #[structopt(long("out"))]
+        out_path: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OnFailure {
+    Exit,
+    Inspect,
+}
+
+impl FromStr for OnFailure {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "exit" => Ok(OnFailure::Exit),
+            "inspect" => Ok(OnFailure::Inspect),
+            _ => bail!("Invalid value for --on-failure, expected \"exit\" or \"inspect\""),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WaitFor {
+    Return,
+    Done,
+}
+
+impl FromStr for WaitFor {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "return" => Ok(WaitFor::Return),
+            "done" => Ok(WaitFor::Done),
+            _ => bail!("Invalid value for --wait-for, expected \"return\" or \"done\""),
+        }
+    }
+}
+
+/// Runs the scripts. With --triage, a failure that ends the run, whether it
+/// happens before the scripts start, like a syntax error, or while they run,
+/// is sorted into a category that decides the exit code and the report's
+/// `triage`, along with the errors and failed checks before it.
+fn run(options: &Options) -> Result<i32, anyhow::Error> {
+    let mut triage = Triage::default();
+
+    match run_triaged(options, &mut triage) {
+        Err(err) if options.triage => {
+            log::error!("{:?}", err);
+            triage.record(triage::categorize(&err), err.to_string());
+            print_summary(options, triage.summary(options.lang()).normal());
+
+            let category = triage.primary_category().unwrap();
+            report::update(|report| report.triage = Some(category.to_string()));
+
+            Ok(category.exit_code())
+        }
+        result => result,
+    }
+}
+
+fn run_triaged(options: &Options, triage: &mut Triage) -> Result<i32, anyhow::Error> {
+    let run_started = Instant::now();
+    let lang = options.lang();
+
+    if options.script_paths.is_empty() {
+        bail!(lang.text(Message::ScriptRequired));
+    }
+
+    if options.script_paths.len() > 1 && options.compare_security {
+        bail!("--compare-security can only be used with one --script");
+    }
+
+    if options.mode != StudioMode::Edit && options.compare_security {
+        bail!("--compare-security can only be used with --mode edit");
+    }
+
+    if options.parallel_scripts == 0 {
+        bail!("--parallel-scripts must be at least 1");
+    }
+
+    if options.parallel_scripts > 1 && options.wait_for == WaitFor::Done {
+        bail!("--parallel-scripts can't be used with --wait-for done");
+    }
+
+    if options.parallel_scripts > 1 && options.isolation != Isolation::None {
+        bail!("--parallel-scripts can't be used with --isolation");
+    }
+
+    if let Some(snapshot_path) = &options.snapshot_path {
+        SnapshotFormat::from_path(snapshot_path)?;
+    }
+
+    // Every run gets a temporary directory for the files it needs, like the
+    // copy of the place that Studio opens.
+    let temp_dir = tempfile::Builder::new()
+        .prefix(TEMP_DIR_PREFIX)
+        .tempdir()
+        .context("Could not create a temporary directory for the run")?;
+    SessionMarker::for_current_process().write(temp_dir.path())?;
+
+    let script_names: Vec<&Path> = options
+        .script_paths
+        .iter()
+        .map(|script_path| script_name(script_path))
+        .collect();
+
+    let scripts = load_scripts(options)?;
+
+    if !options.no_syntax_check {
+        check_syntax(options, &scripts)?;
+    }
+
+    if options.typecheck {
+        let names: Vec<String> = script_names
+            .iter()
+            .map(|script_name| script_name.display().to_string())
+            .collect();
+        let sources: Vec<(String, &str)> = names
+            .into_iter()
+            .zip(scripts.lua_scripts.iter().map(String::as_str))
+            .collect();
+
+        typecheck::run(
+            options
+                .typecheck_command
+                .as_deref()
+                .unwrap_or(typecheck::DEFAULT_COMMAND),
+            &sources,
+            &scripts.modules,
+            temp_dir.path(),
+        )?;
+    }
+
+    let quarantine = match &options.quarantine_path {
+        Some(quarantine_path) => Quarantine::load(quarantine_path)?,
+        None => Quarantine::default(),
+    };
+    let quarantined_scripts: Vec<bool> = options
+        .script_paths
+        .iter()
+        .map(|script_path| quarantine.contains_script(script_path))
+        .collect();
+    let mut script_quarantined = quarantined_scripts[0];
+    let quarantined_skips = skipped_scripts(
+        options
+            .script_paths
+            .iter()
+            .zip(&quarantined_scripts)
+            .filter(|(_, quarantined)| **quarantined)
+            .map(|(script_path, _)| script_path.as_path()),
+        SkipReason::Quarantined,
+    );
+
+    let cache_key = if options.cache_results {
+        Some(result_cache_key(options, &scripts)?)
+    } else {
+        None
+    };
+
+    if let (Some(cache_key), false) = (&cache_key, options.force) {
+        if let Some(pass) = result_cache::lookup(cache_key) {
+            return Ok(report_cached_pass(options, &pass));
+        }
+    }
+
+    // The port claim is held until the run is over, so that no other run
+    // picks the same port.
+    let BuiltRunner {
+        runner,
+        place_path: studio_place_path,
+        port_claim: _port_claim,
+    } = build_runner(options, temp_dir.path(), scripts)?;
+
+    let mut source_map = SourceMap::default();
+    for (index, script_name) in script_names.iter().enumerate() {
+        source_map.add_module(&plugin::main_module_name(index), script_name);
+    }
+    source_map.add_file(
+        &temp_dir.path().join(lune_runner::SCRIPT_FILE_NAME),
+        script_names[0],
+    );
+    if let Some(probe_path) = &options.probe_path {
+        source_map.add_module("Probe", probe_path);
+    }
+    if let Some(warmup_path) = &options.warmup_path {
+        source_map.add_module("Warmup", warmup_path);
+    }
+    if let Some(sourcemap_path) = &options.sourcemap_path {
+        source_map.add_rojo_sourcemap(sourcemap_path)?;
+    }
+
+    transcript::record(
+        EntryKind::Lifecycle,
+        None,
+        format!(
+            "Running {} with the {} backend",
+            script_names
+                .iter()
+                .map(|script_name| script_name.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            options.backend
+        ),
+    );
+
+    let receiver = runner::spawn(runner);
+
+    let mut exit_code = 0;
+    let mut script_exit_code = None;
+    let mut checks_passed = 0;
+    let mut studio_relaunched = false;
+    let mut checks_failed = 0;
+    let mut quarantined_failures = Vec::new();
+    let mut metrics = MetricSeries::default();
+    let mut in_warmup = false;
+
+    let keepalive = options.keepalive;
+    let mut last_output = Instant::now();
+
+    let run_result = loop {
+        let event = match keepalive {
+            Some(interval) => match receiver.recv_timeout(interval) {
+                Ok(event) => Some(event),
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    eprintln!(
+                        "{}{}",
+                        timestamp(options, run_started, Instant::now()),
+                        lang.format(
+                            Message::StillRunning,
+                            &[&humantime::format_duration(Duration::from_secs(
+                                last_output.elapsed().as_secs()
+                            ))]
+                        )
+                        .dimmed()
+                    );
+                    continue;
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => None,
+            },
+            None => receiver.recv().ok(),
+        };
+
+        let TimedMessage {
+            at,
+            script,
+            context,
+            message,
+        } = match event {
+            Some(RunnerEvent::Message(message)) => message,
+            Some(RunnerEvent::Lifecycle { at, event }) => {
+                if event == LifecycleEvent::StudioRelaunched {
+                    studio_relaunched = true;
+                }
+
+                print_lifecycle(options, run_started, at, event);
+                continue;
+            }
+            Some(RunnerEvent::Finished(process)) => break Ok(process),
+            Some(RunnerEvent::Failed(err)) => break Err(err),
+            None => {
+                break Err(anyhow!(
+                    "The runner stopped without reporting how the run ended"
+                ))
+            }
+        };
+
+        let message = source_map.apply_to_message(message);
+
+        last_output = Instant::now();
+
+        let mut prefix = timestamp(options, run_started, at);
+        if in_warmup {
+            prefix.push_str(&"[warmup] ".dimmed().to_string());
+        }
+
+        // Output labeled with the script it came from belongs to that script,
+        // rather than whichever one started last.
+        let from_quarantined_script = match script {
+            Some(index) => quarantined_scripts
+                .get(index.wrapping_sub(1))
+                .copied()
+                .unwrap_or(false),
+            None => script_quarantined,
+        };
+
+        if let Some(index) = script {
+            if let Some(script_name) = script_names.get(index.wrapping_sub(1)) {
+                prefix.push_str(&format!("[{}] ", script_name.display()).dimmed().to_string());
+            }
+        }
+
+        if let Some(context) = context {
+            prefix.push_str(&format!("[{}] ", context).dimmed().to_string());
+        }
+
+        // Output hidden by --grep and --exclude is still counted, so that
+        // hidden errors fail the run.
+        let shown = match &message {
+            RobloxMessage::Output { body, .. } => options.shows_body(body),
+            _ => true,
+        };
+
+        if options.output.is_json() && shown {
+            let quarantined = match &message {
+                RobloxMessage::Output {
+                    level: OutputLevel::Error,
+                    ..
+                } => from_quarantined_script,
+                RobloxMessage::Check {
+                    name,
+                    passed: false,
+                    ..
+                } => from_quarantined_script || quarantine.contains_check(name),
+                _ => false,
+            };
+
+            print_json(
+                options,
+                &JsonMessage {
+                    format_version: options.format_version.0,
+                    timestamp: at.saturating_duration_since(run_started).as_secs_f64(),
+                    quarantined,
+                    script,
+                    context,
+                    message: &message,
+                },
+            );
+        }
+
+        match message {
+            RobloxMessage::Output {
+                level,
+                body,
+                source,
+                line,
+                history,
+                ..
+            } => {
+                if history {
+                    prefix.push_str(&"[history] ".dimmed().to_string());
+                }
+
+                if let (true, Some(source), Some(line)) = (options.locations, &source, line) {
+                    prefix.push_str(&format!("[{}:{}] ", source, line).dimmed().to_string());
+                }
+
+                let body = match options.newline {
+                    Some(style) => output::normalize_newlines(&body, style),
+                    None => body,
+                };
+
+                let quarantined = level == OutputLevel::Error && from_quarantined_script;
+
+                let (tag, colored_body) = match level {
+                    OutputLevel::Trace => ("TRACE", body.dimmed()),
+                    OutputLevel::Debug => ("DEBUG", body.dimmed()),
+                    OutputLevel::Print => ("PRINT", body.normal()),
+                    OutputLevel::Info => ("INFO", body.cyan()),
+                    OutputLevel::Warning => ("WARN", body.yellow()),
+                    OutputLevel::Error if quarantined => ("WARN", body.yellow()),
+                    OutputLevel::Error => ("ERROR", body.red()),
+                };
+
+                // --verbose and --quiet only change what's printed, so JSON
+                // output has every level.
+                if shown && options.shows_level(level) {
+                    print_output(options, &source_map, &prefix, tag, colored_body);
+                }
+
+                // Errors from before the plugin started, like problems loading
+                // the place, are shown but don't fail the run.
+                if quarantined {
+                    quarantined_failures.push(body);
+                } else if level == OutputLevel::Error && !history {
+                    exit_code = 1;
+                    triage.record(FailureCategory::ScriptError, body);
+                }
+            }
+            RobloxMessage::Check {
+                name,
+                passed,
+                reason,
+            } => {
+                if passed {
+                    checks_passed += 1;
+                } else {
+                    let failure = match reason {
+                        Some(reason) => format!("FAIL {}: {}", name, reason),
+                        None => format!("FAIL {}", name),
+                    };
+
+                    if from_quarantined_script || quarantine.contains_check(&name) {
+                        print_output(
+                            options,
+                            &source_map,
+                            &prefix,
+                            "WARN",
+                            lang.format(Message::Quarantined, &[&failure]).yellow(),
+                        );
+                        quarantined_failures.push(failure);
+                    } else {
+                        checks_failed += 1;
+                        exit_code = 1;
+
+                        print_output(options, &source_map, &prefix, "ERROR", failure.red());
+                        triage.record(FailureCategory::ScriptError, failure);
+                    }
+                }
+            }
+            RobloxMessage::Metrics { time, values } => {
+                metrics.record(time, values);
+            }
+            RobloxMessage::Phase { name } => {
+                in_warmup = name == "warmup";
+            }
+            RobloxMessage::ScriptStart { index } => {
+                script_quarantined = quarantined_scripts
+                    .get(index.wrapping_sub(1))
+                    .copied()
+                    .unwrap_or(false);
+
+                let script_name = script_names
+                    .get(index.wrapping_sub(1))
+                    .map(|script_name| script_name.display().to_string())
+                    .unwrap_or_default();
+
+                print_output(
+                    options,
+                    &source_map,
+                    &prefix,
+                    "INFO",
+                    lang.format(
+                        Message::ScriptHeader,
+                        &[&script_name, &index, &script_names.len()],
+                    )
+                    .bold(),
+                );
+            }
+            RobloxMessage::ScriptEnd { .. } => {}
+            RobloxMessage::Data { value } => {
+                // Data is meant for the JSON output, so it's only printed as
+                // text for debugging.
+                if options.shows_level(OutputLevel::Debug) {
+                    print_output(
+                        options,
+                        &source_map,
+                        &prefix,
+                        "DATA",
+                        value.to_string().dimmed(),
+                    );
+                }
+            }
+            RobloxMessage::ExitCode { code } => {
+                log::info!("The script set the exit code to {}", code);
+                script_exit_code = Some(code);
+            }
+            // The Studio backend writes snapshots and artifacts itself.
+            RobloxMessage::Evaluated { .. }
+            | RobloxMessage::Reloaded { .. }
+            | RobloxMessage::SnapshotInstance { .. }
+            | RobloxMessage::SnapshotEnd { .. }
+            | RobloxMessage::Artifact { .. } => {}
+            RobloxMessage::Hello {
+                studio_version,
+                variant,
+            } => {
+                log::info!(
+                    "Running in Roblox Studio {} with the {} plugin variant",
+                    studio_version,
+                    variant
+                );
+
+                report::update(|report| {
+                    report.studio_version = Some(studio_version);
+                    report.variant = Some(variant);
+                });
+            }
+        }
+    };
+
+    if studio_relaunched {
+        print_summary(options, lang.text(Message::StudioRelaunched).yellow());
+    }
+
+    if !metrics.is_empty() {
+        print_summary(options, metrics.summary().normal());
+    }
+
+    if checks_passed + checks_failed > 0 {
+        let summary = lang.format(Message::ChecksSummary, &[&checks_passed, &checks_failed]);
+
+        if checks_failed > 0 {
+            print_summary(options, summary.red());
+        } else {
+            print_summary(options, summary.green());
+        }
+    }
+
+    report::update(|report| {
+        report.checks_passed = checks_passed;
+        report.checks_failed = checks_failed;
+        report.quarantined_failures = quarantined_failures.clone();
+        report.skipped = quarantined_skips.clone();
+    });
+
+    if options.output.is_json() {
+        print_json(
+            options,
+            &JsonSummary {
+                format_version: options.format_version.0,
+                checks_passed,
+                checks_failed,
+                quarantined_failures: quarantined_failures.len(),
+                cached: false,
+                skipped: quarantined_skips,
+            },
+        );
+    }
+
+    if !quarantined_failures.is_empty() {
+        print_summary(
+            options,
+            lang.format(Message::QuarantinedFailures, &[&quarantined_failures.len()])
+                .yellow(),
+        );
+
+        for failure in &quarantined_failures {
+            let first_line = failure.lines().next().unwrap_or("");
+            print_summary(options, format!("  {}", first_line).yellow());
+        }
+    }
+
+    if let Some(usage_path) = &options.usage_path {
+        // Studio has exited by now if the run failed, so its CPU time is only
+        // known for runs that finished.
+        let studio_cpu_time = match &run_result {
+            Ok(Some(studio_process)) => studio_process.cpu_time(),
+            _ => None,
+        };
+
+        UsageReport::new(
+            options.format_version,
+            &options.meta,
+            run_started.elapsed(),
+            studio_cpu_time,
+            metrics.stats(),
+        )
+        .write(usage_path)
+        .context("Could not write the --usage report")?;
+    }
+
+    let studio_process = run_result?;
+
+    if options.triage {
+        print_summary(options, triage.summary(lang).normal());
+
+        if let Some(category) = triage.primary_category() {
+            exit_code = category.exit_code();
+            report::update(|report| report.triage = Some(category.to_string()));
+        }
+    }
+
+    // Scripts know better than we do what their failures mean, so the exit code
+    // a script asks for replaces the one that errors and failed checks give.
+    if let Some(code) = script_exit_code {
+        exit_code = code;
+    }
+
+    if let (Some(cache_key), 0) = (&cache_key, exit_code) {
+        if let Err(err) = result_cache::record(cache_key, &CachedPass::new(checks_passed)) {
+            log::warn!("Could not record the pass for --cache-results: {:#}", err);
+        }
+    }
+
+    // Only Studio leaves anything behind to inspect.
+    let (studio_process, studio_place_path) = match (studio_process, studio_place_path) {
+        (Some(studio_process), Some(studio_place_path)) => (studio_process, studio_place_path),
+        _ => return Ok(exit_code),
+    };
+
+    if exit_code != 0 && options.on_failure == OnFailure::Inspect {
+        match inspect_failure(lang, &studio_process)? {
+            Inspection::KeepOpen => {}
+            Inspection::Rerun => {
+                close_studio(options, run_started, studio_process);
+                drop(temp_dir);
+
+                return run(options);
+            }
+            Inspection::Close => {
+                close_studio(options, run_started, studio_process);
+                return Ok(exit_code);
+            }
+        }
+    } else if !options.keep_open {
+        close_studio(options, run_started, studio_process);
+        return Ok(exit_code);
+    }
+
+    studio_process.detach();
+
+    let temp_dir = temp_dir.into_path();
+
+    eprintln!(
+        "{}",
+        lang.format(Message::LeftOpen, &[&studio_place_path.display()])
+    );
+
+    for mount in &options.mounts {
+        eprintln!(
+            "{}",
+            lang.format(
+                Message::MountInserted,
+                &[&mount.source.display(), &mount.target]
+            )
+        );
+    }
+
+    eprintln!("{}", lang.format(Message::WhenDone, &[&temp_dir.display()]));
+
+    Ok(exit_code)
+}
+
+/// A runner set up by `build_runner`.
+struct BuiltRunner {
+    runner: Box<dyn ScriptRunner>,
+
+    /// The place Studio will open, if it's the Studio backend.
+    place_path: Option<PathBuf>,
+
+    /// The port claimed for the plugin to talk to us on, if it's the Studio
+    /// backend, which is given back when this is dropped.
+    port_claim: Option<PortClaim>,
+}
+
+/// Claims the port given with --port, or the first free one.
+fn claim_port(options: &Options) -> Result<PortClaim, anyhow::Error> {
+    match options.port {
+        Some(port) => port_registry::claim_exact(port),
+        None => port_registry::claim(place_runner::DEFAULT_PORT),
+    }
+}
+
+/// Sets up the runner for the backend given with --backend.
+fn build_runner(
+    options: &Options,
+    temp_dir: &Path,
+    mut scripts: LoadedScripts,
+) -> Result<BuiltRunner, anyhow::Error> {
+    match options.backend {
+        Backend::Studio => {
+            let port_claim = claim_port(options)?;
+            let place_runner = studio_runner(options, temp_dir, port_claim.port(), scripts)?;
+
+            Ok(BuiltRunner {
+                place_path: Some(place_runner.place_path.clone()),
+                runner: Box::new(place_runner),
+                port_claim: Some(port_claim),
+            })
+        }
+        Backend::Lune => {
+            check_studio_only_options(options)?;
+
+            let lune_runner = LuneRunner {
+                script_path: temp_dir.join(lune_runner::SCRIPT_FILE_NAME),
+                lua_script: scripts.lua_scripts.remove(0),
+                timeout: options.timeout,
+                env: script_env::resolve(&options.env),
+            };
+
+            Ok(BuiltRunner {
+                runner: Box::new(lune_runner),
+                place_path: None,
+                port_claim: None,
+            })
+        }
+        Backend::Mock => {
+            check_studio_only_options(options)?;
+
+            Ok(BuiltRunner {
+                runner: Box::new(MockRunner {
+                    lua_script: scripts.lua_scripts.remove(0),
+                }),
+                place_path: None,
+                port_claim: None,
+            })
+        }
+    }
+}
+
+/// Runs the script, then runs it again whenever one of the files the run uses
+/// changes. Only returns if a run can't be started at all.
+fn watch(options: &Options, lang: Lang) -> Result<i32, anyhow::Error> {
+    if options
+        .script_paths
+        .iter()
+        .any(|script_path| script_path == Path::new(STDIN_PATH))
+    {
+        bail!("--watch can't be used with --script -, since there's no file to watch");
+    }
+
+    let mut watched_paths: Vec<PathBuf> = options.script_paths.clone();
+
+    if options.bundle {
+        for script_path in &options.script_paths {
+            let script_dir = match script_path.parent() {
+                Some(parent) if parent != Path::new("") => parent.to_path_buf(),
+                _ => PathBuf::from("."),
+            };
+
+            watched_paths.push(script_dir);
+        }
+    }
+
+    // Scripts given as models can have instances inside them, which are only
+    // put in place when a session starts.
+    let script_paths: Vec<PathBuf> = watched_paths
+        .iter()
+        .filter(|path| !model::is_script_model(path))
+        .cloned()
+        .collect();
+
+    watched_paths.extend(options.mounts.iter().map(|mount| mount.source.clone()));
+    watched_paths.extend(options.models.iter().map(|model| model.source.clone()));
+    watched_paths.extend(options.read_dirs.iter().cloned());
+
+    if let Some(project_path) = &options.project_path {
+        watched_paths.extend(project::source_paths(project_path)?);
+    }
+
+    watched_paths.extend(
+        [
+            &options.place_path,
+            &options.probe_path,
+            &options.warmup_path,
+            &options.quarantine_path,
+            &options.severity_path,
+        ]
+        .iter()
+        .filter_map(|path| (*path).clone()),
+    );
+
+    if options.hot {
+        if options.backend != Backend::Studio {
+            check_studio_only_options(options)?;
+        }
+
+        return hot_watch(options, lang, &watched_paths, &script_paths);
+    }
+
+    loop {
+        // Changes made while the script is running start another run as soon
+        // as it's over.
+        let snapshot = watch::Snapshot::take(&watched_paths);
+
+        if let Err(err) = run(options) {
+            log::error!("{:?}", err);
+        }
+
+        print_summary(options, lang.text(Message::WatchWaiting).dimmed());
+
+        let changed = watch::wait_for_change(&watched_paths, &snapshot);
+
+        print_summary(
+            options,
+            lang.format(Message::WatchChanged, &[&display_paths(&changed)])
+                .bold(),
+        );
+    }
+}
+
+/// Runs the scripts for --watch --hot, in one Studio session for as long as
+/// only the scripts and their modules change. Changes to anything else, or a
+/// session that fails, start a new one.
+fn hot_watch(
+    options: &Options,
+    lang: Lang,
+    watched_paths: &[PathBuf],
+    script_paths: &[PathBuf],
+) -> Result<i32, anyhow::Error> {
+    loop {
+        let snapshot = watch::Snapshot::take(watched_paths);
+
+        let changed = match hot_session(options, lang, watched_paths, script_paths, snapshot) {
+            Ok(changed) => changed,
+            Err(err) => {
+                log::error!("{:?}", err);
+                print_summary(options, lang.text(Message::WatchWaiting).dimmed());
+
+                let snapshot = watch::Snapshot::take(watched_paths);
+                watch::wait_for_change(watched_paths, &snapshot)
+            }
+        };
+
+        print_summary(
+            options,
+            lang.format(Message::WatchChanged, &[&display_paths(&changed)])
+                .bold(),
+        );
+    }
+}
+
+/// Keeps one Studio session open for --watch --hot, sending the scripts to it
+/// again whenever they change. Returns the files that changed once something
+/// other than the scripts does, after closing Studio.
+fn hot_session(
+    options: &Options,
+    lang: Lang,
+    watched_paths: &[PathBuf],
+    script_paths: &[PathBuf],
+    snapshot: watch::Snapshot,
+) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let temp_dir = tempfile::Builder::new()
+        .prefix(TEMP_DIR_PREFIX)
+        .tempdir()
+        .context("Could not create a temporary directory for the run")?;
+    SessionMarker::for_current_process().write(temp_dir.path())?;
+
+    let scripts = load_scripts(options)?;
+    let mut module_names: Vec<String> = scripts.modules.iter().map(|m| m.name.clone()).collect();
+
+    let port_claim = claim_port(options)?;
+    let commands = CommandQueue::new();
+
+    let mut place_runner = studio_runner(options, temp_dir.path(), port_claim.port(), scripts)?;
+    place_runner.repl = true;
+    place_runner.timeout = None;
+    place_runner.commands = commands.clone();
+
+    let env = place_runner.env.clone();
+    let events = runner::spawn(Box::new(place_runner));
+
+    // Waiting for changes blocks, so it happens on its own thread while output
+    // from the session keeps being printed.
+    let (change_sender, changes) = mpsc::channel();
+    let paths = watched_paths.to_vec();
+    thread::spawn(move || {
+        let mut snapshot = snapshot;
+
+        loop {
+            let changed = watch::wait_for_change(&paths, &snapshot);
+            snapshot = watch::Snapshot::take(&paths);
+
+            if change_sender.send(changed).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut next_id = 0;
+    let mut restart_for = None;
+
+    loop {
+        if let Ok(changed) = changes.try_recv() {
+            let only_scripts = changed.iter().all(|path| {
+                script_paths
+                    .iter()
+                    .any(|script_path| path.starts_with(script_path))
+            });
+
+            if only_scripts && restart_for.is_none() {
+                print_summary(
+                    options,
+                    lang.format(Message::WatchReloading, &[&display_paths(&changed)])
+                        .bold(),
+                );
+
+                match load_scripts(options) {
+                    Ok(scripts) => {
+                        let removed = module_names
+                            .iter()
+                            .filter(|name| !scripts.modules.iter().any(|m| &m.name == *name))
+                            .cloned()
+                            .collect();
+                        module_names = scripts.modules.iter().map(|m| m.name.clone()).collect();
+
+                        next_id += 1;
+                        commands.push(HostCommand::Reload {
+                            id: next_id,
+                            scripts: plugin::reload_sources(&scripts.lua_scripts, &env),
+                            modules: scripts.modules,
+                            removed,
+                        });
+                    }
+                    Err(err) => {
+                        log::error!("{:?}", err);
+                        print_summary(options, lang.text(Message::WatchWaiting).dimmed());
+                    }
+                }
+            } else {
+                commands.push(HostCommand::Cancel);
+                restart_for.get_or_insert_with(Vec::new).extend(changed);
+            }
+        }
+
+        let event = match events.recv_timeout(REPL_POLL_INTERVAL) {
+            Ok(event) => event,
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                bail!("The runner stopped without reporting how the run ended")
+            }
+        };
+
+        match event {
+            RunnerEvent::Message(TimedMessage { message, .. }) => match message {
+                RobloxMessage::Output { level, body, .. } => {
+                    print_session_output(options, level, body);
+                }
+                RobloxMessage::Reloaded { .. } => {
+                    print_summary(options, lang.text(Message::WatchWaiting).dimmed());
+                }
+                _ => {}
+            },
+            RunnerEvent::Lifecycle { .. } => {}
+            RunnerEvent::Finished(studio_process) => {
+                drop(studio_process);
+
+                return match restart_for {
+                    Some(changed) => Ok(changed),
+                    None => bail!("Roblox Studio stopped before any files changed"),
+                };
+            }
+            RunnerEvent::Failed(err) => return Err(err),
+        }
+    }
+}
+
+/// Joins paths into a list for messages about files that changed.
+fn display_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Starts the run in the background for --detach.
+fn start_detached(options: &Options, lang: Lang) -> Result<i32, anyhow::Error> {
+    if options
+        .script_paths
+        .iter()
+        .any(|script_path| script_path == Path::new(STDIN_PATH))
+    {
+        bail!("--detach can't be used with --script -, since a run in the background can't read stdin");
+    }
+
+    let args = env::args_os()
+        .skip(1)
+        .filter(|arg| arg != "--detach")
+        .collect();
+    let run = detach::spawn(args)?;
+
+    println!(
+        "{}",
+        lang.format(
+            Message::Detached,
+            &[&run.id, &run.log_path.display(), &run.id, &run.id, &run.id]
+        )
+    );
+
+    Ok(0)
+}
+
+fn print_detached_status(id: Option<&str>, lang: Lang) -> Result<i32, anyhow::Error> {
+    let runs = match id {
+        Some(id) => vec![detach::load(id)?],
+        None => detach::list()?,
+    };
+
+    if runs.is_empty() {
+        println!("{}", lang.text(Message::NoDetachedRuns));
+    }
+
+    for run in runs {
+        let started = humantime::format_rfc3339_seconds(run.started_at()).to_string();
+        let state = run.state();
+
+        let line = lang.format(
+            Message::DetachedStatus,
+            &[&run.id, &state, &started, &run.log_path.display()],
+        );
+
+        match state {
+            RunState::Running => println!("{}", line),
+            RunState::Finished { exit_code: 0 } => println!("{}", line.green()),
+            RunState::Finished { .. } | RunState::Stopped => println!("{}", line.red()),
+        }
+    }
+
+    Ok(0)
+}
+
+fn wait_for_detached(id: &str, lang: Lang) -> Result<i32, anyhow::Error> {
+    let run = detach::load(id)?;
+    let state = run.wait();
+
+    println!("{}", detached_status(&run, state, lang));
+
+    Ok(detached_exit_code(state))
+}
+
+fn attach_detached(id: &str, lang: Lang) -> Result<i32, anyhow::Error> {
+    let run = detach::load(id)?;
+    let state = run
+        .follow(io::stdout())
+        .with_context(|| format!("Could not read the output of run {}", run.id))?;
+
+    // The run's own output is on stdout, so how it ended goes to stderr.
+    eprintln!("{}", detached_status(&run, state, lang));
+
+    Ok(detached_exit_code(state))
+}
+
+fn detached_status(run: &detach::DetachedRun, state: RunState, lang: Lang) -> String {
+    lang.format(
+        Message::DetachedStatus,
+        &[
+            &run.id,
+            &state,
+            &humantime::format_rfc3339_seconds(run.started_at()),
+            &run.log_path.display(),
+        ],
+    )
+}
+
+fn detached_exit_code(state: RunState) -> i32 {
+    match state {
+        RunState::Finished { exit_code } => exit_code,
+        _ => 2,
+    }
+}
+
+/// How long the REPL waits for events before checking for another line.
+const REPL_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Keeps Roblox Studio open and runs each line typed on stdin in it, for
+/// `run-in-roblox repl`.
+fn repl(options: &Options) -> Result<i32, anyhow::Error> {
+    let lang = options.lang();
+
+    let temp_dir = tempfile::Builder::new()
+        .prefix(TEMP_DIR_PREFIX)
+        .tempdir()
+        .context("Could not create a temporary directory for the run")?;
+    SessionMarker::for_current_process().write(temp_dir.path())?;
+
+    let mut scripts = load_scripts(options)?;
+
+    if scripts.lua_scripts.is_empty() {
+        scripts.lua_scripts.push(String::new());
+    }
+
+    let port_claim = claim_port(options)?;
+    let commands = CommandQueue::new();
+
+    let mut place_runner = studio_runner(options, temp_dir.path(), port_claim.port(), scripts)?;
+    place_runner.repl = true;
+    place_runner.timeout = None;
+    place_runner.commands = commands.clone();
+
+    let env = place_runner.env.clone();
+    let events = runner::spawn(Box::new(place_runner));
+
+    // Reading stdin blocks, so lines are read on their own thread while output
+    // from the session keeps being printed.
+    let (line_sender, lines) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            match line {
+                Ok(line) => {
+                    if line_sender.send(line).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    });
+
+    eprintln!("{}", lang.text(Message::ReplStarting).dimmed());
+
+    let mut ready = false;
+    let mut evaluating = None;
+    let mut prompted = false;
+    let mut next_id = 0;
+
+    loop {
+        if ready && evaluating.is_none() {
+            if !prompted {
+                eprint!("> ");
+                io::stderr().flush()?;
+                prompted = true;
+            }
+
+            match lines.try_recv() {
+                Ok(line) if line.trim().is_empty() => prompted = false,
+                Ok(line) => {
+                    next_id += 1;
+                    commands.push(HostCommand::Eval {
+                        id: next_id,
+                        source: plugin::repl_source(&line, &env),
+                    });
+
+                    evaluating = Some(next_id);
+                    prompted = false;
+                }
+                Err(mpsc::TryRecvError::Empty) => {}
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    eprintln!();
+                    commands.push(HostCommand::Cancel);
+                    ready = false;
+                }
+            }
+        }
+
+        let event = match events.recv_timeout(REPL_POLL_INTERVAL) {
+            Ok(event) => event,
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                bail!("The runner stopped without reporting how the run ended")
+            }
+        };
+
+        match event {
+            RunnerEvent::Message(TimedMessage { message, .. }) => match message {
+                RobloxMessage::Output { level, body, .. } => {
+                    if !options.shows_level(level) {
+                        continue;
+                    }
+
+                    if prompted {
+                        eprintln!();
+                        prompted = false;
+                    }
+
+                    print_session_output(options, level, body);
+                }
+                RobloxMessage::Evaluated { id } if evaluating == Some(id) => evaluating = None,
+                _ => {}
+            },
+            RunnerEvent::Lifecycle {
+                event: LifecycleEvent::ScriptStarted,
+                ..
+            } => ready = true,
+            RunnerEvent::Lifecycle { .. } => {}
+            RunnerEvent::Finished(_studio_process) => return Ok(0),
+            RunnerEvent::Failed(err) => return Err(err),
+        }
+    }
+}
+
+/// Prints output from a session kept open for `run-in-roblox repl` or
+/// `--watch --hot`, colored by its level.
+fn print_session_output(options: &Options, level: OutputLevel, body: String) {
+    if !options.shows_level(level) {
+        return;
+    }
+
+    let body = match level {
+        OutputLevel::Trace | OutputLevel::Debug => body.dimmed(),
+        OutputLevel::Print => body.normal(),
+        OutputLevel::Info => body.cyan(),
+        OutputLevel::Warning => body.yellow(),
+        OutputLevel::Error => body.red(),
+    };
+
+    println!("{}", body);
+}
+
+/// Sends a command to a running script for `run-in-roblox send`.
+fn send_command(
+    name: &str,
+    payload: Option<&str>,
+    run_id: Option<&str>,
+    port: Option<u16>,
+    lang: Lang,
+) -> Result<i32, anyhow::Error> {
+    let payload = match payload {
+        Some(payload) => serde_json::from_str(payload).context(
+            "The payload of a command must be JSON, like '\"text\"' or '{\"count\": 3}'",
+        )?,
+        None => serde_json::Value::Null,
+    };
+
+    let claims = port_registry::claims();
+
+    let port = match (port, run_id) {
+        (Some(port), _) => port,
+        (None, Some(run_id)) => {
+            let run = detach::load(run_id)?;
+
+            match claims.iter().find(|(_, pid)| *pid == run.pid) {
+                Some((port, _)) => *port,
+                None => bail!(
+                    "Run {} isn't talking to Roblox Studio, so it can't be sent commands",
+                    run_id
+                ),
+            }
+        }
+        (None, None) => match claims.as_slice() {
+            [(port, _)] => *port,
+            [] => bail!("There are no runs going to send the command to"),
+            _ => bail!("Several runs are going; pick one with --run or --port"),
+        },
+    };
+
+    let token = port_registry::read_token(port)?;
+    message_receiver::send_command(port, &token, &HostCommand::named(name, payload))?;
+    println!("{}", lang.format(Message::CommandSent, &[&name, &port]));
+
+    Ok(0)
+}
+
+/// The name a script given with --script is referred to by in output. Scripts
+/// read from stdin don't have a path of their own, so they're given one.
+fn script_name(script_path: &Path) -> &Path {
+    if script_path == Path::new(STDIN_PATH) {
+        Path::new(STDIN_NAME)
+    } else {
+        script_path
+    }
+}
+
+/// The scripts given with --script, ready to run.
+struct LoadedScripts {
+    lua_scripts: Vec<String>,
+
+    /// The modules the scripts need, from --bundle or from the folders
+    /// they're in.
+    modules: Vec<BundledModule>,
+
+    /// The instances inside the scripts given as models.
+    script_models: Vec<ScriptModel>,
+}
+
+/// Reads the scripts given with --script, along with the modules they need
+/// from --bundle or from the folders they're in.
+fn load_scripts(options: &Options) -> Result<LoadedScripts, anyhow::Error> {
+    let mut lua_scripts = Vec::new();
+    let mut modules = Vec::<BundledModule>::new();
+    let mut script_models = Vec::new();
+
+    for (index, script_path) in options.script_paths.iter().enumerate() {
+        if model::is_script_model(script_path) {
+            let (script_contents, script_model) = model::load_script_model(script_path, index)?;
+
+            script_models.push(script_model);
+            lua_scripts.push(script_contents);
+            continue;
+        }
+
+        if script_path.is_dir() {
+            let (script_contents, dir_modules) = bundle::bundle_dir(script_path)?;
+
+            add_bundled_modules(&mut modules, dir_modules)?;
+            lua_scripts.push(script_contents);
+            continue;
+        }
+
+        let mut script_contents = read_script(script_path)?;
+
+        if options.bundle {
+            let (bundled_contents, script_modules) = bundle::bundle(script_path, &script_contents)?;
+
+            add_bundled_modules(&mut modules, script_modules)?;
+            script_contents = bundled_contents;
+        }
+
+        lua_scripts.push(script_contents);
+    }
+
+    if let Some(command) = &options.preprocess {
+        for (lua_script, script_path) in lua_scripts.iter_mut().zip(&options.script_paths) {
+            *lua_script = preprocess::run(command, lua_script).with_context(|| {
+                format!(
+                    "Could not preprocess {}",
+                    script_name(script_path).display()
+                )
+            })?;
+        }
+
+        for module in &mut modules {
+            preprocess::run_module(command, module)?;
+        }
+    }
+
+    Ok(LoadedScripts {
+        lua_scripts,
+        modules,
+        script_models,
+    })
+}
+
+/// Checks the scripts and their modules for syntax errors, so that a script
+/// that won't compile fails before Studio has started instead of after.
+fn check_syntax(options: &Options, scripts: &LoadedScripts) -> Result<(), anyhow::Error> {
+    let script_names = options
+        .script_paths
+        .iter()
+        .map(|script_path| script_name(script_path).display().to_string());
+    let mut sources: Vec<(String, &str)> = script_names
+        .zip(scripts.lua_scripts.iter().map(String::as_str))
+        .collect();
+
+    for module in &scripts.modules {
+        for module in module.descendants() {
+            if let Some(source) = &module.source {
+                sources.push((format!("the module {}", module.name), source));
+            }
+        }
+    }
+
+    for (name, source) in sources {
+        if let Err(error) = syntax::check(source) {
+            let line = source.lines().nth(error.line - 1).unwrap_or_default();
+
+            bail!(RunFailure::new(
+                FailureCategory::ScriptError,
+                format!(
+                    "Syntax error in {} on line {}: {}\n{:>5} | {}",
+                    name,
+                    error.line,
+                    error.message,
+                    error.line,
+                    line.trim_end()
+                )
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Adds the modules bundled for one of the scripts given with --script to
+/// those of the others. Every module ends up next to every script, so scripts
+/// can share modules, but not have different modules with the same name.
+fn add_bundled_modules(
+    modules: &mut Vec<BundledModule>,
+    script_modules: Vec<BundledModule>,
+) -> Result<(), anyhow::Error> {
+    for module in script_modules {
+        match modules.iter().find(|existing| existing.name == module.name) {
+            Some(existing) if *existing == module => {}
+            Some(_) => bail!(
+                "The scripts given with --script use different modules named {}, which \
+                 can't be told apart",
+                module.name
+            ),
+            None => modules.push(module),
+        }
+    }
+
+    Ok(())
+}
 
-#[derive(Debug, StructOpt)]
-struct Options {
-    /// A path to the place file to open in Roblox Studio. If not specified, an
-    /// empty place file is used.
-    #[structopt(long("place"))]
-    place_path: Option<PathBuf>,
+/// Reads the script given with --script, which is read from stdin if its path
+/// is "-". A byte order mark at the start, which Studio won't compile, is
+/// left out.
+fn read_script(script_path: &Path) -> Result<String, anyhow::Error> {
+    if script_path != Path::new(STDIN_PATH) {
+        let script = fs::read_to_string(script_path)?;
+        return Ok(script.trim_start_matches('\u{feff}').to_owned());
+    }
 
-    /// A path to the script to run in Roblox Studio.
-    ///
-    /// The script will be run at plugin-level security.
-    #[structopt(long("script"))]
-    script_path: PathBuf,
+    if let Some(script) = STDIN_SCRIPT.get() {
+        return Ok(script.clone());
+    }
 
-    /// A path to the Roblox Studio executable to run.
-    #[structopt(long("app"))]
-    studio_app_path: Option<PathBuf>,
+    let mut script = String::new();
+    io::stdin()
+        .read_to_string(&mut script)
+        .context("Could not read the script from stdin")?;
 
-    /// A path to the Roblox Studio plugins folder to use.
-    #[structopt(long("plugins"))]
-    studio_plugins_path: Option<PathBuf>,
+    let script = script.trim_start_matches('\u{feff}').to_owned();
+    Ok(STDIN_SCRIPT.get_or_init(|| script).clone())
 }
 
-fn run(options: Options) -> Result<i32, anyhow::Error> {
-    // Create a temp directory to house our place, even if a path is given from
-    // the command line. This helps ensure Studio won't hang trying to tell the
-    // user that the place is read-only because of a .lock file.
-    let temp_place_folder = tempdir()?;
-    let temp_place_path;
+/// Sets up a run in Roblox Studio, copying the place into `temp_dir`.
+fn studio_runner(
+    options: &Options,
+    temp_dir: &Path,
+    port: u16,
+    scripts: LoadedScripts,
+) -> Result<PlaceRunner, anyhow::Error> {
+    let LoadedScripts {
+        lua_scripts,
+        modules,
+        script_models,
+    } = scripts;
 
-    match &options.place_path {
-        Some(place_path) => {
-            let extension = place_path
-                .extension()
-                .ok_or_else(|| anyhow!("Place file did not have a file extension"))?
-                .to_str()
-                .ok_or_else(|| anyhow!("Place file extension had invalid Unicode"))?;
+    let place_source = match &options.project_path {
+        Some(project_path) => Some(project::build(project_path, temp_dir)?),
+        None => options.place_path.clone(),
+    };
 
-            temp_place_path = temp_place_folder
-                .path()
-                .join(format!("run-in-roblox-place.{}", extension));
+    if let (Some(place_path), true) = (&place_source, options.verify_paths) {
+        let script_names: Vec<String> = options
+            .script_paths
+            .iter()
+            .map(|script_path| script_name(script_path).display().to_string())
+            .collect();
 
-            fs::copy(place_path, &temp_place_path)?;
-        }
-        None => {
-            unimplemented!("run-in-roblox with no place argument");
-        }
+        let scripts: Vec<(&str, &str)> = script_names
+            .iter()
+            .map(String::as_str)
+            .zip(lua_scripts.iter().map(String::as_str))
+            .collect();
+
+        verify_paths::verify(place_path, &scripts, &modules, &options.mounts)?;
     }
 
+    let place_path = place_runner::copy_place(place_source.as_deref(), temp_dir)?;
+
     let studio_plugins_path = match &options.studio_plugins_path {
         Some(plugins_path) => {
             if !plugins_path.exists() {
@@ -80,10 +2221,10 @@ fn run(options: Options) -> Result<i32, anyhow::Error> {
             plugins_path.clone()
         }
         None => {
-            let studio_install =
-                RobloxStudio::locate().context("Could not locate a Roblox Studio installation.")?;
+            let studio_install = studio_cache::locate(!options.no_cache)
+                .context(options.lang().text(Message::StudioNotFound))?;
 
-            studio_install.plugins_path().to_path_buf()
+            studio_install.plugins_path
         }
     };
 
@@ -99,75 +2240,1037 @@ fn run(options: Options) -> Result<i32, anyhow::Error> {
             path.clone()
         }
         None => {
-            let studio_install =
-                RobloxStudio::locate().context("Could not locate a Roblox Studio installation.")?;
+            let studio_install = studio_cache::locate(!options.no_cache)
+                .context(options.lang().text(Message::StudioNotFound))?;
 
-            studio_install.application_path().to_path_buf()
+            studio_install.application_path
         }
     };
 
-    let script_contents = fs::read_to_string(&options.script_path)?;
+    let probe = match &options.probe_path {
+        Some(probe_path) => Some(fs::read_to_string(probe_path)?),
+        None => None,
+    };
+
+    let warmup = match &options.warmup_path {
+        Some(warmup_path) => Some(fs::read_to_string(warmup_path)?),
+        None => None,
+    };
 
-    // Generate a random, unique ID for this session. The plugin we inject will
-    // compare this value with the one reported by the server and abort if they
-    // don't match.
-    let server_id = format!("run-in-roblox-{:x}", rand::random::<u128>());
+    let mounts = options
+        .mounts
+        .iter()
+        .map(Mount::load)
+        .collect::<Result<Vec<_>, _>>()?;
 
-    let place_runner = PlaceRunner {
-        port: 50312,
-        place_path: temp_place_path.clone(),
-        server_id: server_id.clone(),
-        lua_script: script_contents.clone(),
+    Ok(PlaceRunner {
+        port,
+        place_path,
+        place_source,
+        server_id: place_runner::new_server_id(),
+        lua_scripts,
+        parallel_scripts: options.parallel_scripts,
+        isolation: options.isolation,
+        modules,
+        script_models,
+        mounts,
+        models: options.models.clone(),
         studio_app_path,
         studio_plugins_path,
-    };
+        inject_method: options.inject_method,
+        mode: options.mode,
+        capture: options.capture,
+        wait_for_done: options.wait_for == WaitFor::Done,
+        compare_security: options.compare_security,
+        chaos_http: options.chaos_http,
+        forbid_network: options.forbid_network,
+        read_only: options.read_only,
+        budget: options.budget,
+        snapshot: options
+            .snapshot_path
+            .as_ref()
+            .map(|snapshot_path| SnapshotOptions {
+                path: snapshot_path.clone(),
+                root: options
+                    .snapshot_root
+                    .clone()
+                    .unwrap_or_else(|| "game".to_owned()),
+            }),
+        probe,
+        probe_interval: options.probe_interval.unwrap_or(DEFAULT_PROBE_INTERVAL),
+        warmup,
+        duration: options.duration,
+        flush_interval: options.flush_interval,
+        env: script_env::resolve(&options.env),
+        timeout: options.timeout,
+        unresponsive_timeout: options.unresponsive_timeout,
+        validate_output: options.validate_output,
+        severity: match &options.severity_path {
+            Some(severity_path) => SeverityMap::load(severity_path)?,
+            None => SeverityMap::default(),
+        },
+        format_version: options.format_version,
+        artifacts_dir: options.artifacts_dir.clone(),
+        out_dir: options.out_dir.clone(),
+        read_dirs: options.read_dirs.clone(),
+        commands: CommandQueue::new(),
+        repl: false,
+        inspect: options.on_failure == OnFailure::Inspect,
+    })
+}
 
-    let (sender, receiver) = mpsc::channel();
+/// Hashes everything that could change whether a run passes: the scripts
+/// after bundling, the files they're run against, and the options that affect
+/// how they run. Options that only change how results are reported, like
+/// --output, are left out.
+fn result_cache_key(options: &Options, scripts: &LoadedScripts) -> Result<CacheKey, anyhow::Error> {
+    let mut key = CacheKey::builder();
 
-    thread::spawn(move || {
-        place_runner.run(sender).unwrap();
+    for lua_script in &scripts.lua_scripts {
+        key.add("script", lua_script.as_bytes());
+    }
+
+    for module in &scripts.modules {
+        key.add_debug(&format!("module {}", module.name), module);
+    }
+
+    // The source of a script given as a model is hashed above, but not the
+    // instances inside it.
+    for script_path in &options.script_paths {
+        if model::is_script_model(script_path) {
+            key.add_path("script model", script_path).with_context(|| {
+                format!(
+                    "Could not read {} for --cache-results",
+                    script_path.display()
+                )
+            })?;
+        }
+    }
+
+    let files = [
+        ("place", &options.place_path),
+        ("probe", &options.probe_path),
+        ("warmup", &options.warmup_path),
+        ("quarantine", &options.quarantine_path),
+        ("severity", &options.severity_path),
+    ];
+
+    for (label, path) in files {
+        if let Some(path) = path {
+            key.add_path(label, path).with_context(|| {
+                format!("Could not read {} for --cache-results", path.display())
+            })?;
+        }
+    }
+
+    for mount in &options.mounts {
+        key.add_path(&format!("mount {}", mount.target), &mount.source)
+            .with_context(|| {
+                format!(
+                    "Could not read {} for --cache-results",
+                    mount.source.display()
+                )
+            })?;
+    }
+
+    for read_dir in &options.read_dirs {
+        key.add_path(&format!("read-dir {}", read_dir.display()), read_dir)
+            .with_context(|| {
+                format!("Could not read {} for --cache-results", read_dir.display())
+            })?;
+    }
+
+    if let Some(project_path) = &options.project_path {
+        for path in project::source_paths(project_path)? {
+            // Optional paths don't have to exist.
+            if path.exists() {
+                key.add_path(&format!("project {}", path.display()), &path)
+                    .with_context(|| {
+                        format!("Could not read {} for --cache-results", path.display())
+                    })?;
+            }
+        }
+    }
+
+    for model in &options.models {
+        key.add_path(&format!("model {}", model.target), &model.source)
+            .with_context(|| {
+                format!(
+                    "Could not read {} for --cache-results",
+                    model.source.display()
+                )
+            })?;
+    }
+
+    key.add_debug("env", &script_env::resolve(&options.env));
+    key.add_debug("backend", &options.backend);
+    key.add_debug("parallel-scripts", &options.parallel_scripts);
+    key.add_debug("isolation", &options.isolation);
+    key.add_debug("bundle", &options.bundle);
+    key.add_debug("mode", &options.mode);
+    key.add_debug("capture", &options.capture);
+    key.add_debug("wait-for", &options.wait_for);
+    key.add_debug("compare-security", &options.compare_security);
+    key.add_debug("chaos-http", &options.chaos_http);
+    key.add_debug("forbid-network", &options.forbid_network);
+    key.add_debug("read-only", &options.read_only);
+    key.add_debug("budget", &options.budget);
+    key.add_debug("snapshot", &options.snapshot_path);
+    key.add_debug("snapshot-root", &options.snapshot_root);
+    key.add_debug("out-dir", &options.out_dir);
+    key.add_debug("probe-interval", &options.probe_interval);
+    key.add_debug("duration", &options.duration);
+    key.add_debug("timeout", &options.timeout);
+    key.add_debug("unresponsive-timeout", &options.unresponsive_timeout);
+
+    // A Studio update can change how scripts behave, so runs in a different
+    // version of Studio aren't skipped.
+    if options.backend == Backend::Studio {
+        let studio_version = studio_cache::locate(!options.no_cache)
+            .ok()
+            .and_then(|studio| studio.version);
+
+        key.add_debug("studio", &studio_version);
+    }
+
+    Ok(key.finish())
+}
+
+/// Reports a run that --cache-results skipped, returning its exit code.
+fn report_cached_pass(options: &Options, pass: &CachedPass) -> i32 {
+    let lang = options.lang();
+    let passed_at = humantime::format_rfc3339_seconds(pass.passed_at()).to_string();
+    let skipped = skipped_scripts(
+        options.script_paths.iter().map(PathBuf::as_path),
+        SkipReason::Cached,
+    );
+
+    print_summary(
+        options,
+        lang.format(Message::CachedPass, &[&passed_at]).green(),
+    );
+
+    report::update(|report| {
+        report.cached = true;
+        report.checks_passed = pass.checks_passed;
+        report.skipped = skipped.clone();
     });
 
-    let mut exit_code = 0;
+    if options.output.is_json() {
+        print_json(
+            options,
+            &JsonSummary {
+                format_version: options.format_version.0,
+                checks_passed: pass.checks_passed,
+                checks_failed: 0,
+                quarantined_failures: 0,
+                cached: true,
+                skipped,
+            },
+        );
+    }
 
-    while let Some(message) = receiver.recv()? {
-        match message {
-            RobloxMessage::Output { level, body } => {
-                let colored_body = match level {
-                    OutputLevel::Print => body.normal(),
-                    OutputLevel::Info => body.cyan(),
-                    OutputLevel::Warning => body.yellow(),
-                    OutputLevel::Error => body.red(),
-                };
+    0
+}
 
-                println!("{}", colored_body);
+/// Lists the scripts at `script_paths` as skipped for `reason`.
+fn skipped_scripts<'a>(
+    script_paths: impl Iterator<Item = &'a Path>,
+    reason: SkipReason,
+) -> Vec<SkippedScript> {
+    script_paths
+        .map(|script_path| SkippedScript {
+            script: script_name(script_path).display().to_string(),
+            reason,
+        })
+        .collect()
+}
 
-                if level == OutputLevel::Error {
-                    exit_code = 1;
+/// Rejects options that only make sense when running in Roblox Studio.
+fn check_studio_only_options(options: &Options) -> Result<(), anyhow::Error> {
+    let studio_only = [
+        ("More than one --script", options.script_paths.len() > 1),
+        (
+            "A folder given with --script",
+            options.script_paths.iter().any(|path| path.is_dir()),
+        ),
+        (
+            "A model given with --script",
+            options
+                .script_paths
+                .iter()
+                .any(|path| model::is_script_model(path)),
+        ),
+        ("--parallel-scripts", options.parallel_scripts != 1),
+        ("--isolation", options.isolation != Isolation::None),
+        ("--place", options.place_path.is_some()),
+        ("--project", options.project_path.is_some()),
+        ("--port", options.port.is_some()),
+        ("--mount", !options.mounts.is_empty()),
+        ("--model", !options.models.is_empty()),
+        ("--app", options.studio_app_path.is_some()),
+        ("--plugins", options.studio_plugins_path.is_some()),
+        ("--mode", options.mode != StudioMode::Edit),
+        ("--capture", options.capture != Capture::All),
+        ("--wait-for", options.wait_for != WaitFor::Return),
+        ("--hot", options.hot),
+        ("--compare-security", options.compare_security),
+        ("--chaos-http", options.chaos_http.is_some()),
+        ("--forbid-network", options.forbid_network),
+        ("--read-only", options.read_only),
+        ("--budget", options.budget.is_some()),
+        ("--snapshot", options.snapshot_path.is_some()),
+        ("--out-dir", options.out_dir.is_some()),
+        ("--read-dir", !options.read_dirs.is_empty()),
+        ("--probe", options.probe_path.is_some()),
+        ("--warmup", options.warmup_path.is_some()),
+        ("--duration", options.duration.is_some()),
+        ("--keep-open", options.keep_open),
+        ("--severity", options.severity_path.is_some()),
+    ];
+
+    for (flag, given) in &studio_only {
+        if *given {
+            bail!("{} can only be used with the studio backend", flag);
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints a message from the script. With --plain, each of its lines is
+/// printed separately and tagged with the message's level, so that every line
+/// makes sense on its own.
+///
+/// With --output github, warnings and errors are printed as workflow commands
+/// instead, pointing at the first place in the script the message mentions.
+fn print_output(
+    options: &Options,
+    source_map: &SourceMap,
+    prefix: &str,
+    tag: &str,
+    body: ColoredString,
+) {
+    transcript::record(EntryKind::Output, Some(tag), &*body);
+
+    if options.output.is_json() {
+        return;
+    }
+
+    if options.output == OutputFormat::Github {
+        let command = match tag {
+            "DEBUG" | "TRACE" => Some("debug"),
+            "WARN" => Some("warning"),
+            "ERROR" => Some("error"),
+            _ => None,
+        };
+
+        if let Some(command) = command {
+            let location = source_map.first_location(&body);
+            let location = location.as_ref().map(|(path, line)| (path.as_str(), *line));
+
+            print_line(options, &output::github_command(command, location, &body));
+            return;
+        }
+    }
+
+    if options.plain {
+        for line in body.split('\n') {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            print_line(options, &format!("{}[{}] {}", prefix, tag, line));
+        }
+    } else {
+        print_line(options, &format!("{}{}", prefix, body));
+    }
+}
+
+/// Prints part of the summary at the end of a run.
+fn print_summary(options: &Options, summary: ColoredString) {
+    transcript::record(EntryKind::Output, Some("SUMMARY"), &*summary);
+
+    if !options.output.is_json() {
+        print_line(options, &summary.to_string());
+    }
+}
+
+/// Prints a step of the run for --output ndjson.
+fn print_lifecycle(options: &Options, run_started: Instant, at: Instant, event: LifecycleEvent) {
+    if options.output == OutputFormat::Ndjson {
+        print_json(
+            options,
+            &JsonLifecycle {
+                format_version: options.format_version.0,
+                timestamp: at.saturating_duration_since(run_started).as_secs_f64(),
+                event,
+            },
+        );
+    }
+}
+
+/// Closes Roblox Studio once we're done with it.
+fn close_studio(options: &Options, run_started: Instant, studio_process: KillOnDrop) {
+    drop(studio_process);
+    print_lifecycle(
+        options,
+        run_started,
+        Instant::now(),
+        LifecycleEvent::StudioExited,
+    );
+}
+
+/// Prints a line of --output json.
+fn print_json<T: Serialize>(options: &Options, value: &T) {
+    print_line(
+        options,
+        &serde_json::to_string(value).expect("Could not serialize JSON output"),
+    );
+}
+
+/// Prints a line of the run's output, ending it and any lines within it with
+/// the line ending chosen with --newline.
+fn print_line(options: &Options, line: &str) {
+    match options.newline {
+        Some(style) => print!(
+            "{}",
+            output::normalize_newlines(&format!("{}\n", line), style)
+        ),
+        None => println!("{}", line),
+    }
+}
+
+/// Formats the time since the start of the run for --timestamps.
+fn timestamp(options: &Options, run_started: Instant, at: Instant) -> String {
+    if !options.timestamps {
+        return String::new();
+    }
+
+    let elapsed = at.saturating_duration_since(run_started);
+
+    format!("[+{:.3}s] ", elapsed.as_secs_f64())
+        .dimmed()
+        .to_string()
+}
+
+enum Inspection {
+    KeepOpen,
+    Rerun,
+    Close,
+}
+
+/// Asks the user what to do with Roblox Studio after a failed run.
+fn inspect_failure(lang: Lang, studio_process: &KillOnDrop) -> Result<Inspection, anyhow::Error> {
+    let print_menu = || {
+        eprintln!("{}", lang.text(Message::InspectPrompt).red());
+        eprintln!("{}", lang.text(Message::InspectKeepOpen));
+        eprintln!("{}", lang.text(Message::InspectSave));
+        eprintln!("{}", lang.text(Message::InspectDump));
+        eprintln!("{}", lang.text(Message::InspectRerun));
+        eprintln!("{}", lang.text(Message::InspectClose));
+    };
+
+    print_menu();
+
+    loop {
+        let choice = match prompt("> ")? {
+            Some(choice) => choice,
+            None => return Ok(Inspection::Close),
+        };
+
+        let snapshot = match choice.as_str() {
+            "k" => return Ok(Inspection::KeepOpen),
+            "r" => return Ok(Inspection::Rerun),
+            "q" => return Ok(Inspection::Close),
+            "s" => {
+                prompt(lang.text(Message::InspectSavePath))?.map(|path| ("game".to_owned(), path))
+            }
+            "d" => match prompt(lang.text(Message::InspectDumpRoot))? {
+                Some(root) => prompt(lang.text(Message::InspectDumpPath))?.map(|path| (root, path)),
+                None => None,
+            },
+            _ => {
+                eprintln!("{}", lang.text(Message::InspectInvalid));
+                continue;
+            }
+        };
+
+        let (root, path) = match snapshot {
+            Some(snapshot) => snapshot,
+            None => return Ok(Inspection::Close),
+        };
+
+        match studio_process.save_snapshot(&root, Path::new(&path)) {
+            Ok(count) => eprintln!(
+                "{}",
+                lang.format(Message::InspectSaved, &[&count, &root, &path])
+            ),
+            Err(err) => eprintln!("{}", format!("{:#}", err).red()),
+        }
+
+        eprintln!();
+        print_menu();
+    }
+}
+
+/// Reads one trimmed line from stdin after printing `message`, or `None` once
+/// stdin is closed. When the script was read from stdin, the line is read from
+/// the terminal instead.
+fn prompt(message: &str) -> Result<Option<String>, anyhow::Error> {
+    eprint!("{}", message);
+    io::stderr().flush()?;
+
+    let mut line = String::new();
+    let read = if STDIN_SCRIPT.get().is_some() {
+        match fs::File::open(TERMINAL_PATH) {
+            Ok(terminal) => io::BufReader::new(terminal).read_line(&mut line)?,
+            Err(err) => {
+                log::debug!("Could not open the terminal: {}", err);
+                0
+            }
+        }
+    } else {
+        io::stdin().read_line(&mut line)?
+    };
+
+    if read == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(line.trim().to_owned()))
+}
+
+fn clean(
+    studio_plugins_path: Option<PathBuf>,
+    use_cache: bool,
+    lang: Lang,
+) -> Result<i32, anyhow::Error> {
+    let mut plugins_paths = Vec::new();
+
+    if let Ok(studio_install) = studio_cache::locate(use_cache) {
+        plugins_paths.push(studio_install.plugins_path);
+    }
+
+    plugins_paths.extend(studio_plugins_path);
+
+    let removed = clean::clean(&plugins_paths);
+
+    if removed.is_empty() {
+        println!("{}", lang.text(Message::NothingToClean));
+    } else {
+        for item in &removed {
+            println!("{}", lang.format(Message::Removed, &[item]));
+        }
+    }
+
+    Ok(0)
+}
+
+fn print_schema(name: Option<String>, format_version: FormatVersion) -> Result<i32, anyhow::Error> {
+    match name {
+        Some(name) => {
+            let source = schema::find(&name, format_version).ok_or_else(|| {
+                anyhow!(
+                    "Unknown schema {} for format version {}",
+                    name,
+                    format_version
+                )
+            })?;
+            println!("{}", source);
+        }
+        None => {
+            for schema in schema::SCHEMAS {
+                if schema.version == format_version {
+                    println!("{}", schema.name);
                 }
             }
         }
     }
 
-    Ok(exit_code)
+    Ok(0)
+}
+
+fn self_test(
+    mut options: SelfTestOptions,
+    out_path: Option<PathBuf>,
+    use_studio_cache: bool,
+) -> Result<i32, anyhow::Error> {
+    // The plugins folder is needed to check that runs clean up their plugins.
+    if options.backend == Backend::Studio && options.studio_plugins_path.is_none() {
+        options.studio_plugins_path = studio_cache::locate(use_studio_cache)
+            .ok()
+            .map(|studio| studio.plugins_path);
+    }
+
+    let report = self_test::run(&options, |case| {
+        let line = format!(
+            "{:<7} {} ({:.1}s)",
+            format!("{:?}", case.status).to_uppercase(),
+            case.name,
+            case.seconds
+        );
+
+        match case.status {
+            CaseStatus::Passed => eprintln!("{}", line.green()),
+            CaseStatus::Failed => eprintln!("{}", line.red()),
+            CaseStatus::Skipped => eprintln!("{}", line.dimmed()),
+        }
+
+        for problem in &case.problems {
+            eprintln!("  {}", problem);
+        }
+    })?;
+
+    let contents = serde_json::to_string_pretty(&report)?;
+
+    match out_path {
+        Some(out_path) => report::write_atomic(&out_path, contents.as_bytes())?,
+        None => println!("{}", contents),
+    }
+
+    Ok(if report.passed { 0 } else { 1 })
+}
+
+fn smoke(
+    backend: Backend,
+    studio_app_path: Option<PathBuf>,
+    studio_plugins_path: Option<PathBuf>,
+    timeout: Duration,
+    lang: Lang,
+) -> Result<i32, anyhow::Error> {
+    let temp_dir = tempfile::Builder::new()
+        .prefix(TEMP_DIR_PREFIX)
+        .tempdir()
+        .context("Could not create a temporary directory for the run")?;
+    SessionMarker::for_current_process().write(temp_dir.path())?;
+
+    let place_path = temp_dir.path().join("smoke.rbxl");
+    let script_path = temp_dir.path().join("smoke.lua");
+    smoke::write_place(&place_path).context("Could not write the smoke test place")?;
+    fs::write(&script_path, smoke::SCRIPT)?;
+
+    // The smoke test is an ordinary run with everything else left at its
+    // defaults. Only Studio can open the place.
+    let mut options = Options::from_iter(&["run-in-roblox"]);
+    options.backend = backend;
+    options.script_paths = vec![script_path];
+    options.studio_app_path = studio_app_path;
+    options.studio_plugins_path = studio_plugins_path;
+    options.timeout = Some(timeout);
+    if backend == Backend::Studio {
+        options.place_path = Some(place_path);
+    }
+
+    println!("{}", lang.format(Message::SmokeRunning, &[&backend]));
+
+    let scripts = LoadedScripts {
+        lua_scripts: vec![smoke::SCRIPT.to_owned()],
+        modules: Vec::new(),
+        script_models: Vec::new(),
+    };
+    let built = build_runner(&options, temp_dir.path(), scripts)?;
+    let receiver = runner::spawn(built.runner);
+
+    let mut messages = Vec::new();
+    loop {
+        match receiver.recv() {
+            Ok(RunnerEvent::Message(message)) => messages.push(message.message),
+            Ok(RunnerEvent::Lifecycle { .. }) => {}
+            Ok(RunnerEvent::Finished(_)) => break,
+            Ok(RunnerEvent::Failed(err)) => return Err(err.context("The smoke test couldn't run")),
+            Err(_) => bail!("The runner stopped without reporting how the run ended"),
+        }
+    }
+
+    let problems = smoke::check(&messages);
+
+    if problems.is_empty() {
+        println!("{}", lang.text(Message::SmokePassed).green());
+        Ok(0)
+    } else {
+        println!("{}", lang.text(Message::SmokeFailed).red());
+
+        for problem in &problems {
+            println!("  {}", problem);
+        }
+
+        Ok(1)
+    }
+}
+
+/// Writes the example project for `run-in-roblox examples`, then runs the
+/// examples that work on the mock backend if asked to.
+fn write_examples(dir: &Path, run_examples: bool, lang: Lang) -> Result<i32, anyhow::Error> {
+    if !(run_examples && examples::is_written(dir)) {
+        let paths = examples::write(dir)?;
+        println!(
+            "{}",
+            lang.format(Message::ExamplesWritten, &[&paths.len(), &dir.display()])
+        );
+    }
+
+    if !run_examples {
+        return Ok(0);
+    }
+
+    let mut failed = Vec::new();
+
+    for (index, (script, extra_args)) in examples::MOCK_RUNS.iter().enumerate() {
+        let script_path = dir.join(script);
+
+        println!();
+        println!(
+            "{}",
+            lang.format(
+                Message::ScriptHeader,
+                &[&script, &(index + 1), &examples::MOCK_RUNS.len()]
+            )
+            .bold()
+        );
+
+        let mut args = vec![
+            "run-in-roblox".into(),
+            "--backend".into(),
+            "mock".into(),
+            "--script".into(),
+            script_path.into_os_string(),
+        ];
+        args.extend(extra_args.iter().map(Into::into));
+
+        if run(&Options::from_iter(args))? != 0 {
+            failed.push(script);
+        }
+    }
+
+    println!();
+
+    if failed.is_empty() {
+        println!(
+            "{}",
+            lang.format(Message::ExamplesPassed, &[&examples::MOCK_RUNS.len()])
+                .green()
+        );
+        Ok(0)
+    } else {
+        println!(
+            "{}",
+            lang.format(
+                Message::ExamplesFailed,
+                &[&failed.len(), &examples::MOCK_RUNS.len()]
+            )
+            .red()
+        );
+
+        for script in &failed {
+            println!("  {}", script);
+        }
+
+        Ok(1)
+    }
+}
+
+fn write_typings(out_path: Option<PathBuf>) -> Result<i32, anyhow::Error> {
+    match out_path {
+        Some(out_path) => fs::write(out_path, RIR_TYPINGS)?,
+        None => print!("{}", RIR_TYPINGS),
+    }
+
+    Ok(0)
+}
+
+/// The options with short flags, along with their long names.
+const SHORT_FLAGS: &[(char, &str)] = &[('v', "verbose"), ('q', "quiet")];
+
+/// Tells `config::layer` about our options by asking clap.
+struct ClapOptionSpec;
+
+impl ClapOptionSpec {
+    /// The arguments that give the option, with a placeholder value if it
+    /// takes one.
+    fn probe_args(&self, name: &str) -> Option<Vec<String>> {
+        let flag = format!("--{}", name);
+
+        match self.kind_of(name)? {
+            ArgKind::Flag => Some(vec![flag]),
+            ArgKind::Value => Some(vec![flag, "-".to_owned()]),
+        }
+    }
+}
+
+impl OptionSpec for ClapOptionSpec {
+    /// Tells whether one of our options is a flag or takes a value, by seeing
+    /// whether it accepts the argument after it as its value.
+    fn kind_of(&self, name: &str) -> Option<ArgKind> {
+        let flag = format!("--{}", name);
+        let args = ["run-in-roblox", &flag, "-"];
+
+        match Options::clap().get_matches_from_safe(args) {
+            Err(err) if err.kind == ErrorKind::UnknownArgument => {
+                let unknown = err.info.unwrap_or_default();
+
+                if unknown.contains(&flag) {
+                    None
+                } else {
+                    Some(ArgKind::Flag)
+                }
+            }
+            _ => Some(ArgKind::Value),
+        }
+    }
+
+    fn long_name(&self, short: char) -> Option<&'static str> {
+        SHORT_FLAGS
+            .iter()
+            .find(|(flag, _)| *flag == short)
+            .map(|(_, name)| *name)
+    }
+
+    /// Tells whether two options conflict by seeing whether clap accepts them
+    /// together.
+    fn conflicts(&self, name: &str, other: &str) -> bool {
+        let (args, other_args) = match (self.probe_args(name), self.probe_args(other)) {
+            (Some(args), Some(other_args)) => (args, other_args),
+            _ => return false,
+        };
+
+        let args = iter::once("run-in-roblox".to_owned())
+            .chain(args)
+            .chain(other_args);
+
+        matches!(
+            Options::clap().get_matches_from_safe(args),
+            Err(err) if err.kind == ErrorKind::ArgumentConflict
+        )
+    }
+
+    /// Tells whether an option is global by seeing whether clap knows it
+    /// after `status`, which has no options of its own.
+    fn is_global(&self, name: &str) -> bool {
+        let args = match self.probe_args(name) {
+            Some(args) => args,
+            None => return false,
+        };
+
+        let args = ["run-in-roblox", "status"]
+            .iter()
+            .map(|arg| (*arg).to_owned())
+            .chain(args);
+
+        !matches!(
+            Options::clap().get_matches_from_safe(args),
+            Err(err) if err.kind == ErrorKind::UnknownArgument
+        )
+    }
+}
+
+/// Prints the options that are set for --show-config.
+fn show_config(options: &Options, settings: &[Setting]) {
+    let settings: Vec<&Setting> = settings
+        .iter()
+        .filter(|setting| setting.name != "show-config")
+        .collect();
+
+    let name_width = settings
+        .iter()
+        .map(|setting| setting.name.len())
+        .max()
+        .unwrap_or(0);
+
+    for setting in settings {
+        println!(
+            "{:width$}  {}  {}",
+            setting.name,
+            setting.value_text(),
+            format!("({})", setting.source).dimmed(),
+            width = name_width
+        );
+    }
+
+    println!("{}", options.lang().text(Message::ConfigDefaults).dimmed());
 }
 
 fn main() {
-    let options = Options::from_args();
+    let layered = match config::layer(env::args_os().collect(), &ClapOptionSpec) {
+        Ok(layered) => layered,
+        Err(err) => {
+            eprintln!("{:?}", err.context("Could not read the configuration"));
+            process::exit(2);
+        }
+    };
+
+    let options = Options::from_iter(layered.args);
+
+    // Only global options, like --plain, mean anything to subcommands.
+    if options.command.is_some() {
+        let run_only = layered
+            .settings
+            .iter()
+            .find(|setting| !ClapOptionSpec.is_global(&setting.name));
+
+        if let Some(setting) = run_only {
+            clap::Error::with_description(
+                &format!("--{} can't be used with a subcommand", setting.name),
+                ErrorKind::ArgumentConflict,
+            )
+            .exit();
+        }
+    }
 
     {
-        let log_env = env_logger::Env::default().default_filter_or("warn");
+        // --verbose shows our logs of each step of the run, and --quiet
+        // hides our warnings. RUST_LOG still takes precedence.
+        let default_filter = if options.verbose >= 1 {
+            "info"
+        } else if options.quiet {
+            "error"
+        } else {
+            "warn"
+        };
+        let log_env = env_logger::Env::default().default_filter_or(default_filter);
 
-        env_logger::Builder::from_env(log_env)
+        let write_style = if options.plain {
+            env_logger::WriteStyle::Never
+        } else {
+            env_logger::WriteStyle::Auto
+        };
+
+        let logger = env_logger::Builder::from_env(log_env)
             .format_timestamp(None)
-            .init();
+            .write_style(write_style)
+            .build();
+
+        // Transcripts get our debug logs even when they aren't printed.
+        let max_level = if options.transcript_path.is_some() {
+            logger.filter().max(log::LevelFilter::Debug)
+        } else {
+            logger.filter()
+        };
+
+        log::set_boxed_logger(Box::new(TranscriptLogger { inner: logger }))
+            .expect("The logger was already set");
+        log::set_max_level(max_level);
+    }
+
+    for warning in &layered.warnings {
+        log::warn!("{}", warning);
+    }
+
+    if options.show_config {
+        show_config(&options, &layered.settings);
+        return;
+    }
+
+    // A detached run writes its own transcript and report from the background.
+    if options.command.is_none() && !options.detach {
+        if let Some(transcript_path) = &options.transcript_path {
+            if let Err(err) = transcript::start(transcript_path) {
+                log::error!("{:?}", err);
+                process::exit(2);
+            }
+        }
+
+        if let Some(report_path) = &options.report_path {
+            if let Err(err) = report::start(report_path, options.format_version)
+                .context("Could not create the --report file")
+            {
+                log::error!("{:?}", err);
+                process::exit(2);
+            }
+        }
     }
 
-    match run(options) {
-        Ok(exit_code) => process::exit(exit_code),
+    if options.plain {
+        colored::control::set_override(false);
+    }
+
+    let lang = options.lang();
+
+    let result = match options.command {
+        Some(Subcommand::Clean {
+            studio_plugins_path,
+        }) => clean(studio_plugins_path, !options.no_cache, lang),
+        Some(Subcommand::Schema {
+            name,
+            format_version,
+        }) => print_schema(name, format_version),
+        Some(Subcommand::Smoke {
+            backend,
+            studio_app_path,
+            studio_plugins_path,
+            timeout,
+        }) => smoke(backend, studio_app_path, studio_plugins_path, timeout, lang),
+        Some(Subcommand::Typings { out_path }) => write_typings(out_path),
+        Some(Subcommand::Repl {
+            place_path,
+            script_paths,
+            mounts,
+            env,
+            studio_app_path,
+            studio_plugins_path,
+        }) => {
+            // The session is otherwise an ordinary run with everything left
+            // at its defaults.
+            let mut repl_options = Options::from_iter(&["run-in-roblox"]);
+            repl_options.place_path = place_path;
+            repl_options.script_paths = script_paths;
+            repl_options.mounts = mounts;
+            repl_options.env = env;
+            repl_options.studio_app_path = studio_app_path;
+            repl_options.studio_plugins_path = studio_plugins_path;
+            repl_options.no_cache = options.no_cache;
+            repl_options.plain = options.plain;
+
+            repl(&repl_options)
+        }
+        Some(Subcommand::SelfTest {
+            backend,
+            studio_app_path,
+            studio_plugins_path,
+            timeout,
+            out_path,
+        }) => self_test(
+            SelfTestOptions {
+                backend,
+                studio_app_path,
+                studio_plugins_path,
+                timeout,
+            },
+            out_path,
+            !options.no_cache,
+        ),
+        Some(Subcommand::Examples { dir, run }) => write_examples(&dir, run, lang),
+        Some(Subcommand::InspectTranscript { path, kind }) => {
+            transcript::inspect(&path, kind).map(|()| 0)
+        }
+        Some(Subcommand::Status { id }) => print_detached_status(id.as_deref(), lang),
+        Some(Subcommand::Wait { id }) => wait_for_detached(&id, lang),
+        Some(Subcommand::Attach { id }) => attach_detached(&id, lang),
+        Some(Subcommand::Send {
+            name,
+            payload,
+            run_id,
+            port,
+        }) => send_command(&name, payload.as_deref(), run_id.as_deref(), port, lang),
+        None if options.detach => start_detached(&options, lang),
+        None if options.watch => watch(&options, lang),
+        None => run(&options),
+    };
+
+    let mut exit_code = match result {
+        Ok(exit_code) => exit_code,
         Err(err) => {
             log::error!("{:?}", err);
-            process::exit(2);
+            2
         }
+    };
+
+    if let Err(err) = report::finish(exit_code).context("Could not finalize the --report file") {
+        log::error!("{:?}", err);
+        exit_code = 2;
     }
+
+    transcript::record(
+        EntryKind::Lifecycle,
+        None,
+        format!("run-in-roblox exited with code {}", exit_code),
+    );
+    process::exit(exit_code);
 }
@@ -1,40 +1,781 @@
 use std::{
-    sync::{mpsc, Arc},
+    collections::{HashMap, VecDeque},
+    fmt,
+    io::{Read, Write},
+    mem,
+    net::TcpStream,
+    path::{Component, Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
+use anyhow::{anyhow, bail, Context};
+use fs_err as fs;
 use futures::{future, stream::Stream, sync::oneshot, Future};
-use hyper::{service::service_fn, Body, Method, Request, Response, Server, StatusCode};
-use serde::Deserialize;
+use hyper::{
+    header::{self, HeaderValue},
+    service::service_fn,
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bundle::BundledModule,
+    output::FormatVersion,
+    schema::Schema,
+    severity::SeverityMap,
+    transcript::{self, EntryKind},
+    websocket,
+};
+
+/// The header the plugin sends its token in. The plugin template names it too.
+pub const TOKEN_HEADER: &str = "X-Run-In-Roblox-Token";
+
+/// How many bytes the plugin template puts in each piece of a batch it posts
+/// to `/chunk`, at most.
+const CHUNK_SIZE: usize = 128 * 1024;
+
+/// The biggest batch we'll put back together from pieces. Anything bigger is
+/// a broken plugin rather than output.
+const MAX_CHUNKED_BATCH_LENGTH: usize = 64 * 1024 * 1024;
+
+/// How many pieces the biggest batch can come in. Pieces end between
+/// characters, which are at most 4 bytes long, so every piece but the last
+/// has at least `CHUNK_SIZE - 3` bytes.
+const MAX_CHUNK_COUNT: usize = MAX_CHUNKED_BATCH_LENGTH / (CHUNK_SIZE - 3) + 1;
+
+/// How long a batch can go without a new piece before it's given up on.
+const CHUNKED_BATCH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How many batches that were put back together or given up on are
+/// remembered, so that pieces of them that arrive late are ignored.
+const FINISHED_BATCH_MEMORY: usize = 256;
 
 type HyperResponse = Box<dyn Future<Item = Response<Body>, Error = hyper::Error> + Send>;
 
 #[derive(Debug, Clone)]
 pub enum Message {
-    Start,
+    /// The plugin checked that it's talking to this run-in-roblox.
+    Connected,
+    /// The plugin has started running the script. Carries the plugin's
+    /// `os.clock()` at that moment, used to line up its timestamps with ours.
+    Start(Option<f64>),
     Stop,
-    Messages(Vec<RobloxMessage>),
+    Alive,
+    Messages(Vec<StampedMessage>),
+    Invalid(Vec<String>),
 }
 
+/// A message from the plugin, along with the plugin's `os.clock()` when it was
+/// queued.
 #[derive(Debug, Clone, Deserialize)]
+pub struct StampedMessage {
+    #[serde(default)]
+    pub clock: Option<f64>,
+
+    /// The index of the script the message came from, for output from
+    /// scripts run with --parallel-scripts.
+    #[serde(default)]
+    pub script: Option<usize>,
+
+    /// Which side of the play test the message came from, for output from
+    /// scripts run with --mode play.
+    #[serde(default)]
+    pub context: Option<PlayContext>,
+
+    #[serde(flatten)]
+    pub message: RobloxMessage,
+}
+
+/// One side of a play test started with --mode play.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlayContext {
+    Server,
+    Client,
+}
+
+impl fmt::Display for PlayContext {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlayContext::Server => write!(formatter, "server"),
+            PlayContext::Client => write!(formatter, "client"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum RobloxMessage {
-    Output { level: OutputLevel, body: String },
+    Output {
+        level: OutputLevel,
+        body: String,
+        /// The script the output came from, like `ReplicatedStorage.Foo`,
+        /// when the plugin could tell.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        source: Option<String>,
+        /// The line in `source` that the output came from.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        line: Option<usize>,
+        /// Whether the output came from the scripts themselves, rather than
+        /// Studio, another plugin, or the place, when that's known.
+        #[serde(
+            default,
+            rename = "fromScript",
+            skip_serializing_if = "Option::is_none"
+        )]
+        from_script: Option<bool>,
+        /// Whether this is something Studio logged before the plugin
+        /// started, sent along with `--capture all`. Errors in it don't fail
+        /// the run.
+        #[serde(default, skip_serializing_if = "is_false")]
+        history: bool,
+    },
+    Check {
+        name: String,
+        passed: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    Metrics {
+        time: f64,
+        values: HashMap<String, f64>,
+    },
+    /// Marks the start of a phase of the run, either "warmup" for the
+    /// --warmup script or "main" for the script itself.
+    Phase { name: String },
+    /// Marks the start of one of the scripts when several are given with
+    /// --script. `index` counts from 1, in the order they were given.
+    ScriptStart { index: usize },
+    /// Marks the end of one of the scripts started with `ScriptStart`.
+    ScriptEnd { index: usize },
+    /// A value the script sent with `rir.send()` for tools reading the JSON
+    /// output, like a test reporter's results.
+    Data { value: serde_json::Value },
+    /// One of the instances recorded with --snapshot, sent parents first.
+    /// The snapshot's root has no parent.
+    SnapshotInstance {
+        id: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        parent: Option<u32>,
+        #[serde(rename = "className")]
+        class_name: String,
+        name: String,
+        #[serde(default)]
+        properties: HashMap<String, serde_json::Value>,
+    },
+    /// Sent once every instance recorded with --snapshot has been sent.
+    SnapshotEnd { count: u32 },
+    /// Sent once a line from `HostCommand::Eval` has been run and its output
+    /// has been sent.
+    Evaluated { id: u64 },
+    /// Sent once the scripts have run again for `HostCommand::Reload`, and
+    /// with an `id` of 0 once they've first run in a session that's kept open.
+    Reloaded { id: u64 },
+    /// Sets the code run-in-roblox exits with, sent when the script calls
+    /// `rir.exit(code)` or returns a number.
+    ExitCode { code: i32 },
+    /// Sent once the plugin starts, describing the Studio release it's
+    /// running in and which variant of the plugin it chose for it.
+    Hello {
+        #[serde(rename = "studioVersion")]
+        studio_version: String,
+        variant: String,
+    },
+    /// A file the script saved with `rir.artifact()`, like a screenshot or a
+    /// serialized model, with its contents encoded as base64.
+    Artifact { name: String, data: String },
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum OutputLevel {
+    /// Detailed output from `rir.trace()`, only shown with `--verbose` given
+    /// twice.
+    Trace,
+    /// Output from `rir.debug()`, only shown with `--verbose`.
+    Debug,
     Print,
     Info,
     Warning,
     Error,
 }
 
+/// A command sent from run-in-roblox to the plugin, which gets it over
+/// `/stream`, or picks it up from `/commands` if it can't open a WebSocket.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum HostCommand {
+    /// Stops waiting for the script and ends the run, which fails. Scripts can
+    /// notice with `rir.isCancelled()` or handle the "cancel" command with
+    /// `rir.onCommand()` to clean up.
+    Cancel,
+
+    /// A command for the script to handle with `rir.onCommand(name, handler)`,
+    /// which is called with the payload.
+    Custom {
+        name: String,
+        #[serde(default)]
+        payload: serde_json::Value,
+    },
+
+    /// Runs a line typed into `run-in-roblox repl`, made into a ModuleScript
+    /// with `plugin::repl_source`. The plugin answers with
+    /// `RobloxMessage::Evaluated` once it's done.
+    Eval { id: u64, source: String },
+
+    /// Replaces the scripts and bundled modules with the ones that changed
+    /// for `--watch --hot`, then runs the scripts again. `scripts` are made
+    /// into ModuleScripts with `plugin::reload_sources`, and `removed` names
+    /// modules that aren't needed anymore. The plugin answers with
+    /// `RobloxMessage::Reloaded` once the scripts have finished.
+    Reload {
+        id: u64,
+        scripts: Vec<String>,
+        modules: Vec<BundledModule>,
+        removed: Vec<String>,
+    },
+
+    /// Sends the subtree at `root`, an instance path like the one given with
+    /// --snapshot, as `RobloxMessage::SnapshotInstance` messages. Only handled
+    /// after the run for `--on-failure inspect`.
+    Snapshot { root: String },
+}
+
+impl HostCommand {
+    /// The command named `name`, where "cancel" cancels the run and anything
+    /// else is left for the script to handle.
+    pub fn named(name: &str, payload: serde_json::Value) -> Self {
+        match name {
+            "cancel" => HostCommand::Cancel,
+            _ => HostCommand::Custom {
+                name: name.to_owned(),
+                payload,
+            },
+        }
+    }
+}
+
+/// Commands waiting for the plugin to pick them up. Clones share the same
+/// queue, so commands can be sent from any thread while the run goes on.
+#[derive(Debug, Clone, Default)]
+pub struct CommandQueue(Arc<Mutex<QueuedCommands>>);
+
+#[derive(Debug, Default)]
+struct QueuedCommands {
+    pending: Vec<HostCommand>,
+
+    /// Where to send commands straight away while the plugin is connected to
+    /// `/stream`, instead of waiting for it to ask for them.
+    stream: Option<futures::sync::mpsc::UnboundedSender<String>>,
+}
+
+impl CommandQueue {
+    pub fn new() -> Self {
+        CommandQueue::default()
+    }
+
+    pub fn push(&self, command: HostCommand) {
+        let mut queued = self.lock();
+
+        if let Some(stream) = &queued.stream {
+            let encoded = encode_commands(std::slice::from_ref(&command));
+
+            if stream.unbounded_send(encoded.clone()).is_ok() {
+                transcript::record(EntryKind::Protocol, Some("/stream"), encoded);
+                return;
+            }
+
+            // The plugin has disconnected, so it's back to asking for them.
+            queued.stream = None;
+        }
+
+        queued.pending.push(command);
+    }
+
+    fn take(&self) -> Vec<HostCommand> {
+        mem::take(&mut self.lock().pending)
+    }
+
+    /// Sends commands to the plugin over `/stream` from now on, starting with
+    /// any that are waiting.
+    fn attach_stream(&self) -> futures::sync::mpsc::UnboundedReceiver<String> {
+        let (stream_tx, stream_rx) = futures::sync::mpsc::unbounded();
+        let mut queued = self.lock();
+        let pending = mem::take(&mut queued.pending);
+
+        if !pending.is_empty() {
+            let encoded = encode_commands(&pending);
+            transcript::record(EntryKind::Protocol, Some("/stream"), encoded.as_str());
+            let _ignored = stream_tx.unbounded_send(encoded);
+        }
+
+        queued.stream = Some(stream_tx);
+        stream_rx
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, QueuedCommands> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Commands as the plugin reads them, from `/commands` or `/stream`.
+fn encode_commands(commands: &[HostCommand]) -> String {
+    serde_json::to_string(commands).expect("Failed serializing commands for Roblox Studio")
+}
+
+/// Sends a command to the run whose plugin is talking to it on `port`, for
+/// `run-in-roblox send`. The run only accepts commands sent with its token.
+pub fn send_command(port: u16, token: &str, command: &HostCommand) -> Result<(), anyhow::Error> {
+    let body = serde_json::to_vec(command)?;
+
+    let mut stream = TcpStream::connect(("127.0.0.1", port))
+        .with_context(|| format!("No run is listening on port {}", port))?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    write!(
+        stream,
+        "POST /commands HTTP/1.1\r\nHost: 127.0.0.1:{}\r\nContent-Type: application/json\r\n\
+         {}: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        port,
+        TOKEN_HEADER,
+        token,
+        body.len()
+    )?;
+    stream.write_all(&body)?;
+
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .with_context(|| format!("The run on port {} didn't answer", port))?;
+
+    let status = response.split_whitespace().nth(1).unwrap_or_default();
+
+    if status != "200" {
+        bail!(
+            "The program listening on port {} didn't accept the command; it might not be a \
+             run-in-roblox run",
+            port
+        );
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct StartBody {
+    clock: Option<f64>,
+}
+
+/// Whether a request has to come with the run's token, which everything the
+/// plugin sends after checking the server ID does. `run-in-roblox send` reads
+/// the token from the file the run writes it to.
+fn requires_token(request: &Request<Body>) -> bool {
+    match *request.method() {
+        Method::GET => request.uri().path() != "/",
+        Method::POST => true,
+        _ => false,
+    }
+}
+
+/// Whether a request has the run's token, in a header or, for `/stream`, in
+/// the query string, since Studio can't set headers on WebSockets.
+fn has_token(request: &Request<Body>, token: &str) -> bool {
+    let header = request
+        .headers()
+        .get(TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok());
+
+    let query = request
+        .uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("token="));
+
+    header == Some(token) || query == Some(token)
+}
+
+/// A piece of a batch of messages that was too big for the plugin to send at
+/// once, posted to `/chunk`.
+#[derive(Debug, Deserialize)]
+struct ChunkBody {
+    /// Which batch this is part of.
+    id: u64,
+
+    /// Which piece this is, counting from 1, out of `count`.
+    index: usize,
+    count: usize,
+    data: String,
+}
+
+/// What the plugin posts to `/files` for `rir.writeFile()`.
+#[derive(Debug, Deserialize)]
+struct FileBody {
+    /// Where to write the file, relative to --out-dir.
+    path: String,
+    contents: String,
+}
+
+/// What the plugin posts to `/read` for `rir.readFile()`.
+#[derive(Debug, Deserialize)]
+struct ReadBody {
+    /// The file to read, relative to where run-in-roblox was run.
+    path: String,
+}
+
+/// What the plugin sends over `/stream`: what it would otherwise have posted
+/// to `path`.
+#[derive(Debug, Deserialize)]
+struct StreamMessage {
+    path: String,
+    #[serde(default)]
+    body: String,
+}
+
+/// Writes a file the script asked for with `rir.writeFile()` inside
+/// `out_dir`, returning where it went.
+fn write_out_file(out_dir: Option<&Path>, body: &[u8]) -> Result<PathBuf, anyhow::Error> {
+    let file: FileBody =
+        serde_json::from_slice(body).context("The plugin sent a file that couldn't be read")?;
+
+    let out_dir = match out_dir {
+        Some(out_dir) => out_dir,
+        None => bail!(
+            "rir.writeFile() can't write {}, since run-in-roblox wasn't given --out-dir to \
+             write files into",
+            file.path
+        ),
+    };
+
+    // The path comes from the script, so it mustn't lead outside of the
+    // directory.
+    let relative = Path::new(&file.path);
+    let inside = relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+
+    if file.path.is_empty() || !inside {
+        bail!(
+            "rir.writeFile() can't write {:?}, which isn't a path inside --out-dir",
+            file.path
+        );
+    }
+
+    let path = out_dir.join(relative);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, file.contents)?;
+
+    Ok(path)
+}
+
+/// Reads a file the script asked for with `rir.readFile()`, which has to be
+/// inside one of `read_dirs`.
+fn read_host_file(read_dirs: &[PathBuf], body: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    let file: ReadBody = serde_json::from_slice(body)
+        .context("The plugin asked for a file that couldn't be read")?;
+
+    if read_dirs.is_empty() {
+        bail!(
+            "rir.readFile() can't read {}, since run-in-roblox wasn't given --read-dir to read \
+             files from",
+            file.path
+        );
+    }
+
+    // Both sides are resolved fully, so that neither `..` nor links can lead
+    // outside of the folders.
+    let path = fs::canonicalize(&file.path)
+        .with_context(|| format!("rir.readFile() can't read {}", file.path))?;
+    let inside = read_dirs.iter().any(|read_dir| {
+        fs::canonicalize(read_dir).is_ok_and(|read_dir| path.starts_with(read_dir))
+    });
+
+    if !inside {
+        bail!(
+            "rir.readFile() can't read {}, which isn't inside a folder given with --read-dir",
+            file.path
+        );
+    }
+
+    Ok(fs::read(&path)?)
+}
+
+/// Handles what the plugin sends us, whether it's posted to one of our
+/// endpoints or sent over `/stream`.
+#[derive(Clone)]
+struct PluginHandler {
+    message_tx: mpsc::Sender<Message>,
+    schema: Option<Arc<Schema>>,
+    severity: Arc<SeverityMap>,
+
+    chunks: Arc<Mutex<ChunkedBatches>>,
+}
+
+/// The batches sent to `/chunk` in pieces.
+#[derive(Default)]
+struct ChunkedBatches {
+    /// The pieces of batches that haven't all arrived yet, by ID.
+    pending: HashMap<u64, PendingBatch>,
+
+    /// The IDs of the batches that were put back together or given up on
+    /// most recently, oldest first.
+    finished: VecDeque<u64>,
+}
+
+impl ChunkedBatches {
+    fn finish(&mut self, id: u64) {
+        self.pending.remove(&id);
+        self.finished.push_back(id);
+
+        if self.finished.len() > FINISHED_BATCH_MEMORY {
+            self.finished.pop_front();
+        }
+    }
+}
+
+struct PendingBatch {
+    pieces: Vec<Option<String>>,
+
+    /// How many bytes of the batch have arrived.
+    length: usize,
+    last_piece_at: Instant,
+}
+
+impl PluginHandler {
+    fn handles(path: &str) -> bool {
+        matches!(path, "/start" | "/stop" | "/alive" | "/messages" | "/chunk")
+    }
+
+    /// Passes a message on to the run. Requests can still arrive after the run
+    /// has stopped listening, which are dropped.
+    fn send(&self, message: Message) {
+        if self.message_tx.send(message).is_err() {
+            log::debug!("Dropped a message from Roblox Studio that arrived after the run ended");
+        }
+    }
+
+    /// Handles a body sent to `path`, returning the status and body to
+    /// answer with.
+    fn post(&self, path: &str, body: &[u8]) -> (StatusCode, &'static str) {
+        match path {
+            "/start" => {
+                transcript::record(
+                    EntryKind::Protocol,
+                    Some("/start"),
+                    String::from_utf8_lossy(body),
+                );
+
+                let clock = serde_json::from_slice::<StartBody>(body)
+                    .ok()
+                    .and_then(|body| body.clock);
+
+                self.send(Message::Start(clock));
+                (StatusCode::OK, "Started")
+            }
+            "/stop" => {
+                transcript::record(EntryKind::Protocol, Some("/stop"), "");
+                self.send(Message::Stop);
+                (StatusCode::OK, "Finished")
+            }
+            "/alive" => {
+                transcript::record(EntryKind::Protocol, Some("/alive"), "");
+                self.send(Message::Alive);
+                (StatusCode::OK, "Still here")
+            }
+            "/messages" => {
+                transcript::record(
+                    EntryKind::Protocol,
+                    Some("/messages"),
+                    String::from_utf8_lossy(body),
+                );
+
+                if let Some(schema) = &self.schema {
+                    let errors = match serde_json::from_slice(body) {
+                        Ok(value) => schema.validate(&value),
+                        Err(err) => vec![err.to_string()],
+                    };
+
+                    if !errors.is_empty() {
+                        self.send(Message::Invalid(errors));
+                        return (StatusCode::BAD_REQUEST, "");
+                    }
+                }
+
+                let mut messages: Vec<StampedMessage> = match serde_json::from_slice(body) {
+                    Ok(messages) => messages,
+                    Err(err) => {
+                        log::debug!(
+                            "Rejecting a batch of messages that couldn't be read: {}",
+                            err
+                        );
+                        self.send(Message::Invalid(vec![err.to_string()]));
+                        return (StatusCode::BAD_REQUEST, "");
+                    }
+                };
+
+                for stamped in &mut messages {
+                    self.severity.apply(&mut stamped.message);
+                }
+
+                self.send(Message::Messages(messages));
+                (StatusCode::OK, "Got it!")
+            }
+            "/chunk" => {
+                let chunk: ChunkBody = match serde_json::from_slice(body) {
+                    Ok(chunk) => chunk,
+                    Err(err) => {
+                        log::debug!("Ignoring a chunk that couldn't be read: {}", err);
+                        return (StatusCode::BAD_REQUEST, "");
+                    }
+                };
+
+                transcript::record(
+                    EntryKind::Protocol,
+                    Some("/chunk"),
+                    format!("{} {}/{}", chunk.id, chunk.index, chunk.count),
+                );
+
+                match self.add_chunk(chunk) {
+                    Some(messages) => self.post("/messages", messages.as_bytes()),
+                    None => (StatusCode::OK, "Got it!"),
+                }
+            }
+            _ => (StatusCode::NOT_FOUND, ""),
+        }
+    }
+
+    /// Adds a piece of a batch of messages, returning the whole batch once
+    /// every piece of it has arrived. Batches that are too big or that stop
+    /// getting pieces are dropped.
+    fn add_chunk(&self, chunk: ChunkBody) -> Option<String> {
+        if chunk.index == 0 || chunk.index > chunk.count || chunk.data.len() > CHUNK_SIZE {
+            log::debug!(
+                "Ignoring chunk {} of {} for batch {}",
+                chunk.index,
+                chunk.count,
+                chunk.id
+            );
+            return None;
+        }
+
+        let mut batches = self
+            .chunks
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let stale: Vec<u64> = batches
+            .pending
+            .iter()
+            .filter(|(_, batch)| batch.last_piece_at.elapsed() > CHUNKED_BATCH_TIMEOUT)
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in stale {
+            log::warn!(
+                "Gave up on a batch of output from Roblox Studio that stopped arriving part way \
+                 through"
+            );
+            batches.finish(id);
+        }
+
+        if batches.finished.contains(&chunk.id) {
+            log::debug!("Ignoring a late chunk of batch {}", chunk.id);
+            return None;
+        }
+
+        if chunk.count > MAX_CHUNK_COUNT {
+            log::warn!(
+                "Dropped a batch of output from Roblox Studio sent in {} pieces, which is too big",
+                chunk.count
+            );
+            batches.finish(chunk.id);
+            return None;
+        }
+
+        let batch = batches
+            .pending
+            .entry(chunk.id)
+            .or_insert_with(|| PendingBatch {
+                pieces: vec![None; chunk.count],
+                length: 0,
+                last_piece_at: Instant::now(),
+            });
+
+        if batch.pieces.len() != chunk.count {
+            log::debug!(
+                "Ignoring a chunk of batch {}, which was sent in {} pieces before",
+                chunk.id,
+                batch.pieces.len()
+            );
+            return None;
+        }
+
+        let piece = &mut batch.pieces[chunk.index - 1];
+        if piece.is_none() {
+            batch.length += chunk.data.len();
+            *piece = Some(chunk.data);
+        }
+        batch.last_piece_at = Instant::now();
+
+        if batch.length > MAX_CHUNKED_BATCH_LENGTH {
+            log::warn!(
+                "Dropped a batch of output from Roblox Studio over {} bytes long, which is too big",
+                MAX_CHUNKED_BATCH_LENGTH
+            );
+            batches.finish(chunk.id);
+            return None;
+        }
+
+        if !batch.pieces.iter().all(Option::is_some) {
+            return None;
+        }
+
+        let pieces = batches.pending.remove(&chunk.id)?.pieces;
+        batches.finish(chunk.id);
+
+        Some(pieces.into_iter().flatten().collect())
+    }
+
+    fn stream_message(&self, text: &str) {
+        match serde_json::from_str::<StreamMessage>(text) {
+            Ok(message) if PluginHandler::handles(&message.path) => {
+                self.post(&message.path, message.body.as_bytes());
+            }
+            Ok(message) => log::debug!("Ignoring a message for {} on /stream", message.path),
+            Err(err) => log::debug!("Ignoring a message on /stream that isn't JSON: {}", err),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct MessageReceiverOptions {
     pub port: u16,
     pub server_id: String,
+
+    /// The token the plugin has to send with everything but the first
+    /// request, which only checks that it's talking to us.
+    pub token: String,
+    pub validate: bool,
+    pub format_version: FormatVersion,
+    pub severity: SeverityMap,
+
+    /// Commands for the plugin, handed out from `/commands`.
+    pub commands: CommandQueue,
+
+    /// Where `rir.writeFile()` writes files, for --out-dir.
+    pub out_dir: Option<PathBuf>,
+
+    /// The folders `rir.readFile()` can read files from, for --read-dir.
+    pub read_dirs: Vec<PathBuf>,
 }
 
 pub struct MessageReceiver {
@@ -43,47 +784,191 @@ pub struct MessageReceiver {
 }
 
 impl MessageReceiver {
-    pub fn start(options: MessageReceiverOptions) -> MessageReceiver {
+    pub fn start(options: MessageReceiverOptions) -> Result<MessageReceiver, anyhow::Error> {
         let (message_tx, message_rx) = mpsc::channel();
+        let (bound_tx, bound_rx) = mpsc::channel();
         let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
 
+        let port = options.port;
         let server_id = Arc::new(options.server_id.clone());
+        let token = Arc::new(options.token.clone());
+        let schema = if options.validate {
+            Schema::load("protocol", options.format_version).map(Arc::new)
+        } else {
+            None
+        };
+        let handler = PluginHandler {
+            message_tx,
+            schema,
+            severity: Arc::new(options.severity.clone()),
+            chunks: Arc::default(),
+        };
+        let commands = options.commands.clone();
+        let out_dir = options.out_dir.clone();
+        let read_dirs = Arc::new(options.read_dirs.clone());
 
         thread::spawn(move || {
             let service = move || {
                 let server_id = server_id.clone();
-                let message_tx = message_tx.clone();
+                let token = token.clone();
+                let handler = handler.clone();
+                let commands = commands.clone();
+                let out_dir = out_dir.clone();
+                let read_dirs = read_dirs.clone();
 
                 service_fn(move |request: Request<Body>| -> HyperResponse {
                     let server_id = server_id.clone();
-                    let message_tx = message_tx.clone();
+                    let handler = handler.clone();
+                    let commands = commands.clone();
+                    let out_dir = out_dir.clone();
+                    let read_dirs = read_dirs.clone();
                     let mut response = Response::new(Body::empty());
 
                     log::debug!("Request: {} {}", request.method(), request.uri().path());
 
+                    if requires_token(&request) && !has_token(&request, &token) {
+                        log::warn!(
+                            "Ignored a request to {} without this run's token, which didn't come \
+                             from its plugin",
+                            request.uri().path()
+                        );
+                        *response.status_mut() = StatusCode::FORBIDDEN;
+                        return Box::new(future::ok(response));
+                    }
+
                     match (request.method(), request.uri().path()) {
                         (&Method::GET, "/") => {
+                            transcript::record(EntryKind::Protocol, Some("/"), "");
+                            handler.send(Message::Connected);
                             *response.body_mut() = Body::from(server_id.as_str().to_owned());
                         }
-                        (&Method::POST, "/start") => {
-                            message_tx.send(Message::Start).unwrap();
-                            *response.body_mut() = Body::from("Started");
+                        (&Method::POST, path) if PluginHandler::handles(path) => {
+                            let path = path.to_owned();
+
+                            let future = request.into_body().concat2().map(move |chunk| {
+                                let (status, body) = handler.post(&path, &chunk);
+
+                                *response.status_mut() = status;
+                                *response.body_mut() = Body::from(body);
+                                response
+                            });
+
+                            return Box::new(future);
+                        }
+                        // Studio releases that can open WebSockets send
+                        // everything over one instead, and get commands
+                        // pushed to them as soon as they're sent.
+                        (&Method::GET, "/stream") => {
+                            let accept_key = websocket::upgrade_accept_key(request.headers());
+
+                            let accept_key = match accept_key {
+                                Some(accept_key) => accept_key,
+                                None => {
+                                    *response.status_mut() = StatusCode::BAD_REQUEST;
+                                    return Box::new(future::ok(response));
+                                }
+                            };
+
+                            transcript::record(EntryKind::Protocol, Some("/stream"), "");
+
+                            let outgoing = commands.attach_stream();
+                            let connection = request
+                                .into_body()
+                                .on_upgrade()
+                                .map_err(|err| log::debug!("Could not open a WebSocket: {}", err))
+                                .and_then(move |upgraded| {
+                                    websocket::serve(upgraded, outgoing, move |text| {
+                                        handler.stream_message(&text)
+                                    })
+                                });
+                            hyper::rt::spawn(connection);
+
+                            let headers = response.headers_mut();
+                            headers.insert(header::UPGRADE, HeaderValue::from_static("websocket"));
+                            headers.insert(header::CONNECTION, HeaderValue::from_static("Upgrade"));
+                            headers.insert(
+                                header::SEC_WEBSOCKET_ACCEPT,
+                                HeaderValue::from_str(&accept_key).unwrap(),
+                            );
+                            *response.status_mut() = StatusCode::SWITCHING_PROTOCOLS;
                         }
-                        (&Method::POST, "/stop") => {
-                            message_tx.send(Message::Stop).unwrap();
-                            *response.body_mut() = Body::from("Finished");
+                        (&Method::GET, "/commands") => {
+                            let pending = commands.take();
+                            let encoded = encode_commands(&pending);
+
+                            // The plugin polls for commands several times a
+                            // second, so only deliveries are worth recording.
+                            if !pending.is_empty() {
+                                transcript::record(
+                                    EntryKind::Protocol,
+                                    Some("/commands"),
+                                    encoded.as_str(),
+                                );
+                            }
+
+                            *response.body_mut() = Body::from(encoded);
                         }
-                        (&Method::POST, "/messages") => {
-                            let message_tx = message_tx.clone();
+                        // Files are always posted rather than sent over
+                        // /stream, so that the script finds out whether they
+                        // were written.
+                        (&Method::POST, "/files") => {
+                            let future = request.into_body().concat2().map(move |chunk| {
+                                match write_out_file(out_dir.as_deref(), &chunk) {
+                                    Ok(path) => {
+                                        transcript::record(
+                                            EntryKind::Protocol,
+                                            Some("/files"),
+                                            path.display().to_string(),
+                                        );
+                                        log::info!("The script wrote {}", path.display());
+                                    }
+                                    Err(err) => {
+                                        *response.status_mut() = StatusCode::BAD_REQUEST;
+                                        *response.body_mut() = Body::from(format!("{:#}", err));
+                                    }
+                                }
+
+                                response
+                            });
 
+                            return Box::new(future);
+                        }
+                        (&Method::POST, "/read") => {
                             let future = request.into_body().concat2().map(move |chunk| {
-                                let source = chunk.to_vec();
-                                let messages: Vec<RobloxMessage> = serde_json::from_slice(&source)
-                                    .expect("Failed deserializing message from Roblox Studio");
+                                match read_host_file(&read_dirs, &chunk) {
+                                    Ok(contents) => {
+                                        transcript::record(
+                                            EntryKind::Protocol,
+                                            Some("/read"),
+                                            String::from_utf8_lossy(&chunk),
+                                        );
+                                        *response.body_mut() = Body::from(contents);
+                                    }
+                                    Err(err) => {
+                                        *response.status_mut() = StatusCode::BAD_REQUEST;
+                                        *response.body_mut() = Body::from(format!("{:#}", err));
+                                    }
+                                }
 
-                                message_tx.send(Message::Messages(messages)).unwrap();
+                                response
+                            });
+
+                            return Box::new(future);
+                        }
+                        (&Method::POST, "/commands") => {
+                            let future = request.into_body().concat2().map(move |chunk| {
+                                match serde_json::from_slice::<HostCommand>(&chunk) {
+                                    Ok(command) => {
+                                        log::debug!("Queued command {:?}", command);
+                                        commands.push(command);
+                                        *response.body_mut() = Body::from("Queued");
+                                    }
+                                    Err(err) => {
+                                        *response.status_mut() = StatusCode::BAD_REQUEST;
+                                        *response.body_mut() = Body::from(err.to_string());
+                                    }
+                                }
 
-                                *response.body_mut() = Body::from("Got it!");
                                 response
                             });
 
@@ -99,7 +984,18 @@ impl MessageReceiver {
             };
 
             let addr = ([127, 0, 0, 1], options.port).into();
-            let server = Server::bind(&addr)
+            let builder = match Server::try_bind(&addr) {
+                Ok(builder) => {
+                    let _ignored = bound_tx.send(Ok(()));
+                    builder
+                }
+                Err(err) => {
+                    let _ignored = bound_tx.send(Err(err));
+                    return;
+                }
+            };
+
+            let server = builder
                 .serve(service)
                 .with_graceful_shutdown(shutdown_rx)
                 .map_err(|e| eprintln!("server error: {}", e));
@@ -107,14 +1003,20 @@ impl MessageReceiver {
             hyper::rt::run(server);
         });
 
-        MessageReceiver {
+        bound_rx
+            .recv()
+            .map_err(|_| anyhow!("The run-in-roblox server stopped unexpectedly"))?
+            .with_context(|| {
+                format!(
+                    "Could not listen on port {}, which might be in use by another program",
+                    port
+                )
+            })?;
+
+        Ok(MessageReceiver {
             shutdown_tx,
             message_rx,
-        }
-    }
-
-    pub fn recv(&self) -> Message {
-        self.message_rx.recv().unwrap()
+        })
     }
 
     pub fn recv_timeout(&self, timeout: Duration) -> Option<Message> {
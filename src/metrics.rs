@@ -0,0 +1,81 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+/// The number of samples of a metric, and their minimum, average, and maximum.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct MetricStats {
+    pub samples: usize,
+    pub min: f64,
+    pub avg: f64,
+    pub max: f64,
+}
+
+/// Samples of each metric reported by a `--probe` script over the course of a
+/// run.
+#[derive(Debug, Default)]
+pub struct MetricSeries {
+    samples: BTreeMap<String, Vec<(f64, f64)>>,
+}
+
+impl MetricSeries {
+    pub fn record<I>(&mut self, time: f64, values: I)
+    where
+        I: IntoIterator<Item = (String, f64)>,
+    {
+        for (name, value) in values {
+            self.samples.entry(name).or_default().push((time, value));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn stats(&self) -> BTreeMap<String, MetricStats> {
+        self.samples
+            .iter()
+            .map(|(name, samples)| {
+                let values = samples.iter().map(|(_, value)| *value);
+
+                let stats = MetricStats {
+                    samples: samples.len(),
+                    min: values.clone().fold(f64::INFINITY, f64::min),
+                    avg: values.clone().sum::<f64>() / samples.len() as f64,
+                    max: values.fold(f64::NEG_INFINITY, f64::max),
+                };
+
+                (name.clone(), stats)
+            })
+            .collect()
+    }
+
+    /// Formats a table with the number of samples and the minimum, average,
+    /// and maximum of each metric.
+    pub fn summary(&self) -> String {
+        let name_width = self.samples.keys().map(String::len).max().unwrap_or(0);
+        let mut lines = vec![format!(
+            "{:width$}  {:>7}  {:>12}  {:>12}  {:>12}",
+            "metric",
+            "samples",
+            "min",
+            "avg",
+            "max",
+            width = name_width
+        )];
+
+        for (name, stats) in self.stats() {
+            lines.push(format!(
+                "{:width$}  {:>7}  {:>12.3}  {:>12.3}  {:>12.3}",
+                name,
+                stats.samples,
+                stats.min,
+                stats.avg,
+                stats.max,
+                width = name_width
+            ));
+        }
+
+        lines.join("\n")
+    }
+}
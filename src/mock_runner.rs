@@ -0,0 +1,150 @@
+use std::time::Instant;
+
+use regex::Regex;
+
+use crate::{
+    message_receiver::{OutputLevel, RobloxMessage},
+    place_runner::KillOnDrop,
+    runner::{LifecycleEvent, MessageSender, ScriptRunner, TimedMessage},
+};
+
+/// Pretends to run scripts, without Roblox Studio or any other runtime.
+///
+/// Calls to `print`, `warn`, `error`, `rir.debug`, `rir.trace`, `rir.pass`,
+/// and `rir.fail` with string literal arguments are reported in the order they
+/// appear, along with calls to `rir.exit` with a number, stopping at the first
+/// `error` or `rir.exit`. This is enough to exercise run-in-roblox's own output
+/// handling, like summaries, `--triage`, and `--quarantine`, offline.
+pub struct MockRunner {
+    pub lua_script: String,
+}
+
+impl ScriptRunner for MockRunner {
+    fn run(&self, sender: MessageSender) -> Result<Option<KillOnDrop>, anyhow::Error> {
+        sender.lifecycle(LifecycleEvent::ScriptStarted)?;
+
+        for message in mock_messages(&self.lua_script) {
+            let is_last = matches!(
+                message,
+                RobloxMessage::Output {
+                    level: OutputLevel::Error,
+                    ..
+                } | RobloxMessage::ExitCode { .. }
+            );
+
+            sender.send(TimedMessage {
+                at: Instant::now(),
+                script: None,
+                context: None,
+                message,
+            })?;
+
+            if is_last {
+                break;
+            }
+        }
+
+        sender.lifecycle(LifecycleEvent::ScriptFinished)?;
+
+        Ok(None)
+    }
+}
+
+fn mock_messages(source: &str) -> Vec<RobloxMessage> {
+    let string = r#"("(?:[^"\\]|\\.)*"|'(?:[^'\\]|\\.)*')"#;
+    let call = Regex::new(&format!(
+        r"\b(?:(print|warn|error|rir\.debug|rir\.trace|rir\.pass|rir\.fail)\s*\(\s*{}(?:\s*,\s*{})?|rir\.exit\s*\(\s*(\d+))",
+        string, string
+    ))
+    .unwrap();
+
+    call.captures_iter(source)
+        .map(|captures| {
+            if let Some(code) = captures.get(4) {
+                return RobloxMessage::ExitCode {
+                    code: code.as_str().parse().unwrap_or(255),
+                };
+            }
+
+            let first = unquote(&captures[2]);
+            let second = captures.get(3).map(|second| unquote(second.as_str()));
+
+            match &captures[1] {
+                "print" => RobloxMessage::Output {
+                    level: OutputLevel::Print,
+                    body: first,
+                    source: None,
+                    line: None,
+                    from_script: Some(true),
+                    history: false,
+                },
+                "warn" => RobloxMessage::Output {
+                    level: OutputLevel::Warning,
+                    body: first,
+                    source: None,
+                    line: None,
+                    from_script: Some(true),
+                    history: false,
+                },
+                "error" => RobloxMessage::Output {
+                    level: OutputLevel::Error,
+                    body: first,
+                    source: None,
+                    line: None,
+                    from_script: Some(true),
+                    history: false,
+                },
+                "rir.debug" => RobloxMessage::Output {
+                    level: OutputLevel::Debug,
+                    body: first,
+                    source: None,
+                    line: None,
+                    from_script: Some(true),
+                    history: false,
+                },
+                "rir.trace" => RobloxMessage::Output {
+                    level: OutputLevel::Trace,
+                    body: first,
+                    source: None,
+                    line: None,
+                    from_script: Some(true),
+                    history: false,
+                },
+                "rir.pass" => RobloxMessage::Check {
+                    name: first,
+                    passed: true,
+                    reason: None,
+                },
+                _ => RobloxMessage::Check {
+                    name: first,
+                    passed: false,
+                    reason: second,
+                },
+            }
+        })
+        .collect()
+}
+
+/// Turns a quoted Lua string literal into its contents, handling the common
+/// escape sequences.
+fn unquote(literal: &str) -> String {
+    let inner = &literal[1..literal.len() - 1];
+    let mut output = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => output.push('\n'),
+            Some('t') => output.push('\t'),
+            Some(other) => output.push(other),
+            None => {}
+        }
+    }
+
+    output
+}
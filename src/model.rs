@@ -0,0 +1,212 @@
+use std::{
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{bail, Context};
+use fs_err as fs;
+use rbx_dom_weak::{types::Ref, InstanceBuilder, WeakDom};
+use rbx_types::Variant;
+
+use crate::verify_paths;
+
+/// A model file to insert into the place at a given instance path, given on
+/// the command line as `--model lib.rbxm@ReplicatedStorage`.
+#[derive(Debug, Clone)]
+pub struct ModelInsert {
+    pub source: PathBuf,
+    pub target: String,
+}
+
+impl FromStr for ModelInsert {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (source, target) = match value.rfind('@') {
+            Some(index) => (&value[..index], &value[index + 1..]),
+            None => bail!("Invalid model {:?}, expected <path>@<instance path>", value),
+        };
+
+        if source.is_empty() || target.is_empty() {
+            bail!("Invalid model {:?}, expected <path>@<instance path>", value);
+        }
+
+        Ok(ModelInsert {
+            source: PathBuf::from(source),
+            target: target.to_owned(),
+        })
+    }
+}
+
+/// Whether a place or model file is binary or XML, picked by its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileFormat {
+    Binary,
+    Xml,
+}
+
+impl FileFormat {
+    fn from_path(path: &Path) -> Option<Self> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("rbxl") | Some("rbxm") => Some(FileFormat::Binary),
+            Some("rbxlx") | Some("rbxmx") => Some(FileFormat::Xml),
+            _ => None,
+        }
+    }
+}
+
+fn read_file(path: &Path) -> Result<WeakDom, anyhow::Error> {
+    let format = FileFormat::from_path(path).with_context(|| {
+        format!(
+            "{} isn't an .rbxl, .rbxlx, .rbxm, or .rbxmx file",
+            path.display()
+        )
+    })?;
+    let reader = BufReader::new(fs::File::open(path)?);
+
+    let dom = match format {
+        FileFormat::Binary => rbx_binary::from_reader(reader)?,
+        FileFormat::Xml => rbx_xml::from_reader_default(reader)?,
+    };
+
+    Ok(dom)
+}
+
+fn write_file(dom: &WeakDom, path: &Path) -> Result<(), anyhow::Error> {
+    let output = BufWriter::new(fs::File::create(path)?);
+    let top = dom.root().children();
+
+    match FileFormat::from_path(path) {
+        Some(FileFormat::Xml) => rbx_xml::to_writer_default(output, dom, top)?,
+        _ => rbx_binary::to_writer(output, dom, top)?,
+    }
+
+    Ok(())
+}
+
+/// Inserts each of `models` into the place at `place_path`, which is
+/// rewritten in the same format. The instances at the top of each model
+/// become children of its target, which is created as a Folder if the place
+/// doesn't have it yet.
+pub fn insert_models(place_path: &Path, models: &[ModelInsert]) -> Result<(), anyhow::Error> {
+    if models.is_empty() {
+        return Ok(());
+    }
+
+    let mut place = read_file(place_path)
+        .with_context(|| format!("Could not read the place {}", place_path.display()))?;
+
+    for model in models {
+        let mut model_dom = read_file(&model.source)
+            .with_context(|| format!("Could not read the model {}", model.source.display()))?;
+        let parent = find_or_create(&mut place, &model.target)?;
+        let top = model_dom.root().children().to_vec();
+
+        for referent in top {
+            model_dom.transfer(referent, &mut place, parent);
+        }
+    }
+
+    write_file(&place, place_path)
+        .with_context(|| format!("Could not write the place {}", place_path.display()))
+}
+
+/// Finds the instance at a path like `ReplicatedStorage.Packages`, where the
+/// first part is a service, creating the service and any Folders on the way
+/// that are missing.
+fn find_or_create(place: &mut WeakDom, target: &str) -> Result<Ref, anyhow::Error> {
+    let mut segments = target.split('.');
+    let service = segments.next().unwrap_or_default();
+
+    if !verify_paths::is_service(service) {
+        bail!(
+            "Can't insert a model at {}, since {} isn't a service",
+            target,
+            service
+        );
+    }
+
+    let mut parent = find_child(place, place.root_ref(), service)
+        .unwrap_or_else(|| place.insert(place.root_ref(), InstanceBuilder::new(service)));
+
+    for segment in segments {
+        parent = match find_child(place, parent, segment) {
+            Some(child) => child,
+            None => place.insert(parent, InstanceBuilder::new("Folder").with_name(segment)),
+        };
+    }
+
+    Ok(parent)
+}
+
+fn find_child(place: &WeakDom, parent: Ref, name: &str) -> Option<Ref> {
+    place
+        .get_by_ref(parent)?
+        .children()
+        .iter()
+        .copied()
+        .find(|child| {
+            place
+                .get_by_ref(*child)
+                .map(|instance| instance.name == name)
+                .unwrap_or(false)
+        })
+}
+
+/// The instances inside a ModuleScript model given with --script, which are
+/// kept inside the script when it's run.
+#[derive(Debug)]
+pub struct ScriptModel {
+    /// Which of the scripts given with --script this is, counting from 0.
+    pub index: usize,
+
+    /// A Folder holding the ModuleScript's children.
+    pub children: WeakDom,
+}
+
+/// Whether a path given with --script is a model rather than a Lua file.
+pub fn is_script_model(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|extension| extension.to_str()),
+        Some("rbxm") | Some("rbxmx")
+    )
+}
+
+/// Reads a model given with --script, which should hold one ModuleScript,
+/// returning the script's source and the instances inside it.
+pub fn load_script_model(
+    path: &Path,
+    index: usize,
+) -> Result<(String, ScriptModel), anyhow::Error> {
+    let mut dom =
+        read_file(path).with_context(|| format!("Could not read the model {}", path.display()))?;
+
+    let module_ref = match dom.root().children() {
+        [module_ref] => *module_ref,
+        _ => bail!("{} must hold a single ModuleScript to run", path.display()),
+    };
+    let module = dom.get_by_ref(module_ref).unwrap();
+
+    if module.class != "ModuleScript" {
+        bail!(
+            "{} must hold a single ModuleScript to run, but it holds a {}",
+            path.display(),
+            module.class
+        );
+    }
+
+    let source = match module.properties.get("Source") {
+        Some(Variant::String(source)) => source.clone(),
+        _ => String::new(),
+    };
+
+    let mut children = WeakDom::new(InstanceBuilder::new("Folder"));
+    let children_root = children.root_ref();
+
+    for child in module.children().to_vec() {
+        dom.transfer(child, &mut children, children_root);
+    }
+
+    Ok((source, ScriptModel { index, children }))
+}
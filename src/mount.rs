@@ -0,0 +1,157 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{bail, Context};
+use fs_err as fs;
+use rbx_dom_weak::{InstanceBuilder, WeakDom};
+use rbx_types::Variant;
+
+use crate::{ignore::IgnoreRules, verify_paths::is_service};
+
+/// A folder on disk to insert into the place at a given instance path, given
+/// on the command line as `--mount src/=ReplicatedStorage.MyLib`.
+#[derive(Debug, Clone)]
+pub struct Mount {
+    pub source: PathBuf,
+    pub target: String,
+}
+
+impl FromStr for Mount {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (source, target) = match value.rfind('=') {
+            Some(index) => (&value[..index], &value[index + 1..]),
+            None => bail!("Invalid mount {:?}, expected <path>=<instance path>", value),
+        };
+
+        if source.is_empty() || target.split('.').any(str::is_empty) {
+            bail!("Invalid mount {:?}, expected <path>=<instance path>", value);
+        }
+
+        // The plugin finds the first instance in the path with GetService.
+        let service = target.split('.').next().unwrap_or_default();
+        if !is_service(service) {
+            bail!(
+                "Invalid mount {:?}, {:?} is not a service. Mount paths start with one, \
+                 like ReplicatedStorage.MyLib",
+                value,
+                service
+            );
+        }
+
+        Ok(Mount {
+            source: PathBuf::from(source),
+            target: target.to_owned(),
+        })
+    }
+}
+
+impl Mount {
+    pub fn load(&self) -> Result<LoadedMount, anyhow::Error> {
+        let root = load_path(&self.source)?
+            .with_context(|| format!("Nothing to mount at {}", self.source.display()))?;
+
+        Ok(LoadedMount {
+            target: self.target.clone(),
+            tree: WeakDom::new(root),
+        })
+    }
+}
+
+/// The instances built from a mount, ready to be sent to Studio.
+#[derive(Debug)]
+pub struct LoadedMount {
+    pub target: String,
+    pub tree: WeakDom,
+}
+
+/// Turns a file or folder into instances, following the same conventions as
+/// Rojo: `init.lua` turns its folder into a script, and `.server.lua` and
+/// `.client.lua` files become Scripts and LocalScripts. Files that aren't Lua
+/// are skipped, as are paths matched by `.gitignore` or `.rirignore` files.
+pub fn load_path(path: &Path) -> Result<Option<InstanceBuilder>, anyhow::Error> {
+    let rules = match path.parent() {
+        Some(parent) => IgnoreRules::for_dir(parent),
+        None => IgnoreRules::default(),
+    };
+
+    load_entry(path, &rules)
+}
+
+fn load_entry(path: &Path, rules: &IgnoreRules) -> Result<Option<InstanceBuilder>, anyhow::Error> {
+    if path.is_dir() {
+        return load_dir(path, &rules.with_dir(path)).map(Some);
+    }
+
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .with_context(|| format!("Path had invalid Unicode: {}", path.display()))?;
+
+    let (name, class) = match script_kind(file_name) {
+        Some(kind) => kind,
+        None => return Ok(None),
+    };
+
+    let source = fs::read_to_string(path)?;
+
+    Ok(Some(
+        InstanceBuilder::new(class)
+            .with_name(name)
+            .with_property("Source", Variant::String(source)),
+    ))
+}
+
+fn load_dir(path: &Path, rules: &IgnoreRules) -> Result<InstanceBuilder, anyhow::Error> {
+    let dir_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .with_context(|| format!("Path had invalid Unicode: {}", path.display()))?;
+
+    let mut entries = fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+
+    let mut instance = InstanceBuilder::new("Folder").with_name(dir_name);
+
+    for entry in entries {
+        if rules.is_ignored(&entry, entry.is_dir()) {
+            continue;
+        }
+
+        let file_name = entry.file_name().and_then(|name| name.to_str());
+
+        if let Some((name, class)) = file_name.and_then(script_kind) {
+            if name == "init" && entry.is_file() {
+                instance.set_class(class);
+                instance.add_property("Source", Variant::String(fs::read_to_string(&entry)?));
+                continue;
+            }
+        }
+
+        if let Some(child) = load_entry(&entry, rules)? {
+            instance.add_child(child);
+        }
+    }
+
+    Ok(instance)
+}
+
+/// Returns the instance name and ClassName for a Lua file name.
+fn script_kind(file_name: &str) -> Option<(&str, &'static str)> {
+    let stem = file_name
+        .strip_suffix(".lua")
+        .or_else(|| file_name.strip_suffix(".luau"))?;
+
+    if let Some(name) = stem.strip_suffix(".server") {
+        Some((name, "Script"))
+    } else if let Some(name) = stem.strip_suffix(".client") {
+        Some((name, "LocalScript"))
+    } else {
+        Some((stem, "ModuleScript"))
+    }
+}
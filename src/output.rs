@@ -0,0 +1,241 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{anyhow, bail};
+use serde::Serialize;
+
+use crate::{
+    message_receiver::{PlayContext, RobloxMessage},
+    runner::LifecycleEvent,
+};
+
+/// Versions of the machine-readable output formats that can still be emitted.
+/// New versions are added when a format changes in a way that could break
+/// existing parsers.
+pub const FORMAT_VERSIONS: &[u32] = &[1];
+
+/// A version of run-in-roblox's machine-readable formats, chosen with
+/// `--format-version`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatVersion(pub u32);
+
+impl FormatVersion {
+    pub fn latest() -> Self {
+        FormatVersion(*FORMAT_VERSIONS.last().unwrap())
+    }
+}
+
+impl Default for FormatVersion {
+    fn default() -> Self {
+        FormatVersion::latest()
+    }
+}
+
+impl fmt::Display for FormatVersion {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{}", self.0)
+    }
+}
+
+impl FromStr for FormatVersion {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let version: u32 = value
+            .parse()
+            .map_err(|_| anyhow!("Invalid format version {:?}, expected a number", value))?;
+
+        if !FORMAT_VERSIONS.contains(&version) {
+            bail!(
+                "Unknown format version {}, supported versions are {:?}",
+                version,
+                FORMAT_VERSIONS
+            );
+        }
+
+        Ok(FormatVersion(version))
+    }
+}
+
+/// How script output is printed, chosen with `--output`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Colored text for people to read.
+    Text,
+
+    /// One JSON object per line for tools to parse. See `run-in-roblox schema
+    /// output`.
+    Json,
+
+    /// Like `Json`, with extra lines for each step of the run, like Studio
+    /// launching and the script starting.
+    Ndjson,
+
+    /// Like `Text`, except that warnings and errors are printed as GitHub
+    /// Actions workflow commands, so they show up as annotations.
+    Github,
+}
+
+impl OutputFormat {
+    /// Whether output is printed as JSON instead of text.
+    pub fn is_json(self) -> bool {
+        self == OutputFormat::Json || self == OutputFormat::Ndjson
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            "github" => Ok(OutputFormat::Github),
+            _ => bail!(
+                "Invalid value for --output, expected \"text\", \"json\", \"ndjson\", or \
+                 \"github\""
+            ),
+        }
+    }
+}
+
+/// A message from the script, printed as a line of `--output json`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JsonMessage<'a> {
+    pub format_version: u32,
+
+    /// Seconds since the run started.
+    pub timestamp: f64,
+
+    /// Whether this is an error or failed check that --quarantine excused.
+    #[serde(skip_serializing_if = "is_false")]
+    pub quarantined: bool,
+
+    /// The index of the script the message came from, when scripts are run
+    /// with --parallel-scripts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub script: Option<usize>,
+
+    /// Which side of the play test the message came from, with --mode play.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<PlayContext>,
+
+    #[serde(flatten)]
+    pub message: &'a RobloxMessage,
+}
+
+/// A step of the run, printed as a line of `--output ndjson`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename = "Lifecycle", rename_all = "camelCase")]
+pub struct JsonLifecycle {
+    pub format_version: u32,
+
+    /// Seconds since the run started.
+    pub timestamp: f64,
+
+    pub event: LifecycleEvent,
+}
+
+/// The last line of `--output json`, printed once the script has finished.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename = "Summary", rename_all = "camelCase")]
+pub struct JsonSummary {
+    pub format_version: u32,
+    pub checks_passed: usize,
+    pub checks_failed: usize,
+    pub quarantined_failures: usize,
+
+    /// Whether the run was skipped because --cache-results found a recorded
+    /// pass, whose checks are counted here.
+    #[serde(skip_serializing_if = "is_false")]
+    pub cached: bool,
+
+    /// The scripts whose results don't count, and why.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<SkippedScript>,
+}
+
+/// A script whose result doesn't count toward the run's, so that tools can
+/// tell it apart from one that passed.
+#[derive(Debug, Clone, Serialize)]
+pub struct SkippedScript {
+    /// The script as it was given with --script.
+    pub script: String,
+    pub reason: SkipReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SkipReason {
+    /// The script wasn't run, because --cache-results found a recorded pass.
+    Cached,
+
+    /// The script ran, but --quarantine excuses its failures.
+    Quarantined,
+}
+
+/// Formats a GitHub Actions workflow command, like `::error file=a.lua,line=3::
+/// message`, that annotates the file and line it's given.
+pub fn github_command(command: &str, location: Option<(&str, usize)>, message: &str) -> String {
+    let properties = match location {
+        Some((file, line)) => format!(" file={},line={}", escape_github_property(file), line),
+        None => String::new(),
+    };
+
+    format!(
+        "::{}{}::{}",
+        command,
+        properties,
+        escape_github_data(message)
+    )
+}
+
+fn escape_github_data(value: &str) -> String {
+    value
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn escape_github_property(value: &str) -> String {
+    escape_github_data(value)
+        .replace(':', "%3A")
+        .replace(',', "%2C")
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+/// The line ending that multi-line output is normalized to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NewlineStyle {
+    Lf,
+    CrLf,
+}
+
+impl FromStr for NewlineStyle {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "lf" => Ok(NewlineStyle::Lf),
+            "crlf" => Ok(NewlineStyle::CrLf),
+            _ => bail!("Invalid value for --newline, expected \"lf\" or \"crlf\""),
+        }
+    }
+}
+
+/// Rewrites every line ending in `body`, including lone carriage returns, to
+/// the given style.
+pub fn normalize_newlines(body: &str, style: NewlineStyle) -> String {
+    let newline = match style {
+        NewlineStyle::Lf => "\n",
+        NewlineStyle::CrLf => "\r\n",
+    };
+
+    body.replace("\r\n", "\n")
+        .replace('\r', "\n")
+        .replace('\n', newline)
+}
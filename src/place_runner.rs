@@ -1,94 +1,1170 @@
 use std::{
-    path::PathBuf,
-    process::{self, Command, Stdio},
-    sync::mpsc,
-    time::Duration,
+    env, io,
+    path::{Path, PathBuf},
+    process::{self, Command, ExitStatus},
+    thread,
+    time::{Duration, Instant, SystemTime},
 };
 
-use anyhow::{anyhow, bail};
+use anyhow::{anyhow, bail, Context};
 use fs_err as fs;
 use fs_err::File;
+use regex::Regex;
 
 use crate::{
-    message_receiver::{Message, MessageReceiver, MessageReceiverOptions, RobloxMessage},
-    plugin::RunInRbxPlugin,
+    baseplate,
+    budget::Budget,
+    bundle::BundledModule,
+    chaos::ChaosHttp,
+    clean::SessionMarker,
+    crash_dumps,
+    message_receiver::{
+        CommandQueue, HostCommand, Message, MessageReceiver, MessageReceiverOptions, OutputLevel,
+        RobloxMessage,
+    },
+    model::{self, ModelInsert, ScriptModel},
+    mount::LoadedMount,
+    output::FormatVersion,
+    plugin::{Capture, InjectMethod, Isolation, RunInRbxPlugin, StudioMode},
+    port_registry::{self, LockFile},
+    runner::{LifecycleEvent, MessageSender, ScriptRunner, TimedMessage},
+    severity::SeverityMap,
+    snapshot::{SnapshotBuilder, SnapshotFormat, SnapshotOptions},
+    studio_settings::AutoRecoverySetting,
+    transcript::{self, EntryKind},
+    triage::{FailureCategory, RunFailure},
+    usage,
 };
 
-/// A wrapper for process::Child that force-kills the process on drop.
-struct KillOnDrop(process::Child);
+/// The port run-in-roblox listens on for the plugin, unless told otherwise.
+pub const DEFAULT_PORT: u16 = 50312;
+
+/// How long the plugin can take to pick up a command when it has to ask for
+/// them instead of having them streamed to it.
+const COMMAND_PICKUP_TIME: Duration = Duration::from_millis(500);
+
+/// Studio loads every plugin in its plugins folder when it starts, so runs
+/// using the same plugins folder, in this process or any other, take turns
+/// starting up. Otherwise, a Studio could pick up another run's plugin and run
+/// its script as well. Each run holds this lock file in its plugins folder from
+/// installing its plugin until the plugin has started, when the plugin is
+/// removed.
+const STARTUP_LOCK_NAME: &str = "run_in_roblox-startup.lock";
+
+/// How often the message loop wakes up to check timeouts.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long Studio has to start up and run the plugin.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often we check on Studio while waiting for the plugin to start.
+const STARTUP_SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How little CPU time Studio can use between checks for us to think it's
+/// sitting idle.
+const IDLE_CPU_TIME: Duration = Duration::from_millis(500);
+
+/// Names of the files that Studio's own output and exit status are written
+/// to, separate from the script's output.
+const STUDIO_STDOUT_NAME: &str = "studio-stdout.log";
+const STUDIO_STDERR_NAME: &str = "studio-stderr.log";
+const STUDIO_STATUS_NAME: &str = "studio-exit-status.txt";
+
+/// How many lines from the end of Studio's stderr to include when it exits
+/// unexpectedly.
+const STDERR_TAIL_LINES: usize = 20;
+
+/// Text in Studio's output that means it couldn't open the place, usually
+/// because of a bad copy or because something else had the file open, along
+/// with a description for the warning we print. Matched case-insensitively.
+const PLACE_OPEN_ERRORS: &[(&str, &str)] = &[
+    ("corrupt", "the place copy was corrupt"),
+    ("failed to load place", "the place copy couldn't be loaded"),
+    (
+        "being used by another process",
+        "another program, like antivirus software, had the place copy open",
+    ),
+    (
+        "access is denied",
+        "another program, like antivirus software, blocked access to the place copy",
+    ),
+    (
+        "permission denied",
+        "another program, like antivirus software, blocked access to the place copy",
+    ),
+];
+
+/// A wrapper for process::Child that force-kills the process on drop, unless
+/// it has been detached. The process's exit status is written to
+/// `status_path` once it's known.
+pub struct KillOnDrop {
+    child: process::Child,
+    detached: bool,
+    status_path: Option<PathBuf>,
+
+    /// Where to report the process exiting while the run is still going.
+    exit_sender: Option<MessageSender>,
+
+    /// Studio's auto-recovery setting from before it started, put back once
+    /// it has exited.
+    auto_recovery: Option<AutoRecoverySetting>,
+
+    /// How to reach the plugin after the run, for `--on-failure inspect`.
+    inspector: Option<Inspector>,
+}
+
+impl KillOnDrop {
+    fn new(child: process::Child, status_path: Option<PathBuf>) -> Self {
+        KillOnDrop {
+            child,
+            detached: false,
+            status_path,
+            exit_sender: None,
+            auto_recovery: None,
+            inspector: None,
+        }
+    }
+
+    /// Leaves the process running once we're done with it.
+    pub fn detach(mut self) {
+        self.detached = true;
+    }
+
+    fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Asks the plugin for the subtree at `root`, like `game` or
+    /// `Workspace.Model`, and writes it to `path`, returning how many
+    /// instances it had. Only works with `--on-failure inspect`.
+    pub fn save_snapshot(&self, root: &str, path: &Path) -> Result<usize, anyhow::Error> {
+        match &self.inspector {
+            Some(inspector) => inspector.save_snapshot(root, path),
+            None => {
+                bail!("Roblox Studio can only be asked for snapshots with --on-failure inspect")
+            }
+        }
+    }
+
+    /// How much CPU time the process has used so far, if it can be measured.
+    pub fn cpu_time(&self) -> Option<Duration> {
+        usage::process_cpu_time(self.id())
+    }
+
+    fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        let status = self.child.try_wait()?;
+
+        if let Some(status) = status {
+            self.record_status(&status.to_string());
+        }
+
+        Ok(status)
+    }
+
+    fn record_status(&self, status: &str) {
+        transcript::record(
+            EntryKind::Lifecycle,
+            None,
+            format!("Roblox Studio exited: {}", status),
+        );
+
+        if let Some(sender) = &self.exit_sender {
+            let _ignored = sender.lifecycle(LifecycleEvent::StudioExited);
+        }
+
+        if let Some(status_path) = &self.status_path {
+            if let Err(err) = fs::write(status_path, format!("{}\n", status)) {
+                log::debug!("Could not record exit status: {}", err);
+            }
+        }
+    }
+}
 
 impl Drop for KillOnDrop {
     fn drop(&mut self) {
-        let _ignored = self.0.kill();
+        if self.detached {
+            return;
+        }
+
+        if let Some(inspector) = &mut self.inspector {
+            inspector.abandon();
+        }
+
+        if let Ok(None) = self.child.try_wait() {
+            let _ignored = self.child.kill();
+
+            if let Ok(status) = self.child.wait() {
+                self.record_status(&format!("{}, killed by run-in-roblox", status));
+            }
+        }
+
+        if let Some(auto_recovery) = &self.auto_recovery {
+            auto_recovery.restore();
+        }
     }
 }
 
+/// Keeps talking to the plugin after a run for `--on-failure inspect`, which
+/// leaves it waiting for commands until it's cancelled.
+struct Inspector {
+    message_receiver: Option<MessageReceiver>,
+    commands: CommandQueue,
+    timeout: Duration,
+}
+
+impl Inspector {
+    fn save_snapshot(&self, root: &str, path: &Path) -> Result<usize, anyhow::Error> {
+        SnapshotFormat::from_path(path)?;
+
+        let message_receiver = match &self.message_receiver {
+            Some(message_receiver) => message_receiver,
+            None => bail!("Roblox Studio has stopped listening"),
+        };
+        self.commands.push(HostCommand::Snapshot {
+            root: root.to_owned(),
+        });
+
+        let mut snapshot = SnapshotBuilder::default();
+        let mut last_heard = Instant::now();
+
+        loop {
+            if last_heard.elapsed() >= self.timeout {
+                bail!("Roblox Studio didn't send the snapshot");
+            }
+
+            let stamped_messages = match message_receiver.recv_timeout(POLL_INTERVAL) {
+                Some(Message::Messages(stamped_messages)) => stamped_messages,
+                Some(_) | None => continue,
+            };
+
+            last_heard = Instant::now();
+
+            for stamped in stamped_messages {
+                match stamped.message {
+                    RobloxMessage::SnapshotInstance {
+                        id,
+                        parent,
+                        class_name,
+                        name,
+                        properties,
+                    } => snapshot.add(id, parent, &class_name, &name, &properties)?,
+                    RobloxMessage::SnapshotEnd { .. } => {
+                        snapshot.write(path).with_context(|| {
+                            format!("Could not write the snapshot to {}", path.display())
+                        })?;
+
+                        return Ok(snapshot.len());
+                    }
+                    RobloxMessage::Output {
+                        level: OutputLevel::Error,
+                        body,
+                        ..
+                    } => bail!("{}", body),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Stops listening without telling the plugin, for when Studio is about
+    /// to be closed anyway.
+    fn abandon(&mut self) {
+        if let Some(message_receiver) = self.message_receiver.take() {
+            message_receiver.stop();
+        }
+    }
+}
+
+impl Drop for Inspector {
+    fn drop(&mut self) {
+        if let Some(message_receiver) = self.message_receiver.take() {
+            self.commands.push(HostCommand::Cancel);
+
+            // A plugin without a WebSocket has to ask for the command before
+            // the server goes away.
+            thread::sleep(COMMAND_PICKUP_TIME);
+            message_receiver.stop();
+        }
+    }
+}
+
+/// Removes a file when dropped, so that it's cleaned up however the run ends.
+struct RemoveOnDrop(PathBuf);
+
+impl Drop for RemoveOnDrop {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.0) {
+            log::warn!("{}", err);
+        }
+    }
+}
+
+/// What could be seen of Studio while waiting for the plugin to start the
+/// script, used to explain startup timeouts.
+#[derive(Debug, Default)]
+struct StartupObservations {
+    plugin_connected: bool,
+    samples: Vec<StudioSample>,
+}
+
+#[derive(Debug)]
+struct StudioSample {
+    /// How long after launching Studio the sample was taken.
+    at: Duration,
+    cpu_time: Option<Duration>,
+    window_title: Option<String>,
+}
+
+impl StartupObservations {
+    fn sample(&mut self, studio_process: &KillOnDrop, launched: Instant) {
+        let sample = StudioSample {
+            at: launched.elapsed(),
+            cpu_time: studio_process.cpu_time(),
+            window_title: usage::process_window_title(studio_process.id()),
+        };
+
+        log::debug!("Waiting for Roblox Studio: {:?}", sample);
+        self.samples.push(sample);
+    }
+
+    fn describe(&self, waited: Duration) -> String {
+        let mut lines = Vec::new();
+
+        if self.plugin_connected {
+            lines.push(format!(
+                "Roblox Studio was still running after {}s. The plugin connected, but never \
+                 started the script.",
+                waited.as_secs()
+            ));
+        } else {
+            lines.push(format!(
+                "Roblox Studio was still running after {}s, but the plugin never connected.",
+                waited.as_secs()
+            ));
+        }
+
+        let last = match self.samples.last() {
+            Some(last) => last,
+            None => return lines.join("\n"),
+        };
+
+        if let Some(cpu_time) = last.cpu_time {
+            lines.push(format!(
+                "Studio had used {:.1}s of CPU time.",
+                cpu_time.as_secs_f64()
+            ));
+        }
+
+        // Studio uses plenty of CPU time while it's loading, so if it stops,
+        // it's probably showing something and waiting for someone to click it.
+        let idle_since = self
+            .samples
+            .iter()
+            .rev()
+            .take_while(|sample| match (sample.cpu_time, last.cpu_time) {
+                (Some(cpu_time), Some(last_cpu_time)) => {
+                    last_cpu_time.saturating_sub(cpu_time) < IDLE_CPU_TIME
+                }
+                _ => false,
+            })
+            .last();
+
+        if let Some(idle_since) = idle_since {
+            let idle_for = last.at.saturating_sub(idle_since.at);
+
+            if idle_for >= STARTUP_SAMPLE_INTERVAL * 2 {
+                lines.push(format!(
+                    "It barely used the CPU in the last {}s, so it may be waiting on a dialog, \
+                     like a login prompt or an update notice.",
+                    idle_for.as_secs()
+                ));
+            }
+        }
+
+        if let Some(window_title) = &last.window_title {
+            lines.push(format!("Its window was titled {:?}.", window_title));
+        }
+
+        lines.join("\n")
+    }
+}
+
+/// Lines up the plugin's `os.clock()` with our clock, using the time the
+/// plugin reported when it started the script.
+struct ClockSync {
+    host_time: Instant,
+    plugin_clock: Option<f64>,
+}
+
+impl ClockSync {
+    fn to_host_time(&self, clock: Option<f64>) -> Instant {
+        let offset = match (clock, self.plugin_clock) {
+            (Some(clock), Some(plugin_clock)) => clock - plugin_clock,
+            _ => return Instant::now(),
+        };
+
+        if offset >= 0.0 {
+            self.host_time + Duration::from_secs_f64(offset)
+        } else {
+            self.host_time
+                .checked_sub(Duration::from_secs_f64(-offset))
+                .unwrap_or(self.host_time)
+        }
+    }
+}
+
+/// Runs scripts in Roblox Studio, the default backend.
 pub struct PlaceRunner {
     pub port: u16,
     pub place_path: PathBuf,
+
+    /// The place that `place_path` was copied from, if any, so that it can be
+    /// copied again. Otherwise `place_path` is an empty baseplate.
+    pub place_source: Option<PathBuf>,
+
     pub studio_app_path: PathBuf,
     pub studio_plugins_path: PathBuf,
+    pub inject_method: InjectMethod,
     pub server_id: String,
-    pub lua_script: String,
+    pub lua_scripts: Vec<String>,
+    pub parallel_scripts: usize,
+    pub isolation: Isolation,
+    pub modules: Vec<BundledModule>,
+
+    /// The instances inside the scripts given with --script as models.
+    pub script_models: Vec<ScriptModel>,
+    pub mounts: Vec<LoadedMount>,
+
+    /// Model files inserted into the place before Studio opens it.
+    pub models: Vec<ModelInsert>,
+
+    pub mode: StudioMode,
+    pub capture: Capture,
+    pub wait_for_done: bool,
+    pub compare_security: bool,
+    pub chaos_http: Option<ChaosHttp>,
+    pub forbid_network: bool,
+    pub read_only: bool,
+    pub budget: Option<Budget>,
+    pub probe: Option<String>,
+    pub probe_interval: Duration,
+    pub warmup: Option<String>,
+    pub duration: Option<Duration>,
+    pub flush_interval: Duration,
+    pub env: Vec<(String, String)>,
+    pub timeout: Option<Duration>,
+
+    /// How long the plugin can go without contacting us before we assume that
+    /// Studio has stopped running Lua, for --unresponsive-timeout.
+    pub unresponsive_timeout: Duration,
+
+    pub validate_output: bool,
+    pub severity: SeverityMap,
+    pub format_version: FormatVersion,
+    pub artifacts_dir: Option<PathBuf>,
+
+    /// Where `rir.writeFile()` writes files, for --out-dir.
+    pub out_dir: Option<PathBuf>,
+
+    /// The folders `rir.readFile()` can read files from, for --read-dir.
+    pub read_dirs: Vec<PathBuf>,
+
+    /// Commands to send to the plugin while the script runs, like cancelling
+    /// the run.
+    pub commands: CommandQueue,
+
+    /// Keeps Studio running after the scripts finish, for
+    /// `run-in-roblox repl`, until `HostCommand::Cancel` is sent.
+    pub repl: bool,
+
+    /// Where to write the instances the script leaves behind, for
+    /// --snapshot.
+    pub snapshot: Option<SnapshotOptions>,
+
+    /// Keeps the plugin listening after the run, so that snapshots can be
+    /// taken for `--on-failure inspect`.
+    pub inspect: bool,
 }
 
-impl PlaceRunner {
-    pub fn run(&self, sender: mpsc::Sender<Option<RobloxMessage>>) -> Result<(), anyhow::Error> {
-        let plugin_file_path = self
-            .studio_plugins_path
-            .join(format!("run_in_roblox-{}.rbxmx", self.port));
+impl ScriptRunner for PlaceRunner {
+    /// Runs the script in Roblox Studio, sending each message it produces to
+    /// `sender`. Studio is left running so that the caller can decide what to
+    /// do with it; it's closed when the returned process is dropped.
+    fn run(&self, sender: MessageSender) -> Result<Option<KillOnDrop>, anyhow::Error> {
+        let plugin_file_path = match self.inject_method {
+            InjectMethod::PluginsFolder => self
+                .studio_plugins_path
+                .join(format!("run_in_roblox-{}.rbxmx", self.port)),
+
+            // Studio only loads plugins from the plugins folder and doesn't
+            // offer launch flags for running a script in edit mode, so there's
+            // nothing to hand the plugin to yet.
+            InjectMethod::LocalPlugin => bail!(
+                "--inject-method local-plugin isn't supported by Roblox Studio yet, use \
+                 plugins-folder"
+            ),
+            InjectMethod::CommandLine => bail!(
+                "--inject-method command-line isn't supported by Roblox Studio yet, use \
+                 plugins-folder"
+            ),
+        };
+
+        // A new token for every run, so that a plugin left behind by an
+        // earlier one can't talk to this one either.
+        let token = new_token();
+        let _token_file = port_registry::write_token(self.port, &token);
 
         let plugin = RunInRbxPlugin {
             port: self.port,
             server_id: &self.server_id,
-            lua_script: &self.lua_script,
+            token: &token,
+            lua_scripts: &self.lua_scripts,
+            parallel_scripts: self.parallel_scripts,
+            isolation: self.isolation,
+            modules: &self.modules,
+            script_models: &self.script_models,
+            mounts: &self.mounts,
+            mode: self.mode,
+            capture: self.capture,
+            wait_for_done: self.wait_for_done,
+            compare_security: self.compare_security,
+            chaos_http: self.chaos_http,
+            forbid_network: self.forbid_network,
+            read_only: self.read_only,
+            budget: self.budget,
+            probe: self.probe.as_deref(),
+            probe_interval: self.probe_interval,
+            warmup: self.warmup.as_deref(),
+            duration: self.duration,
+            flush_interval: self.flush_interval,
+            env: &self.env,
+            repl: self.repl,
+            snapshot_root: self
+                .snapshot
+                .as_ref()
+                .map(|snapshot| snapshot.root.as_str()),
+            inspect: self.inspect,
         };
 
-        let plugin_file = File::create(&plugin_file_path)?;
-        plugin.write(plugin_file)?;
+        model::insert_models(&self.place_path, &self.models)?;
 
         let message_receiver = MessageReceiver::start(MessageReceiverOptions {
             port: self.port,
             server_id: self.server_id.to_owned(),
-        });
+            token: token.clone(),
+            validate: self.validate_output,
+            format_version: self.format_version,
+            severity: self.severity.clone(),
+            commands: self.commands.clone(),
+            out_dir: self.out_dir.clone(),
+            read_dirs: self.read_dirs.clone(),
+        })?;
 
-        let _studio_process = KillOnDrop(
-            Command::new(&self.studio_app_path)
-                .arg(format!("{}", self.place_path.display()))
-                .stdout(Stdio::null())
-                .stderr(Stdio::null())
-                .spawn()?,
+        let startup_guard =
+            LockFile::acquire(&self.studio_plugins_path.join(STARTUP_LOCK_NAME), None)?;
+
+        let plugin_file = File::create(&plugin_file_path).with_context(|| {
+            format!(
+                "Could not create the run-in-roblox plugin in the Studio plugins folder {}",
+                self.studio_plugins_path.display()
+            )
+        })?;
+        let plugin_file_guard = RemoveOnDrop(plugin_file_path.clone());
+
+        plugin.write(plugin_file).with_context(|| {
+            format!(
+                "Could not write the run-in-roblox plugin to {}",
+                plugin_file_path.display()
+            )
+        })?;
+
+        transcript::record(
+            EntryKind::Lifecycle,
+            None,
+            format!("Installed the plugin at {}", plugin_file_path.display()),
         );
 
-        let first_message = message_receiver
-            .recv_timeout(Duration::from_secs(60))
-            .ok_or_else(|| {
-                anyhow!("Timeout reached while waiting for Roblox Studio to come online")
-            })?;
+        let log_dir = self.log_dir();
+        fs::create_dir_all(&log_dir)?;
 
-        match first_message {
-            Message::Start => {}
-            _ => bail!("Invalid first message received from Roblox Studio plugin"),
-        }
+        let mut relaunched = false;
+
+        let (mut studio_process, started_at, first_message) = loop {
+            let started_at = SystemTime::now();
+            let mut studio_process = self.launch_studio(&sender, &log_dir, &plugin_file_path)?;
+
+            let err = match self.wait_for_plugin(
+                &mut studio_process,
+                started_at,
+                &message_receiver,
+                &sender,
+            ) {
+                Ok(first_message) => break (studio_process, started_at, first_message),
+                Err(err) => err,
+            };
+
+            // Studio sometimes can't open its copy of the place for reasons
+            // that have nothing to do with the place itself, so it gets one
+            // more try with a fresh copy.
+            let problem = match self.place_open_problem(&err, &log_dir) {
+                Some(problem) if !relaunched => problem,
+                _ => return Err(err),
+            };
+
+            log::warn!(
+                "Roblox Studio couldn't open the place because {}. Copying it again and \
+                 relaunching Studio.",
+                problem
+            );
+            transcript::record(
+                EntryKind::Lifecycle,
+                None,
+                format!("Relaunching Roblox Studio because {}", problem),
+            );
+
+            drop(studio_process);
+            self.recopy_place()?;
+
+            sender.lifecycle(LifecycleEvent::StudioRelaunched)?;
+            relaunched = true;
+        };
+
+        let clock_sync = match first_message {
+            Message::Start(plugin_clock) => {
+                transcript::record(EntryKind::Lifecycle, None, "The plugin started the script");
+                sender.lifecycle(LifecycleEvent::ScriptStarted)?;
+
+                ClockSync {
+                    host_time: Instant::now(),
+                    plugin_clock,
+                }
+            }
+            _ => bail!(RunFailure::new(
+                FailureCategory::Infra,
+                "Invalid first message received from Roblox Studio plugin",
+            )),
+        };
+
+        // Studio has loaded the plugin by now, so it can be removed to make way
+        // for the next session starting with the same plugins folder.
+        drop(plugin_file_guard);
+        drop(startup_guard);
+
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let mut last_heard = Instant::now();
+        let mut snapshot = SnapshotBuilder::default();
 
         loop {
-            match message_receiver.recv() {
-                Message::Start => {}
+            if let Some(deadline) = deadline {
+                if Instant::now() >= deadline {
+                    bail!(RunFailure::new(
+                        FailureCategory::Timeout,
+                        "Timeout reached while waiting for the script to finish",
+                    ));
+                }
+            }
+
+            self.check_studio_running(&mut studio_process, started_at)?;
+
+            if last_heard.elapsed() >= self.unresponsive_timeout {
+                bail!(RunFailure::new(
+                    FailureCategory::Timeout,
+                    format!(
+                        "Roblox Studio stopped responding for {} (breakpoint or long \
+                         synchronous work). Remove any breakpoints saved in the place, or \
+                         raise --unresponsive-timeout if the script blocks for longer.",
+                        humantime::format_duration(self.unresponsive_timeout)
+                    ),
+                ));
+            }
+
+            let message = match message_receiver.recv_timeout(POLL_INTERVAL) {
+                Some(message) => message,
+                None => continue,
+            };
+
+            last_heard = Instant::now();
+
+            match message {
+                Message::Connected | Message::Start(_) | Message::Alive => {}
                 Message::Stop => {
-                    sender.send(None)?;
+                    transcript::record(EntryKind::Lifecycle, None, "The plugin finished");
+                    sender.lifecycle(LifecycleEvent::ScriptFinished)?;
                     break;
                 }
                 Message::Messages(roblox_messages) => {
-                    for message in roblox_messages.into_iter() {
-                        sender.send(Some(message))?;
+                    for stamped in roblox_messages.into_iter() {
+                        let at = clock_sync.to_host_time(stamped.clock);
+
+                        // Snapshots and artifacts are written here rather
+                        // than handed on, since they can be as big as the
+                        // place.
+                        let message = match stamped.message {
+                            RobloxMessage::SnapshotInstance {
+                                id,
+                                parent,
+                                class_name,
+                                name,
+                                properties,
+                            } => {
+                                snapshot.add(id, parent, &class_name, &name, &properties)?;
+                                continue;
+                            }
+                            RobloxMessage::SnapshotEnd { .. } => match &self.snapshot {
+                                Some(options) => self.write_snapshot(&snapshot, options)?,
+                                None => continue,
+                            },
+                            RobloxMessage::Artifact { name, data } => {
+                                self.write_artifact(&name, &data)?
+                            }
+                            message => message,
+                        };
+
+                        sender.send(TimedMessage {
+                            at,
+                            script: stamped.script,
+                            context: stamped.context,
+                            message,
+                        })?;
                     }
                 }
+                Message::Invalid(errors) => {
+                    bail!(RunFailure::new(
+                        FailureCategory::OutputRule,
+                        format!(
+                            "Roblox Studio sent messages that don't match the protocol \
+                             schema:\n{}",
+                            errors.join("\n")
+                        ),
+                    ));
+                }
+            }
+        }
+
+        if self.inspect {
+            studio_process.inspector = Some(Inspector {
+                message_receiver: Some(message_receiver),
+                commands: self.commands.clone(),
+                timeout: self.unresponsive_timeout,
+            });
+        } else {
+            message_receiver.stop();
+        }
+
+        remove_recovery_files(&self.place_path);
+
+        // Whoever closes Studio from here on reports it exiting.
+        studio_process.exit_sender = None;
+
+        Ok(Some(studio_process))
+    }
+}
+
+impl PlaceRunner {
+    /// Writes the snapshot for --snapshot, returning a message saying where it
+    /// went.
+    fn write_snapshot(
+        &self,
+        snapshot: &SnapshotBuilder,
+        options: &SnapshotOptions,
+    ) -> Result<RobloxMessage, anyhow::Error> {
+        snapshot.write(&options.path).with_context(|| {
+            format!("Could not write the snapshot to {}", options.path.display())
+        })?;
+
+        transcript::record(
+            EntryKind::Lifecycle,
+            None,
+            format!("Wrote the snapshot to {}", options.path.display()),
+        );
+
+        Ok(RobloxMessage::Output {
+            level: OutputLevel::Info,
+            body: format!(
+                "Wrote a snapshot of {} with {} instances to {}",
+                options.root,
+                snapshot.len(),
+                options.path.display()
+            ),
+            source: None,
+            line: None,
+            from_script: None,
+            history: false,
+        })
+    }
+
+    /// Saves a file the script sent with `rir.artifact()` in the artifacts
+    /// directory, returning a message saying where it went.
+    fn write_artifact(&self, name: &str, data: &str) -> Result<RobloxMessage, anyhow::Error> {
+        let warning = |body: String| {
+            Ok(RobloxMessage::Output {
+                level: OutputLevel::Warning,
+                body,
+                source: None,
+                line: None,
+                from_script: None,
+                history: false,
+            })
+        };
+
+        let artifacts_dir = match &self.artifacts_dir {
+            Some(artifacts_dir) => artifacts_dir,
+            None => {
+                return warning(format!(
+                    "The script saved the artifact {}, but it was thrown away. Pass \
+                     --artifacts-dir to keep it.",
+                    name
+                ))
             }
+        };
+
+        // The name comes from the script, so it mustn't lead outside of the
+        // directory.
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\', ':']) {
+            return warning(format!(
+                "The script saved an artifact named {:?}, which isn't a file name, so it was \
+                 thrown away",
+                name
+            ));
         }
 
-        message_receiver.stop();
-        fs::remove_file(&plugin_file_path)?;
+        let contents = base64::decode(data).with_context(|| {
+            format!(
+                "Roblox Studio sent the artifact {} with contents that aren't base64",
+                name
+            )
+        })?;
+
+        let dir = artifacts_dir.join("script-artifacts");
+        fs::create_dir_all(&dir)?;
+
+        let path = dir.join(name);
+        fs::write(&path, &contents)?;
+
+        transcript::record(
+            EntryKind::Lifecycle,
+            None,
+            format!("Saved the artifact {} to {}", name, path.display()),
+        );
 
-        Ok(())
+        Ok(RobloxMessage::Output {
+            level: OutputLevel::Info,
+            body: format!(
+                "Saved the artifact {} ({} bytes) to {}",
+                name,
+                contents.len(),
+                path.display()
+            ),
+            source: None,
+            line: None,
+            from_script: None,
+            history: false,
+        })
+    }
+
+    /// Starts Studio with the place, writing its output to `log_dir`.
+    fn launch_studio(
+        &self,
+        sender: &MessageSender,
+        log_dir: &Path,
+        plugin_file_path: &Path,
+    ) -> Result<KillOnDrop, anyhow::Error> {
+        // Read before Studio starts, since the plugin changes it.
+        let auto_recovery = AutoRecoverySetting::read();
+
+        let mut studio_process = KillOnDrop::new(
+            Command::new(&self.studio_app_path)
+                .arg(format!("{}", self.place_path.display()))
+                .stdout(
+                    File::create(log_dir.join(STUDIO_STDOUT_NAME))?
+                        .into_parts()
+                        .0,
+                )
+                .stderr(
+                    File::create(log_dir.join(STUDIO_STDERR_NAME))?
+                        .into_parts()
+                        .0,
+                )
+                .spawn()
+                .with_context(|| {
+                    format!(
+                        "Could not start Roblox Studio at {}",
+                        self.studio_app_path.display()
+                    )
+                })?,
+            Some(log_dir.join(STUDIO_STATUS_NAME)),
+        );
+        studio_process.exit_sender = Some(sender.clone());
+        studio_process.auto_recovery = auto_recovery;
+        sender.lifecycle(LifecycleEvent::StudioLaunched)?;
+
+        transcript::record(
+            EntryKind::Lifecycle,
+            None,
+            format!(
+                "Started Roblox Studio (pid {}) with {}",
+                studio_process.id(),
+                self.place_path.display()
+            ),
+        );
+
+        if let Some(temp_dir) = self.place_path.parent() {
+            SessionMarker {
+                studio_pid: Some(studio_process.id()),
+                plugin_path: Some(plugin_file_path.to_path_buf()),
+                ..SessionMarker::for_current_process()
+            }
+            .write(temp_dir)?;
+        }
+
+        Ok(studio_process)
+    }
+
+    /// Waits for the plugin to start the script, returning the first message
+    /// that isn't just the plugin checking in.
+    ///
+    /// Studio is checked on every few seconds while we wait, so that a timeout
+    /// can say what it was doing.
+    fn wait_for_plugin(
+        &self,
+        studio_process: &mut KillOnDrop,
+        started_at: SystemTime,
+        message_receiver: &MessageReceiver,
+        sender: &MessageSender,
+    ) -> Result<Message, anyhow::Error> {
+        let launched = Instant::now();
+        let startup_deadline = launched + STARTUP_TIMEOUT;
+
+        let mut observations = StartupObservations::default();
+        let mut next_sample = launched;
+
+        loop {
+            self.check_studio_running(studio_process, started_at)?;
+
+            if Instant::now() >= next_sample {
+                observations.sample(studio_process, launched);
+                next_sample += STARTUP_SAMPLE_INTERVAL;
+            }
+
+            if Instant::now() >= startup_deadline {
+                observations.sample(studio_process, launched);
+
+                bail!(RunFailure::new(
+                    FailureCategory::Infra,
+                    format!(
+                        "Timeout reached while waiting for Roblox Studio to come online.\n{}",
+                        observations.describe(launched.elapsed())
+                    ),
+                ));
+            }
+
+            match message_receiver.recv_timeout(POLL_INTERVAL) {
+                Some(Message::Alive) | None => {}
+                Some(Message::Connected) => {
+                    observations.plugin_connected = true;
+                    sender.lifecycle(LifecycleEvent::PluginConnected)?;
+                }
+                Some(message) => return Ok(message),
+            }
+        }
+    }
+
+    /// Looks for signs that Studio failed to start because it couldn't open
+    /// its copy of the place, returning a description of the problem.
+    fn place_open_problem(&self, err: &anyhow::Error, log_dir: &Path) -> Option<&'static str> {
+        let category = err.downcast_ref::<RunFailure>()?.category;
+
+        // A lock file left behind by a Studio that exited on its own means the
+        // next one will refuse to open the place.
+        if category == FailureCategory::EngineCrash && lock_path(&self.place_path).exists() {
+            return Some("the place copy was left locked");
+        }
+
+        let mut logs = String::new();
+        for name in &[STUDIO_STDOUT_NAME, STUDIO_STDERR_NAME] {
+            logs.push_str(&fs::read_to_string(log_dir.join(name)).unwrap_or_default());
+        }
+        let logs = logs.to_lowercase();
+
+        PLACE_OPEN_ERRORS
+            .iter()
+            .find(|(pattern, _)| logs.contains(pattern))
+            .map(|(_, problem)| *problem)
+    }
+
+    /// Replaces Studio's copy of the place with a fresh one, removing any lock
+    /// file left next to it.
+    fn recopy_place(&self) -> Result<(), anyhow::Error> {
+        let lock_path = lock_path(&self.place_path);
+        if lock_path.exists() {
+            fs::remove_file(&lock_path)?;
+        }
+
+        match &self.place_source {
+            Some(place_source) => {
+                fs::copy(place_source, &self.place_path)?;
+            }
+            None => baseplate::write_place(&baseplate::new_place(), &self.place_path)?,
+        }
+
+        model::insert_models(&self.place_path, &self.models)
+    }
+
+    /// Fails the run if Studio has exited before the script finished, copying
+    /// any crash dumps it left behind into the artifacts directory.
+    fn check_studio_running(
+        &self,
+        studio_process: &mut KillOnDrop,
+        started_at: SystemTime,
+    ) -> Result<(), anyhow::Error> {
+        let status = match studio_process.try_wait()? {
+            Some(status) => status,
+            None => return Ok(()),
+        };
+
+        let ran_for = started_at.elapsed().unwrap_or_default();
+        let mut lines = vec![format!(
+            "Roblox Studio exited unexpectedly after {}s ({}).",
+            ran_for.as_secs(),
+            status
+        )];
+
+        let stderr =
+            fs::read_to_string(self.log_dir().join(STUDIO_STDERR_NAME)).unwrap_or_default();
+        let stderr_lines: Vec<&str> = stderr.lines().collect();
+
+        if !stderr_lines.is_empty() {
+            lines.push("Studio wrote to stderr:".to_owned());
+
+            let tail_start = stderr_lines.len().saturating_sub(STDERR_TAIL_LINES);
+            for line in &stderr_lines[tail_start..] {
+                lines.push(format!("  {}", line));
+            }
+        }
+
+        match &self.artifacts_dir {
+            Some(artifacts_dir) => {
+                let dumps = crash_dumps::collect(started_at, &artifacts_dir.join("crash-dumps"));
+
+                if dumps.is_empty() {
+                    lines.push("No crash dumps were found.".to_owned());
+                } else {
+                    lines.push("Crash dumps were copied to:".to_owned());
+
+                    for dump in dumps {
+                        lines.push(format!("  {}", dump.display()));
+                    }
+                }
+            }
+            None => lines.push("Pass --artifacts-dir to collect its crash dumps.".to_owned()),
+        }
+
+        bail!(RunFailure::new(
+            FailureCategory::EngineCrash,
+            lines.join("\n")
+        ))
+    }
+
+    /// Where Studio's own stdout, stderr, and exit status are written: the
+    /// artifacts directory if there is one, or the run's temporary directory.
+    fn log_dir(&self) -> PathBuf {
+        match &self.artifacts_dir {
+            Some(artifacts_dir) => artifacts_dir.clone(),
+            None => self
+                .place_path
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_else(env::temp_dir),
+        }
+    }
+}
+
+/// Copies the place into `temp_dir` for Studio to open, or writes an empty
+/// baseplate there if there's no place. Studio is always given a copy, which
+/// helps ensure it won't hang trying to tell the user that the place is
+/// read-only because of a .lock file.
+pub fn copy_place(place_path: Option<&Path>, temp_dir: &Path) -> Result<PathBuf, anyhow::Error> {
+    match place_path {
+        Some(place_path) => {
+            let extension = place_path
+                .extension()
+                .ok_or_else(|| anyhow!("Place file did not have a file extension"))?
+                .to_str()
+                .ok_or_else(|| anyhow!("Place file extension had invalid Unicode"))?;
+
+            let temp_place_path = temp_dir.join(format!("run-in-roblox-place.{}", extension));
+
+            fs::copy(place_path, &temp_place_path)?;
+
+            Ok(temp_place_path)
+        }
+        None => {
+            let temp_place_path = temp_dir.join("run-in-roblox-place.rbxl");
+
+            baseplate::write_place(&baseplate::new_place(), &temp_place_path)
+                .context("Could not create an empty place to run the script in")?;
+
+            Ok(temp_place_path)
+        }
+    }
+}
+
+/// Generates a random, unique ID for a session. The plugin we inject compares
+/// this value with the one reported by the server and aborts if they don't
+/// match.
+pub fn new_server_id() -> String {
+    format!("run-in-roblox-{:x}", rand::random::<u128>())
+}
+
+/// A secret for the plugin to send with its requests. Unlike the server ID,
+/// which anyone can ask for, it's only ever given to the plugin.
+pub fn new_token() -> String {
+    format!("{:x}", rand::random::<u128>())
+}
+
+/// The lock file Studio keeps next to a place while it has it open.
+fn lock_path(place_path: &Path) -> PathBuf {
+    let mut lock_path = place_path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    PathBuf::from(lock_path)
+}
+
+/// Removes any auto-recovery files Studio saved for our temporary place before
+/// the plugin had a chance to turn auto-recovery off. Studio names them after
+/// the place, like `run-in-roblox-place_AutoRecovery_0.rbxl`.
+fn remove_recovery_files(place_path: &Path) {
+    let place_stem = match place_path.file_stem().and_then(|stem| stem.to_str()) {
+        Some(stem) => stem,
+        None => return,
+    };
+
+    let recovery_name = Regex::new(&format!(
+        r"^{}_AutoRecovery_\d+\.rbxlx?$",
+        regex::escape(place_stem)
+    ))
+    .unwrap();
+
+    let auto_saves_path = match dirs::document_dir() {
+        Some(documents) => documents.join("ROBLOX").join("AutoSaves"),
+        None => return,
+    };
+
+    let entries = match fs::read_dir(&auto_saves_path) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        let is_ours = entry
+            .file_name()
+            .to_str()
+            .map(|name| recovery_name.is_match(name))
+            .unwrap_or(false);
+
+        if is_ours {
+            if let Err(err) = fs::remove_file(entry.path()) {
+                log::debug!("Could not remove recovery file: {}", err);
+            }
+        }
     }
 }
@@ -1,17 +1,213 @@
-use std::io::Write;
+use std::{io::Write, str::FromStr, time::Duration};
 
+use anyhow::bail;
 use rbx_xml::EncodeError;
 
-use rbx_dom_weak::{InstanceBuilder, WeakDom};
+use rbx_dom_weak::{types::Ref, InstanceBuilder, WeakDom};
 
 use rbx_types::Variant;
 
-static PLUGIN_TEMPLATE: &'static str = include_str!("plugin_main_template.lua");
+use crate::{
+    budget::Budget, bundle::BundledModule, chaos::ChaosHttp, model::ScriptModel,
+    mount::LoadedMount, script_env, snapshot,
+};
+
+static PLUGIN_TEMPLATE: &str = include_str!("plugin_main_template.lua");
+static RIR_MODULE: &str = include_str!("plugin_rir_module.lua");
+
+/// The longest Source we give a ModuleScript. Studio has trouble with longer
+/// scripts, so longer sources are split into chunks that the plugin puts back
+/// together when it starts.
+const MAX_SOURCE_LEN: usize = 190_000;
+
+/// The names of the ModuleScripts holding the chunks of a long source, which
+/// are numbered from 1.
+const CHUNK_NAME_PREFIX: &str = "RunInRobloxChunk";
+
+/// Luau type definitions for the `rir` global, which need to be kept in sync
+/// with `plugin_rir_module.lua`.
+pub static RIR_TYPINGS: &str = include_str!("rir.d.luau");
+
+/// How the generated plugin reaches Roblox Studio, chosen with
+/// `--inject-method`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InjectMethod {
+    /// Written to the user's Studio plugins folder, where Studio loads it on
+    /// startup.
+    PluginsFolder,
+
+    /// Written as a local plugin file that the place refers to.
+    LocalPlugin,
+
+    /// Passed to Studio with its script execution launch flags.
+    CommandLine,
+}
+
+impl FromStr for InjectMethod {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "plugins-folder" => Ok(InjectMethod::PluginsFolder),
+            "local-plugin" => Ok(InjectMethod::LocalPlugin),
+            "command-line" => Ok(InjectMethod::CommandLine),
+            _ => bail!(
+                "Invalid value for --inject-method, expected \"plugins-folder\", \
+                 \"local-plugin\", or \"command-line\""
+            ),
+        }
+    }
+}
+
+/// Which of Studio's modes the scripts run in, chosen with `--mode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StudioMode {
+    /// In the place being edited, like a command bar script.
+    Edit,
+
+    /// While the place is simulated, like after pressing Run, so that physics
+    /// and the place's own scripts are running.
+    Run,
+
+    /// On the server of a play test with a player, like after pressing Play,
+    /// so that LocalScripts run and the server replicates to a client.
+    Play,
+}
+
+impl StudioMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            StudioMode::Edit => "edit",
+            StudioMode::Run => "run",
+            StudioMode::Play => "play",
+        }
+    }
+}
+
+impl FromStr for StudioMode {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "edit" => Ok(StudioMode::Edit),
+            "run" => Ok(StudioMode::Run),
+            "play" => Ok(StudioMode::Play),
+            _ => bail!("Invalid value for --mode, expected \"edit\", \"run\", or \"play\""),
+        }
+    }
+}
+
+/// Which of the output Studio logs is sent back, chosen with `--capture`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capture {
+    /// Everything Studio logs, including what it logged before the plugin
+    /// started, like warnings from loading the place.
+    All,
+
+    /// Only output from the scripts themselves.
+    Script,
+}
+
+impl Capture {
+    fn as_str(self) -> &'static str {
+        match self {
+            Capture::All => "all",
+            Capture::Script => "script",
+        }
+    }
+}
+
+impl FromStr for Capture {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "all" => Ok(Capture::All),
+            "script" => Ok(Capture::Script),
+            _ => bail!("Invalid value for --capture, expected \"all\" or \"script\""),
+        }
+    }
+}
+
+/// What's put back between the scripts of a multi-script session, chosen with
+/// `--isolation`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Isolation {
+    /// Nothing; each script sees what the ones before it left behind.
+    None,
+
+    /// The contents of `_G` and `shared`.
+    Globals,
+
+    /// The globals, and the contents of the services that are saved with the
+    /// place, as they were before the first script ran.
+    Reload,
+}
+
+impl Isolation {
+    fn as_str(self) -> &'static str {
+        match self {
+            Isolation::None => "none",
+            Isolation::Globals => "globals",
+            Isolation::Reload => "reload",
+        }
+    }
+}
+
+impl FromStr for Isolation {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "none" => Ok(Isolation::None),
+            "globals" => Ok(Isolation::Globals),
+            "reload" => Ok(Isolation::Reload),
+            _ => bail!(
+                "Invalid value for --isolation, expected \"none\", \"globals\", or \"reload\""
+            ),
+        }
+    }
+}
 
 pub struct RunInRbxPlugin<'a> {
     pub port: u16,
     pub server_id: &'a str,
-    pub lua_script: &'a str,
+    /// The secret the plugin sends with everything it sends us, so that
+    /// nothing else on the machine can pretend to be it.
+    pub token: &'a str,
+    /// The scripts given with --script, run one after another.
+    pub lua_scripts: &'a [String],
+    /// How many of `lua_scripts` are run at once.
+    pub parallel_scripts: usize,
+    pub isolation: Isolation,
+    pub modules: &'a [BundledModule],
+    /// The instances inside the scripts given with --script as models, which
+    /// are put back inside the scripts.
+    pub script_models: &'a [ScriptModel],
+    pub mounts: &'a [LoadedMount],
+    pub mode: StudioMode,
+    pub capture: Capture,
+    pub wait_for_done: bool,
+    pub compare_security: bool,
+    pub chaos_http: Option<ChaosHttp>,
+    pub forbid_network: bool,
+    pub read_only: bool,
+    pub budget: Option<Budget>,
+    pub probe: Option<&'a str>,
+    pub probe_interval: Duration,
+    pub warmup: Option<&'a str>,
+    pub duration: Option<Duration>,
+    pub flush_interval: Duration,
+    pub env: &'a [(String, String)],
+    /// Keeps the plugin running after the scripts finish, evaluating lines
+    /// for `run-in-roblox repl` until the run is cancelled.
+    pub repl: bool,
+    /// The instance path of the subtree to send back for --snapshot once the
+    /// scripts finish.
+    pub snapshot_root: Option<&'a str>,
+    /// Keeps the plugin running after the run for `--on-failure inspect`,
+    /// sending snapshots of the place when asked until the run is cancelled.
+    pub inspect: bool,
 }
 
 impl<'a> RunInRbxPlugin<'a> {
@@ -23,26 +219,325 @@ impl<'a> RunInRbxPlugin<'a> {
     }
 
     fn build_plugin(&self) -> WeakDom {
+        let chaos_http = match &self.chaos_http {
+            Some(chaos) => format!(
+                "{{ latency = {}, failRate = {} }}",
+                chaos.latency.as_secs_f64(),
+                chaos.fail_rate
+            ),
+            None => "nil".to_owned(),
+        };
+
+        let duration = match self.duration {
+            Some(duration) => duration.as_secs_f64().to_string(),
+            None => "nil".to_owned(),
+        };
+
+        let budget = match &self.budget {
+            Some(budget) => budget.to_lua(),
+            None => "nil".to_owned(),
+        };
+
+        let snapshot_root = match self.snapshot_root {
+            Some(root) => script_env::lua_string(root),
+            None => "nil".to_owned(),
+        };
+
         let complete_source = PLUGIN_TEMPLATE
             .replace("{{PORT}}", &self.port.to_string())
-            .replace("{{SERVER_ID}}", self.server_id);
+            .replace("{{SERVER_ID}}", self.server_id)
+            .replace("{{TOKEN}}", self.token)
+            .replace("{{MODE}}", self.mode.as_str())
+            .replace("{{CAPTURE}}", self.capture.as_str())
+            .replace("{{WAIT_FOR_DONE}}", &self.wait_for_done.to_string())
+            .replace("{{COMPARE_SECURITY}}", &self.compare_security.to_string())
+            .replace("{{CHAOS_HTTP}}", &chaos_http)
+            .replace("{{FORBID_NETWORK}}", &self.forbid_network.to_string())
+            .replace("{{READ_ONLY}}", &self.read_only.to_string())
+            .replace("{{BUDGET}}", &budget)
+            .replace(
+                "{{PROBE_INTERVAL}}",
+                &self.probe_interval.as_secs_f64().to_string(),
+            )
+            .replace("{{DURATION}}", &duration)
+            .replace(
+                "{{FLUSH_INTERVAL}}",
+                &self.flush_interval.as_secs_f64().to_string(),
+            )
+            .replace("{{PARALLEL_SCRIPTS}}", &self.parallel_scripts.to_string())
+            .replace("{{ISOLATION}}", self.isolation.as_str())
+            .replace("{{REPL}}", &self.repl.to_string())
+            .replace("{{SNAPSHOT_ROOT}}", &snapshot_root)
+            .replace("{{INSPECT}}", &self.inspect.to_string());
 
         let plugin_script = InstanceBuilder::new("Script")
             .with_name("run-in-roblox-plugin")
             .with_property("Source", Variant::String(complete_source));
 
-        let main_source = format!("return function()\n{}\nend", self.lua_script);
-
-        let injected_main = InstanceBuilder::new("ModuleScript")
-            .with_name("Main")
-            .with_property("Source", Variant::String(main_source));
-        
+        let rir_module = InstanceBuilder::new("ModuleScript")
+            .with_name("rir")
+            .with_property("Source", Variant::String(RIR_MODULE.to_owned()));
 
+        let env = script_env::to_lua(self.env);
         let mut tree = WeakDom::new(plugin_script);
 
         let root_ref = tree.root_ref();
-        tree.insert(root_ref, injected_main);
+        for (index, lua_script) in self.lua_scripts.iter().enumerate() {
+            let main_ref = insert_module(
+                &mut tree,
+                root_ref,
+                &main_module_name(index),
+                wrap_script(lua_script, &env),
+            );
+
+            if let Some(script_model) = self.script_models.iter().find(|m| m.index == index) {
+                let children = &script_model.children;
+
+                for child_ref in children.root().children() {
+                    let copy_ref = children.clone_into_external(*child_ref, &mut tree);
+                    tree.transfer_within(copy_ref, main_ref);
+                }
+            }
+        }
+        tree.insert(root_ref, rir_module);
+
+        if let Some(probe) = self.probe {
+            insert_module(&mut tree, root_ref, "Probe", wrap_script(probe, &env));
+        }
+
+        if let Some(warmup) = self.warmup {
+            insert_module(&mut tree, root_ref, "Warmup", wrap_script(warmup, &env));
+        }
+
+        if self.snapshot_root.is_some() || self.inspect {
+            insert_module(
+                &mut tree,
+                root_ref,
+                "SnapshotProperties",
+                snapshot::properties_module(),
+            );
+        }
+
+        for module in self.modules {
+            insert_bundled_module(&mut tree, root_ref, module);
+        }
+
+        // The plugin copies everything in here into the place before running
+        // the script, using each folder's name as the instance path to put its
+        // contents at.
+        if !self.mounts.is_empty() {
+            let mounts_ref =
+                tree.insert(root_ref, InstanceBuilder::new("Folder").with_name("Mounts"));
+
+            for mount in self.mounts {
+                let target_ref = tree.insert(
+                    mounts_ref,
+                    InstanceBuilder::new("Folder").with_name(mount.target.as_str()),
+                );
+
+                let copy_ref = mount
+                    .tree
+                    .clone_into_external(mount.tree.root_ref(), &mut tree);
+                tree.transfer_within(copy_ref, target_ref);
+            }
+        }
 
         tree
     }
 }
+
+/// The name of the ModuleScript holding the script at `index` in
+/// `lua_scripts`: "Main" for the first, then "Main2", "Main3", and so on.
+pub fn main_module_name(index: usize) -> String {
+    if index == 0 {
+        "Main".to_owned()
+    } else {
+        format!("Main{}", index + 1)
+    }
+}
+
+/// Adds a ModuleScript with the given source. Sources longer than
+/// `MAX_SOURCE_LEN` are split across child ModuleScripts that each return one
+/// chunk of it as a string, which the plugin joins back together and assigns to
+/// the module's Source before anything is run.
+fn insert_module(tree: &mut WeakDom, parent: Ref, name: &str, source: String) -> Ref {
+    if source.len() <= MAX_SOURCE_LEN {
+        return tree.insert(
+            parent,
+            InstanceBuilder::new("ModuleScript")
+                .with_name(name)
+                .with_property("Source", Variant::String(source)),
+        );
+    }
+
+    let module_ref = tree.insert(
+        parent,
+        InstanceBuilder::new("ModuleScript")
+            .with_name(name)
+            .with_property(
+                "Source",
+                Variant::String(
+                    "error(\"run-in-roblox didn't put this script back together\")".to_owned(),
+                ),
+            ),
+    );
+
+    for (index, chunk) in split_source(&source).into_iter().enumerate() {
+        tree.insert(
+            module_ref,
+            InstanceBuilder::new("ModuleScript")
+                .with_name(format!("{}{}", CHUNK_NAME_PREFIX, index + 1))
+                .with_property("Source", Variant::String(string_chunk(chunk))),
+        );
+    }
+
+    module_ref
+}
+
+/// Adds a bundled module along with the modules inside it.
+fn insert_bundled_module(tree: &mut WeakDom, parent: Ref, module: &BundledModule) {
+    let module_ref = match &module.source {
+        Some(source) => insert_module(tree, parent, &module.name, source.clone()),
+        None => tree.insert(
+            parent,
+            InstanceBuilder::new("Folder").with_name(module.name.as_str()),
+        ),
+    };
+
+    for child in &module.children {
+        insert_bundled_module(tree, module_ref, child);
+    }
+}
+
+/// Splits a source into pieces of at most `MAX_SOURCE_LEN` bytes, leaving
+/// room for the code around each one and never splitting a character.
+fn split_source(source: &str) -> Vec<&str> {
+    let max_len = MAX_SOURCE_LEN - 100;
+    let mut chunks = Vec::new();
+    let mut rest = source;
+
+    while !rest.is_empty() {
+        let mut end = rest.len().min(max_len);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let (chunk, remaining) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = remaining;
+    }
+
+    chunks
+}
+
+/// Builds the source of a ModuleScript that returns `chunk` as it is, using a
+/// long string with enough `=` signs that nothing in the chunk can end it.
+fn string_chunk(chunk: &str) -> String {
+    let mut level = String::new();
+
+    loop {
+        let closing = format!("]{}]", level);
+        let quoted = format!("{}{}", chunk, closing);
+
+        if quoted.find(&closing) == Some(chunk.len()) {
+            // The newline after the opening bracket is dropped by Lua, which
+            // keeps any newline the chunk starts with.
+            return format!("return [{}[\n{}", level, quoted);
+        }
+
+        level.push('=');
+    }
+}
+
+/// Turns the scripts given with --script into the sources of the ModuleScripts
+/// that `HostCommand::Reload` swaps in for them, like the ones the plugin
+/// starts with.
+pub fn reload_sources(lua_scripts: &[String], env: &[(String, String)]) -> Vec<String> {
+    let env = script_env::to_lua(env);
+
+    lua_scripts
+        .iter()
+        .map(|lua_script| wrap_script(lua_script, &env))
+        .collect()
+}
+
+/// Turns a line typed into `run-in-roblox repl` into the source of a
+/// ModuleScript like the ones made for --script. Expressions are returned, so
+/// that the plugin can print their values.
+pub fn repl_source(line: &str, env: &[(String, String)]) -> String {
+    let env = script_env::to_lua(env);
+
+    if is_statement(line) {
+        wrap_script(line, &env)
+    } else {
+        wrap_script(&format!("return {}", line), &env)
+    }
+}
+
+/// Guesses whether a line is a statement rather than an expression, by whether
+/// it starts with a keyword or assigns something with an `=` outside of
+/// brackets and strings.
+fn is_statement(line: &str) -> bool {
+    const KEYWORDS: &[&str] = &[
+        "local", "if", "for", "while", "repeat", "function", "return", "do", "break",
+    ];
+
+    let first_word: String = line
+        .trim_start()
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || *c == '_')
+        .collect();
+
+    if KEYWORDS.contains(&first_word.as_str()) {
+        return true;
+    }
+
+    let mut depth = 0;
+    let mut quote = None;
+    let mut previous = ' ';
+
+    for (index, c) in line.char_indices() {
+        if let Some(open) = quote {
+            if c == open && previous != '\\' {
+                quote = None;
+            }
+        } else {
+            match c {
+                '"' | '\'' | '`' => quote = Some(c),
+                '(' | '{' | '[' => depth += 1,
+                ')' | '}' | ']' => depth -= 1,
+                '=' if depth == 0 => {
+                    let comparison = matches!(previous, '=' | '~' | '<' | '>')
+                        || line[index + 1..].starts_with('=');
+
+                    if !comparison {
+                        return true;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        previous = c;
+    }
+
+    false
+}
+
+/// Wraps a user script in a ModuleScript that returns it as a function, with
+/// the environment that run-in-roblox gives to scripts it runs, including the
+/// variables given with `--env` as `RIR_ENV`.
+///
+/// Everything before the script is kept on its first line, so that line
+/// numbers in errors from the script match the file it came from.
+fn wrap_script(source: &str, env: &str) -> String {
+    format!(
+        "local rir = require(script.Parent.rir); \
+         local task = rir._task; \
+         local game = rir._wrapGame(game); \
+         local RIR_ENV = {}; \
+         local print, warn = rir._outputFunctions(script); \
+         return function() {}\nend",
+        env, source
+    )
+}
@@ -1,12 +1,67 @@
 local PORT = "{{PORT}}"
 local SERVER_ID = "{{SERVER_ID}}"
+local TOKEN = "{{TOKEN}}"
+local MODE = "{{MODE}}"
+local CAPTURE = "{{CAPTURE}}"
+local WAIT_FOR_DONE = {{WAIT_FOR_DONE}}
+local COMPARE_SECURITY = {{COMPARE_SECURITY}}
+local CHAOS_HTTP = {{CHAOS_HTTP}}
+local FORBID_NETWORK = {{FORBID_NETWORK}}
+local READ_ONLY = {{READ_ONLY}}
+local BUDGET = {{BUDGET}}
+local PROBE_INTERVAL = {{PROBE_INTERVAL}}
+local DURATION = {{DURATION}}
+local FLUSH_INTERVAL = {{FLUSH_INTERVAL}}
+local PARALLEL_SCRIPTS = {{PARALLEL_SCRIPTS}}
+local ISOLATION = "{{ISOLATION}}"
+local REPL = {{REPL}}
+local SNAPSHOT_ROOT = {{SNAPSHOT_ROOT}}
+local INSPECT = {{INSPECT}}
 
 local SERVER_URL = string.format("http://localhost:%s", PORT)
 
+-- Everything we send after checking the server ID carries the run's token, so
+-- that nothing else on the machine can send output or end the run.
+local TOKEN_HEADERS = { ["X-Run-In-Roblox-Token"] = TOKEN }
+
 local HttpService = game:GetService("HttpService")
 local LogService = game:GetService("LogService")
+local Players = game:GetService("Players")
 local RunService = game:GetService("RunService")
 
+-- Studio releases differ in which APIs they offer. Rather than parsing
+-- version(), we check for the APIs we'd like to use and fall back to older ones
+-- when they're missing, then report which variant of the plugin is running.
+local STUDIO_VERSION = version()
+local hasTaskLibrary = typeof(task) == "table" and typeof(task.spawn) == "function"
+local PLUGIN_VARIANT = hasTaskLibrary and "task" or "legacy"
+
+local spawnThread = hasTaskLibrary and task.spawn
+	or function(callback, ...)
+		return coroutine.wrap(callback)(...)
+	end
+local waitFor = hasTaskLibrary and task.wait or wait
+
+-- Auto-recovery stalls Studio every few minutes during long runs and leaves
+-- recovery files behind for our temporary place, so we turn it off until the
+-- run is over. Studio settings are persisted, so we need to restore them.
+local AUTO_RECOVERY_SETTING = "Auto-Recovery Enabled"
+
+local function setAutoRecoveryEnabled(enabled)
+	local success, previous = pcall(function()
+		local studioSettings = settings().Studio
+		local previous = studioSettings[AUTO_RECOVERY_SETTING]
+		studioSettings[AUTO_RECOVERY_SETTING] = enabled
+		return previous
+	end)
+
+	if success then
+		return previous
+	end
+
+	return nil
+end
+
 local pingSuccess, remoteServerId = pcall(function()
 	return HttpService:GetAsync(SERVER_URL)
 end)
@@ -25,9 +80,202 @@ if remoteServerId ~= SERVER_ID then
 	return
 end
 
+-- Studio loads plugins again for each side of a play test. With --mode play,
+-- the scripts run on the server side of the test, and otherwise they only run
+-- in the place being edited.
+local inPlayTest = not RunService:IsEdit()
+
+if inPlayTest and (MODE ~= "play" or RunService:IsClient()) then
+	return
+end
+
+-- Scripts too long for one ModuleScript arrive split across children named
+-- RunInRobloxChunk1, RunInRobloxChunk2, and so on, which are joined back
+-- together before anything can require them.
+local function joinChunkedModules()
+	for _, module in ipairs(script:GetDescendants()) do
+		if module:IsA("ModuleScript") and module:FindFirstChild("RunInRobloxChunk1") then
+			local chunks = {}
+
+			while true do
+				local chunk = module:FindFirstChild("RunInRobloxChunk" .. (#chunks + 1))
+				if chunk == nil then
+					break
+				end
+
+				table.insert(chunks, require(chunk))
+				chunk:Destroy()
+			end
+
+			module.Source = table.concat(chunks)
+		end
+	end
+end
+
+joinChunkedModules()
+
+local autoRecoveryWasEnabled = setAutoRecoveryEnabled(false)
+
 local queuedMessages = {}
 local timeSinceLastSend = 0
-local messageSendRate = 0.1
+local messageSendRate = FLUSH_INTERVAL
+
+-- How long we give LogService to deliver output before we move on.
+local logSettleTime = 0.2
+
+-- The shortest time the second run of the script for --compare-security is
+-- given to finish, in seconds.
+local COMPARE_SECURITY_MIN_TIMEOUT = 30
+
+-- We let the server know we're still alive periodically so that it can tell
+-- when Lua execution has stopped, like when paused at a breakpoint.
+local timeSinceLastAlive = 0
+local aliveRate = 1
+
+-- Messages are stamped with the plugin's clock when they're queued, which the
+-- server lines up with its own clock using the time sent with /start.
+local function queueMessage(message)
+	message.clock = os.clock()
+
+	-- With --mode play, output comes from both sides of the play test, and
+	-- anything not sent on behalf of the client comes from the server.
+	if MODE == "play" and message.type == "Output" and message.context == nil then
+		message.context = "server"
+	end
+
+	table.insert(queuedMessages, message)
+end
+
+-- Studio releases that can open WebSockets send everything to run-in-roblox
+-- over one, which gets output there sooner than a request for each batch and
+-- lets it push commands to us as soon as they're sent. Other releases, and
+-- any run where the WebSocket can't be opened, post to it instead.
+local STREAM_OPEN_TIMEOUT = 2
+
+local stream = nil
+local streamedCommands = {}
+
+local function openStream()
+	local success, client = pcall(function()
+		return HttpService:CreateWebStreamClient(Enum.WebStreamClientType.WebSocket, {
+			Url = string.format("ws://localhost:%s/stream?token=%s", PORT, TOKEN),
+		})
+	end)
+
+	if not success then
+		return
+	end
+
+	local opened = nil
+
+	client.Opened:Connect(function()
+		opened = true
+	end)
+
+	client.Error:Connect(function()
+		if opened == nil then
+			opened = false
+		end
+	end)
+
+	client.Closed:Connect(function()
+		if opened == nil then
+			opened = false
+		end
+
+		if stream == client then
+			stream = nil
+		end
+	end)
+
+	client.MessageReceived:Connect(function(message)
+		for _, command in ipairs(HttpService:JSONDecode(message)) do
+			table.insert(streamedCommands, command)
+		end
+	end)
+
+	local deadline = os.clock() + STREAM_OPEN_TIMEOUT
+	while opened == nil and os.clock() < deadline do
+		waitFor(0.05)
+	end
+
+	if opened then
+		stream = client
+	else
+		pcall(function()
+			client:Close()
+		end)
+	end
+end
+
+-- Sends a body to one of run-in-roblox's endpoints, over the WebSocket if
+-- it's open.
+local function post(path, body)
+	if stream ~= nil then
+		local success = pcall(function()
+			stream:Send(HttpService:JSONEncode({ path = path, body = body }))
+		end)
+
+		if success then
+			return
+		end
+
+		stream = nil
+	end
+
+	HttpService:PostAsync(
+		SERVER_URL .. path,
+		body,
+		Enum.HttpContentType.ApplicationJson,
+		false,
+		TOKEN_HEADERS
+	)
+end
+
+-- Batches too big for Studio to send at once, like output from a script that
+-- prints a huge dump, are split into chunks that run-in-roblox puts back
+-- together. Chunks end between characters, so that each one is valid text.
+local MAX_MESSAGES_SIZE = 512 * 1024
+-- run-in-roblox ignores pieces bigger than this, so it names it too.
+local CHUNK_SIZE = 128 * 1024
+local nextChunkedId = 1
+
+local function postChunked(encoded)
+	local chunks = {}
+	local start = 1
+
+	while start <= #encoded do
+		local finish = math.min(start + CHUNK_SIZE - 1, #encoded)
+
+		-- Bytes from 0x80 to 0xBF continue a character started before them.
+		while finish < #encoded do
+			local nextByte = string.byte(encoded, finish + 1)
+			if nextByte < 0x80 or nextByte > 0xBF then
+				break
+			end
+
+			finish = finish - 1
+		end
+
+		table.insert(chunks, string.sub(encoded, start, finish))
+		start = finish + 1
+	end
+
+	local id = nextChunkedId
+	nextChunkedId = nextChunkedId + 1
+
+	for index, data in ipairs(chunks) do
+		post(
+			"/chunk",
+			HttpService:JSONEncode({
+				id = id,
+				index = index,
+				count = #chunks,
+				data = data,
+			})
+		)
+	end
+end
 
 local function flushMessages()
 	if #queuedMessages == 0 then
@@ -38,15 +286,54 @@ local function flushMessages()
 	queuedMessages = {}
 
 	timeSinceLastSend = 0
-	HttpService:PostAsync(SERVER_URL .. "/messages", encoded)
+
+	if #encoded > MAX_MESSAGES_SIZE then
+		postChunked(encoded)
+	else
+		post("/messages", encoded)
+	end
 end
 
+-- With --mode play, the copy of the plugin in the place being edited only
+-- starts the play test and waits for the copy on its server to end it.
+if MODE == "play" and not inPlayTest then
+	local success, message = pcall(function()
+		game:GetService("StudioTestService"):ExecutePlayModeAsync({})
+	end)
+
+	if not success then
+		post("/start", HttpService:JSONEncode({ clock = os.clock() }))
+		queueMessage({
+			type = "Output",
+			level = "Error",
+			body = "Could not start a play test: " .. tostring(message),
+		})
+		flushMessages()
+		post("/stop", "")
+	end
+
+	if autoRecoveryWasEnabled ~= nil then
+		setAutoRecoveryEnabled(autoRecoveryWasEnabled)
+	end
+
+	return
+end
+
+openStream()
+
 local heartbeatConnection = RunService.Heartbeat:Connect(function(dt)
 	timeSinceLastSend = timeSinceLastSend + dt
 
+	timeSinceLastAlive = timeSinceLastAlive + dt
+
 	if timeSinceLastSend >= messageSendRate then
 		flushMessages()
 	end
+
+	if timeSinceLastAlive >= aliveRate then
+		timeSinceLastAlive = 0
+		post("/alive", "")
+	end
 end)
 
 local logTypeToLevel = {
@@ -56,29 +343,285 @@ local logTypeToLevel = {
 	[Enum.MessageType.MessageError] = "Error",
 }
 
+-- Errors seen while the script is running in the current security context,
+-- used to compare behavior with --compare-security.
+local contextErrors = {}
+
+local rir = require(script.rir)
+
+-- Finds where an error was raised from its message, like
+-- `ReplicatedStorage.Foo:12: attempt to index nil`.
+local function errorLocation(message)
+	local source, line = string.match(message, "^([^:\n]+):(%d+): ")
+	return source, tonumber(line)
+end
+
+-- With --capture all, what Studio logged before the plugin started, like
+-- warnings from loading the place, is sent along with everything after.
+if CAPTURE == "all" then
+	for _, entry in ipairs(LogService:GetLogHistory()) do
+		queueMessage({
+			type = "Output",
+			level = logTypeToLevel[entry.messageType] or "Info",
+			body = entry.message,
+			fromScript = false,
+			history = true,
+		})
+	end
+end
+
 local logConnection = LogService.MessageOut:Connect(function(body, messageType)
-	table.insert(queuedMessages, {
+	if messageType == Enum.MessageType.MessageError then
+		table.insert(contextErrors, body)
+	end
+
+	-- LogService doesn't say where output came from, but the scripts note
+	-- what they print, and errors start with where they were raised.
+	local fromScript, source, line = rir._takeOutput(body)
+	if source == nil and messageType == Enum.MessageType.MessageError then
+		source, line = errorLocation(body)
+		fromScript = fromScript
+			or (source ~= nil and string.find(source, "run-in-roblox-plugin.Main", 1, true) ~= nil)
+	end
+
+	if CAPTURE == "script" and not fromScript then
+		return
+	end
+
+	queueMessage({
 		type = "Output",
 		level = logTypeToLevel[messageType] or "Info",
 		body = body,
+		source = source,
+		line = line,
+		fromScript = fromScript,
 	})
 end)
 
-HttpService:PostAsync(SERVER_URL .. "/start", "")
+rir._flush = flushMessages
 
-local loadSuccess, messageOrMain = xpcall(require, debug.traceback, script.Main)
+-- Sends a request for rir.writeFile() or rir.readFile(), returning whether it
+-- worked and the response's body. These are always requests, even with the
+-- WebSocket open, since the script needs the answer.
+local function requestFile(path, body)
+	local success, response = pcall(function()
+		return HttpService:RequestAsync({
+			Url = SERVER_URL .. path,
+			Method = "POST",
+			Headers = {
+				["Content-Type"] = "application/json",
+				["X-Run-In-Roblox-Token"] = TOKEN,
+			},
+			Body = HttpService:JSONEncode(body),
+		})
+	end)
 
-if not loadSuccess then
-	local sacrificialEvent = Instance.new("BindableEvent")
-	sacrificialEvent.Event:Connect(function()
-		error(messageOrMain, 0)
+	if not success then
+		return false, "Could not reach run-in-roblox: " .. tostring(response)
+	end
+
+	return response.Success, response.Body
+end
+
+rir._writeFile = function(path, contents)
+	return requestFile("/files", { path = path, contents = contents })
+end
+
+rir._readFile = function(path)
+	return requestFile("/read", { path = path })
+end
+rir._emit = function(message)
+	if message.type == "Output" and message.level == "Error" then
+		table.insert(contextErrors, message.body)
+	end
+
+	queueMessage(message)
+end
+
+if CHAOS_HTTP ~= nil then
+	rir._configureChaosHttp(CHAOS_HTTP)
+end
+
+if FORBID_NETWORK then
+	rir._forbidNetwork()
+end
+
+-- Copies the instances given with --mount into the place. Each folder in
+-- Mounts is named after an instance path like ReplicatedStorage.MyLib, where
+-- the first part is a service.
+local function replaceChild(parent, instance)
+	local existing = parent:FindFirstChild(instance.Name)
+	if existing ~= nil then
+		existing:Destroy()
+	end
+
+	instance.Parent = parent
+end
+
+local function applyMounts()
+	local mounts = script:FindFirstChild("Mounts")
+	if mounts == nil then
+		return
+	end
+
+	for _, mount in ipairs(mounts:GetChildren()) do
+		local segments = string.split(mount.Name, ".")
+		local root = mount:GetChildren()[1]
+		local parent = game:GetService(segments[1])
+
+		if #segments == 1 then
+			for _, child in ipairs(root:GetChildren()) do
+				replaceChild(parent, child:Clone())
+			end
+		else
+			for index = 2, #segments - 1 do
+				local child = parent:FindFirstChild(segments[index])
+
+				if child == nil then
+					child = Instance.new("Folder")
+					child.Name = segments[index]
+					child.Parent = parent
+				end
+
+				parent = child
+			end
+
+			local copy = root:Clone()
+			copy.Name = segments[#segments]
+			replaceChild(parent, copy)
+		end
+	end
+end
+
+post("/start", HttpService:JSONEncode({ clock = os.clock() }))
+
+queueMessage({
+	type = "Hello",
+	studioVersion = STUDIO_VERSION,
+	variant = PLUGIN_VARIANT,
+})
+
+local mountSuccess, mountError = pcall(applyMounts)
+
+if not mountSuccess then
+	queueMessage({
+		type = "Output",
+		level = "Error",
+		body = "Could not insert the folders given with --mount: " .. tostring(mountError),
+	})
+
+	heartbeatConnection:Disconnect()
+	logConnection:Disconnect()
+
+	if autoRecoveryWasEnabled ~= nil then
+		setAutoRecoveryEnabled(autoRecoveryWasEnabled)
+	end
+
+	flushMessages()
+	post("/stop", "")
+
+	if stream ~= nil then
+		pcall(function()
+			stream:Close()
+		end)
+	end
+
+	if inPlayTest then
+		pcall(function()
+			game:GetService("StudioTestService"):EndTest(nil)
+		end)
+	end
+
+	return
+end
+
+-- Only the server of a play test can reach run-in-roblox, so the client's
+-- output is passed along to it by a companion LocalScript.
+local CLIENT_OUTPUT_NAME = "RunInRobloxClientOutput"
+
+local CLIENT_OUTPUT_SOURCE = string.format(
+	[[
+local LogService = game:GetService("LogService")
+local remote = game:GetService("ReplicatedStorage"):WaitForChild(%q)
+
+LogService.MessageOut:Connect(function(body, messageType)
+	remote:FireServer(body, messageType.Name)
+end)
+]],
+	CLIENT_OUTPUT_NAME
+)
+
+local function startClientOutput()
+	local remote = Instance.new("RemoteEvent")
+	remote.Name = CLIENT_OUTPUT_NAME
+	-- A copy wouldn't be connected, so --isolation reload leaves it alone.
+	remote.Archivable = false
+	remote.OnServerEvent:Connect(function(_, body, messageTypeName)
+		-- The scripts run on the server, so nothing the client logs is theirs.
+		if CAPTURE == "script" then
+			return
+		end
+
+		local known, messageType = pcall(function()
+			return Enum.MessageType[messageTypeName]
+		end)
+
+		queueMessage({
+			type = "Output",
+			level = known and logTypeToLevel[messageType] or "Info",
+			body = tostring(body),
+			context = "client",
+			fromScript = false,
+		})
 	end)
-	sacrificialEvent:Fire()
+	remote.Parent = game:GetService("ReplicatedStorage")
+
+	local companion = Instance.new("LocalScript")
+	companion.Name = CLIENT_OUTPUT_NAME
+	companion.Source = CLIENT_OUTPUT_SOURCE
+	companion.Parent = game:GetService("StarterPlayer"):WaitForChild("StarterPlayerScripts")
+
+	-- Players who joined before the companion was added don't get a copy of
+	-- it from StarterPlayerScripts.
+	for _, player in ipairs(Players:GetPlayers()) do
+		companion:Clone().Parent = player:WaitForChild("PlayerGui")
+	end
 end
 
-local mainSuccess, message = xpcall(messageOrMain, debug.traceback)
+-- With --mode run, the place is simulated while the scripts run, which starts
+-- the place's own scripts, so it happens once the mounts are in place for them.
+-- With --mode play, the scripts wait for the player to join the test.
+if MODE == "run" then
+	RunService:Run()
+elseif MODE == "play" then
+	startClientOutput()
+
+	while #Players:GetPlayers() == 0 do
+		waitFor(0.1)
+	end
+end
+
+-- Reports an error raised while loading or running a script. Errors from
+-- scripts run with --parallel-scripts are labeled with the script's index;
+-- anything else goes through LogService like an error raised by Studio.
+local function reportError(message, index)
+	if index ~= nil then
+		local source, line = errorLocation(message)
+
+		rir._emit({
+			type = "Output",
+			level = "Error",
+			body = message,
+			script = index,
+			source = source,
+			line = line,
+			fromScript = true,
+		})
+		return
+	end
+
+	rir._expectOutput(message, errorLocation(message))
 
-if not mainSuccess then
 	local sacrificialEvent = Instance.new("BindableEvent")
 	sacrificialEvent.Event:Connect(function()
 		error(message, 0)
@@ -86,12 +629,1024 @@ if not mainSuccess then
 	sacrificialEvent:Fire()
 end
 
+-- Runs one of the injected ModuleScripts, reporting errors raised while
+-- loading or running it. Returns what the script returned.
+local function runModule(module, index)
+	local loadSuccess, messageOrMain = xpcall(require, debug.traceback, module)
+
+	if not loadSuccess then
+		reportError(messageOrMain, index)
+	end
+
+	local mainSuccess, result = xpcall(messageOrMain, rir._traceback)
+
+	if not mainSuccess and result ~= rir._exitSignal then
+		reportError(result, index)
+	end
+
+	return mainSuccess and result or nil
+end
+
+-- Scripts can set run-in-roblox's exit code by returning it, like they can
+-- with rir.exit().
+local function runMainModule(module, index)
+	local result = runModule(module, index)
+
+	if typeof(result) == "number" then
+		local success, message = pcall(rir.exit, result)
+
+		if not success and message ~= rir._exitSignal then
+			reportError(message, index)
+		end
+	end
+end
+
+-- Runs the scripts on PARALLEL_SCRIPTS threads at once, each taking the next
+-- script that hasn't been started whenever it finishes one. Their output is
+-- labeled with the script it came from, since it's interleaved.
+local function runParallel(mainModules)
+	for index, module in ipairs(mainModules) do
+		rir._scriptIndexes[module] = index
+	end
+
+	local started = 0
+	local running = math.min(PARALLEL_SCRIPTS, #mainModules)
+
+	local function runScripts()
+		while started < #mainModules do
+			started = started + 1
+			local index = started
+
+			queueMessage({ type = "ScriptStart", index = index })
+			runMainModule(mainModules[index], index)
+			queueMessage({ type = "ScriptEnd", index = index })
+		end
+
+		running = running - 1
+	end
+
+	for _ = 1, running do
+		spawnThread(runScripts)
+	end
+
+	while running > 0 do
+		waitFor(0.1)
+	end
+end
+
+-- The services that are saved with a place, which are what a snapshot of the
+-- whole game records and what --isolation reload puts back between scripts.
+local SNAPSHOT_SERVICES = {
+	Workspace = true,
+	Lighting = true,
+	MaterialService = true,
+	ReplicatedFirst = true,
+	ReplicatedStorage = true,
+	ServerScriptService = true,
+	ServerStorage = true,
+	StarterGui = true,
+	StarterPack = true,
+	StarterPlayer = true,
+	Teams = true,
+	SoundService = true,
+	Chat = true,
+	TextChatService = true,
+	LocalizationService = true,
+}
+
+-- Copies what --isolation puts back between scripts, so that each script sees
+-- what the first one did. Returns a function that puts it back.
+local function saveIsolatedState()
+	local globals = {}
+	local sharedValues = {}
+
+	for key, value in pairs(_G) do
+		globals[key] = value
+	end
+	for key, value in pairs(shared) do
+		sharedValues[key] = value
+	end
+
+	-- Only archivable instances can be copied, so anything else is left where
+	-- it is, as are the terrain, the camera, and players' characters.
+	local services = {}
+
+	if ISOLATION == "reload" then
+		for _, service in ipairs(game:GetChildren()) do
+			if SNAPSHOT_SERVICES[service.ClassName] then
+				local copies = {}
+
+				for _, child in ipairs(service:GetChildren()) do
+					if child.Archivable and not child:IsA("Terrain") and not child:IsA("Camera") then
+						local copy = child:Clone()
+						if copy ~= nil then
+							table.insert(copies, copy)
+						end
+					end
+				end
+
+				services[service] = copies
+			end
+		end
+	end
+
+	return function()
+		for key in pairs(_G) do
+			if globals[key] == nil then
+				_G[key] = nil
+			end
+		end
+		for key, value in pairs(globals) do
+			_G[key] = value
+		end
+
+		for key in pairs(shared) do
+			if sharedValues[key] == nil then
+				shared[key] = nil
+			end
+		end
+		for key, value in pairs(sharedValues) do
+			shared[key] = value
+		end
+
+		for service, copies in pairs(services) do
+			for _, child in ipairs(service:GetChildren()) do
+				local isCharacter = Players:GetPlayerFromCharacter(child) ~= nil
+
+				if
+					child.Archivable
+					and not child:IsA("Terrain")
+					and not child:IsA("Camera")
+					and not isCharacter
+				then
+					pcall(child.Destroy, child)
+				end
+			end
+
+			for _, copy in ipairs(copies) do
+				copy:Clone().Parent = service
+			end
+		end
+	end
+end
+
+-- Runs each script given with --script in order, waiting for each to call
+-- rir.done() with --wait-for done. When there's more than one, each is marked
+-- with ScriptStart and ScriptEnd messages.
+local function runMain()
+	local mainModules = { script.Main }
+	while true do
+		local module = script:FindFirstChild("Main" .. (#mainModules + 1))
+		if module == nil then
+			break
+		end
+
+		table.insert(mainModules, module)
+	end
+
+	if PARALLEL_SCRIPTS > 1 and #mainModules > 1 then
+		runParallel(mainModules)
+		return
+	end
+
+	local marked = #mainModules > 1
+	local restoreIsolatedState = nil
+
+	if ISOLATION ~= "none" and marked then
+		restoreIsolatedState = saveIsolatedState()
+	end
+
+	for index, module in ipairs(mainModules) do
+		if restoreIsolatedState ~= nil and index > 1 then
+			restoreIsolatedState()
+		end
+
+		if marked then
+			queueMessage({ type = "ScriptStart", index = index })
+		end
+
+		runMainModule(module)
+
+		-- The script has asked to signal completion itself with rir.done(),
+		-- which might not have happened yet if it's waiting on asynchronous
+		-- work.
+		if WAIT_FOR_DONE then
+			rir._waitForDone()
+			rir._resetDone()
+		end
+
+		if marked then
+			-- Let the script's output arrive before marking its end.
+			waitFor(logSettleTime)
+			queueMessage({ type = "ScriptEnd", index = index })
+		end
+	end
+end
+
+-- Counts the instances and parts in the place, in total and for each child of
+-- every service.
+local function countPlace()
+	local totals = { instances = 0, parts = 0 }
+	local subtrees = {}
+
+	for _, service in ipairs(game:GetChildren()) do
+		local serviceReadable, children = pcall(function()
+			return service:GetChildren()
+		end)
+
+		if serviceReadable then
+			for _, child in ipairs(children) do
+				local counts = { name = child:GetFullName(), instances = 1, parts = 0 }
+
+				if child:IsA("BasePart") then
+					counts.parts = 1
+				end
+
+				for _, descendant in ipairs(child:GetDescendants()) do
+					counts.instances = counts.instances + 1
+
+					if descendant:IsA("BasePart") then
+						counts.parts = counts.parts + 1
+					end
+				end
+
+				totals.instances = totals.instances + counts.instances
+				totals.parts = totals.parts + counts.parts
+				table.insert(subtrees, counts)
+			end
+		end
+	end
+
+	return totals, subtrees
+end
+
+-- With --read-only, every change made to the place while the scripts run is
+-- recorded so that the run can fail if there were any. Cameras move on their
+-- own in Studio, so changes to them are ignored.
+local MAX_REPORTED_MUTATIONS = 20
+
+local mutations = {}
+local mutationCount = 0
+local mutationConnections = {}
+
+-- Only the first few changes are kept, since a script that changes something
+-- every frame would otherwise fill up memory.
+local function recordMutation(description)
+	mutationCount = mutationCount + 1
+
+	if #mutations < MAX_REPORTED_MUTATIONS then
+		table.insert(mutations, description)
+	end
+end
+
+local function isIgnoredForMutations(instance)
+	return instance:IsA("Camera")
+end
+
+local function watchInstance(instance)
+	if isIgnoredForMutations(instance) then
+		return
+	end
+
+	local success, connection = pcall(function()
+		return instance.Changed:Connect(function(property)
+			-- ValueBase objects pass their new value instead of a property name.
+			if instance:IsA("ValueBase") then
+				property = "Value"
+			end
+
+			recordMutation(string.format("changed %s.%s", instance:GetFullName(), tostring(property)))
+		end)
+	end)
+
+	if success then
+		table.insert(mutationConnections, connection)
+	end
+end
+
+local function startWatchingMutations()
+	for _, service in ipairs(game:GetChildren()) do
+		local serviceReadable, descendants = pcall(function()
+			return service:GetDescendants()
+		end)
+
+		if serviceReadable then
+			watchInstance(service)
+
+			for _, descendant in ipairs(descendants) do
+				watchInstance(descendant)
+			end
+		end
+	end
+
+	table.insert(
+		mutationConnections,
+		game.DescendantAdded:Connect(function(instance)
+			if not isIgnoredForMutations(instance) then
+				recordMutation(string.format("added %s (%s)", instance:GetFullName(), instance.ClassName))
+			end
+
+			watchInstance(instance)
+		end)
+	)
+
+	table.insert(
+		mutationConnections,
+		game.DescendantRemoving:Connect(function(instance)
+			if not isIgnoredForMutations(instance) then
+				recordMutation(string.format("removed %s (%s)", instance:GetFullName(), instance.ClassName))
+			end
+		end)
+	)
+end
+
+-- Stops watching the place and reports an error listing what changed, if
+-- anything did.
+local function stopWatchingMutations()
+	for _, connection in ipairs(mutationConnections) do
+		connection:Disconnect()
+	end
+	mutationConnections = {}
+
+	if mutationCount == 0 then
+		return
+	end
+
+	local lines = {
+		string.format("Read-only run changed the place (%d change(s)):", mutationCount),
+	}
+
+	for _, description in ipairs(mutations) do
+		table.insert(lines, "  " .. description)
+	end
+
+	if mutationCount > #mutations then
+		table.insert(lines, string.format("  ...and %d more", mutationCount - #mutations))
+	end
+
+	queueMessage({
+		type = "Output",
+		level = "Error",
+		body = table.concat(lines, "\n"),
+	})
+end
+
+if READ_ONLY then
+	startWatchingMutations()
+end
+
+-- The --warmup script runs before anything is measured: probes haven't started
+-- yet and whatever it adds to the place isn't counted by --budget.
+local warmupAdded = nil
+
+local function runWarmup()
+	local warmupModule = script:FindFirstChild("Warmup")
+	if warmupModule == nil then
+		return
+	end
+
+	local countsBefore = BUDGET ~= nil and countPlace() or nil
+
+	queueMessage({ type = "Phase", name = "warmup" })
+	runModule(warmupModule)
+	waitFor(logSettleTime)
+	queueMessage({ type = "Phase", name = "main" })
+
+	if countsBefore ~= nil then
+		local countsAfter = countPlace()
+
+		warmupAdded = {
+			instances = countsAfter.instances - countsBefore.instances,
+			parts = countsAfter.parts - countsBefore.parts,
+		}
+	end
+end
+
+runWarmup()
+
+-- Runs the --probe script every PROBE_INTERVAL seconds while the script runs,
+-- sending the table of metrics it returns to the server.
+local probing = false
+
+local function startProbing()
+	local probeModule = script:FindFirstChild("Probe")
+	if probeModule == nil then
+		return
+	end
+
+	local probe = require(probeModule)
+	local startTime = os.clock()
+	probing = true
+
+	spawnThread(function()
+		while probing do
+			local success, metrics = xpcall(probe, debug.traceback)
+
+			if not success then
+				queueMessage({
+					type = "Output",
+					level = "Error",
+					body = "Probe failed: " .. tostring(metrics),
+				})
+			elseif typeof(metrics) == "table" then
+				local values = {}
+
+				for name, value in pairs(metrics) do
+					if typeof(value) == "number" then
+						values[tostring(name)] = value
+					end
+				end
+
+				-- An empty table would be encoded as an array, so skip it.
+				if next(values) ~= nil then
+					queueMessage({
+						type = "Metrics",
+						time = os.clock() - startTime,
+						values = values,
+					})
+				end
+			end
+
+			waitFor(PROBE_INTERVAL)
+		end
+	end)
+end
+
+startProbing()
+
+-- Runs a line typed into `run-in-roblox repl`, printing the values of
+-- expressions, then lets the server know that it can ask for the next line.
+local function evaluate(command)
+	local module = Instance.new("ModuleScript")
+	module.Name = "Repl"
+	module.Source = command.source
+	module.Parent = script
+
+	local loadSuccess, main = xpcall(require, debug.traceback, module)
+
+	if loadSuccess then
+		local results = table.pack(xpcall(main, rir._traceback))
+
+		if not results[1] then
+			if results[2] ~= rir._exitSignal then
+				reportError(results[2])
+			end
+		elseif results.n > 1 then
+			print(table.unpack(results, 2, results.n))
+		end
+	else
+		reportError(main)
+	end
+
+	module:Destroy()
+
+	-- Output from the line has to reach us through LogService before we say
+	-- that it's done.
+	waitFor(logSettleTime)
+	queueMessage({ type = "Evaluated", id = command.id })
+	flushMessages()
+end
+
+-- Adds a module sent with a Reload command, along with the modules inside it.
+local function insertModule(parent, module)
+	local instance
+	if module.source ~= nil then
+		instance = Instance.new("ModuleScript")
+		instance.Source = module.source
+	else
+		instance = Instance.new("Folder")
+	end
+
+	instance.Name = module.name
+
+	for _, child in ipairs(module.children) do
+		insertModule(instance, child)
+	end
+
+	instance.Parent = parent
+end
+
+-- Swaps in the scripts and modules that changed for `--watch --hot`. Each
+-- gets a new ModuleScript, since require() hands back what a ModuleScript
+-- returned the first time for as long as it exists.
+local function replaceModules(command)
+	for index, source in ipairs(command.scripts) do
+		local name = index == 1 and "Main" or "Main" .. index
+		local module = Instance.new("ModuleScript")
+		module.Name = name
+		module.Source = source
+
+		-- Instances from a script given as a model stay inside it.
+		local old = script:FindFirstChild(name)
+		if old ~= nil then
+			for _, child in ipairs(old:GetChildren()) do
+				child.Parent = module
+			end
+
+			old:Destroy()
+		end
+
+		module.Parent = script
+	end
+
+	for _, name in ipairs(command.removed) do
+		local old = script:FindFirstChild(name)
+		if old ~= nil then
+			old:Destroy()
+		end
+	end
+
+	for _, module in ipairs(command.modules) do
+		local old = script:FindFirstChild(module.name)
+		if old ~= nil then
+			old:Destroy()
+		end
+
+		insertModule(script, module)
+	end
+end
+
+-- Runs the scripts again with the changes from a Reload command, then lets
+-- the server know that they're done.
+local function reload(command)
+	local success, message = pcall(replaceModules, command)
+
+	if success then
+		runMain()
+	else
+		queueMessage({
+			type = "Output",
+			level = "Error",
+			body = "Could not replace the scripts that changed: " .. tostring(message),
+		})
+	end
+
+	waitFor(logSettleTime)
+	queueMessage({ type = "Reloaded", id = command.id })
+	flushMessages()
+end
+
+-- Commands sent with `run-in-roblox send`, like cancelling the run, arrive
+-- over the WebSocket, or wait on the server until we ask for them if there
+-- isn't one.
+local commandPollRate = 0.25
+local pollingCommands = true
+
+local function takeCommands()
+	local commands = streamedCommands
+	streamedCommands = {}
+
+	if stream == nil then
+		local success, response = pcall(function()
+			return HttpService:GetAsync(SERVER_URL .. "/commands", true, TOKEN_HEADERS)
+		end)
+
+		if success then
+			for _, command in ipairs(HttpService:JSONDecode(response)) do
+				table.insert(commands, command)
+			end
+		end
+	end
+
+	return commands
+end
+
+spawnThread(function()
+	while pollingCommands do
+		local commands = takeCommands()
+
+		for _, command in ipairs(commands) do
+			-- Commands that run code are only sent by `run-in-roblox repl` and
+			-- --hot, so they're ignored in any other run.
+			if command.type == "Eval" then
+				if REPL then
+					spawnThread(evaluate, command)
+				end
+			elseif command.type == "Reload" then
+				if REPL then
+					spawnThread(reload, command)
+				end
+			else
+				rir._dispatchCommand(command)
+			end
+		end
+
+		waitFor(stream ~= nil and 0 or commandPollRate)
+	end
+end)
+
+-- The run lasts until the scripts return, or for a fixed amount of time
+-- whether or not they do with --duration, which is used for soak tests.
+-- Either way, it ends early if it's cancelled. `run-in-roblox repl` keeps it
+-- going until it's cancelled.
+local mainFinished = false
+local mainStartTime = os.clock()
+
+spawnThread(function()
+	runMain()
+	mainFinished = true
+
+	if REPL then
+		queueMessage({ type = "Reloaded", id = 0 })
+	end
+end)
+
+while not rir.isCancelled() do
+	if DURATION ~= nil then
+		if os.clock() - mainStartTime >= DURATION then
+			break
+		end
+	elseif mainFinished and not REPL then
+		break
+	end
+
+	waitFor(0.1)
+end
+
+pollingCommands = false
+
+if rir.isCancelled() and not REPL then
+	queueMessage({
+		type = "Output",
+		level = "Error",
+		body = "The run was cancelled",
+	})
+end
+
+probing = false
+
+if READ_ONLY then
+	-- Give deferred changes a chance to happen before we stop watching.
+	waitFor(logSettleTime)
+	stopWatchingMutations()
+end
+
+-- Reports an error if the place has more instances than --budget allows after
+-- the script has run, along with the subtrees that contribute the most.
+-- Anything the --warmup script added isn't counted.
+local function checkBudget(budget)
+	local totals, subtrees = countPlace()
+
+	if warmupAdded ~= nil then
+		totals.instances = totals.instances - warmupAdded.instances
+		totals.parts = totals.parts - warmupAdded.parts
+	end
+
+	for _, kind in ipairs({ "instances", "parts" }) do
+		local limit = budget[kind]
+
+		if limit ~= nil and totals[kind] > limit then
+			table.sort(subtrees, function(a, b)
+				return a[kind] > b[kind]
+			end)
+
+			local lines = {
+				string.format("Budget exceeded: the place has %d %s, the limit is %d", totals[kind], kind, limit),
+				"Largest subtrees:",
+			}
+
+			for index = 1, math.min(5, #subtrees) do
+				table.insert(lines, string.format("  %s: %d", subtrees[index].name, subtrees[index][kind]))
+			end
+
+			queueMessage({
+				type = "Output",
+				level = "Error",
+				body = table.concat(lines, "\n"),
+			})
+		end
+	end
+end
+
+if BUDGET ~= nil then
+	checkBudget(BUDGET)
+end
+
+-- How many instances are sent at a time, so that big snapshots are streamed
+-- to the server rather than sent in one enormous request.
+local SNAPSHOT_BATCH_SIZE = 500
+
+local function isFinite(number)
+	return number == number and number ~= math.huge and number ~= -math.huge
+end
+
+-- Turns a property's value into something that can be sent as JSON, or nil if
+-- it can't be. run-in-roblox knows the type of each property, so values only
+-- need to keep their numbers.
+local function encodeValue(value)
+	local kind = typeof(value)
+	local encoded
+
+	if kind == "boolean" or kind == "string" then
+		return value
+	elseif kind == "number" then
+		encoded = { value }
+	elseif kind == "EnumItem" then
+		return value.Value
+	elseif kind == "BrickColor" then
+		return value.Number
+	elseif kind == "Content" then
+		return tostring(value)
+	elseif kind == "Vector2" then
+		encoded = { value.X, value.Y }
+	elseif kind == "Vector3" then
+		encoded = { value.X, value.Y, value.Z }
+	elseif kind == "Color3" then
+		encoded = { value.R, value.G, value.B }
+	elseif kind == "CFrame" then
+		encoded = { value:GetComponents() }
+	elseif kind == "UDim" then
+		encoded = { value.Scale, value.Offset }
+	elseif kind == "UDim2" then
+		encoded = { value.X.Scale, value.X.Offset, value.Y.Scale, value.Y.Offset }
+	elseif kind == "NumberRange" then
+		encoded = { value.Min, value.Max }
+	else
+		return nil
+	end
+
+	-- JSON has no infinity or NaN.
+	for _, number in ipairs(encoded) do
+		if not isFinite(number) then
+			return nil
+		end
+	end
+
+	if kind == "number" then
+		return value
+	end
+
+	return encoded
+end
+
+-- Finds an instance from a path like Workspace.Model, where the first part is
+-- a service. "game" is the whole place.
+local function findInstance(path)
+	local instance = game
+	local segments = string.split(path, ".")
+	local first = segments[1] == "game" and 2 or 1
+
+	for index = first, #segments do
+		local segment = segments[index]
+		local child
+
+		if instance == game then
+			local success, service = pcall(game.FindService, game, segment)
+			child = success and service or nil
+		end
+
+		child = child or instance:FindFirstChild(segment)
+
+		if child == nil then
+			return nil
+		end
+
+		instance = child
+	end
+
+	return instance
+end
+
+-- Sends the subtree at `rootPath` back to run-in-roblox for --snapshot, one
+-- instance at a time with the properties Studio would save, parents first.
+local function takeSnapshot(rootPath)
+	local root = findInstance(rootPath)
+
+	if root == nil then
+		queueMessage({
+			type = "Output",
+			level = "Error",
+			body = string.format("Could not take a snapshot of %s, since it doesn't exist", rootPath),
+		})
+		return
+	end
+
+	local snapshotProperties = require(script.SnapshotProperties)
+	local count = 0
+
+	local function propertiesOf(instance)
+		local properties = {}
+		local className = instance.ClassName
+
+		while className ~= nil do
+			for _, name in ipairs(snapshotProperties.properties[className] or {}) do
+				local success, value = pcall(function()
+					return instance[name]
+				end)
+
+				if success then
+					properties[name] = encodeValue(value)
+				end
+			end
+
+			className = snapshotProperties.superclasses[className]
+		end
+
+		-- An empty table would be encoded as an array, so leave it out.
+		if next(properties) == nil then
+			return nil
+		end
+
+		return properties
+	end
+
+	local function visit(instance, parentId)
+		count = count + 1
+		local id = count
+
+		queueMessage({
+			type = "SnapshotInstance",
+			id = id,
+			parent = parentId,
+			className = instance.ClassName,
+			name = instance.Name,
+			properties = propertiesOf(instance),
+		})
+
+		if count % SNAPSHOT_BATCH_SIZE == 0 then
+			flushMessages()
+		end
+
+		for _, child in ipairs(instance:GetChildren()) do
+			-- Studio doesn't save instances that aren't Archivable.
+			local saved = child.Archivable and (instance ~= game or SNAPSHOT_SERVICES[child.ClassName])
+
+			if saved then
+				visit(child, id)
+			end
+		end
+	end
+
+	visit(root, nil)
+	queueMessage({ type = "SnapshotEnd", count = count })
+end
+
+if SNAPSHOT_ROOT ~= nil and not rir.isCancelled() then
+	takeSnapshot(SNAPSHOT_ROOT)
+end
+
+-- Stopping puts the place back the way it was before it was run, so it waits
+-- until everything that looks at the place is done.
+if MODE == "run" then
+	RunService:Stop()
+end
+
+-- Strips the script location from an error so that errors raised from the
+-- plugin and from a normal script can be compared.
+local function normalizeError(body)
+	local firstLine = string.match(body, "^[^\n]*")
+	return string.gsub(firstLine, "^.-:%d+: ", "")
+end
+
+local function errorsOnlyIn(errors, otherErrors)
+	local otherSet = {}
+	for _, body in ipairs(otherErrors) do
+		otherSet[normalizeError(body)] = true
+	end
+
+	local only = {}
+	for _, body in ipairs(errors) do
+		if not otherSet[normalizeError(body)] then
+			table.insert(only, normalizeError(body))
+		end
+	end
+
+	return only
+end
+
+-- Runs the script again as a normal Script in Run mode and reports errors that
+-- only happened at one of the two security levels.
+local function compareSecurity()
+	-- The second run gets twice as long as the first took, so a script that
+	-- never finishes at script security can't hold the run open.
+	local firstRunTime = os.clock() - mainStartTime
+	local deadline = os.clock() + math.max(COMPARE_SECURITY_MIN_TIMEOUT, 2 * firstRunTime)
+
+	waitFor(logSettleTime)
+	local pluginErrors = contextErrors
+	contextErrors = {}
+
+	local runner = Instance.new("Script")
+	runner.Name = "RunInRobloxCompareSecurity"
+	runner.Source = string.format(
+		[[
+local rir = require(script.rir)
+rir._emit = function(message)
+	if message.type == "Output" and message.level == "Error" then
+		task.spawn(error, message.body, 0)
+	end
+end
+
+local success, message = xpcall(require, rir._traceback, script.Main)
+if success then
+	success, message = xpcall(message, rir._traceback)
+end
+
+if not success and message ~= rir._exitSignal then
+	task.spawn(error, message, 0)
+end
+
+if %s then
+	rir._waitForDone()
+end
+
+script:SetAttribute("RunInRobloxFinished", true)
+]],
+		tostring(WAIT_FOR_DONE)
+	)
+	script.Main:Clone().Parent = runner
+	script.rir:Clone().Parent = runner
+	runner.Parent = game:GetService("ServerScriptService")
+
+	RunService:Run()
+
+	while not runner:GetAttribute("RunInRobloxFinished") do
+		if os.clock() >= deadline then
+			RunService:Stop()
+			queueMessage({
+				type = "Output",
+				level = "Error",
+				body = "The script didn't finish when run again as a normal Script for --compare-security",
+			})
+
+			-- The run ends as usual from here, posting /stop.
+			return
+		end
+
+		waitFor(0.1)
+	end
+
+	waitFor(logSettleTime)
+	RunService:Stop()
+
+	local scriptErrors = contextErrors
+	local onlyPlugin = errorsOnlyIn(pluginErrors, scriptErrors)
+	local onlyScript = errorsOnlyIn(scriptErrors, pluginErrors)
+
+	if #onlyPlugin > 0 or #onlyScript > 0 then
+		local lines = { "Script behaved differently at plugin and script security:" }
+
+		for _, body in ipairs(onlyPlugin) do
+			table.insert(lines, "  only at plugin security: " .. body)
+		end
+
+		for _, body in ipairs(onlyScript) do
+			table.insert(lines, "  only at script security: " .. body)
+		end
+
+		queueMessage({
+			type = "Output",
+			level = "Error",
+			body = table.concat(lines, "\n"),
+		})
+	end
+end
+
+if COMPARE_SECURITY and not rir.isCancelled() then
+	compareSecurity()
+end
+
 -- Wait for any remaining messages to be sent to LogService, then flush them
 -- explicitly.
-wait(2 * messageSendRate)
+waitFor(logSettleTime)
 heartbeatConnection:Disconnect()
 logConnection:Disconnect()
 
+if autoRecoveryWasEnabled ~= nil then
+	setAutoRecoveryEnabled(autoRecoveryWasEnabled)
+end
+
 flushMessages()
 
-HttpService:PostAsync(SERVER_URL .. "/stop", "")
\ No newline at end of file
+post("/stop", "")
+
+-- With --on-failure inspect, run-in-roblox can ask for snapshots of the place
+-- while it waits to hear what to do with Studio, until it cancels the run.
+if INSPECT then
+	local inspecting = true
+
+	while inspecting do
+		for _, command in ipairs(takeCommands()) do
+			if command.type == "Snapshot" then
+				takeSnapshot(command.root)
+				flushMessages()
+			elseif command.type == "Cancel" then
+				inspecting = false
+			end
+		end
+
+		waitFor(stream ~= nil and 0.1 or commandPollRate)
+	end
+end
+
+if stream ~= nil then
+	pcall(function()
+		stream:Close()
+	end)
+end
+
+if inPlayTest then
+	pcall(function()
+		game:GetService("StudioTestService"):EndTest(nil)
+	end)
+end
\ No newline at end of file
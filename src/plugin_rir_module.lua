@@ -0,0 +1,616 @@
+-- Helpers available to scripts run by run-in-roblox as the `rir` global.
+
+local rir = {}
+
+-- Set by the plugin to queue a message for the run-in-roblox server.
+rir._emit = function() end
+
+-- Set by the plugin to send queued messages right away.
+rir._flush = function() end
+
+-- Set by the plugin to write a file into run-in-roblox's --out-dir, returning
+-- whether it worked and why not.
+rir._writeFile = function()
+	return true
+end
+
+-- Set by the plugin to read a file from one of run-in-roblox's --read-dir
+-- folders, returning whether it worked and the contents or why not.
+rir._readFile = function()
+	return false, "rir.readFile() can only be used by the script run-in-roblox started"
+end
+
+-- Raised by rir.exit() to end the script. It isn't an error, so it's never
+-- reported as one.
+rir._exitSignal = setmetatable({}, {
+	__tostring = function()
+		return "rir.exit() was called"
+	end,
+})
+
+-- Adds a traceback to errors raised by the script, for use with xpcall.
+function rir._traceback(message)
+	if message == rir._exitSignal then
+		return message
+	end
+
+	return debug.traceback(message, 2)
+end
+
+local function reportThreadError(message)
+	rir._emit({
+		type = "Output",
+		level = "Error",
+		body = message,
+	})
+end
+
+-- Wraps a function so that errors raised from it are reported to the
+-- run-in-roblox server instead of disappearing with the thread. Nothing can
+-- handle errors from a thread the task library started, so every one is.
+local function guard(callback)
+	return function(...)
+		local results = table.pack(xpcall(callback, rir._traceback, ...))
+
+		if not results[1] and results[2] ~= rir._exitSignal then
+			reportThreadError(results[2])
+		end
+
+		return table.unpack(results, 2, results.n)
+	end
+end
+
+local function guardIfFunction(functionOrThread)
+	if typeof(functionOrThread) == "function" then
+		return guard(functionOrThread)
+	end
+
+	return functionOrThread
+end
+
+-- A stand-in for the task library that the injected script sees. Errors from
+-- coroutines reach whatever resumes them, where the script can handle them or
+-- let them fail the run, so the coroutine library is left as it is.
+rir._task = setmetatable({
+	spawn = function(callback, ...)
+		return task.spawn(guardIfFunction(callback), ...)
+	end,
+	defer = function(callback, ...)
+		return task.defer(guardIfFunction(callback), ...)
+	end,
+	delay = function(duration, callback, ...)
+		return task.delay(duration, guardIfFunction(callback), ...)
+	end,
+}, { __index = task })
+
+-- Replacement methods for services, keyed by service name and then method
+-- name. When any are registered, the injected script sees a stand-in for
+-- `game` that hands out wrapped services.
+local serviceMethodOverrides = {}
+
+local function wrapService(service, overrides)
+	local wrapper = {}
+
+	return setmetatable(wrapper, {
+		__index = function(_, key)
+			local override = overrides[key]
+			if override ~= nil then
+				return function(_, ...)
+					return override(service, ...)
+				end
+			end
+
+			local value = service[key]
+			if typeof(value) == "function" then
+				return function(self, ...)
+					if self == wrapper then
+						self = service
+					end
+
+					return value(self, ...)
+				end
+			end
+
+			return value
+		end,
+		__newindex = function(_, key, value)
+			service[key] = value
+		end,
+		__tostring = function()
+			return tostring(service)
+		end,
+	})
+end
+
+function rir._overrideServiceMethod(serviceName, methodName, override)
+	serviceMethodOverrides[serviceName] = serviceMethodOverrides[serviceName] or {}
+	serviceMethodOverrides[serviceName][methodName] = override
+end
+
+function rir._wrapGame(realGame)
+	if next(serviceMethodOverrides) == nil then
+		return realGame
+	end
+
+	local function maybeWrap(service)
+		if service == nil then
+			return nil
+		end
+
+		local overrides = serviceMethodOverrides[service.ClassName]
+		if overrides == nil then
+			return service
+		end
+
+		return wrapService(service, overrides)
+	end
+
+	return wrapService(realGame, {
+		GetService = function(self, name)
+			return maybeWrap(self:GetService(name))
+		end,
+		FindService = function(self, name)
+			return maybeWrap(self:FindService(name))
+		end,
+		FindFirstChild = function(self, ...)
+			return maybeWrap(self:FindFirstChild(...))
+		end,
+	})
+end
+
+local HTTP_METHODS = { "GetAsync", "PostAsync", "RequestAsync" }
+
+-- Makes HttpService requests from the injected script slower and fail some of
+-- the time, for --chaos-http.
+function rir._configureChaosHttp(options)
+	for _, methodName in ipairs(HTTP_METHODS) do
+		rir._overrideServiceMethod("HttpService", methodName, function(service, ...)
+			if options.latency > 0 then
+				task.wait(options.latency)
+			end
+
+			if math.random() < options.failRate then
+				error(string.format("HttpService:%s failed (injected by --chaos-http)", methodName), 2)
+			end
+
+			return service[methodName](service, ...)
+		end)
+	end
+end
+
+local NETWORK_METHODS = {
+	HttpService = HTTP_METHODS,
+	DataStoreService = { "GetDataStore", "GetGlobalDataStore", "GetOrderedDataStore", "ListDataStoresAsync" },
+	MemoryStoreService = { "GetQueue", "GetSortedMap" },
+	MessagingService = { "PublishAsync", "SubscribeAsync" },
+}
+
+-- Makes any network access from the injected script fail the run, reporting
+-- where it happened, for --forbid-network.
+function rir._forbidNetwork()
+	for serviceName, methodNames in pairs(NETWORK_METHODS) do
+		for _, methodName in ipairs(methodNames) do
+			rir._overrideServiceMethod(serviceName, methodName, function()
+				local message = string.format(
+					"%s:%s was called, but network access is forbidden by --forbid-network",
+					serviceName,
+					methodName
+				)
+
+				rir._emit({
+					type = "Output",
+					level = "Error",
+					body = debug.traceback(message, 3),
+				})
+
+				error(message, 2)
+			end)
+		end
+	end
+end
+
+-- Sends output at the given level, joining the arguments like print() does.
+-- `index` labels the output with the script it came from.
+-- Joins print's arguments the way print does.
+local function joinOutput(...)
+	local pieces = {}
+
+	for i = 1, select("#", ...) do
+		pieces[i] = tostring((select(i, ...)))
+	end
+
+	return table.concat(pieces, " ")
+end
+
+-- Finds the script and line that called the output function, given how far up
+-- the stack it is from the function asking.
+local function callerLocation(level)
+	local source, line = debug.info(level + 1, "sl")
+
+	if source == nil or source == "[C]" or line == nil or line < 1 then
+		return nil, nil
+	end
+
+	return source, line
+end
+
+local function emitOutput(level, index, ...)
+	local source, line = callerLocation(3)
+
+	rir._emit({
+		type = "Output",
+		level = level,
+		body = joinOutput(...),
+		script = index,
+		source = source,
+		line = line,
+		fromScript = true,
+	})
+end
+
+-- Output from the scripts that LogService hasn't delivered yet, which it
+-- can't tell apart from anything else Studio logs, as lists of where each
+-- line came from by its body.
+local pendingOutput = {}
+
+-- Notes that LogService will deliver output with this body from one of the
+-- scripts, and where it came from if that's known.
+function rir._expectOutput(body, source, line)
+	local locations = pendingOutput[body] or {}
+	pendingOutput[body] = locations
+
+	table.insert(locations, { source = source, line = line })
+end
+
+-- Takes what's known about output from LogService: whether it came from one
+-- of the scripts, and where from.
+function rir._takeOutput(body)
+	local locations = pendingOutput[body]
+	if locations == nil then
+		return false, nil, nil
+	end
+
+	local location = table.remove(locations, 1)
+	if #locations == 0 then
+		pendingOutput[body] = nil
+	end
+
+	return true, location.source, location.line
+end
+
+-- The index of each script run with --parallel-scripts, by its ModuleScript.
+-- Set by the plugin.
+rir._scriptIndexes = {}
+
+-- Finds the print and warn that a script sees. LogService can't tell which
+-- script printed something, so scripts run with --parallel-scripts get their
+-- own, which send output labeled with the script it came from. Others note
+-- where each line came from before printing it.
+function rir._outputFunctions(module)
+	local index = rir._scriptIndexes[module]
+
+	if index == nil then
+		return function(...)
+			rir._expectOutput(joinOutput(...), callerLocation(2))
+			print(...)
+		end, function(...)
+			rir._expectOutput(joinOutput(...), callerLocation(2))
+			warn(...)
+		end
+	end
+
+	return function(...)
+		emitOutput("Print", index, ...)
+	end, function(...)
+		emitOutput("Warning", index, ...)
+	end
+end
+
+local doneEvent = Instance.new("BindableEvent")
+local isDone = false
+
+-- Signals that the script has finished its work when run-in-roblox is run with
+-- `--wait-for done`. Calling it more than once has no effect.
+function rir.done()
+	if isDone then
+		return
+	end
+
+	isDone = true
+	doneEvent:Fire()
+end
+
+function rir._waitForDone()
+	if not isDone then
+		doneEvent.Event:Wait()
+	end
+end
+
+-- Ends the script and sets the code run-in-roblox exits with, which replaces
+-- the code that errors and failed checks would give. Returning a number from
+-- the script does the same.
+function rir.exit(code)
+	if typeof(code) ~= "number" or code % 1 ~= 0 or code < 0 or code > 255 then
+		error("rir.exit() expects a whole number from 0 to 255", 2)
+	end
+
+	rir._emit({
+		type = "ExitCode",
+		code = code,
+	})
+	rir.done()
+
+	error(rir._exitSignal, 0)
+end
+
+-- Lets the next script signal that it's done, when several are run.
+function rir._resetDone()
+	isDone = false
+end
+
+-- Records a passing check, which is counted in the summary at the end of the
+-- run.
+function rir.pass(name)
+	rir._emit({
+		type = "Check",
+		name = tostring(name),
+		passed = true,
+	})
+end
+
+-- Records a failing check. Any failing check makes the run fail.
+function rir.fail(name, reason)
+	rir._emit({
+		type = "Check",
+		name = tostring(name),
+		passed = false,
+		reason = reason ~= nil and tostring(reason) or nil,
+	})
+end
+
+-- Prints its arguments like print(), but only shows them when run-in-roblox is
+-- run with --verbose, for output that's only useful when something's wrong.
+function rir.debug(...)
+	emitOutput("Debug", nil, ...)
+end
+
+-- Like rir.debug(), for even more detailed output that's only shown when
+-- --verbose is given twice.
+function rir.trace(...)
+	emitOutput("Trace", nil, ...)
+end
+
+-- Sends a value to tools reading run-in-roblox's JSON output, like a test
+-- reporter's results. The value can be anything that can be converted to JSON.
+function rir.send(value)
+	local success, message = pcall(function()
+		return game:GetService("HttpService"):JSONEncode(value)
+	end)
+
+	if not success then
+		error("rir.send() expects a value that can be converted to JSON: " .. tostring(message), 2)
+	end
+
+	rir._emit({
+		type = "Data",
+		value = value,
+	})
+end
+
+-- The character for each 6-bit value in base64.
+local base64Digits = {}
+for index = 1, 64 do
+	base64Digits[index - 1] =
+		string.sub("ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/", index, index)
+end
+
+-- Encodes any bytes as base64, since only text can be sent to run-in-roblox.
+local function encodeBase64(data)
+	local digits = base64Digits
+	local parts = {}
+	for index = 1, #data, 3 do
+		local first, second, third = string.byte(data, index, index + 2)
+		local value = first * 65536 + (second or 0) * 256 + (third or 0)
+
+		table.insert(
+			parts,
+			digits[bit32.extract(value, 18, 6)]
+				.. digits[bit32.extract(value, 12, 6)]
+				.. (second and digits[bit32.extract(value, 6, 6)] or "=")
+				.. (third and digits[bit32.extract(value, 0, 6)] or "=")
+		)
+	end
+
+	return table.concat(parts)
+end
+
+-- Writes a text file into the directory run-in-roblox was given with --out-dir,
+-- like a coverage report. The path is relative to that directory and can
+-- include folders, which are created as needed.
+function rir.writeFile(path, contents)
+	if typeof(path) ~= "string" then
+		error("rir.writeFile() expects the path to write to as a string", 2)
+	end
+
+	if typeof(contents) ~= "string" then
+		error("rir.writeFile() expects the file's contents as a string", 2)
+	end
+
+	local success, message = rir._writeFile(path, contents)
+	if not success then
+		error(message, 2)
+	end
+end
+
+-- Returns the contents of a file in one of the folders run-in-roblox was given
+-- with --read-dir, like fixtures or golden files. The path is relative to where
+-- run-in-roblox was run.
+function rir.readFile(path)
+	if typeof(path) ~= "string" then
+		error("rir.readFile() expects the path to read as a string", 2)
+	end
+
+	local success, result = rir._readFile(path)
+	if not success then
+		error(result, 2)
+	end
+
+	return result
+end
+
+-- Saves a file in run-in-roblox's --artifacts-dir, like a screenshot, a
+-- serialized model, or a profiler dump. The contents can be a string, which can
+-- hold any bytes, or a buffer.
+function rir.artifact(name, contents)
+	if typeof(name) ~= "string" or name == "" or string.find(name, "[/\\:]") then
+		error("rir.artifact() expects a file name without any folders in it", 2)
+	end
+
+	if typeof(contents) == "buffer" then
+		contents = buffer.tostring(contents)
+	elseif typeof(contents) ~= "string" then
+		error("rir.artifact() expects the file's contents as a string or a buffer", 2)
+	end
+
+	rir._emit({
+		type = "Artifact",
+		name = name,
+		data = encodeBase64(contents),
+	})
+end
+
+-- Sends output and other messages queued so far to run-in-roblox right away
+-- instead of waiting for the next --flush-interval. Output that LogService
+-- hasn't delivered yet is sent with the next flush.
+function rir.flush()
+	rir._flush()
+end
+
+-- Handlers for commands sent with `run-in-roblox send`, by name, and the
+-- commands that arrived before there was a handler for them.
+local commandHandlers = {}
+local pendingCommands = {}
+local isCancelled = false
+
+-- Calls `handler` with the payload of each command named `name` sent to the
+-- run with `run-in-roblox send`, including ones sent before the handler was
+-- registered. Registering another handler for the same name replaces it.
+function rir.onCommand(name, handler)
+	if typeof(handler) ~= "function" then
+		error("rir.onCommand() expects a function to handle the command", 2)
+	end
+
+	commandHandlers[name] = guard(handler)
+
+	local stillPending = {}
+
+	for _, command in ipairs(pendingCommands) do
+		if command.name == name then
+			task.spawn(commandHandlers[name], command.payload)
+		else
+			table.insert(stillPending, command)
+		end
+	end
+
+	pendingCommands = stillPending
+end
+
+-- Whether the run has been cancelled with `run-in-roblox send cancel`.
+-- run-in-roblox stops waiting for the script when that happens, so scripts
+-- doing long work can check this to stop early.
+function rir.isCancelled()
+	return isCancelled
+end
+
+-- Handles a command picked up by the plugin. Cancelling the run also lets the
+-- script clean up with a handler for the "cancel" command.
+function rir._dispatchCommand(command)
+	local name = command.name
+
+	if command.type == "Cancel" then
+		isCancelled = true
+		name = "cancel"
+	end
+
+	local handler = commandHandlers[name]
+
+	if handler ~= nil then
+		task.spawn(handler, command.payload)
+	else
+		table.insert(pendingCommands, { name = name, payload = command.payload })
+	end
+end
+
+local function describe(value)
+	if typeof(value) == "string" then
+		return string.format("%q", value)
+	end
+
+	return tostring(value)
+end
+
+local function compareTree(instance, spec, path, mismatches)
+	if spec.class ~= nil and instance.ClassName ~= spec.class then
+		table.insert(mismatches, string.format("%s: ClassName", path))
+		table.insert(mismatches, string.format("  - %s", spec.class))
+		table.insert(mismatches, string.format("  + %s", instance.ClassName))
+	end
+
+	if spec.properties ~= nil then
+		for name, expected in pairs(spec.properties) do
+			local readSuccess, actual = pcall(function()
+				return instance[name]
+			end)
+
+			if not readSuccess then
+				table.insert(mismatches, string.format("%s: could not read property %s", path, name))
+			elseif actual ~= expected then
+				table.insert(mismatches, string.format("%s: %s", path, name))
+				table.insert(mismatches, string.format("  - %s", describe(expected)))
+				table.insert(mismatches, string.format("  + %s", describe(actual)))
+			end
+		end
+	end
+
+	if spec.children ~= nil then
+		for name, childSpec in pairs(spec.children) do
+			local child = instance:FindFirstChild(name)
+			local childPath = path .. "." .. name
+
+			if child == nil then
+				table.insert(mismatches, string.format("%s: missing child", childPath))
+			else
+				compareTree(child, childSpec, childPath, mismatches)
+			end
+		end
+
+		if spec.strict then
+			for _, child in ipairs(instance:GetChildren()) do
+				if spec.children[child.Name] == nil then
+					table.insert(
+						mismatches,
+						string.format("%s.%s: unexpected child (%s)", path, child.Name, child.ClassName)
+					)
+				end
+			end
+		end
+	end
+end
+
+-- Compares an instance tree against a declarative spec of the form
+--
+--     { class = "Model", properties = { ... }, children = { Name = spec } }
+--
+-- and raises an error listing every mismatch by path if they differ.
+function rir.expect_tree(instance, spec)
+	local mismatches = {}
+	compareTree(instance, spec, instance:GetFullName(), mismatches)
+
+	if #mismatches > 0 then
+		error("expect_tree failed:\n" .. table.concat(mismatches, "\n"), 2)
+	end
+
+	return true
+end
+
+return rir
@@ -0,0 +1,428 @@
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    net::TcpListener,
+    path::{Path, PathBuf},
+    process, thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+
+use crate::{clean, report};
+
+/// How many ports after the preferred one are tried before giving up.
+const PORT_RANGE: u16 = 100;
+
+/// How long to wait for another run to finish with the registry.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often to check whether the registry has been unlocked.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(25);
+
+/// How old a lock can get before we assume whoever held it crashed. Nobody
+/// holds the lock for more than a moment.
+const STALE_LOCK_AGE: Duration = Duration::from_secs(10);
+
+/// The ports that runs on this machine are using, along with the process that
+/// claimed each one.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Registry {
+    claims: Vec<Claim>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Claim {
+    port: u16,
+    pid: u32,
+}
+
+/// A port claimed in the registry, which is given back when this is dropped.
+#[derive(Debug)]
+pub struct PortClaim {
+    port: u16,
+
+    /// The registry the port was claimed in, if there is one.
+    registry_path: Option<PathBuf>,
+}
+
+impl PortClaim {
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl Drop for PortClaim {
+    fn drop(&mut self) {
+        let registry_path = match &self.registry_path {
+            Some(registry_path) => registry_path,
+            None => return,
+        };
+
+        let claim = Claim {
+            port: self.port,
+            pid: process::id(),
+        };
+
+        let released = update(registry_path, |registry| {
+            registry.claims.retain(|existing| *existing != claim);
+            Ok(())
+        });
+
+        if let Err(err) = released {
+            log::debug!("Could not give back port {}: {:#}", self.port, err);
+        }
+    }
+}
+
+/// A run's token, written next to the registry so that `run-in-roblox send`
+/// can send commands to the run on its port. Only the user running
+/// run-in-roblox can read it. It's removed when this is dropped.
+pub struct TokenFile(Option<PathBuf>);
+
+impl Drop for TokenFile {
+    fn drop(&mut self) {
+        if let Some(path) = &self.0 {
+            let _ignored = fs::remove_file(path);
+        }
+    }
+}
+
+/// Writes the token of the run on `port` for `read_token`. If the registry
+/// can't be used, the token isn't written and the run can't be sent commands.
+pub fn write_token(port: u16, token: &str) -> TokenFile {
+    let path = match token_path(port) {
+        Some(path) => path,
+        None => return TokenFile(None),
+    };
+
+    match write_private(&path, token) {
+        Ok(()) => TokenFile(Some(path)),
+        Err(err) => {
+            log::warn!(
+                "Could not write the token for `run-in-roblox send` to {}: {:#}",
+                path.display(),
+                err
+            );
+            TokenFile(None)
+        }
+    }
+}
+
+/// Reads the token of the run on `port`, written by `write_token`.
+pub fn read_token(port: u16) -> Result<String, anyhow::Error> {
+    let path = token_path(port).context("Could not find run-in-roblox's cache folder")?;
+
+    fs::read_to_string(&path)
+        .map(|token| token.trim().to_owned())
+        .with_context(|| format!("Could not read the token of the run on port {}", port))
+}
+
+fn token_path(port: u16) -> Option<PathBuf> {
+    registry_path().and_then(|registry_path| {
+        Some(
+            registry_path
+                .parent()?
+                .join("tokens")
+                .join(port.to_string()),
+        )
+    })
+}
+
+/// Writes a file that only the current user can read. A token left behind by
+/// a run that crashed is replaced, rather than written into, so that it can't
+/// have been made readable by anyone else.
+fn write_private(path: &Path, contents: &str) -> Result<(), anyhow::Error> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+        if let Some(parent) = path.parent() {
+            fs::set_permissions(parent, std::fs::Permissions::from_mode(0o700))?;
+        }
+
+        options.mode(0o600);
+    }
+
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+        _ => {}
+    }
+
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Could not create {}", path.display()))?;
+    file.write_all(contents.as_bytes())?;
+
+    Ok(())
+}
+
+/// Claims the first port from `preferred` onwards that no other run on this
+/// machine has claimed and that nothing else is listening on. Claims left
+/// behind by processes that have exited are removed first.
+///
+/// Runs pick ports in the same order, so a run that has the machine to itself
+/// always gets `preferred`. If the registry can't be used, `preferred` is
+/// used without claiming it, like it was before runs kept track of their
+/// ports.
+pub fn claim(preferred: u16) -> Result<PortClaim, anyhow::Error> {
+    let registry_path = match registry_path() {
+        Some(registry_path) => registry_path,
+        None => {
+            return Ok(PortClaim {
+                port: preferred,
+                registry_path: None,
+            })
+        }
+    };
+
+    let last = preferred.saturating_add(PORT_RANGE - 1);
+    let mut claimed = None;
+
+    let result = update(&registry_path, |registry| {
+        for port in preferred..=last {
+            let taken = registry.claims.iter().any(|claim| claim.port == port);
+
+            if !taken && TcpListener::bind(("127.0.0.1", port)).is_ok() {
+                registry.claims.push(Claim {
+                    port,
+                    pid: process::id(),
+                });
+                claimed = Some(port);
+                break;
+            }
+        }
+
+        Ok(())
+    });
+
+    if let Err(err) = result {
+        log::warn!(
+            "Could not use the port registry at {}, using port {}: {:#}",
+            registry_path.display(),
+            preferred,
+            err
+        );
+
+        return Ok(PortClaim {
+            port: preferred,
+            registry_path: None,
+        });
+    }
+
+    match claimed {
+        Some(port) => Ok(PortClaim {
+            port,
+            registry_path: Some(registry_path),
+        }),
+        None => bail!(
+            "Could not find a free port between {} and {}; other runs or programs are \
+             using all of them",
+            preferred,
+            last
+        ),
+    }
+}
+
+/// Claims `port` and only `port`, for runs given --port, failing with an
+/// error that says what's using it if it's taken.
+pub fn claim_exact(port: u16) -> Result<PortClaim, anyhow::Error> {
+    if port == 0 {
+        bail!("--port must be a port between 1 and 65535");
+    }
+
+    let in_use = || TcpListener::bind(("127.0.0.1", port)).is_err();
+
+    let registry_path = match registry_path() {
+        Some(registry_path) => registry_path,
+        None if in_use() => bail!("Port {} is already in use by another program", port),
+        None => {
+            return Ok(PortClaim {
+                port,
+                registry_path: None,
+            })
+        }
+    };
+
+    let mut claimed_by = None;
+    let mut claimed = false;
+
+    let result = update(&registry_path, |registry| {
+        claimed_by = registry
+            .claims
+            .iter()
+            .find(|claim| claim.port == port)
+            .map(|claim| claim.pid);
+
+        if claimed_by.is_none() && !in_use() {
+            registry.claims.push(Claim {
+                port,
+                pid: process::id(),
+            });
+            claimed = true;
+        }
+
+        Ok(())
+    });
+
+    if let Err(err) = result {
+        log::warn!(
+            "Could not use the port registry at {}, using port {}: {:#}",
+            registry_path.display(),
+            port,
+            err
+        );
+
+        if in_use() {
+            bail!("Port {} is already in use by another program", port);
+        }
+
+        return Ok(PortClaim {
+            port,
+            registry_path: None,
+        });
+    }
+
+    match (claimed, claimed_by) {
+        (true, _) => Ok(PortClaim {
+            port,
+            registry_path: Some(registry_path),
+        }),
+        (false, Some(pid)) => bail!(
+            "Port {} is already being used by another run of run-in-roblox (process {})",
+            port,
+            pid
+        ),
+        (false, None) => bail!("Port {} is already in use by another program", port),
+    }
+}
+
+/// The ports claimed by runs that are still going, along with the process that
+/// claimed each one.
+pub fn claims() -> Vec<(u16, u32)> {
+    let registry: Registry = registry_path()
+        .and_then(|registry_path| fs::read(registry_path).ok())
+        .and_then(|contents| serde_json::from_slice(&contents).ok())
+        .unwrap_or_default();
+
+    registry
+        .claims
+        .into_iter()
+        .filter(|claim| clean::process_exists(claim.pid))
+        .map(|claim| (claim.port, claim.pid))
+        .collect()
+}
+
+fn registry_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("run-in-roblox").join("ports.json"))
+}
+
+/// Changes the registry while holding its lock, after removing claims made by
+/// processes that have exited.
+fn update(
+    registry_path: &Path,
+    change: impl FnOnce(&mut Registry) -> Result<(), anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    let _lock = LockFile::acquire(&registry_path.with_extension("lock"), Some(LOCK_TIMEOUT))?;
+
+    // A missing or unreadable registry is treated as empty, since the worst
+    // that can happen is that we pick a port another run is about to use, like
+    // we would without a registry.
+    let mut registry: Registry = fs::read(registry_path)
+        .ok()
+        .and_then(|contents| serde_json::from_slice(&contents).ok())
+        .unwrap_or_default();
+
+    registry.claims.retain(|claim| {
+        let alive = claim.pid == process::id() || clean::process_exists(claim.pid);
+
+        if !alive {
+            log::debug!(
+                "Removing port {} claimed by process {}, which has exited",
+                claim.port,
+                claim.pid
+            );
+        }
+
+        alive
+    });
+
+    change(&mut registry)?;
+
+    report::write_atomic(registry_path, &serde_json::to_vec_pretty(&registry)?)
+}
+
+/// A lock file that keeps other runs on the machine from doing something at
+/// the same time, like changing the registry, removed when dropped. It holds
+/// the ID of the process that took it, so that a lock left behind by a run
+/// that crashed can be told apart from one that's still held.
+pub struct LockFile(PathBuf);
+
+impl LockFile {
+    /// Takes the lock at `path`, waiting up to `timeout` for whoever holds it,
+    /// or for as long as they're running if there's no timeout. Locks taken
+    /// with a timeout are only ever held for a moment, so one that's older
+    /// than that is assumed to have been left behind too.
+    pub fn acquire(path: &Path, timeout: Option<Duration>) -> Result<Self, anyhow::Error> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let started = Instant::now();
+
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(mut file) => {
+                    // If this fails, the lock still works, but can only be
+                    // recognized as stale by its age.
+                    let _ignored = write!(file, "{}", process::id());
+
+                    return Ok(LockFile(path.to_path_buf()));
+                }
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(err) => {
+                    return Err(err).with_context(|| format!("Could not lock {}", path.display()))
+                }
+            }
+
+            let owner_exited = fs::read_to_string(path)
+                .ok()
+                .and_then(|contents| contents.trim().parse::<u32>().ok())
+                .is_some_and(|pid| !clean::process_exists(pid));
+
+            let lock_age = fs::metadata(path)
+                .ok()
+                .and_then(|metadata| metadata.modified().ok())
+                .and_then(|modified| modified.elapsed().ok());
+            let too_old =
+                timeout.is_some() && matches!(lock_age, Some(age) if age > STALE_LOCK_AGE);
+
+            if owner_exited || too_old {
+                log::debug!("Removing stale lock {}", path.display());
+                let _ignored = fs::remove_file(path);
+                continue;
+            }
+
+            if matches!(timeout, Some(timeout) if started.elapsed() > timeout) {
+                bail!("Timed out waiting for {} to be unlocked", path.display());
+            }
+
+            thread::sleep(LOCK_POLL_INTERVAL);
+        }
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        let _ignored = fs::remove_file(&self.0);
+    }
+}
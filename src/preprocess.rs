@@ -0,0 +1,78 @@
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+    thread,
+};
+
+use anyhow::{anyhow, bail, Context};
+
+use crate::bundle::BundledModule;
+
+/// Runs a source through the --preprocess command, which reads it from stdin
+/// and writes the source to run to stdout.
+pub fn run(command: &str, source: &str) -> Result<String, anyhow::Error> {
+    let mut child = shell_command(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Could not run the --preprocess command {:?}", command))?;
+
+    // The source is written from another thread so that a command that starts
+    // writing before it has read everything can't fill up its stdout and
+    // stall both of us.
+    let mut stdin = child.stdin.take().unwrap();
+    let input = source.to_owned();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+    let output = child.wait_with_output()?;
+
+    // Commands that don't read all of their input close stdin early, which is
+    // fine as long as they succeed.
+    let _ignored = writer
+        .join()
+        .map_err(|_| anyhow!("Writing to the --preprocess command panicked"))?;
+
+    if !output.status.success() {
+        bail!(
+            "The --preprocess command {:?} failed ({}):\n{}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        );
+    }
+
+    String::from_utf8(output.stdout)
+        .with_context(|| format!("The --preprocess command {:?} wrote invalid UTF-8", command))
+}
+
+/// Runs a bundled module, and every module inside it, through the
+/// --preprocess command.
+pub fn run_module(command: &str, module: &mut BundledModule) -> Result<(), anyhow::Error> {
+    if let Some(source) = &module.source {
+        module.source = Some(
+            run(command, source)
+                .with_context(|| format!("Could not preprocess the module {}", module.name))?,
+        );
+    }
+
+    for child in &mut module.children {
+        run_module(command, child)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(windows)]
+fn shell_command(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(not(windows))]
+fn shell_command(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
@@ -0,0 +1,86 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{bail, Context};
+use fs_err as fs;
+use serde_json::Value;
+
+use crate::transcript::{self, EntryKind};
+
+/// Builds the place described by a Rojo project with `rojo build`, returning
+/// the path of the place file it wrote in `temp_dir`.
+pub fn build(project_path: &Path, temp_dir: &Path) -> Result<PathBuf, anyhow::Error> {
+    let place_path = temp_dir.join("run-in-roblox-project.rbxl");
+
+    let output = Command::new("rojo")
+        .arg("build")
+        .arg(project_path)
+        .arg("--output")
+        .arg(&place_path)
+        .output()
+        .context(
+            "Could not run rojo, which --project needs to build the place. Is Rojo installed \
+             and on the PATH?",
+        )?;
+
+    if !output.status.success() {
+        bail!(
+            "Rojo could not build {} ({}):\n{}",
+            project_path.display(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        );
+    }
+
+    transcript::record(
+        EntryKind::Lifecycle,
+        None,
+        format!("Built {} with Rojo", project_path.display()),
+    );
+
+    Ok(place_path)
+}
+
+/// The files that a Rojo project builds from: the project file itself and
+/// everything its tree points to with `$path`.
+pub fn source_paths(project_path: &Path) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let contents = fs::read(project_path)?;
+    let project: Value = serde_json::from_slice(&contents)
+        .with_context(|| format!("Could not read the Rojo project {}", project_path.display()))?;
+
+    let project_dir = project_path.parent().unwrap_or_else(|| Path::new(""));
+    let mut paths = vec![project_path.to_path_buf()];
+
+    if let Some(tree) = project.get("tree") {
+        collect_paths(tree, project_dir, &mut paths);
+    }
+
+    Ok(paths)
+}
+
+fn collect_paths(node: &Value, project_dir: &Path, paths: &mut Vec<PathBuf>) {
+    let children = match node.as_object() {
+        Some(children) => children,
+        None => return,
+    };
+
+    // `$path` is either a path or an object with an `optional` path that
+    // might not exist.
+    let path = children.get("$path").and_then(|path| match path {
+        Value::String(path) => Some(path.as_str()),
+        Value::Object(path) => path.get("optional").and_then(Value::as_str),
+        _ => None,
+    });
+
+    if let Some(path) = path {
+        paths.push(project_dir.join(path));
+    }
+
+    for (name, child) in children {
+        if !name.starts_with('$') {
+            collect_paths(child, project_dir, paths);
+        }
+    }
+}
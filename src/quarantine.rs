@@ -0,0 +1,150 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use fs_err as fs;
+
+/// Known-flaky scripts and checks, loaded from the file given with
+/// `--quarantine`. Their failures are still reported, but as warnings that
+/// don't fail the run.
+///
+/// The file uses a small subset of TOML, with string arrays named `scripts`
+/// and `checks`:
+///
+/// ```toml
+/// # Paths are relative to this file.
+/// scripts = ["tests/flaky-teleport.lua"]
+/// checks = ["leaderboard sorts ties"]
+/// ```
+#[derive(Debug, Default)]
+pub struct Quarantine {
+    scripts: Vec<PathBuf>,
+    checks: Vec<String>,
+}
+
+impl Quarantine {
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let contents = fs::read_to_string(path)?;
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+
+        let mut quarantine = Quarantine::default();
+
+        for (key, values) in parse_string_arrays(&contents)
+            .with_context(|| format!("Invalid quarantine file {}", path.display()))?
+        {
+            match key.as_str() {
+                "scripts" => quarantine
+                    .scripts
+                    .extend(values.iter().map(|value| normalize_path(&base.join(value)))),
+                "checks" => quarantine.checks.extend(values),
+                _ => bail!(
+                    "Unknown key {:?} in quarantine file {}, expected scripts or checks",
+                    key,
+                    path.display()
+                ),
+            }
+        }
+
+        Ok(quarantine)
+    }
+
+    pub fn contains_script(&self, script_path: &Path) -> bool {
+        self.scripts.contains(&normalize_path(script_path))
+    }
+
+    pub fn contains_check(&self, name: &str) -> bool {
+        self.checks.iter().any(|check| check == name)
+    }
+}
+
+/// Makes paths comparable even if they were written differently, falling back
+/// to the path as given if it doesn't exist.
+fn normalize_path(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Parses `key = ["a", "b"]` entries, which may span several lines, skipping
+/// blank lines and `#` comments.
+pub fn parse_string_arrays(contents: &str) -> Result<Vec<(String, Vec<String>)>, anyhow::Error> {
+    let mut entries = Vec::new();
+    let mut chars = contents.chars().peekable();
+
+    loop {
+        skip_trivia(&mut chars);
+
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut key = String::new();
+        while let Some(&c) = chars.peek() {
+            if c == '=' || c.is_whitespace() {
+                break;
+            }
+            key.push(c);
+            chars.next();
+        }
+
+        skip_trivia(&mut chars);
+
+        if key.is_empty() || chars.next() != Some('=') {
+            bail!("Expected `key = [...]`");
+        }
+
+        skip_trivia(&mut chars);
+
+        if chars.next() != Some('[') {
+            bail!("Expected an array of strings for {}", key);
+        }
+
+        let mut values = Vec::new();
+
+        loop {
+            skip_trivia(&mut chars);
+
+            match chars.next() {
+                Some(']') => break,
+                Some(',') => continue,
+                Some(quote @ '"') | Some(quote @ '\'') => {
+                    let mut value = String::new();
+
+                    loop {
+                        match chars.next() {
+                            Some(c) if c == quote => break,
+                            Some('\\') if quote == '"' => match chars.next() {
+                                Some('n') => value.push('\n'),
+                                Some('t') => value.push('\t'),
+                                Some(c) => value.push(c),
+                                None => bail!("Unterminated string in {}", key),
+                            },
+                            Some('\n') | None => bail!("Unterminated string in {}", key),
+                            Some(c) => value.push(c),
+                        }
+                    }
+
+                    values.push(value);
+                }
+                _ => bail!("Expected an array of strings for {}", key),
+            }
+        }
+
+        entries.push((key, values));
+    }
+
+    Ok(entries)
+}
+
+fn skip_trivia(chars: &mut std::iter::Peekable<std::str::Chars>) {
+    while let Some(&c) = chars.peek() {
+        if c == '#' {
+            for c in chars.by_ref() {
+                if c == '\n' {
+                    break;
+                }
+            }
+        } else if c.is_whitespace() {
+            chars.next();
+        } else {
+            break;
+        }
+    }
+}
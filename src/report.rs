@@ -0,0 +1,211 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process,
+    sync::Mutex,
+};
+
+use fs_err as fs;
+use serde::Serialize;
+
+use crate::output::{FormatVersion, SkippedScript};
+
+/// The report being written for --report, if any. Like the transcript, it's
+/// filled in from wherever the information turns up during the run.
+static REPORT: Mutex<Option<(PathBuf, RunReport)>> = Mutex::new(None);
+
+/// How a run went, written with --report for tools that start run-in-roblox to
+/// read once it exits.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunReport {
+    pub format_version: u32,
+
+    /// Whether run-in-roblox got as far as recording how the run ended. A
+    /// report that isn't finalized belongs to a run that's still going, or
+    /// one where run-in-roblox crashed or was killed.
+    pub finalized: bool,
+
+    /// The code run-in-roblox exited with, once the report is finalized.
+    pub exit_code: Option<i32>,
+
+    pub studio_version: Option<String>,
+    pub variant: Option<String>,
+    pub checks_passed: usize,
+    pub checks_failed: usize,
+
+    /// Errors and failed checks that --quarantine excused.
+    pub quarantined_failures: Vec<String>,
+
+    /// The main category of failure, when --triage is given and the run
+    /// failed.
+    pub triage: Option<String>,
+
+    /// Whether the run was skipped because --cache-results found a recorded
+    /// pass, whose checks are counted here.
+    pub cached: bool,
+
+    /// The scripts whose results don't count, and why.
+    pub skipped: Vec<SkippedScript>,
+}
+
+/// Starts a report at `path`, writing it out straight away so that a run that
+/// never finishes still leaves an unfinalized report behind.
+pub fn start(path: &Path, format_version: FormatVersion) -> Result<(), anyhow::Error> {
+    let report = RunReport {
+        format_version: format_version.0,
+        ..RunReport::default()
+    };
+
+    write_atomic(path, &serde_json::to_vec_pretty(&report)?)?;
+    *REPORT.lock().unwrap() = Some((path.to_owned(), report));
+
+    Ok(())
+}
+
+/// Changes the report, if one is being written. Failing to write the change
+/// out isn't fatal, since the report is written again when it's finalized.
+pub fn update(change: impl FnOnce(&mut RunReport)) {
+    let mut report = REPORT.lock().unwrap();
+
+    if let Some((path, report)) = report.as_mut() {
+        change(report);
+
+        if let Err(err) = write_report(path, report) {
+            log::debug!("Could not update the --report file: {}", err);
+        }
+    }
+}
+
+/// Records the exit code and marks the report as finalized. This is the last
+/// thing run-in-roblox does before exiting.
+pub fn finish(exit_code: i32) -> Result<(), anyhow::Error> {
+    let mut report = REPORT.lock().unwrap();
+
+    if let Some((path, report)) = report.as_mut() {
+        report.finalized = true;
+        report.exit_code = Some(exit_code);
+
+        write_report(path, report)?;
+    }
+
+    Ok(())
+}
+
+fn write_report(path: &Path, report: &RunReport) -> Result<(), anyhow::Error> {
+    write_atomic(path, &serde_json::to_vec_pretty(report)?)
+}
+
+/// Replaces the file at `path` in one step by writing to a temporary file next
+/// to it and renaming it into place, so that readers only ever see the old
+/// contents or the new ones, even if we crash part way through. The temporary
+/// file is flushed to disk before it's renamed, so that a power loss can't
+/// leave the new name pointing at contents that were never written.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), anyhow::Error> {
+    if let Some(parent) = path.parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+
+    let temp_path = path.with_extension(format!("{}.tmp", process::id()));
+    let written = fs::File::create(&temp_path).and_then(|mut file| {
+        file.write_all(contents)?;
+        file.sync_all()
+    });
+
+    if let Err(err) = written {
+        let _ignored = fs::remove_file(&temp_path);
+        return Err(err.into());
+    }
+
+    if let Err(err) = fs::rename(&temp_path, path) {
+        let _ignored = fs::remove_file(&temp_path);
+        return Err(err.into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::Value;
+
+    /// Tests that start reports share `REPORT`, so they take turns.
+    static REPORT_LOCK: Mutex<()> = Mutex::new(());
+
+    fn read_report(path: &Path) -> Value {
+        serde_json::from_slice(&fs::read(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn unfinished_report_is_not_finalized() {
+        let _lock = REPORT_LOCK.lock().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.json");
+
+        start(&path, FormatVersion::default()).unwrap();
+        update(|report| report.checks_passed = 2);
+        *REPORT.lock().unwrap() = None;
+
+        let report = read_report(&path);
+        assert_eq!(report["finalized"], false);
+        assert_eq!(report["exitCode"], Value::Null);
+        assert_eq!(report["checksPassed"], 2);
+    }
+
+    #[test]
+    fn finish_records_exit_code() {
+        let _lock = REPORT_LOCK.lock().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.json");
+
+        start(&path, FormatVersion::default()).unwrap();
+        finish(3).unwrap();
+        *REPORT.lock().unwrap() = None;
+
+        let report = read_report(&path);
+        assert_eq!(report["finalized"], true);
+        assert_eq!(report["exitCode"], 3);
+    }
+
+    #[test]
+    fn leftover_temp_file_does_not_touch_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.json");
+        write_atomic(&path, b"{\"finalized\":true}").unwrap();
+
+        // Like a run that crashed while writing its report.
+        let other_temp_path = dir.path().join("report.1.tmp");
+        fs::write(&other_temp_path, b"{\"finali").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"{\"finalized\":true}");
+
+        // A leftover from an earlier process with our ID is longer than what
+        // replaces it, which mustn't leave its end behind.
+        let temp_path = path.with_extension(format!("{}.tmp", process::id()));
+        fs::write(&temp_path, b"{\"finalized\":false,\"exitCode\":null}").unwrap();
+
+        write_atomic(&path, b"{}").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"{}");
+        assert!(!temp_path.exists());
+        assert_eq!(fs::read(&other_temp_path).unwrap(), b"{\"finali");
+    }
+
+    #[test]
+    fn failed_rename_removes_temp_file() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // A file can't be renamed over a folder that has something in it.
+        let path = dir.path().join("report.json");
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join("inside"), b"").unwrap();
+
+        assert!(write_atomic(&path, b"{}").is_err());
+        assert!(!path
+            .with_extension(format!("{}.tmp", process::id()))
+            .exists());
+        assert!(path.is_dir());
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    fmt::Debug,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+
+use crate::report;
+
+/// Identifies a run by everything that could change whether it passes, like
+/// the contents of its scripts and place and the options it was given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheKey(String);
+
+impl CacheKey {
+    pub fn builder() -> KeyBuilder {
+        let mut builder = KeyBuilder {
+            hasher: blake3::Hasher::new(),
+        };
+
+        // A new version of run-in-roblox might run scripts differently, so it
+        // doesn't trust passes recorded by older ones.
+        builder.add("version", env!("CARGO_PKG_VERSION").as_bytes());
+        builder
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Hashes the inputs of a run into a `CacheKey`.
+pub struct KeyBuilder {
+    hasher: blake3::Hasher,
+}
+
+impl KeyBuilder {
+    /// Adds one input. Inputs are labeled and their lengths are hashed along
+    /// with them, so moving bytes from one input to the next changes the key.
+    pub fn add(&mut self, label: &str, contents: &[u8]) {
+        for part in [label.as_bytes(), contents] {
+            self.hasher.update(&(part.len() as u64).to_le_bytes());
+            self.hasher.update(part);
+        }
+    }
+
+    /// Adds an option's value, as it's printed with `{:?}`.
+    pub fn add_debug(&mut self, label: &str, value: &impl Debug) {
+        self.add(label, format!("{:?}", value).as_bytes());
+    }
+
+    /// Adds the contents of a file, or of every file in a folder.
+    pub fn add_path(&mut self, label: &str, path: &Path) -> Result<(), anyhow::Error> {
+        if path.is_dir() {
+            let mut entries = fs::read_dir(path)?.collect::<Result<Vec<_>, _>>()?;
+            entries.sort_by_key(|entry| entry.file_name());
+
+            for entry in entries {
+                let name = entry.file_name();
+                let label = format!("{}/{}", label, name.to_string_lossy());
+
+                self.add_path(&label, &entry.path())?;
+            }
+        } else {
+            self.add(label, &fs::read(path)?);
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> CacheKey {
+        CacheKey(self.hasher.finalize().to_hex().to_string())
+    }
+}
+
+/// A run that passed, recorded with --cache-results so that it can be skipped
+/// while nothing changes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedPass {
+    /// When the run passed, in seconds since the Unix epoch.
+    pub passed_at: u64,
+    pub checks_passed: usize,
+}
+
+impl CachedPass {
+    pub fn new(checks_passed: usize) -> Self {
+        let passed_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        CachedPass {
+            passed_at,
+            checks_passed,
+        }
+    }
+
+    pub fn passed_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.passed_at)
+    }
+}
+
+/// Finds the pass recorded for a run with the same inputs, if there is one.
+pub fn lookup(key: &CacheKey) -> Option<CachedPass> {
+    let path = entry_path(key)?;
+    let contents = fs::read(&path).ok()?;
+
+    match serde_json::from_slice(&contents) {
+        Ok(pass) => Some(pass),
+        Err(err) => {
+            log::debug!(
+                "Ignoring unreadable cached result {}: {}",
+                path.display(),
+                err
+            );
+            None
+        }
+    }
+}
+
+/// Records that a run with these inputs passed.
+pub fn record(key: &CacheKey, pass: &CachedPass) -> Result<(), anyhow::Error> {
+    let path = entry_path(key).context("Could not find a cache folder to record the pass in")?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    report::write_atomic(&path, &serde_json::to_vec_pretty(pass)?)
+}
+
+/// Where passes are recorded, which CI systems can keep between builds.
+pub fn cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("run-in-roblox").join("results"))
+}
+
+fn entry_path(key: &CacheKey) -> Option<PathBuf> {
+    cache_dir().map(|dir| dir.join(format!("{}.json", key.as_str())))
+}
@@ -0,0 +1,165 @@
+use std::{any::Any, fmt, panic, str::FromStr, sync::mpsc, thread, time::Instant};
+
+use anyhow::{anyhow, bail};
+use serde::Serialize;
+
+use crate::{
+    message_receiver::{PlayContext, RobloxMessage},
+    place_runner::KillOnDrop,
+};
+
+/// A message from the script along with when it was produced, in host time.
+#[derive(Debug)]
+pub struct TimedMessage {
+    pub at: Instant,
+
+    /// The index of the script the message came from, starting at 1, when
+    /// scripts are run in parallel and their output is interleaved.
+    pub script: Option<usize>,
+
+    /// Which side of the play test the message came from, with --mode play.
+    pub context: Option<PlayContext>,
+
+    pub message: RobloxMessage,
+}
+
+/// A step in the life of a run. Backends report the steps that make sense for
+/// them; only Studio is launched and has a plugin to connect, for example.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LifecycleEvent {
+    StudioLaunched,
+
+    /// Studio couldn't open the place, so it was closed and launched again
+    /// with a fresh copy.
+    StudioRelaunched,
+
+    PluginConnected,
+    ScriptStarted,
+    ScriptFinished,
+    StudioExited,
+}
+
+/// Something that happened during a run, as seen by whoever started it.
+pub enum RunnerEvent {
+    /// The script produced a message.
+    Message(TimedMessage),
+
+    /// The run reached a new step.
+    Lifecycle { at: Instant, event: LifecycleEvent },
+
+    /// The run finished, leaving behind the process the backend returned, if
+    /// any.
+    Finished(Option<KillOnDrop>),
+
+    /// The run failed, either with an error from the backend or because it
+    /// panicked.
+    Failed(anyhow::Error),
+}
+
+/// Hands messages from a runner to whoever started the run.
+#[derive(Clone)]
+pub struct MessageSender(mpsc::Sender<RunnerEvent>);
+
+impl MessageSender {
+    pub fn send(&self, message: TimedMessage) -> Result<(), anyhow::Error> {
+        self.0
+            .send(RunnerEvent::Message(message))
+            .map_err(|_| anyhow!("The run was abandoned before the script finished"))
+    }
+
+    pub fn lifecycle(&self, event: LifecycleEvent) -> Result<(), anyhow::Error> {
+        self.0
+            .send(RunnerEvent::Lifecycle {
+                at: Instant::now(),
+                event,
+            })
+            .map_err(|_| anyhow!("The run was abandoned before the script finished"))
+    }
+}
+
+/// Starts `runner` on its own thread. However the run ends, the last event
+/// received is `Finished` or `Failed`, including when the runner panics.
+pub fn spawn(runner: Box<dyn ScriptRunner>) -> mpsc::Receiver<RunnerEvent> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let message_sender = MessageSender(sender.clone());
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| runner.run(message_sender)));
+
+        let event = match result {
+            Ok(Ok(process)) => RunnerEvent::Finished(process),
+            Ok(Err(err)) => RunnerEvent::Failed(err),
+            Err(payload) => RunnerEvent::Failed(anyhow!(
+                "The runner crashed: {}",
+                panic_message(payload.as_ref())
+            )),
+        };
+
+        let _ignored = sender.send(event);
+    });
+
+    receiver
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+/// An environment that scripts can be run in, chosen with `--backend`.
+///
+/// Every backend reports what the script does through the same messages, so
+/// output, checks, and summaries work the same no matter where it ran.
+pub trait ScriptRunner: Send {
+    /// Runs the script, sending each message it produces to `sender`.
+    /// Backends that leave a process running for `--keep-open` and
+    /// `--on-failure inspect` return it.
+    fn run(&self, sender: MessageSender) -> Result<Option<KillOnDrop>, anyhow::Error>;
+}
+
+/// Which `ScriptRunner` to use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// Roblox Studio, with the full engine.
+    Studio,
+
+    /// The Lune standalone Luau runtime, for scripts that don't need the
+    /// engine.
+    Lune,
+
+    /// Pretends to run the script by reporting the output and checks it
+    /// contains, without running anything.
+    Mock,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Backend::Studio => "studio",
+            Backend::Lune => "lune",
+            Backend::Mock => "mock",
+        };
+
+        formatter.write_str(name)
+    }
+}
+
+impl FromStr for Backend {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "studio" => Ok(Backend::Studio),
+            "lune" => Ok(Backend::Lune),
+            "mock" => Ok(Backend::Mock),
+            "open-cloud" => bail!("The open-cloud backend isn't supported yet"),
+            _ => bail!("Invalid value for --backend, expected \"studio\", \"lune\", or \"mock\""),
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! JSON Schemas for the data that run-in-roblox exchanges with other programs,
+//! and a small validator for the subset of JSON Schema that they use.
+
+use serde_json::Value;
+
+use crate::output::FormatVersion;
+
+pub struct EmbeddedSchema {
+    pub name: &'static str,
+    pub version: FormatVersion,
+    pub source: &'static str,
+}
+
+/// Every schema embedded in run-in-roblox. Each format version that can still
+/// be emitted keeps its own copy of the schema.
+pub static SCHEMAS: &[EmbeddedSchema] = &[
+    EmbeddedSchema {
+        name: "protocol",
+        version: FormatVersion(1),
+        source: include_str!("schemas/protocol.schema.json"),
+    },
+    EmbeddedSchema {
+        name: "usage",
+        version: FormatVersion(1),
+        source: include_str!("schemas/usage.schema.json"),
+    },
+    EmbeddedSchema {
+        name: "transcript",
+        version: FormatVersion(1),
+        source: include_str!("schemas/transcript.schema.json"),
+    },
+    EmbeddedSchema {
+        name: "output",
+        version: FormatVersion(1),
+        source: include_str!("schemas/output.schema.json"),
+    },
+    EmbeddedSchema {
+        name: "report",
+        version: FormatVersion(1),
+        source: include_str!("schemas/report.schema.json"),
+    },
+    EmbeddedSchema {
+        name: "self-test",
+        version: FormatVersion(1),
+        source: include_str!("schemas/self-test.schema.json"),
+    },
+];
+
+pub fn find(name: &str, version: FormatVersion) -> Option<&'static str> {
+    SCHEMAS
+        .iter()
+        .find(|schema| schema.name == name && schema.version == version)
+        .map(|schema| schema.source)
+}
+
+/// A schema parsed and ready to validate values against.
+pub struct Schema {
+    root: Value,
+}
+
+impl Schema {
+    pub fn load(name: &str, version: FormatVersion) -> Option<Schema> {
+        let source = find(name, version)?;
+        let root = serde_json::from_str(source).expect("Embedded schema was not valid JSON");
+
+        Some(Schema { root })
+    }
+
+    /// Validates a value, returning a description of each problem found.
+    pub fn validate(&self, value: &Value) -> Vec<String> {
+        let mut errors = Vec::new();
+        self.validate_node(&self.root, value, "$", &mut errors);
+        errors
+    }
+
+    fn resolve<'a>(&'a self, schema: &'a Value) -> &'a Value {
+        match schema.get("$ref").and_then(Value::as_str) {
+            Some(reference) => {
+                let pointer = reference.trim_start_matches('#');
+                self.root
+                    .pointer(pointer)
+                    .unwrap_or_else(|| panic!("Unknown schema reference {}", reference))
+            }
+            None => schema,
+        }
+    }
+
+    fn validate_node(&self, schema: &Value, value: &Value, path: &str, errors: &mut Vec<String>) {
+        let schema = self.resolve(schema);
+
+        if let Some(expected) = schema.get("const") {
+            if value != expected {
+                errors.push(format!("{}: expected {}", path, expected));
+            }
+        }
+
+        if let Some(options) = schema.get("enum").and_then(Value::as_array) {
+            if !options.contains(value) {
+                errors.push(format!("{}: {} is not one of {:?}", path, value, options));
+            }
+        }
+
+        if let Some(expected) = schema.get("type").and_then(Value::as_str) {
+            let matches = match expected {
+                "object" => value.is_object(),
+                "array" => value.is_array(),
+                "string" => value.is_string(),
+                "boolean" => value.is_boolean(),
+                "number" => value.is_number(),
+                "integer" => value.is_i64() || value.is_u64(),
+                "null" => value.is_null(),
+                _ => true,
+            };
+
+            if !matches {
+                errors.push(format!("{}: expected {}", path, expected));
+                return;
+            }
+        }
+
+        if let Some(options) = schema.get("oneOf").and_then(Value::as_array) {
+            let matching = options
+                .iter()
+                .filter(|option| {
+                    let mut option_errors = Vec::new();
+                    self.validate_node(option, value, path, &mut option_errors);
+                    option_errors.is_empty()
+                })
+                .count();
+
+            if matching != 1 {
+                errors.push(format!(
+                    "{}: expected exactly one matching schema, found {}",
+                    path, matching
+                ));
+            }
+        }
+
+        if let Some(object) = value.as_object() {
+            if let Some(required) = schema.get("required").and_then(Value::as_array) {
+                for key in required.iter().filter_map(Value::as_str) {
+                    if !object.contains_key(key) {
+                        errors.push(format!("{}: missing required property {}", path, key));
+                    }
+                }
+            }
+
+            if let Some(properties) = schema.get("properties").and_then(Value::as_object) {
+                for (key, property_value) in object {
+                    if let Some(property_schema) = properties.get(key) {
+                        let property_path = format!("{}.{}", path, key);
+                        self.validate_node(property_schema, property_value, &property_path, errors);
+                    }
+                }
+            }
+        }
+
+        if let (Some(items), Some(array)) = (schema.get("items"), value.as_array()) {
+            for (index, item) in array.iter().enumerate() {
+                let item_path = format!("{}[{}]", path, index);
+                self.validate_node(items, item, &item_path, errors);
+            }
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::{env, str::FromStr};
+
+use anyhow::bail;
+
+/// An environment variable to give to the script, given on the command line
+/// like `--env CI` to pass on a variable of ours, or `--env MY_TOKEN=xyz` to
+/// set one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvVar {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+impl FromStr for EnvVar {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (name, value) = match value.find('=') {
+            Some(index) => (&value[..index], Some(value[index + 1..].to_owned())),
+            None => (value, None),
+        };
+
+        if name.is_empty() {
+            bail!("Invalid --env value, expected NAME or NAME=value");
+        }
+
+        Ok(EnvVar {
+            name: name.to_owned(),
+            value,
+        })
+    }
+}
+
+/// Finds the value of each variable. Variables passed on from our environment
+/// that aren't set are left out, so scripts see them as `nil`.
+pub fn resolve(vars: &[EnvVar]) -> Vec<(String, String)> {
+    let mut resolved: Vec<(String, String)> = Vec::new();
+
+    for var in vars {
+        let value = match &var.value {
+            Some(value) => value.clone(),
+            None => match env::var(&var.name) {
+                Ok(value) => value,
+                Err(_) => {
+                    log::debug!("--env {} isn't set, leaving it out", var.name);
+                    continue;
+                }
+            },
+        };
+
+        // Later values replace earlier ones, like they would in a shell.
+        resolved.retain(|(name, _)| name != &var.name);
+        resolved.push((var.name.clone(), value));
+    }
+
+    resolved
+}
+
+/// Formats the variables as the frozen Lua table that scripts see as
+/// `RIR_ENV`.
+pub fn to_lua(vars: &[(String, String)]) -> String {
+    let fields: Vec<String> = vars
+        .iter()
+        .map(|(name, value)| format!("[{}] = {}", lua_string(name), lua_string(value)))
+        .collect();
+
+    format!("table.freeze({{ {} }})", fields.join(", "))
+}
+
+/// Quotes a string for Lua. Anything that could end the string or the line is
+/// written as a decimal escape, so the result always fits on one line.
+pub fn lua_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+
+    for character in value.chars() {
+        match character {
+            '"' | '\\' | '\u{0}'..='\u{1f}' | '\u{7f}' => {
+                quoted.push_str(&format!("\\{:03}", character as u32));
+            }
+            _ => quoted.push(character),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
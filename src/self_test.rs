@@ -0,0 +1,454 @@
+//! A conformance test that runs run-in-roblox end to end, as its own
+//! separate process, to check that it works with a Studio release before the
+//! release is rolled out.
+
+use std::{
+    env,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use fs_err as fs;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    clean::{self, SessionMarker, TEMP_DIR_PREFIX},
+    config,
+    output::FormatVersion,
+    runner::Backend,
+};
+
+/// How long the timeout case lets its script run before it should be stopped.
+const TIMEOUT_CASE_LIMIT: Duration = Duration::from_secs(15);
+
+/// How much longer than its --timeout the timeout case can take, since
+/// launching and closing Studio take a while.
+const TIMEOUT_CASE_GRACE: Duration = Duration::from_secs(120);
+
+/// The conformance report written by `run-in-roblox self-test`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SelfTestReport {
+    pub format_version: u32,
+    pub run_in_roblox_version: &'static str,
+    pub backend: String,
+
+    /// The version of Studio the cases ran in, as reported by the plugin.
+    pub studio_version: Option<String>,
+
+    /// Whether every case that ran passed.
+    pub passed: bool,
+
+    pub cases: Vec<CaseResult>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CaseResult {
+    pub name: &'static str,
+    pub status: CaseStatus,
+
+    /// How long the case took, in seconds.
+    pub seconds: f64,
+
+    /// What went wrong, for cases that failed.
+    pub problems: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CaseStatus {
+    Passed,
+    Failed,
+
+    /// The case doesn't apply to the backend, like launching Studio on the
+    /// mock backend.
+    Skipped,
+}
+
+/// Where to run the cases.
+#[derive(Debug, Clone)]
+pub struct SelfTestOptions {
+    pub backend: Backend,
+    pub studio_app_path: Option<PathBuf>,
+    pub studio_plugins_path: Option<PathBuf>,
+
+    /// How long each case can take.
+    pub timeout: Duration,
+}
+
+/// One run of run-in-roblox, with what it printed and how it exited.
+struct CaseRun {
+    exit_code: Option<i32>,
+    lines: Vec<Value>,
+    elapsed: Duration,
+}
+
+impl CaseRun {
+    fn of_type<'a>(&'a self, kind: &'a str) -> impl Iterator<Item = &'a Value> + 'a {
+        self.lines.iter().filter(move |line| line["type"] == kind)
+    }
+
+    fn expect_exit_code(&self, expected: i32, problems: &mut Vec<String>) {
+        if self.exit_code != Some(expected) {
+            problems.push(format!(
+                "Expected exit code {}, got {}",
+                expected,
+                describe_exit_code(self.exit_code)
+            ));
+        }
+    }
+
+    fn expect_output(&self, level: &str, body: &str, problems: &mut Vec<String>) {
+        let found = self
+            .of_type("Output")
+            .any(|line| line["level"] == level && line["body"] == body);
+
+        if !found {
+            problems.push(format!(
+                "Expected {} output {:?}, which never arrived or arrived garbled",
+                level, body
+            ));
+        }
+    }
+}
+
+struct Case {
+    name: &'static str,
+    script: &'static str,
+    args: &'static [&'static str],
+
+    /// Whether the case only makes sense in Roblox Studio.
+    studio_only: bool,
+    check: fn(&CaseRun, &mut Vec<String>),
+}
+
+static CASES: &[Case] = &[
+    Case {
+        name: "launch",
+        script: "print(\"self-test launch\")\n",
+        args: &[],
+        studio_only: true,
+        check: |run, problems| {
+            run.expect_exit_code(0, problems);
+
+            for event in &["studio-launched", "plugin-connected", "script-finished"] {
+                if !run.of_type("Lifecycle").any(|line| line["event"] == *event) {
+                    problems.push(format!("The run never reached {}", event));
+                }
+            }
+        },
+    },
+    Case {
+        name: "handshake",
+        script: "print(\"self-test handshake\")\n",
+        args: &[],
+        studio_only: true,
+        check: |run, problems| {
+            run.expect_exit_code(0, problems);
+
+            match run.of_type("Hello").next() {
+                Some(hello) if hello["studioVersion"].is_string() => {}
+                Some(_) => problems.push("The plugin didn't report a Studio version".to_owned()),
+                None => problems.push("The plugin never said hello".to_owned()),
+            }
+        },
+    },
+    Case {
+        name: "output-levels",
+        script: "print(\"self-test print: héllo, wörld ✓\")\n\
+                 warn(\"self-test warning\")\n\
+                 rir.debug(\"self-test debug\")\n\
+                 rir.trace(\"self-test trace\")\n",
+        args: &["--verbose", "--verbose"],
+        studio_only: false,
+        check: |run, problems| {
+            run.expect_exit_code(0, problems);
+            run.expect_output("Print", "self-test print: héllo, wörld ✓", problems);
+            run.expect_output("Warning", "self-test warning", problems);
+            run.expect_output("Debug", "self-test debug", problems);
+            run.expect_output("Trace", "self-test trace", problems);
+        },
+    },
+    Case {
+        name: "error-exit-code",
+        script: "error(\"self-test error\")\n",
+        args: &[],
+        studio_only: false,
+        check: |run, problems| {
+            run.expect_exit_code(1, problems);
+
+            let reported = run.of_type("Output").any(|line| {
+                line["level"] == "Error"
+                    && line["body"]
+                        .as_str()
+                        .map(|body| body.contains("self-test error"))
+                        .unwrap_or(false)
+            });
+
+            if !reported {
+                problems.push("The error was never reported".to_owned());
+            }
+        },
+    },
+    Case {
+        name: "checks",
+        script: "rir.pass(\"self-test passing check\")\n\
+                 rir.fail(\"self-test failing check\", \"on purpose\")\n",
+        args: &[],
+        studio_only: false,
+        check: |run, problems| {
+            run.expect_exit_code(1, problems);
+
+            match run.of_type("Summary").next() {
+                Some(summary) if summary["checksPassed"] == 1 && summary["checksFailed"] == 1 => {}
+                Some(summary) => problems.push(format!(
+                    "Expected 1 passing and 1 failing check, got {} and {}",
+                    summary["checksPassed"], summary["checksFailed"]
+                )),
+                None => problems.push("The summary was never printed".to_owned()),
+            }
+        },
+    },
+    Case {
+        name: "script-exit-code",
+        script: "rir.exit(3)\n",
+        args: &[],
+        studio_only: false,
+        check: |run, problems| {
+            run.expect_exit_code(3, problems);
+        },
+    },
+    Case {
+        name: "timeout",
+        script: "while true do\n\ttask.wait(1)\nend\n",
+        args: &["--timeout", "15s"],
+        studio_only: true,
+        check: |run, problems| {
+            run.expect_exit_code(2, problems);
+
+            if run.elapsed > TIMEOUT_CASE_LIMIT + TIMEOUT_CASE_GRACE {
+                problems.push(format!(
+                    "The run took {:.0}s to time out after {}s",
+                    run.elapsed.as_secs_f64(),
+                    TIMEOUT_CASE_LIMIT.as_secs()
+                ));
+            }
+        },
+    },
+];
+
+/// Runs every case, calling `progress` as each one finishes, and returns the
+/// report.
+pub fn run(
+    options: &SelfTestOptions,
+    mut progress: impl FnMut(&CaseResult),
+) -> Result<SelfTestReport, anyhow::Error> {
+    let temp_dir = tempfile::Builder::new()
+        .prefix(TEMP_DIR_PREFIX)
+        .tempdir()
+        .context("Could not create a temporary directory for the self-test")?;
+    SessionMarker::for_current_process().write(temp_dir.path())?;
+
+    // The cases run with an empty config file, so that none of the user's
+    // settings change how they behave.
+    let config_path = temp_dir.path().join(config::CONFIG_FILE_NAME);
+    fs::write(&config_path, "{}")?;
+
+    let plugins_before = options
+        .studio_plugins_path
+        .as_deref()
+        .map(clean::plugin_files)
+        .unwrap_or_default();
+
+    let mut cases = Vec::new();
+    let mut studio_version = None;
+    let mut pids = Vec::new();
+
+    for case in CASES {
+        let started = Instant::now();
+
+        let result = if case.studio_only && options.backend != Backend::Studio {
+            CaseResult {
+                name: case.name,
+                status: CaseStatus::Skipped,
+                seconds: 0.0,
+                problems: Vec::new(),
+            }
+        } else {
+            let mut problems = Vec::new();
+
+            match run_case(options, case, temp_dir.path(), &config_path) {
+                Ok((run, pid)) => {
+                    pids.push(pid);
+                    (case.check)(&run, &mut problems);
+
+                    if let Some(hello) = run.of_type("Hello").next() {
+                        studio_version = hello["studioVersion"].as_str().map(str::to_owned);
+                    }
+                }
+                Err(err) => problems.push(format!("{:#}", err)),
+            }
+
+            CaseResult {
+                name: case.name,
+                status: if problems.is_empty() {
+                    CaseStatus::Passed
+                } else {
+                    CaseStatus::Failed
+                },
+                seconds: started.elapsed().as_secs_f64(),
+                problems,
+            }
+        };
+
+        progress(&result);
+        cases.push(result);
+    }
+
+    let cleanup = check_cleanup(options, &pids, &plugins_before);
+    progress(&cleanup);
+    cases.push(cleanup);
+
+    Ok(SelfTestReport {
+        format_version: FormatVersion::latest().0,
+        run_in_roblox_version: env!("CARGO_PKG_VERSION"),
+        backend: options.backend.to_string(),
+        studio_version,
+        passed: cases.iter().all(|case| case.status != CaseStatus::Failed),
+        cases,
+    })
+}
+
+/// Runs run-in-roblox for one case, returning how it went along with its
+/// process ID.
+fn run_case(
+    options: &SelfTestOptions,
+    case: &Case,
+    temp_dir: &Path,
+    config_path: &Path,
+) -> Result<(CaseRun, u32), anyhow::Error> {
+    let script_path = temp_dir.join(format!("{}.lua", case.name));
+    fs::write(&script_path, case.script)?;
+
+    let mut args: Vec<OsString> = vec![
+        "--config".into(),
+        config_path.into(),
+        "--backend".into(),
+        options.backend.to_string().into(),
+        "--script".into(),
+        script_path.into(),
+        "--output".into(),
+        "ndjson".into(),
+        "--validate-output".into(),
+    ];
+
+    if let Some(app_path) = &options.studio_app_path {
+        args.push("--app".into());
+        args.push(app_path.into());
+    }
+
+    if let Some(plugins_path) = &options.studio_plugins_path {
+        args.push("--plugins".into());
+        args.push(plugins_path.into());
+    }
+
+    if !case.args.contains(&"--timeout") {
+        args.push("--timeout".into());
+        args.push(
+            humantime::format_duration(options.timeout)
+                .to_string()
+                .into(),
+        );
+    }
+
+    args.extend(case.args.iter().map(OsString::from));
+
+    let mut command = Command::new(env::current_exe()?);
+    command
+        .args(&args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    for (name, _) in env::vars_os() {
+        if name.to_string_lossy().starts_with(config::ENV_PREFIX) {
+            command.env_remove(name);
+        }
+    }
+
+    let started = Instant::now();
+    let child = command
+        .spawn()
+        .context("Could not start run-in-roblox for the case")?;
+    let pid = child.id();
+    let output = child.wait_with_output()?;
+
+    let mut lines = Vec::new();
+
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        match serde_json::from_str(line) {
+            Ok(value) => lines.push(value),
+            Err(_) => log::debug!("Ignoring a line that isn't JSON: {}", line),
+        }
+    }
+
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        log::debug!("{}: {}", case.name, line);
+    }
+
+    let run = CaseRun {
+        exit_code: output.status.code(),
+        lines,
+        elapsed: started.elapsed(),
+    };
+
+    Ok((run, pid))
+}
+
+/// Checks that the runs left nothing behind: no temporary directories, and no
+/// plugins in the plugins folder.
+fn check_cleanup(
+    options: &SelfTestOptions,
+    pids: &[u32],
+    plugins_before: &[PathBuf],
+) -> CaseResult {
+    let started = Instant::now();
+    let mut problems = Vec::new();
+
+    for pid in pids {
+        for temp_dir in clean::temp_dirs_of(*pid) {
+            problems.push(format!("Left behind {}", temp_dir.display()));
+        }
+    }
+
+    if let Some(plugins_path) = &options.studio_plugins_path {
+        for plugin in clean::plugin_files(plugins_path) {
+            if !plugins_before.contains(&plugin) {
+                problems.push(format!("Left behind {}", plugin.display()));
+            }
+        }
+    }
+
+    CaseResult {
+        name: "cleanup",
+        status: if problems.is_empty() {
+            CaseStatus::Passed
+        } else {
+            CaseStatus::Failed
+        },
+        seconds: started.elapsed().as_secs_f64(),
+        problems,
+    }
+}
+
+fn describe_exit_code(exit_code: Option<i32>) -> String {
+    match exit_code {
+        Some(exit_code) => exit_code.to_string(),
+        None => "none, since it was killed".to_owned(),
+    }
+}
@@ -0,0 +1,88 @@
+use crate::{
+    embed::{Messages, RunOptions, Runner},
+    place_runner,
+    port_registry::{self, PortClaim},
+};
+
+/// Runs several scripts at the same time from one process, each in its own
+/// session with its own copy of Roblox Studio, port, and temporary directory.
+///
+/// Each session is given its own port, replacing the one in its options. Ports
+/// are claimed in a registry shared with every other run on the machine, so
+/// sessions in other processes won't pick the same one.
+/// Sessions can use different plugins folders, like those of different Studio
+/// installs; sessions sharing a plugins folder run at the same time, but take
+/// turns starting Studio so that each Studio only loads its own plugin.
+///
+/// ```no_run
+/// use std::thread;
+///
+/// use run_in_roblox::{RunOptions, SessionManager};
+///
+/// let mut manager = SessionManager::new();
+/// let scripts = ["print('one')", "print('two')"];
+///
+/// let threads: Vec<_> = scripts
+///     .iter()
+///     .map(|script| {
+///         let session = manager.start(RunOptions::new(*script))?;
+///
+///         Ok(thread::spawn(move || {
+///             let mut messages = session.messages;
+///             let collected: Vec<_> = (&mut messages).collect();
+///             (collected, messages.finish())
+///         }))
+///     })
+///     .collect::<Result<_, anyhow::Error>>()?;
+///
+/// for thread in threads {
+///     let (messages, result) = thread.join().unwrap();
+///     println!("{:?} {:?}", messages, result);
+/// }
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct SessionManager {
+    next_id: usize,
+}
+
+/// A session started by a [`SessionManager`].
+pub struct Session {
+    /// Identifies the session among the others started by the same manager.
+    pub id: usize,
+
+    /// The port the session's plugin talks to it on.
+    pub port: u16,
+
+    /// The messages from the session's script.
+    pub messages: Messages,
+
+    /// Given back once the session is dropped.
+    _port_claim: PortClaim,
+}
+
+impl SessionManager {
+    pub fn new() -> Self {
+        SessionManager::default()
+    }
+
+    /// Starts a new session, returning as soon as its script has been
+    /// started.
+    pub fn start(&mut self, mut options: RunOptions) -> Result<Session, anyhow::Error> {
+        let port_claim = port_registry::claim(place_runner::DEFAULT_PORT)?;
+        let port = port_claim.port();
+        options.port = Some(port);
+
+        let messages = Runner::new(options).run()?;
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        Ok(Session {
+            id,
+            port,
+            messages,
+            _port_claim: port_claim,
+        })
+    }
+}
@@ -0,0 +1,97 @@
+use std::path::Path;
+
+use anyhow::{bail, Context};
+use fs_err as fs;
+use regex::Regex;
+
+use crate::{
+    message_receiver::{OutputLevel, RobloxMessage},
+    quarantine::parse_string_arrays,
+};
+
+/// Output from Roblox Studio that's reported at a different level by default,
+/// as regular expressions matched against the whole message.
+const DEFAULT_RULES: &[(&str, OutputLevel)] = &[
+    // Places opened from a file often can't load their assets, which has
+    // nothing to do with the script being run.
+    (r"^Failed to load sound ", OutputLevel::Info),
+    (r"^Failed to load animation ", OutputLevel::Info),
+    (r"^Image https?://\S+ failed to load", OutputLevel::Info),
+];
+
+/// Rules that change the level Studio's output is reported at, like turning a
+/// noisy engine warning into info or a deprecation notice into an error. The
+/// levels are changed as messages arrive, so errors turned into warnings don't
+/// fail the run.
+///
+/// Rules can be loaded from the file given with `--severity`, which uses the
+/// same subset of TOML as `--quarantine`, with an array of regular
+/// expressions for each level:
+///
+/// ```toml
+/// info = ['^Infinite yield possible']
+/// error = ['is deprecated']
+/// ```
+///
+/// Rules from the file are checked before the default ones, and the first
+/// rule that matches is used.
+#[derive(Debug, Clone)]
+pub struct SeverityMap {
+    rules: Vec<(Regex, OutputLevel)>,
+}
+
+impl Default for SeverityMap {
+    fn default() -> Self {
+        let rules = DEFAULT_RULES
+            .iter()
+            .map(|(pattern, level)| (Regex::new(pattern).unwrap(), *level))
+            .collect();
+
+        SeverityMap { rules }
+    }
+}
+
+impl SeverityMap {
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let contents = fs::read_to_string(path)?;
+        let invalid = || format!("Invalid severity file {}", path.display());
+
+        let mut rules = Vec::new();
+
+        for (key, patterns) in parse_string_arrays(&contents).with_context(invalid)? {
+            let level = match key.as_str() {
+                "trace" => OutputLevel::Trace,
+                "debug" => OutputLevel::Debug,
+                "print" => OutputLevel::Print,
+                "info" => OutputLevel::Info,
+                "warning" => OutputLevel::Warning,
+                "error" => OutputLevel::Error,
+                _ => bail!(
+                    "Unknown key {:?} in severity file {}, expected trace, debug, print, info, \
+                     warning, or error",
+                    key,
+                    path.display()
+                ),
+            };
+
+            for pattern in patterns {
+                let regex = Regex::new(&pattern).with_context(invalid)?;
+                rules.push((regex, level));
+            }
+        }
+
+        rules.extend(SeverityMap::default().rules);
+
+        Ok(SeverityMap { rules })
+    }
+
+    /// Changes the level of an output message if a rule matches it.
+    pub fn apply(&self, message: &mut RobloxMessage) {
+        if let RobloxMessage::Output { level, body, .. } = message {
+            if let Some((_, new_level)) = self.rules.iter().find(|(regex, _)| regex.is_match(body))
+            {
+                *level = *new_level;
+            }
+        }
+    }
+}
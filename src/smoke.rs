@@ -0,0 +1,85 @@
+use std::path::Path;
+
+use rbx_dom_weak::InstanceBuilder;
+use rbx_types::Variant;
+
+use crate::{
+    baseplate,
+    message_receiver::{OutputLevel, RobloxMessage},
+};
+
+/// The script `run-in-roblox smoke` runs. It only succeeds in the place from
+/// `write_place`, which shows that Studio opened the place we gave it.
+pub static SCRIPT: &str = r#"-- Generated by `run-in-roblox smoke`.
+print("run-in-roblox smoke test: héllo, wörld ✓")
+
+local marker = workspace:FindFirstChild("RunInRobloxSmoke")
+assert(marker ~= nil and marker.Value == "smoke", "Roblox Studio didn't open the smoke test place")
+
+rir.pass("smoke test place opened")
+"#;
+
+const EXPECTED_OUTPUT: &str = "run-in-roblox smoke test: héllo, wörld ✓";
+const EXPECTED_CHECK: &str = "smoke test place opened";
+
+/// Writes an empty baseplate place with a marker in the Workspace for the
+/// smoke test script to find.
+pub fn write_place(path: &Path) -> Result<(), anyhow::Error> {
+    let mut place = baseplate::new_place();
+
+    let workspace = place.root().children()[0];
+    place.insert(
+        workspace,
+        InstanceBuilder::new("StringValue")
+            .with_name("RunInRobloxSmoke")
+            .with_property("Value", Variant::String("smoke".to_owned())),
+    );
+
+    baseplate::write_place(&place, path)
+}
+
+/// Compares the messages from a smoke test run with what the script should
+/// have produced, returning a description of each difference.
+pub fn check(messages: &[RobloxMessage]) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let mut saw_output = false;
+    let mut saw_check = false;
+
+    for message in messages {
+        match message {
+            RobloxMessage::Output {
+                level: OutputLevel::Print,
+                body,
+                ..
+            } if body == EXPECTED_OUTPUT => saw_output = true,
+            RobloxMessage::Output {
+                level: OutputLevel::Error,
+                body,
+                ..
+            } => problems.push(format!("The script raised an error: {}", body)),
+            RobloxMessage::Check {
+                name, passed: true, ..
+            } if name == EXPECTED_CHECK => saw_check = true,
+            RobloxMessage::Check {
+                name,
+                passed: false,
+                ..
+            } => problems.push(format!("The check {:?} failed", name)),
+            _ => {}
+        }
+    }
+
+    if !saw_output {
+        problems.push(format!(
+            "The output {:?} never arrived, or arrived garbled",
+            EXPECTED_OUTPUT
+        ));
+    }
+
+    if !saw_check {
+        problems.push(format!("The check {:?} never passed", EXPECTED_CHECK));
+    }
+
+    problems
+}
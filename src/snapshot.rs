@@ -0,0 +1,310 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use fs_err as fs;
+use rbx_dom_weak::{types::Ref, InstanceBuilder, WeakDom};
+use rbx_reflection::{
+    DataType, PropertyDescriptor, PropertyKind, PropertySerialization, Scriptability,
+};
+use rbx_types::{
+    BrickColor, CFrame, Color3, Color3uint8, Content, Enum, Matrix3, NumberRange, UDim, UDim2,
+    Variant, VariantType, Vector2, Vector3,
+};
+use serde_json::Value;
+
+/// Where --snapshot writes the instances the script left behind, and which
+/// subtree of the place to take them from.
+#[derive(Debug, Clone)]
+pub struct SnapshotOptions {
+    pub path: PathBuf,
+
+    /// The instance path of the subtree, like `Workspace.Model`, or `game` for
+    /// the services that are saved with a place.
+    pub root: String,
+}
+
+/// The kinds of files a snapshot can be written as, picked by the extension
+/// of its path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotFormat {
+    Xml,
+    Binary,
+}
+
+impl SnapshotFormat {
+    pub fn from_path(path: &Path) -> Result<Self, anyhow::Error> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default();
+
+        match extension {
+            "rbxlx" | "rbxmx" => Ok(SnapshotFormat::Xml),
+            "rbxl" | "rbxm" => Ok(SnapshotFormat::Binary),
+            _ => bail!("--snapshot must be a .rbxlx, .rbxmx, .rbxl, or .rbxm file"),
+        }
+    }
+}
+
+/// Whether the plugin can read a property and send it back, which it can for
+/// properties that are saved with places and have simple values. Names are
+/// sent separately.
+fn is_recorded(property: &PropertyDescriptor) -> bool {
+    let serializes = matches!(
+        property.kind,
+        PropertyKind::Canonical {
+            serialization: PropertySerialization::Serializes
+                | PropertySerialization::SerializesAs(_)
+        }
+    ) && property.name != "Name";
+    let readable = matches!(
+        property.scriptability,
+        Scriptability::ReadWrite | Scriptability::Read
+    );
+    let supported = match &property.data_type {
+        DataType::Enum(_) => true,
+        DataType::Value(value_type) => matches!(
+            value_type,
+            VariantType::Bool
+                | VariantType::Int32
+                | VariantType::Int64
+                | VariantType::Float32
+                | VariantType::Float64
+                | VariantType::String
+                | VariantType::Content
+                | VariantType::Vector2
+                | VariantType::Vector3
+                | VariantType::Color3
+                | VariantType::Color3uint8
+                | VariantType::CFrame
+                | VariantType::UDim
+                | VariantType::UDim2
+                | VariantType::BrickColor
+                | VariantType::NumberRange
+        ),
+        _ => false,
+    };
+
+    serializes && readable && supported
+}
+
+/// The source of a ModuleScript that tells the plugin which properties to
+/// record for each class, along with each class's superclass so that it can
+/// find inherited ones.
+pub fn properties_module() -> String {
+    let database = rbx_reflection_database::get();
+
+    // Sorted so that the plugin comes out the same every time.
+    let mut properties = BTreeMap::new();
+    let mut superclasses = BTreeMap::new();
+
+    for (class_name, class) in &database.classes {
+        let mut names: Vec<&str> = class
+            .properties
+            .values()
+            .filter(|property| is_recorded(property))
+            .map(|property| property.name.as_ref())
+            .collect();
+
+        if !names.is_empty() {
+            names.sort_unstable();
+            properties.insert(class_name.as_ref(), names);
+        }
+
+        if let Some(superclass) = &class.superclass {
+            superclasses.insert(class_name.as_ref(), superclass.as_ref());
+        }
+    }
+
+    let mut source = String::from("return {\n\tproperties = {\n");
+
+    for (class_name, names) in properties {
+        let names: Vec<String> = names.iter().map(|name| format!("{:?}", name)).collect();
+        source.push_str(&format!(
+            "\t\t[{:?}] = {{ {} }},\n",
+            class_name,
+            names.join(", ")
+        ));
+    }
+
+    source.push_str("\t},\n\tsuperclasses = {\n");
+
+    for (class_name, superclass) in superclasses {
+        source.push_str(&format!("\t\t[{:?}] = {:?},\n", class_name, superclass));
+    }
+
+    source.push_str("\t},\n}\n");
+    source
+}
+
+/// Puts a snapshot back together from the instances the plugin sends, one at
+/// a time, parents first.
+pub struct SnapshotBuilder {
+    dom: WeakDom,
+
+    /// The instances added so far, by the ID the plugin gave them.
+    refs: HashMap<u32, Ref>,
+    len: usize,
+}
+
+impl Default for SnapshotBuilder {
+    fn default() -> Self {
+        SnapshotBuilder {
+            dom: WeakDom::new(InstanceBuilder::new("DataModel")),
+            refs: HashMap::new(),
+            len: 0,
+        }
+    }
+}
+
+impl SnapshotBuilder {
+    /// Adds an instance under the one with the ID `parent`, or at the top of
+    /// the snapshot if it's the root. A root that's the DataModel stands for
+    /// the whole place, so its children go at the top instead.
+    pub fn add(
+        &mut self,
+        id: u32,
+        parent: Option<u32>,
+        class_name: &str,
+        name: &str,
+        properties: &HashMap<String, Value>,
+    ) -> Result<(), anyhow::Error> {
+        let parent_ref = match parent {
+            Some(parent) => *self
+                .refs
+                .get(&parent)
+                .with_context(|| format!("The snapshot is missing the parent of {}", name))?,
+            None if class_name == "DataModel" => {
+                self.refs.insert(id, self.dom.root_ref());
+                return Ok(());
+            }
+            None => self.dom.root_ref(),
+        };
+
+        let mut builder = InstanceBuilder::new(class_name).with_name(name);
+
+        for (property, value) in properties {
+            match to_variant(class_name, property, value) {
+                Some(variant) => builder = builder.with_property(property.as_str(), variant),
+                None => log::debug!(
+                    "Leaving {}.{} out of the snapshot, since its value {} couldn't be read",
+                    class_name,
+                    property,
+                    value
+                ),
+            }
+        }
+
+        let instance_ref = self.dom.insert(parent_ref, builder);
+        self.refs.insert(id, instance_ref);
+        self.len += 1;
+
+        Ok(())
+    }
+
+    /// How many instances the snapshot has.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let format = SnapshotFormat::from_path(path)?;
+        let output = BufWriter::new(fs::File::create(path)?);
+        let top = self.dom.root().children();
+
+        match format {
+            SnapshotFormat::Xml => rbx_xml::to_writer_default(output, &self.dom, top)?,
+            SnapshotFormat::Binary => rbx_binary::to_writer(output, &self.dom, top)?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Finds the type of a property, looking through the classes it inherits
+/// from.
+fn data_type(class_name: &str, property: &str) -> Option<DataType<'static>> {
+    let database = rbx_reflection_database::get();
+    let mut class = database.classes.get(class_name);
+
+    while let Some(descriptor) = class {
+        if let Some(property) = descriptor.properties.get(property) {
+            return Some(property.data_type.clone());
+        }
+
+        class = descriptor
+            .superclass
+            .as_ref()
+            .and_then(|superclass| database.classes.get(superclass.as_ref()));
+    }
+
+    None
+}
+
+/// Turns a value sent by the plugin back into the type the property has.
+fn to_variant(class_name: &str, property: &str, value: &Value) -> Option<Variant> {
+    let numbers: Vec<f64> = match value {
+        Value::Array(items) => items.iter().filter_map(Value::as_f64).collect(),
+        _ => Vec::new(),
+    };
+    let float = |index: usize| numbers.get(index).map(|number| *number as f32);
+
+    let value_type = match data_type(class_name, property)? {
+        DataType::Enum(_) => return Some(Variant::Enum(Enum::from_u32(value.as_u64()? as u32))),
+        DataType::Value(value_type) => value_type,
+        _ => return None,
+    };
+
+    let variant = match value_type {
+        VariantType::Bool => Variant::Bool(value.as_bool()?),
+        VariantType::Int32 => Variant::Int32(value.as_i64()? as i32),
+        VariantType::Int64 => Variant::Int64(value.as_i64()?),
+        VariantType::Float32 => Variant::Float32(value.as_f64()? as f32),
+        VariantType::Float64 => Variant::Float64(value.as_f64()?),
+        VariantType::String => Variant::String(value.as_str()?.to_owned()),
+        VariantType::Content => Variant::Content(Content::from(value.as_str()?.to_owned())),
+        VariantType::Vector2 => Variant::Vector2(Vector2::new(float(0)?, float(1)?)),
+        VariantType::Vector3 => Variant::Vector3(Vector3::new(float(0)?, float(1)?, float(2)?)),
+        VariantType::Color3 => Variant::Color3(Color3::new(float(0)?, float(1)?, float(2)?)),
+        VariantType::Color3uint8 => {
+            let channel =
+                |index: usize| float(index).map(|channel| (channel * 255.0).round() as u8);
+            Variant::Color3uint8(Color3uint8::new(channel(0)?, channel(1)?, channel(2)?))
+        }
+        VariantType::CFrame => {
+            if numbers.len() != 12 {
+                return None;
+            }
+
+            let row = |start: usize| {
+                Vector3::new(
+                    numbers[start] as f32,
+                    numbers[start + 1] as f32,
+                    numbers[start + 2] as f32,
+                )
+            };
+
+            Variant::CFrame(CFrame::new(row(0), Matrix3::new(row(3), row(6), row(9))))
+        }
+        VariantType::UDim => Variant::UDim(UDim::new(float(0)?, numbers.get(1)?.round() as i32)),
+        VariantType::UDim2 => Variant::UDim2(UDim2::new(
+            UDim::new(float(0)?, numbers.get(1)?.round() as i32),
+            UDim::new(float(2)?, numbers.get(3)?.round() as i32),
+        )),
+        VariantType::BrickColor => {
+            Variant::BrickColor(BrickColor::from_number(value.as_u64()? as u16)?)
+        }
+        VariantType::NumberRange => Variant::NumberRange(NumberRange::new(float(0)?, float(1)?)),
+        _ => return None,
+    };
+
+    Some(variant)
+}
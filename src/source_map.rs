@@ -0,0 +1,222 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::Context;
+use fs_err as fs;
+use regex::{Captures, Regex};
+use serde::Deserialize;
+
+use crate::message_receiver::{OutputLevel, RobloxMessage};
+
+/// An instance in a sourcemap written by `rojo sourcemap`.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SourcemapNode {
+    name: String,
+    class_name: String,
+
+    #[serde(default)]
+    file_paths: Vec<String>,
+
+    #[serde(default)]
+    children: Vec<SourcemapNode>,
+}
+
+/// Rewrites locations in errors and tracebacks that point at the copies of
+/// scripts run-in-roblox made, like `run-in-roblox-plugin.Main:12`, so that
+/// they point at the files the scripts came from instead, like
+/// `tests/spawn.lua:12`.
+///
+/// Line numbers don't need adjusting, since scripts are always run with their
+/// lines where they were in the original file. See `plugin::wrap_script`.
+#[derive(Default)]
+pub struct SourceMap {
+    locations: Vec<(Regex, String)>,
+
+    /// Matches locations in each of the original files once `apply` has
+    /// rewritten them, like `tests/spawn.lua:12`, by path.
+    rewritten: HashMap<String, Regex>,
+}
+
+impl SourceMap {
+    /// Rewrites locations matching `pattern`, whose first group is the line
+    /// number, to point at `path`.
+    fn add_location(&mut self, pattern: &str, path: String) {
+        if !self.rewritten.contains_key(&path) {
+            let rewritten = Regex::new(&format!(r"{}:(\d+)", regex::escape(&path))).unwrap();
+            self.rewritten.insert(path.clone(), rewritten);
+        }
+
+        self.locations.push((Regex::new(pattern).unwrap(), path));
+    }
+
+    /// Maps one of the ModuleScripts the plugin runs to the file its source
+    /// came from.
+    pub fn add_module(&mut self, module_name: &str, path: &Path) {
+        let instance = format!(
+            r"[\w.-]*(?:run-in-roblox-plugin|RunInRobloxCompareSecurity)\.{}",
+            regex::escape(module_name)
+        );
+
+        // Errors look like `Path.To.Main:12: message`, while some tracebacks
+        // use `Script 'Path.To.Main', Line 12`.
+        for pattern in &[
+            format!(r"{}:(\d+)", instance),
+            format!(r"Script '{}', Line (\d+)", instance),
+        ] {
+            self.add_location(pattern, path.display().to_string());
+        }
+    }
+
+    /// Maps a file run-in-roblox wrote a script to, like the one Lune runs, to
+    /// the file its source came from.
+    pub fn add_file(&mut self, written_path: &Path, path: &Path) {
+        let pattern = format!(
+            r"{}:(\d+)",
+            regex::escape(&written_path.display().to_string())
+        );
+
+        self.add_location(&pattern, path.display().to_string());
+    }
+
+    /// Maps the scripts in a sourcemap written by `rojo sourcemap` to their
+    /// files, so that errors in modules the script requires, like
+    /// `ReplicatedStorage.Lib.Foo:42`, point at files like `src/Lib/Foo.lua:42`.
+    pub fn add_rojo_sourcemap(&mut self, sourcemap_path: &Path) -> Result<(), anyhow::Error> {
+        let contents = fs::read(sourcemap_path)?;
+        let root: SourcemapNode = serde_json::from_slice(&contents).with_context(|| {
+            format!("Could not read the sourcemap {}", sourcemap_path.display())
+        })?;
+
+        // Paths in the sourcemap are relative to where it was written.
+        let base = sourcemap_path.parent().unwrap_or_else(|| Path::new(""));
+
+        // The root is the DataModel, which isn't part of full names, unless
+        // the sourcemap is for a model.
+        if root.class_name == "DataModel" {
+            for child in &root.children {
+                self.add_sourcemap_node(child, "", base);
+            }
+        } else {
+            self.add_sourcemap_node(&root, "", base);
+        }
+
+        Ok(())
+    }
+
+    fn add_sourcemap_node(&mut self, node: &SourcemapNode, parent_name: &str, base: &Path) {
+        let full_name = if parent_name.is_empty() {
+            node.name.clone()
+        } else {
+            format!("{}.{}", parent_name, node.name)
+        };
+
+        let script_path = node
+            .file_paths
+            .iter()
+            .find(|path| path.ends_with(".lua") || path.ends_with(".luau"));
+
+        if let Some(script_path) = script_path {
+            let instance = format!(r"\b{}", regex::escape(&full_name));
+            let path = base.join(script_path).display().to_string();
+
+            for pattern in &[
+                format!(r"{}:(\d+)", instance),
+                format!(r"Script '{}', Line (\d+)", instance),
+            ] {
+                self.add_location(pattern, path.clone());
+            }
+        }
+
+        for child in &node.children {
+            self.add_sourcemap_node(child, &full_name, base);
+        }
+    }
+
+    pub fn apply(&self, text: &str) -> String {
+        let mut text = text.to_owned();
+
+        for (pattern, path) in &self.locations {
+            text = pattern
+                .replace_all(&text, |captures: &Captures| {
+                    format!("{}:{}", path, &captures[1])
+                })
+                .into_owned();
+        }
+
+        text
+    }
+
+    /// Finds the first location in one of the original files in text that
+    /// `apply` has already rewritten, as a path and line number.
+    pub fn first_location(&self, text: &str) -> Option<(String, usize)> {
+        self.rewritten
+            .iter()
+            .filter_map(|(path, pattern)| {
+                let captures = pattern.captures(text)?;
+
+                Some((captures.get(0)?.start(), path, captures[1].parse().ok()?))
+            })
+            .min_by_key(|(start, _, _)| *start)
+            .map(|(_, path, line)| (path.clone(), line))
+    }
+
+    /// Points where output came from at the file the script came from, if
+    /// it's one of the scripts with a known file.
+    fn apply_to_location(
+        &self,
+        source: Option<String>,
+        line: Option<usize>,
+    ) -> (Option<String>, Option<usize>) {
+        if let (Some(source), Some(line)) = (&source, line) {
+            let location = self.apply(&format!("{}:{}", source, line));
+
+            if let Some((path, line)) = self.first_location(&location) {
+                return (Some(path), Some(line));
+            }
+        }
+
+        (source, line)
+    }
+
+    /// Rewrites the locations in warnings, errors, and the reasons checks
+    /// failed, along with where output came from. Printed output is left as
+    /// the script wrote it.
+    pub fn apply_to_message(&self, message: RobloxMessage) -> RobloxMessage {
+        match message {
+            RobloxMessage::Output {
+                level,
+                body,
+                source,
+                line,
+                from_script,
+                history,
+            } => {
+                let body = if level == OutputLevel::Warning || level == OutputLevel::Error {
+                    self.apply(&body)
+                } else {
+                    body
+                };
+                let (source, line) = self.apply_to_location(source, line);
+
+                RobloxMessage::Output {
+                    level,
+                    body,
+                    source,
+                    line,
+                    from_script,
+                    history,
+                }
+            }
+            RobloxMessage::Check {
+                name,
+                passed,
+                reason,
+            } => RobloxMessage::Check {
+                name,
+                passed,
+                reason: reason.map(|reason| self.apply(&reason)),
+            },
+            message => message,
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use fs_err as fs;
+use roblox_install::RobloxStudio;
+use serde::{Deserialize, Serialize};
+
+use crate::report;
+
+/// Where Roblox Studio is installed, as found by `RobloxStudio::locate` or
+/// read back from the cache.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StudioInstall {
+    pub application_path: PathBuf,
+    pub plugins_path: PathBuf,
+
+    /// The name of the versioned folder Studio is installed in, like
+    /// `version-0123456789abcdef`, on platforms that have one.
+    pub version: Option<String>,
+
+    /// When the Studio executable was last modified, in seconds since the Unix
+    /// epoch. Studio updates replace it, which makes the cache out of date.
+    modified: Option<u64>,
+}
+
+impl StudioInstall {
+    fn from_located(studio: &RobloxStudio) -> Self {
+        let application_path = studio.application_path().to_path_buf();
+
+        let version = application_path
+            .parent()
+            .and_then(Path::file_name)
+            .and_then(|name| name.to_str())
+            .filter(|name| name.starts_with("version-"))
+            .map(str::to_owned);
+
+        StudioInstall {
+            modified: modified_time(&application_path),
+            application_path,
+            plugins_path: studio.plugins_path().to_path_buf(),
+            version,
+        }
+    }
+
+    /// Whether Studio is still where we found it and hasn't been updated since.
+    fn is_current(&self) -> bool {
+        self.modified.is_some() && modified_time(&self.application_path) == self.modified
+    }
+}
+
+/// Finds Roblox Studio, using the result from a previous run when Studio hasn't
+/// changed since. With `use_cache` set to false, Studio is always looked for
+/// again, and the cache is refreshed.
+pub fn locate(use_cache: bool) -> Result<StudioInstall, roblox_install::Error> {
+    let cache_path = cache_path();
+
+    if use_cache {
+        if let Some(install) = cache_path.as_deref().and_then(read_cache) {
+            if install.is_current() {
+                log::debug!("Using cached Roblox Studio install {:?}", install);
+                return Ok(install);
+            }
+        }
+    }
+
+    let install = StudioInstall::from_located(&RobloxStudio::locate()?);
+
+    if let Some(cache_path) = &cache_path {
+        if let Err(err) = write_cache(cache_path, &install) {
+            log::debug!("Could not cache the Roblox Studio install: {}", err);
+        }
+    }
+
+    Ok(install)
+}
+
+fn cache_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("run-in-roblox").join("studio-install.json"))
+}
+
+/// Reads the cache, treating a missing or unreadable cache as empty, since
+/// another run might have been part way through replacing it.
+fn read_cache(path: &Path) -> Option<StudioInstall> {
+    let contents = fs::read(path).ok()?;
+    serde_json::from_slice(&contents).ok()
+}
+
+/// Replaces the cache in one step, so that runs happening at the same time
+/// never see a partly written file.
+fn write_cache(path: &Path, install: &StudioInstall) -> Result<(), anyhow::Error> {
+    report::write_atomic(path, &serde_json::to_vec_pretty(install)?)
+}
+
+fn modified_time(path: &Path) -> Option<u64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs())
+}
@@ -0,0 +1,104 @@
+use std::path::PathBuf;
+
+use fs_err as fs;
+use regex::Regex;
+
+/// The Studio setting that the plugin turns off while scripts run.
+const AUTO_RECOVERY_SETTING: &str = "Auto-Recovery Enabled";
+
+/// Where Studio keeps its settings between sessions.
+fn global_settings_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+
+    if let Some(local_data) = dirs::data_local_dir() {
+        paths.push(local_data.join("Roblox").join("GlobalSettings_13.xml"));
+    }
+
+    if let Some(home) = dirs::home_dir() {
+        paths.push(
+            home.join("Library")
+                .join("Roblox")
+                .join("GlobalSettings_13.xml"),
+        );
+    }
+
+    paths
+}
+
+fn setting_pattern() -> Regex {
+    Regex::new(&format!(
+        r#"(<bool name="{}">)(true|false)(</bool>)"#,
+        regex::escape(AUTO_RECOVERY_SETTING)
+    ))
+    .unwrap()
+}
+
+/// Studio's "Auto-Recovery Enabled" setting as it was before a run started.
+///
+/// The plugin turns auto-recovery off while the script runs and turns it back
+/// on when it finishes, but it never gets the chance when Studio is killed
+/// partway through a run. Studio saves its settings as it goes, so without
+/// this the setting would stay off for the user's next session.
+pub struct AutoRecoverySetting {
+    settings_path: PathBuf,
+    enabled: bool,
+}
+
+impl AutoRecoverySetting {
+    /// Reads the setting from Studio's settings file, if it can be found.
+    pub fn read() -> Option<Self> {
+        let pattern = setting_pattern();
+
+        global_settings_paths()
+            .into_iter()
+            .find_map(|settings_path| {
+                let contents = fs::read_to_string(&settings_path).ok()?;
+                let enabled = &pattern.captures(&contents)?[2] == "true";
+
+                Some(AutoRecoverySetting {
+                    settings_path,
+                    enabled,
+                })
+            })
+    }
+
+    /// Writes the setting back if it has changed since it was read. Studio
+    /// must have exited, or it would overwrite the file again.
+    pub fn restore(&self) {
+        let contents = match fs::read_to_string(&self.settings_path) {
+            Ok(contents) => contents,
+            Err(err) => {
+                log::debug!("Could not read Studio's settings: {}", err);
+                return;
+            }
+        };
+
+        let pattern = setting_pattern();
+        let enabled = match pattern.captures(&contents) {
+            Some(captures) => &captures[2] == "true",
+            None => return,
+        };
+
+        if enabled == self.enabled {
+            return;
+        }
+
+        let restored = pattern.replace(&contents, |captures: &regex::Captures| {
+            format!("{}{}{}", &captures[1], self.enabled, &captures[3])
+        });
+
+        match fs::write(&self.settings_path, restored.as_bytes()) {
+            Ok(()) => log::debug!(
+                "Set Studio's {:?} setting back to {}",
+                AUTO_RECOVERY_SETTING,
+                self.enabled
+            ),
+            Err(err) => log::warn!(
+                "Could not set Studio's {:?} setting back to {}: {}",
+                AUTO_RECOVERY_SETTING,
+                self.enabled,
+                err
+            ),
+        }
+    }
+}
@@ -0,0 +1,489 @@
+/// A mistake in a script that would stop Studio from compiling it.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("line {line}: {message}")]
+pub struct SyntaxError {
+    pub line: usize,
+    pub message: String,
+}
+
+/// Checks a script for the syntax errors that are easy to make and slow to
+/// find out about once Studio has started: blocks without an `end`, brackets
+/// that don't match, unfinished strings and comments, and characters that
+/// aren't Luau.
+///
+/// This isn't a full parser, so a script that passes can still fail to
+/// compile, but a script that fails won't compile either. A byte order mark
+/// and a `#!` line at the start are skipped, like Luau does.
+pub fn check(source: &str) -> Result<(), SyntaxError> {
+    let source = source.strip_prefix('\u{feff}').unwrap_or(source);
+    let position = match source.strip_prefix("#!") {
+        Some(rest) => source.len() - rest.len() + rest.find('\n').unwrap_or(rest.len()),
+        None => 0,
+    };
+
+    Checker {
+        chars: source.chars().collect(),
+        position: source[..position].chars().count(),
+        line: 1,
+        stack: Vec::new(),
+        expression_next: false,
+    }
+    .run()
+}
+
+/// Something that's been opened and needs closing, with the line it was
+/// opened on.
+#[derive(Debug, Clone, Copy)]
+enum Open {
+    /// `function`, `do`, or an `if` statement, closed by `end`.
+    Block(&'static str, usize),
+
+    /// `repeat`, closed by `until`.
+    Repeat(usize),
+
+    /// An `if` expression, which has no `end` but needs an `else`.
+    IfExpression(usize),
+
+    /// A bracket, or the braces around an expression in an interpolated
+    /// string, after which the rest of the string follows.
+    Bracket {
+        opener: char,
+        line: usize,
+        interpolation: bool,
+    },
+}
+
+impl Open {
+    fn describe(self) -> (String, &'static str, usize) {
+        match self {
+            Open::Block(keyword, line) => (format!("'{}'", keyword), "'end'", line),
+            Open::Repeat(line) => ("'repeat'".to_owned(), "'until'", line),
+            Open::IfExpression(line) => ("'if' expression".to_owned(), "'else'", line),
+            Open::Bracket { opener, line, .. } => {
+                let closer = match opener {
+                    '(' => "')'",
+                    '[' => "']'",
+                    _ => "'}'",
+                };
+
+                (format!("'{}'", opener), closer, line)
+            }
+        }
+    }
+}
+
+struct Checker {
+    chars: Vec<char>,
+    position: usize,
+    line: usize,
+    stack: Vec<Open>,
+
+    /// Whether the last token leaves us expecting an expression, which is
+    /// what tells an `if` expression apart from an `if` statement.
+    expression_next: bool,
+}
+
+impl Checker {
+    fn run(mut self) -> Result<(), SyntaxError> {
+        while let Some(current) = self.peek(0) {
+            if current == '\n' {
+                self.line += 1;
+                self.position += 1;
+            } else if current.is_whitespace() {
+                self.position += 1;
+            } else if current == '-' && self.peek(1) == Some('-') {
+                self.skip_comment()?;
+            } else if current == '"' || current == '\'' {
+                self.skip_string(current)?;
+                self.expression_next = false;
+            } else if current == '`' {
+                self.position += 1;
+                self.skip_interpolated_string()?;
+            } else if current == '[' && self.long_bracket_level(0).is_some() {
+                self.skip_long_bracket("string")?;
+                self.expression_next = false;
+            } else if current.is_ascii_digit()
+                || (current == '.' && self.peek(1).is_some_and(|next| next.is_ascii_digit()))
+            {
+                self.skip_number();
+                self.expression_next = false;
+            } else if current.is_ascii_alphabetic() || current == '_' {
+                let word = self.take_word();
+                self.word(&word)?;
+            } else {
+                self.symbol(current)?;
+            }
+        }
+
+        match self.stack.pop() {
+            None => Ok(()),
+            Some(open) => {
+                let (opener, closer, line) = open.describe();
+
+                Err(SyntaxError {
+                    line,
+                    message: format!("{} is never closed with {}", opener, closer),
+                })
+            }
+        }
+    }
+
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.position + offset).copied()
+    }
+
+    fn error(&self, message: impl Into<String>) -> SyntaxError {
+        SyntaxError {
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn take_word(&mut self) -> String {
+        let start = self.position;
+
+        while let Some(current) = self.peek(0) {
+            if current.is_ascii_alphanumeric() || current == '_' {
+                self.position += 1;
+            } else {
+                break;
+            }
+        }
+
+        self.chars[start..self.position].iter().collect()
+    }
+
+    fn word(&mut self, word: &str) -> Result<(), SyntaxError> {
+        let line = self.line;
+
+        match word {
+            "function" => self.stack.push(Open::Block("function", line)),
+            "do" => self.stack.push(Open::Block("do", line)),
+            "repeat" => self.stack.push(Open::Repeat(line)),
+            "if" if self.expression_next => self.stack.push(Open::IfExpression(line)),
+            "if" => self.stack.push(Open::Block("if", line)),
+            "else" => {
+                // The branch after an `if` expression's `else` is the last
+                // part of it, so there's nothing left to close.
+                if let Some(Open::IfExpression(_)) = self.stack.last() {
+                    self.stack.pop();
+                    self.expression_next = true;
+                    return Ok(());
+                }
+            }
+            "then" => {
+                if let Some(Open::IfExpression(_)) = self.stack.last() {
+                    self.expression_next = true;
+                    return Ok(());
+                }
+            }
+            "end" => self.close(Open::Block("function", line), "'end'")?,
+            "until" => self.close(Open::Repeat(line), "'until'")?,
+            _ => {}
+        }
+
+        self.expression_next = matches!(
+            word,
+            "return" | "and" | "or" | "not" | "in" | "if" | "elseif" | "while" | "until"
+        );
+
+        Ok(())
+    }
+
+    /// Closes whatever was opened last, which should be the same kind of
+    /// thing as `closing`.
+    fn close(&mut self, closing: Open, found: &str) -> Result<(), SyntaxError> {
+        let open = match self.stack.pop() {
+            Some(open) => open,
+            None => return Err(self.error(format!("{} doesn't close anything", found))),
+        };
+
+        let matches = match (open, closing) {
+            (Open::Block(..), Open::Block(..)) | (Open::Repeat(_), Open::Repeat(_)) => true,
+            (Open::Bracket { opener, .. }, Open::Bracket { opener: closer, .. }) => {
+                opener == closer
+            }
+            _ => false,
+        };
+
+        if matches {
+            return Ok(());
+        }
+
+        let (opener, closer, line) = open.describe();
+        Err(self.error(format!(
+            "expected {} to close the {} on line {}, but found {}",
+            closer, opener, line, found
+        )))
+    }
+
+    fn symbol(&mut self, current: char) -> Result<(), SyntaxError> {
+        let line = self.line;
+        self.position += 1;
+
+        match current {
+            '(' | '[' | '{' => {
+                self.stack.push(Open::Bracket {
+                    opener: current,
+                    line,
+                    interpolation: false,
+                });
+                self.expression_next = true;
+            }
+            ')' | ']' | '}' => {
+                let opener = match current {
+                    ')' => '(',
+                    ']' => '[',
+                    _ => '{',
+                };
+                let interpolation = matches!(
+                    self.stack.last(),
+                    Some(Open::Bracket {
+                        interpolation: true,
+                        ..
+                    })
+                );
+
+                self.close(
+                    Open::Bracket {
+                        opener,
+                        line,
+                        interpolation: false,
+                    },
+                    &format!("'{}'", current),
+                )?;
+
+                self.expression_next = false;
+
+                if interpolation && current == '}' {
+                    self.skip_interpolated_string()?;
+                }
+            }
+            '!' if self.peek(0) == Some('=') => {
+                return Err(self.error("unexpected '!=', Luau uses '~=' for not equal to"));
+            }
+            '+' | '-' | '*' | '/' | '%' | '^' | '#' | '&' | '|' | '~' | '<' | '=' | ',' | '.'
+            | '@' => {
+                // `...` is a value, but `.` and `..` come before one.
+                self.expression_next =
+                    !(current == '.' && self.peek(0) == Some('.') && self.peek(1) == Some('.'));
+
+                if !self.expression_next {
+                    self.position += 2;
+                }
+            }
+            // `>` also ends generic types, like `Array<T>`, and `:` comes
+            // before types and method names, none of which are expressions.
+            '>' | ';' | ':' | '?' => self.expression_next = false,
+            _ => return Err(self.error(format!("unexpected character '{}'", current))),
+        }
+
+        Ok(())
+    }
+
+    fn skip_comment(&mut self) -> Result<(), SyntaxError> {
+        self.position += 2;
+
+        if self.peek(0) == Some('[') && self.long_bracket_level(0).is_some() {
+            return self.skip_long_bracket("comment");
+        }
+
+        while let Some(current) = self.peek(0) {
+            if current == '\n' {
+                break;
+            }
+
+            self.position += 1;
+        }
+
+        Ok(())
+    }
+
+    /// How many `=` are in the long bracket starting at `offset`, like 2 for
+    /// `[==[`, if there's one there.
+    fn long_bracket_level(&self, offset: usize) -> Option<usize> {
+        let mut level = 0;
+
+        while self.peek(offset + 1 + level) == Some('=') {
+            level += 1;
+        }
+
+        if self.peek(offset + 1 + level) == Some('[') {
+            Some(level)
+        } else {
+            None
+        }
+    }
+
+    fn skip_long_bracket(&mut self, kind: &str) -> Result<(), SyntaxError> {
+        let start_line = self.line;
+        let level = self.long_bracket_level(0).unwrap();
+        self.position += level + 2;
+
+        while let Some(current) = self.peek(0) {
+            if current == '\n' {
+                self.line += 1;
+            }
+
+            if current == ']'
+                && (1..=level).all(|offset| self.peek(offset) == Some('='))
+                && self.peek(level + 1) == Some(']')
+            {
+                self.position += level + 2;
+                return Ok(());
+            }
+
+            self.position += 1;
+        }
+
+        Err(SyntaxError {
+            line: start_line,
+            message: format!("unfinished long {}", kind),
+        })
+    }
+
+    fn skip_string(&mut self, quote: char) -> Result<(), SyntaxError> {
+        self.position += 1;
+
+        while let Some(current) = self.peek(0) {
+            self.position += 1;
+
+            match current {
+                _ if current == quote => return Ok(()),
+                '\\' => self.skip_escape(),
+                '\n' => break,
+                _ => {}
+            }
+        }
+
+        Err(self.error("unfinished string"))
+    }
+
+    /// Skips the rest of an interpolated string, up to its closing backtick
+    /// or the next expression in braces.
+    fn skip_interpolated_string(&mut self) -> Result<(), SyntaxError> {
+        while let Some(current) = self.peek(0) {
+            self.position += 1;
+
+            match current {
+                '`' => {
+                    self.expression_next = false;
+                    return Ok(());
+                }
+                '{' => {
+                    self.stack.push(Open::Bracket {
+                        opener: '{',
+                        line: self.line,
+                        interpolation: true,
+                    });
+                    self.expression_next = true;
+                    return Ok(());
+                }
+                '\\' => self.skip_escape(),
+                '\n' => break,
+                _ => {}
+            }
+        }
+
+        Err(self.error("unfinished string"))
+    }
+
+    /// Skips the character after a backslash in a string, along with the
+    /// whitespace after `\z`.
+    fn skip_escape(&mut self) {
+        match self.peek(0) {
+            Some('\n') => {
+                self.line += 1;
+                self.position += 1;
+            }
+            Some('z') => {
+                self.position += 1;
+
+                while let Some(current) = self.peek(0) {
+                    if !current.is_whitespace() {
+                        break;
+                    }
+
+                    if current == '\n' {
+                        self.line += 1;
+                    }
+
+                    self.position += 1;
+                }
+            }
+            Some(_) => self.position += 1,
+            None => {}
+        }
+    }
+
+    fn skip_number(&mut self) {
+        while let Some(current) = self.peek(0) {
+            let exponent_sign = (current == '+' || current == '-')
+                && matches!(self.chars[self.position - 1], 'e' | 'E' | 'p' | 'P');
+
+            if current.is_ascii_alphanumeric() || current == '_' || current == '.' || exponent_sign
+            {
+                self.position += 1;
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_scripts_pass() {
+        let scripts = [
+            "if ready then\n\tlocal size = if big then 10 else 1\n\tprint(size)\nend",
+            "local kind = if a then 1 elseif b then 2 else 3",
+            "print(if a then if b then 1 else 2 else 3)",
+            "print(`total: {add({1, 2})} of {`{count} items`}`)",
+            "local half = 7 // 2\nlocal text = 'a'\ntext ..= 'b'",
+            "local text = [==[\ncontains ]] and ]=]\n]==]\nprint(text)",
+            "--[==[\ncomment ]]\n]==]\nprint(1)",
+            "local text = 'a\\z\n\t\tb'\nprint(text)",
+            "type Map<K, V> = { [K]: V }\nlocal function id<T>(value: T): T\n\treturn value\nend",
+            "local callback: (number) -> () = print",
+            "local function pack(...)\n\treturn { ... }, select('#', ...)\nend",
+            "\u{feff}print('has a byte order mark')",
+            "#!/usr/bin/env lune\nprint('has a shebang')",
+        ];
+
+        for script in scripts {
+            assert_eq!(check(script), Ok(()), "{:?}", script);
+        }
+    }
+
+    #[test]
+    fn broken_scripts_fail_on_their_line() {
+        let scripts = [
+            ("local function f()\n\tprint(1)\n", 1),
+            ("print(1)\nif ready then\n", 2),
+            ("repeat\n\twait()\n", 1),
+            ("print(1)\nend", 2),
+            ("print((1)\n", 1),
+            ("local t = {1, 2)\n", 1),
+            ("print(1)\nlocal text = 'abc\nprint(text)", 2),
+            ("local text = [==[\nabc ]=]\n", 1),
+            ("print(1)\n--[[ abc", 2),
+            ("print(`abc {x}\n`)", 1),
+            ("print(`{`{x`})", 1),
+            ("local size = if big then 10\n", 1),
+            ("if a != b then\nend", 1),
+            ("local text = 'a\\z\n\n\tb'\nprint((", 4),
+            ("#!/usr/bin/env lune\nprint(", 2),
+            ("print(1)\n#!/usr/bin/env lune", 2),
+        ];
+
+        for (script, line) in scripts {
+            match check(script) {
+                Ok(()) => panic!("{:?} passed", script),
+                Err(error) => assert_eq!(error.line, line, "{:?}: {}", script, error.message),
+            }
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    fmt,
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    str::FromStr,
+    sync::Mutex,
+    time::Instant,
+};
+
+use anyhow::{bail, Context};
+use colored::Colorize;
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+
+/// The transcript being written for --transcript, if any. Anything in
+/// run-in-roblox can add to it, so it's kept globally like the logger.
+static TRANSCRIPT: Mutex<Option<Transcript>> = Mutex::new(None);
+
+struct Transcript {
+    started: Instant,
+    file: fs::File,
+}
+
+/// What part of run-in-roblox an entry in a transcript came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EntryKind {
+    /// Something run-in-roblox logged, labeled with its log level.
+    Diagnostic,
+
+    /// A request from the plugin exactly as it was received, labeled with
+    /// its endpoint.
+    Protocol,
+
+    /// Roblox Studio or the plugin starting, stopping, or changing state.
+    Lifecycle,
+
+    /// A line run-in-roblox printed, labeled with its level.
+    Output,
+}
+
+impl fmt::Display for EntryKind {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            EntryKind::Diagnostic => "diagnostic",
+            EntryKind::Protocol => "protocol",
+            EntryKind::Lifecycle => "lifecycle",
+            EntryKind::Output => "output",
+        };
+
+        formatter.write_str(name)
+    }
+}
+
+impl FromStr for EntryKind {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "diagnostic" => Ok(EntryKind::Diagnostic),
+            "protocol" => Ok(EntryKind::Protocol),
+            "lifecycle" => Ok(EntryKind::Lifecycle),
+            "output" => Ok(EntryKind::Output),
+            _ => bail!(
+                "Invalid value for --kind, expected \"diagnostic\", \"protocol\", \
+                 \"lifecycle\", or \"output\""
+            ),
+        }
+    }
+}
+
+/// One line of a transcript.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    /// Seconds since the transcript was started.
+    pub time: f64,
+    pub kind: EntryKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub text: String,
+}
+
+/// Starts writing everything that happens during the run to `path`, one JSON
+/// entry per line.
+pub fn start(path: &Path) -> Result<(), anyhow::Error> {
+    let file = fs::File::create(path).context("Could not create the --transcript file")?;
+
+    *TRANSCRIPT.lock().unwrap() = Some(Transcript {
+        started: Instant::now(),
+        file,
+    });
+
+    Ok(())
+}
+
+/// Adds an entry to the transcript, if one is being written. Entries are
+/// written out right away so that nothing is lost if run-in-roblox crashes.
+pub fn record(kind: EntryKind, label: Option<&str>, text: impl Into<String>) {
+    let mut transcript = TRANSCRIPT.lock().unwrap();
+
+    if let Some(transcript) = transcript.as_mut() {
+        let entry = Entry {
+            time: transcript.started.elapsed().as_secs_f64(),
+            kind,
+            label: label.map(str::to_owned),
+            text: text.into(),
+        };
+
+        // A transcript is a diagnostic aid, so failing to write it shouldn't
+        // fail the run.
+        if let Ok(line) = serde_json::to_string(&entry) {
+            let _ignored = writeln!(transcript.file, "{}", line);
+        }
+    }
+}
+
+/// Forwards log records to another logger, also adding run-in-roblox's own
+/// debug logs and above to the transcript regardless of `RUST_LOG`.
+pub struct TranscriptLogger<L> {
+    pub inner: L,
+}
+
+impl<L: log::Log> log::Log for TranscriptLogger<L> {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.inner.enabled(metadata) || is_transcribed(metadata)
+    }
+
+    fn log(&self, log_record: &log::Record) {
+        if is_transcribed(log_record.metadata()) {
+            record(
+                EntryKind::Diagnostic,
+                Some(log_record.level().as_str()),
+                log_record.args().to_string(),
+            );
+        }
+
+        if self.inner.enabled(log_record.metadata()) {
+            self.inner.log(log_record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+fn is_transcribed(metadata: &log::Metadata) -> bool {
+    metadata.level() <= log::Level::Debug && metadata.target().starts_with("run_in_roblox")
+}
+
+/// Prints a transcript in a readable form, optionally only the entries of one
+/// kind.
+pub fn inspect(path: &Path, kind: Option<EntryKind>) -> Result<(), anyhow::Error> {
+    let reader = BufReader::new(fs::File::open(path)?);
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let entry: Entry = serde_json::from_str(&line)
+            .with_context(|| format!("Line {} of the transcript is invalid", index + 1))?;
+
+        if kind.map(|kind| kind != entry.kind).unwrap_or(false) {
+            continue;
+        }
+
+        let heading = format!(
+            "[+{:.3}s] {:<10} {}",
+            entry.time,
+            entry.kind.to_string(),
+            entry.label.as_deref().unwrap_or("")
+        );
+        let heading = heading.trim_end();
+
+        let heading = match entry.kind {
+            EntryKind::Diagnostic => heading.yellow(),
+            EntryKind::Protocol => heading.cyan(),
+            EntryKind::Lifecycle => heading.green(),
+            EntryKind::Output => heading.normal(),
+        };
+
+        println!("{}", heading);
+
+        for text_line in entry.text.lines() {
+            println!("    {}", text_line);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,138 @@
+use std::fmt;
+
+use crate::lang::{Lang, Message};
+
+/// A broad category of failure, used by `--triage` to tell at a glance whether
+/// a run failed because of the script, Studio, or the environment it ran in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FailureCategory {
+    /// The script raised an error or reported a failing check.
+    ScriptError,
+
+    /// Roblox Studio exited before the script finished.
+    EngineCrash,
+
+    /// The script ran for too long or Studio stopped responding.
+    Timeout,
+
+    /// Roblox Studio couldn't be started, or the plugin never connected.
+    Infra,
+
+    /// The plugin sent messages that break the rules of the protocol.
+    OutputRule,
+}
+
+impl FailureCategory {
+    /// The exit code used for this category when `--triage` is given.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            FailureCategory::ScriptError => 1,
+            FailureCategory::EngineCrash => 3,
+            FailureCategory::Timeout => 4,
+            FailureCategory::Infra => 5,
+            FailureCategory::OutputRule => 6,
+        }
+    }
+}
+
+impl fmt::Display for FailureCategory {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            FailureCategory::ScriptError => "script error",
+            FailureCategory::EngineCrash => "engine crash",
+            FailureCategory::Timeout => "timeout",
+            FailureCategory::Infra => "infra/handshake",
+            FailureCategory::OutputRule => "output rule violation",
+        };
+
+        formatter.write_str(name)
+    }
+}
+
+/// An error from the place runner that knows which category it belongs to.
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+pub struct RunFailure {
+    pub category: FailureCategory,
+    message: String,
+}
+
+impl RunFailure {
+    pub fn new(category: FailureCategory, message: impl Into<String>) -> Self {
+        RunFailure {
+            category,
+            message: message.into(),
+        }
+    }
+}
+
+/// Classifies an error that ended a run. Errors that weren't raised as a
+/// `RunFailure` come from the environment, like missing files or a port
+/// that's already in use.
+pub fn categorize(error: &anyhow::Error) -> FailureCategory {
+    error
+        .downcast_ref::<RunFailure>()
+        .map(|failure| failure.category)
+        .unwrap_or(FailureCategory::Infra)
+}
+
+/// Failures collected over a run, grouped by category for the `--triage`
+/// summary.
+#[derive(Debug, Default)]
+pub struct Triage {
+    failures: Vec<(FailureCategory, String)>,
+}
+
+impl Triage {
+    pub fn record(&mut self, category: FailureCategory, description: impl Into<String>) {
+        self.failures.push((category, description.into()));
+    }
+
+    /// The category that decides the exit code. Failures outside the script
+    /// win over script errors since they usually caused them.
+    pub fn primary_category(&self) -> Option<FailureCategory> {
+        self.failures
+            .iter()
+            .map(|(category, _)| *category)
+            .find(|category| *category != FailureCategory::ScriptError)
+            .or_else(|| self.failures.first().map(|(category, _)| *category))
+    }
+
+    pub fn summary(&self, lang: Lang) -> String {
+        if self.failures.is_empty() {
+            return lang.text(Message::TriageNoFailures).to_owned();
+        }
+
+        let mut categories: Vec<FailureCategory> = self
+            .failures
+            .iter()
+            .map(|(category, _)| *category)
+            .collect();
+        categories.sort();
+        categories.dedup();
+
+        let mut lines = vec![lang.text(Message::TriageHeader).to_owned()];
+
+        for category in categories {
+            let descriptions: Vec<&str> = self
+                .failures
+                .iter()
+                .filter(|(failure_category, _)| *failure_category == category)
+                .map(|(_, description)| description.as_str())
+                .collect();
+
+            lines.push(format!(
+                "  {} ({})",
+                lang.text(Message::Category(category)),
+                descriptions.len()
+            ));
+
+            for description in descriptions {
+                let first_line = description.lines().next().unwrap_or("");
+                lines.push(format!("    {}", first_line));
+            }
+        }
+
+        lines.join("\n")
+    }
+}
@@ -0,0 +1,138 @@
+use std::{path::Path, process::Command};
+
+use anyhow::{bail, Context};
+use fs_err as fs;
+
+use crate::{
+    bundle::BundledModule,
+    plugin,
+    transcript::{self, EntryKind},
+    triage::{FailureCategory, RunFailure},
+};
+
+/// The type checker used by --typecheck unless --typecheck-command is given.
+pub const DEFAULT_COMMAND: &str = "luau-analyze";
+
+/// Type checks the scripts, given by name along with their source, and the
+/// modules they need with `command`, which is given the files to check after
+/// its own arguments. They're written to `temp_dir` first, laid out the way
+/// the plugin puts them in Studio.
+pub fn run(
+    command: &str,
+    scripts: &[(String, &str)],
+    modules: &[BundledModule],
+    temp_dir: &Path,
+) -> Result<(), anyhow::Error> {
+    let dir = temp_dir.join("typecheck");
+    fs::create_dir_all(&dir)?;
+
+    // Scripts are named like the modules the plugin puts them in, since two
+    // scripts can have the same file name, and their names are put back in
+    // the checker's output.
+    let mut script_files = Vec::new();
+    for (index, (name, source)) in scripts.iter().enumerate() {
+        let file_name = format!("{}.luau", plugin::main_module_name(index));
+        fs::write(dir.join(&file_name), source)?;
+        script_files.push((file_name, name.as_str()));
+    }
+
+    let mut files: Vec<String> = script_files
+        .iter()
+        .map(|(file_name, _)| file_name.clone())
+        .collect();
+    for module in modules {
+        write_module(&dir, "", module, &mut files)?;
+    }
+
+    let mut parts = command.split_whitespace();
+    let program = match parts.next() {
+        Some(program) => program,
+        None => bail!("--typecheck-command can't be empty"),
+    };
+
+    let output = Command::new(program)
+        .args(parts)
+        .args(&files)
+        .current_dir(&dir)
+        .output()
+        .with_context(|| {
+            format!(
+                "Could not run {}, which --typecheck uses to check the scripts. Is it installed \
+                 and on the PATH?",
+                program
+            )
+        })?;
+
+    transcript::record(
+        EntryKind::Lifecycle,
+        None,
+        format!("Type checked the scripts with {}", command),
+    );
+
+    if output.status.success() {
+        return Ok(());
+    }
+
+    // Checkers print their errors to either stdout or stderr.
+    let printed = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let errors: Vec<String> = printed
+        .lines()
+        .map(|line| {
+            for (file_name, name) in &script_files {
+                if let Some(rest) = line.strip_prefix(file_name.as_str()) {
+                    return format!("{}{}", name, rest);
+                }
+            }
+
+            line.to_owned()
+        })
+        .collect();
+
+    bail!(RunFailure::new(
+        FailureCategory::ScriptError,
+        format!(
+            "{} found type errors in the scripts ({}):\n{}",
+            program,
+            output.status,
+            errors.join("\n").trim_end()
+        )
+    ))
+}
+
+/// Writes a bundled module inside `parent`, as a folder with an `init.luau`
+/// if it has modules inside it, and adds the files to check to `files`.
+fn write_module(
+    dir: &Path,
+    parent: &str,
+    module: &BundledModule,
+    files: &mut Vec<String>,
+) -> Result<(), anyhow::Error> {
+    if module.children.is_empty() {
+        if let Some(source) = &module.source {
+            let file_name = format!("{}{}.luau", parent, module.name);
+            fs::write(dir.join(&file_name), source)?;
+            files.push(file_name);
+        }
+
+        return Ok(());
+    }
+
+    let folder = format!("{}{}/", parent, module.name);
+    fs::create_dir_all(dir.join(&folder))?;
+
+    if let Some(source) = &module.source {
+        let file_name = format!("{}init.luau", folder);
+        fs::write(dir.join(&file_name), source)?;
+        files.push(file_name);
+    }
+
+    for child in &module.children {
+        write_module(dir, &folder, child, files)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use std::{collections::BTreeMap, path::Path, process::Command, str::FromStr, time::Duration};
+
+use anyhow::bail;
+use fs_err as fs;
+use serde::Serialize;
+
+use crate::{metrics::MetricStats, output::FormatVersion};
+
+/// A tag describing who a run is for, given on the command line like
+/// `--meta project=lobby`.
+#[derive(Debug, Clone)]
+pub struct MetaTag {
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for MetaTag {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.find('=') {
+            Some(index) if index > 0 => Ok(MetaTag {
+                key: value[..index].to_owned(),
+                value: value[index + 1..].to_owned(),
+            }),
+            _ => bail!("Invalid --meta tag {:?}, expected key=value", value),
+        }
+    }
+}
+
+/// The resources a run used, written with `--usage` so that shared CI
+/// capacity can be accounted to the projects given with `--meta`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageReport {
+    pub format_version: u32,
+    pub meta: BTreeMap<String, String>,
+    pub wall_time_seconds: f64,
+    pub studio_cpu_time_seconds: Option<f64>,
+    pub metrics: BTreeMap<String, MetricStats>,
+}
+
+impl UsageReport {
+    pub fn new(
+        format_version: FormatVersion,
+        meta: &[MetaTag],
+        wall_time: Duration,
+        studio_cpu_time: Option<Duration>,
+        metrics: BTreeMap<String, MetricStats>,
+    ) -> Self {
+        UsageReport {
+            format_version: format_version.0,
+            meta: meta
+                .iter()
+                .map(|tag| (tag.key.clone(), tag.value.clone()))
+                .collect(),
+            wall_time_seconds: wall_time.as_secs_f64(),
+            studio_cpu_time_seconds: studio_cpu_time.map(|time| time.as_secs_f64()),
+            metrics,
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let contents = serde_json::to_string_pretty(self)?;
+        fs::write(path, contents)?;
+
+        Ok(())
+    }
+}
+
+/// Reads one column of what `tasklist /V` shows for a process.
+#[cfg(windows)]
+fn tasklist_field(pid: u32, column: usize) -> Option<String> {
+    let output = Command::new("tasklist")
+        .args(["/V", "/FI", &format!("PID eq {}", pid), "/FO", "CSV", "/NH"])
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    let field = stdout.trim().split("\",\"").nth(column)?;
+    Some(field.trim_matches('"').to_owned())
+}
+
+#[cfg(windows)]
+fn process_cpu_time_text(pid: u32) -> Option<String> {
+    // The CPU Time column is the eighth, formatted like "0:01:23".
+    tasklist_field(pid, 7)
+}
+
+/// Finds the title of a process's main window, where that can be seen.
+#[cfg(windows)]
+pub fn process_window_title(pid: u32) -> Option<String> {
+    // The Window Title column is the ninth, and is "N/A" for processes
+    // without a window.
+    tasklist_field(pid, 8).filter(|title| title != "N/A" && !title.is_empty())
+}
+
+#[cfg(not(windows))]
+pub fn process_window_title(_pid: u32) -> Option<String> {
+    None
+}
+
+#[cfg(not(windows))]
+fn process_cpu_time_text(pid: u32) -> Option<String> {
+    let output = Command::new("ps")
+        .args(["-p", &pid.to_string(), "-o", "time="])
+        .output()
+        .ok()?;
+    let time = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+
+    if time.is_empty() {
+        None
+    } else {
+        Some(time)
+    }
+}
+
+/// Finds how much CPU time a running process has used so far.
+pub fn process_cpu_time(pid: u32) -> Option<Duration> {
+    parse_cpu_time(&process_cpu_time_text(pid)?)
+}
+
+/// Parses CPU times like `1-02:03:04`, `02:03:04`, or `3:04.56`, as printed by
+/// `ps` and `tasklist`.
+fn parse_cpu_time(text: &str) -> Option<Duration> {
+    let (days, clock) = match text.find('-') {
+        Some(index) => (text[..index].parse::<f64>().ok()?, &text[index + 1..]),
+        None => (0.0, text),
+    };
+
+    let mut seconds = 0.0;
+    for part in clock.split(':') {
+        seconds = seconds * 60.0 + part.parse::<f64>().ok()?;
+    }
+
+    Some(Duration::from_secs_f64(days * 86400.0 + seconds))
+}
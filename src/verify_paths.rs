@@ -0,0 +1,311 @@
+use std::{fmt, io::BufReader, path::Path};
+
+use anyhow::{bail, Context};
+use fs_err as fs;
+use rbx_dom_weak::{Instance, WeakDom};
+use rbx_reflection::ClassTag;
+use regex::Regex;
+
+use crate::{bundle::BundledModule, mount::Mount};
+
+/// An instance path that a script refers to, like
+/// `game:GetService("ReplicatedStorage").Modules.Util`, starting at a service.
+#[derive(Debug)]
+struct PathReference {
+    line: usize,
+    service: String,
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug)]
+struct Segment {
+    name: String,
+
+    /// Whether the script asks for a child by name, like with
+    /// `:WaitForChild("Name")`, rather than indexing with `.Name`, which could
+    /// also be a property, method, or event.
+    explicit: bool,
+
+    /// Whether the segment is followed by a call, which suggests it's a method
+    /// or event rather than a child.
+    called: bool,
+}
+
+/// A path the script refers to that isn't in the place.
+#[derive(Debug)]
+pub struct MissingPath {
+    pub source: String,
+    pub line: usize,
+    pub path: String,
+    pub reason: String,
+}
+
+impl fmt::Display for MissingPath {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "{}:{}: {} ({})",
+            self.source, self.line, self.path, self.reason
+        )
+    }
+}
+
+/// Checks that the services and instances the script and its modules refer to
+/// exist in the place, failing with a list of the ones that don't.
+///
+/// Only paths written out from a service are checked, like `workspace.Map` or
+/// `game:GetService("ServerStorage"):WaitForChild("Assets")`. Anything under a
+/// --mount target is skipped, since it's only added once the run starts.
+pub fn verify(
+    place_path: &Path,
+    scripts: &[(&str, &str)],
+    modules: &[BundledModule],
+    mounts: &[Mount],
+) -> Result<(), anyhow::Error> {
+    let place = load_place(place_path)?;
+
+    let mut missing = Vec::new();
+    for (script_name, lua_script) in scripts {
+        missing.extend(find_missing(&place, script_name, lua_script, mounts));
+    }
+    for module in modules.iter().flat_map(BundledModule::descendants) {
+        if let Some(source) = &module.source {
+            missing.extend(find_missing(&place, &module.name, source, mounts));
+        }
+    }
+
+    if !missing.is_empty() {
+        let lines: Vec<String> = missing.iter().map(|path| format!("  {}", path)).collect();
+
+        bail!(
+            "The script refers to {} path(s) that aren't in {}:\n{}",
+            missing.len(),
+            place_path.display(),
+            lines.join("\n")
+        );
+    }
+
+    Ok(())
+}
+
+fn load_place(path: &Path) -> Result<WeakDom, anyhow::Error> {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    let reader = BufReader::new(fs::File::open(path)?);
+
+    let place = match extension {
+        Some("rbxl") => rbx_binary::from_reader(reader)
+            .with_context(|| format!("Could not read place {}", path.display()))?,
+        Some("rbxlx") => rbx_xml::from_reader_default(reader)
+            .with_context(|| format!("Could not read place {}", path.display()))?,
+        _ => bail!(
+            "--verify-paths needs an .rbxl or .rbxlx place, got {}",
+            path.display()
+        ),
+    };
+
+    Ok(place)
+}
+
+fn find_missing(
+    place: &WeakDom,
+    source_name: &str,
+    source: &str,
+    mounts: &[Mount],
+) -> Vec<MissingPath> {
+    let mount_targets: Vec<Vec<&str>> = mounts
+        .iter()
+        .map(|mount| mount.target.split('.').collect())
+        .collect();
+
+    let mut missing = Vec::new();
+
+    for reference in find_references(source) {
+        if !is_service(&reference.service) {
+            missing.push(MissingPath {
+                source: source_name.to_owned(),
+                line: reference.line,
+                path: reference.service.clone(),
+                reason: "not a service".to_owned(),
+            });
+            continue;
+        }
+
+        // Services that haven't been used aren't saved with the place, so
+        // there's nothing to check under them.
+        let service = match place
+            .root()
+            .children()
+            .iter()
+            .filter_map(|child| place.get_by_ref(*child))
+            .find(|child| child.class == reference.service)
+        {
+            Some(service) => service,
+            None => continue,
+        };
+
+        if let Some(missing_path) =
+            walk_reference(place, service, &reference, &mount_targets, source_name)
+        {
+            missing.push(missing_path);
+        }
+    }
+
+    missing
+}
+
+fn walk_reference(
+    place: &WeakDom,
+    service: &Instance,
+    reference: &PathReference,
+    mount_targets: &[Vec<&str>],
+    source_name: &str,
+) -> Option<MissingPath> {
+    let mut path = vec![reference.service.as_str()];
+    let mut current = service;
+
+    for segment in &reference.segments {
+        if mount_targets.iter().any(|target| target[..] == path[..]) {
+            return None;
+        }
+
+        let child = current
+            .children()
+            .iter()
+            .filter_map(|child| place.get_by_ref(*child))
+            .find(|child| child.name == segment.name);
+
+        path.push(&segment.name);
+
+        current = match child {
+            Some(child) => child,
+            None if !segment.explicit
+                && (segment.called || has_property(&current.class, &segment.name)) =>
+            {
+                return None
+            }
+            None => {
+                return Some(MissingPath {
+                    source: source_name.to_owned(),
+                    line: reference.line,
+                    path: path.join("."),
+                    reason: format!("{} has no child named {:?}", current.name, segment.name),
+                })
+            }
+        };
+    }
+
+    None
+}
+
+pub fn is_service(class_name: &str) -> bool {
+    rbx_reflection_database::get()
+        .classes
+        .get(class_name)
+        .map(|class| class.tags.contains(&ClassTag::Service))
+        .unwrap_or(false)
+}
+
+/// Whether instances of the class, or any class it inherits from, have a
+/// property with the given name.
+fn has_property(class_name: &str, property: &str) -> bool {
+    let database = rbx_reflection_database::get();
+    let mut class = database.classes.get(class_name);
+
+    while let Some(descriptor) = class {
+        if descriptor.properties.contains_key(property) {
+            return true;
+        }
+
+        class = descriptor
+            .superclass
+            .as_ref()
+            .and_then(|superclass| database.classes.get(superclass.as_ref()));
+    }
+
+    false
+}
+
+/// Finds the instance paths written out in a script, one line at a time.
+/// Comments are skipped, but only when they take up the whole line.
+fn find_references(source: &str) -> Vec<PathReference> {
+    let string = r#"(?:"([^"]*)"|'([^']*)')"#;
+    let root = Regex::new(&format!(
+        r"(?:\bgame\s*:\s*GetService\s*\(\s*{}\s*\)|\bgame\.(\w+)|(?:^|[^.:\w])(workspace)\b)",
+        string
+    ))
+    .unwrap();
+    let segment = Regex::new(&format!(
+        r"^\s*(?:\.\s*(\w+)|\[\s*{}\s*\]|:\s*WaitForChild\s*\(\s*{}\s*[,)])",
+        string, string
+    ))
+    .unwrap();
+    let call = Regex::new(r#"^\s*[:({"']"#).unwrap();
+
+    let mut references = Vec::new();
+
+    for (index, line) in source.lines().enumerate() {
+        if line.trim_start().starts_with("--") {
+            continue;
+        }
+
+        for captures in root.captures_iter(line) {
+            let service = match (1..=4).find_map(|group| captures.get(group)) {
+                Some(service) if service.as_str() == "workspace" => "Workspace".to_owned(),
+                Some(service) => service.as_str().to_owned(),
+                None => continue,
+            };
+
+            // `game.PlaceId` and the like are properties, not services.
+            if captures.get(3).is_some()
+                && !is_service(&service)
+                && has_property("DataModel", &service)
+            {
+                continue;
+            }
+
+            let mut rest = &line[captures.get(0).unwrap().end()..];
+            let mut segments = Vec::new();
+
+            while let Some(segment_captures) = segment.captures(rest) {
+                rest = &rest[segment_captures.get(0).unwrap().end()..];
+
+                let (name, explicit) = match segment_captures.get(1) {
+                    Some(name) => (name.as_str(), false),
+                    None => match (2..=5).find_map(|group| segment_captures.get(group)) {
+                        Some(name) => (name.as_str(), true),
+                        None => break,
+                    },
+                };
+
+                segments.push(Segment {
+                    name: name.to_owned(),
+                    explicit,
+                    called: false,
+                });
+
+                // `:WaitForChild` closes its own argument list, but the
+                // optional timeout after the name still needs skipping.
+                if segment_captures.get(0).unwrap().as_str().ends_with(',') {
+                    match rest.find(')') {
+                        Some(end) => rest = &rest[end + 1..],
+                        None => break,
+                    }
+                }
+            }
+
+            // Only the last segment can be followed by anything other than
+            // another segment.
+            if let Some(last) = segments.last_mut() {
+                last.called = !last.explicit && call.is_match(rest);
+            }
+
+            references.push(PathReference {
+                line: index + 1,
+                service,
+                segments,
+            });
+        }
+    }
+
+    references
+}
@@ -0,0 +1,99 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use fs_err as fs;
+
+/// How often watched files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long to wait after a change for more changes, since editors and build
+/// tools often write several files, or the same file more than once.
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+/// When each file under the watched paths was last modified, along with its
+/// size. Paths that don't exist are recorded too, so that creating them counts
+/// as a change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot(BTreeMap<PathBuf, Option<(SystemTime, u64)>>);
+
+impl Snapshot {
+    pub fn take(paths: &[PathBuf]) -> Self {
+        let mut files = BTreeMap::new();
+
+        for path in paths {
+            record(path, &mut files);
+        }
+
+        Snapshot(files)
+    }
+
+    /// The paths that were added, removed, or changed since `earlier`.
+    pub fn changed_since(&self, earlier: &Snapshot) -> Vec<PathBuf> {
+        let mut changed: Vec<PathBuf> = self
+            .0
+            .iter()
+            .filter(|(path, state)| earlier.0.get(*path) != Some(state))
+            .map(|(path, _)| path.clone())
+            .collect();
+
+        changed.extend(
+            earlier
+                .0
+                .keys()
+                .filter(|path| !self.0.contains_key(*path))
+                .cloned(),
+        );
+
+        changed
+    }
+}
+
+fn record(path: &Path, files: &mut BTreeMap<PathBuf, Option<(SystemTime, u64)>>) {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => {
+            files.insert(path.to_path_buf(), None);
+            return;
+        }
+    };
+
+    if metadata.is_dir() {
+        if let Ok(entries) = fs::read_dir(path) {
+            for entry in entries.flatten() {
+                record(&entry.path(), files);
+            }
+        }
+    } else {
+        let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        files.insert(path.to_path_buf(), Some((modified, metadata.len())));
+    }
+}
+
+/// Waits until something under `paths` changes from how it was in `since`,
+/// returning what changed once the changes have settled.
+pub fn wait_for_change(paths: &[PathBuf], since: &Snapshot) -> Vec<PathBuf> {
+    let mut latest = loop {
+        let snapshot = Snapshot::take(paths);
+
+        if snapshot != *since {
+            break snapshot;
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    loop {
+        thread::sleep(SETTLE_TIME);
+        let snapshot = Snapshot::take(paths);
+
+        if snapshot == latest {
+            return latest.changed_since(since);
+        }
+
+        latest = snapshot;
+    }
+}
@@ -0,0 +1,429 @@
+use std::io;
+
+use futures::{
+    future::{self, Either, Loop},
+    sync::mpsc,
+    Future, Stream,
+};
+use hyper::header::{self, HeaderMap};
+use tokio_io::{io as async_io, AsyncRead, AsyncWrite};
+
+/// Added to the key a client sends when opening a WebSocket to make the key
+/// we answer with, as RFC 6455 asks.
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The biggest message we'll read, whether it comes in one frame or is split
+/// across several. The plugin's batches of messages are much smaller than
+/// this, so anything bigger is a broken connection.
+const MAX_MESSAGE_LENGTH: u64 = 64 * 1024 * 1024;
+
+/// The only version of the WebSocket protocol there is, from RFC 6455.
+const WEBSOCKET_VERSION: &str = "13";
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+/// The `Sec-WebSocket-Accept` header to answer a request to open a WebSocket
+/// with, or `None` if the request doesn't ask to open one the way RFC 6455
+/// says to, with `Upgrade: websocket`, `Sec-WebSocket-Version: 13`, and a
+/// `Sec-WebSocket-Key`.
+pub fn upgrade_accept_key(headers: &HeaderMap) -> Option<String> {
+    let header_text = |name| headers.get(name).and_then(|value| value.to_str().ok());
+
+    let upgrade = header_text(header::UPGRADE)?;
+    if !upgrade
+        .split(',')
+        .any(|protocol| protocol.trim().eq_ignore_ascii_case("websocket"))
+    {
+        return None;
+    }
+
+    if header_text(header::SEC_WEBSOCKET_VERSION)?.trim() != WEBSOCKET_VERSION {
+        return None;
+    }
+
+    header_text(header::SEC_WEBSOCKET_KEY).map(accept_key)
+}
+
+/// The `Sec-WebSocket-Accept` header to answer a request to open a WebSocket
+/// with, given its `Sec-WebSocket-Key` header.
+fn accept_key(key: &str) -> String {
+    let mut input = key.trim().as_bytes().to_vec();
+    input.extend_from_slice(ACCEPT_GUID.as_bytes());
+
+    base64::encode(sha1(&input))
+}
+
+/// Talks over a WebSocket once the request that opened it has been upgraded.
+/// Each text message from the other end is passed to `on_message`, and each
+/// string from `outgoing` is sent to it. Finishes when the other end closes
+/// the connection.
+pub fn serve<T, F>(
+    io: T,
+    outgoing: mpsc::UnboundedReceiver<String>,
+    on_message: F,
+) -> impl Future<Item = (), Error = ()>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+    F: FnMut(String) + Send + 'static,
+{
+    let (reader, writer) = io.split();
+    let (pong_tx, pong_rx) = mpsc::unbounded();
+
+    let read = future::loop_fn(
+        (reader, on_message, pong_tx, Vec::new()),
+        |(reader, mut on_message, pong_tx, mut partial)| {
+            read_frame(reader).and_then(move |(reader, frame)| {
+                match frame.opcode {
+                    OPCODE_TEXT | OPCODE_BINARY | OPCODE_CONTINUATION => {
+                        let length = (partial.len() + frame.payload.len()) as u64;
+
+                        if length > MAX_MESSAGE_LENGTH {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!(
+                                    "Got a WebSocket message of at least {} bytes, which is too \
+                                     big",
+                                    length
+                                ),
+                            ));
+                        }
+
+                        partial.extend_from_slice(&frame.payload);
+
+                        if frame.fin {
+                            on_message(String::from_utf8_lossy(&partial).into_owned());
+                            partial.clear();
+                        }
+                    }
+                    OPCODE_PING => {
+                        let _ignored = pong_tx.unbounded_send(frame.payload);
+                    }
+                    OPCODE_CLOSE => return Ok(Loop::Break(())),
+                    _ => {}
+                }
+
+                Ok(Loop::Continue((reader, on_message, pong_tx, partial)))
+            })
+        },
+    );
+
+    let frames = outgoing
+        .map(|text| encode_frame(OPCODE_TEXT, text.as_bytes()))
+        .select(pong_rx.map(|payload| encode_frame(OPCODE_PONG, &payload)))
+        .map_err(|()| io::Error::other("The frames to send were dropped"));
+
+    let write = frames.fold(writer, |writer, frame| {
+        async_io::write_all(writer, frame).map(|(writer, _)| writer)
+    });
+
+    // Either half ending ends the connection, which drops the other half.
+    read.select2(write).then(|result| {
+        if let Err(Either::A((err, _)) | Either::B((err, _))) = result {
+            log::debug!("The WebSocket closed: {}", err);
+        }
+
+        Ok(())
+    })
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+type ReadFrame<R> = Box<dyn Future<Item = (R, Frame), Error = io::Error> + Send>;
+
+fn read_frame<R>(reader: R) -> ReadFrame<R>
+where
+    R: AsyncRead + Send + 'static,
+{
+    let frame = async_io::read_exact(reader, [0; 2]).and_then(|(reader, header)| {
+        let fin = header[0] & 0x80 != 0;
+        let opcode = header[0] & 0x0F;
+        let masked = header[1] & 0x80 != 0;
+        let short_length = header[1] & 0x7F;
+
+        let length_size = match short_length {
+            126 => 2,
+            127 => 8,
+            _ => 0,
+        };
+        let mask_size = if masked { 4 } else { 0 };
+
+        async_io::read_exact(reader, vec![0; length_size + mask_size]).and_then(
+            move |(reader, extra)| {
+                let length = match length_size {
+                    0 => u64::from(short_length),
+                    _ => extra[..length_size]
+                        .iter()
+                        .fold(0, |length, byte| length << 8 | u64::from(*byte)),
+                };
+
+                if length > MAX_MESSAGE_LENGTH {
+                    return Either::A(future::err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "Got a WebSocket frame of {} bytes, which is too big",
+                            length
+                        ),
+                    )));
+                }
+
+                let mask = extra[length_size..].to_vec();
+
+                Either::B(async_io::read_exact(reader, vec![0; length as usize]).map(
+                    move |(reader, mut payload)| {
+                        // Frames from clients are always masked.
+                        if masked {
+                            for (index, byte) in payload.iter_mut().enumerate() {
+                                *byte ^= mask[index % 4];
+                            }
+                        }
+
+                        (
+                            reader,
+                            Frame {
+                                fin,
+                                opcode,
+                                payload,
+                            },
+                        )
+                    },
+                ))
+            },
+        )
+    });
+
+    Box::new(frame)
+}
+
+/// Makes an unmasked frame holding all of `payload`, which is how servers
+/// send them.
+fn encode_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+
+    match payload.len() {
+        length if length < 126 => frame.push(length as u8),
+        length if length <= 0xFFFF => {
+            frame.push(126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// SHA-1, which WebSockets only use to show that the server understood the
+/// request to open one, not for security.
+fn sha1(input: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut message = input.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((input.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut words = [0u32; 80];
+
+        for (index, word) in block.chunks(4).enumerate() {
+            words[index] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+
+        for index in 16..80 {
+            words[index] =
+                (words[index - 3] ^ words[index - 8] ^ words[index - 14] ^ words[index - 16])
+                    .rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+
+        for (index, word) in words.iter().enumerate() {
+            let (f, k) = match index {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (value, added) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(added);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (index, value) in state.iter().enumerate() {
+        digest[index * 4..index * 4 + 4].copy_from_slice(&value.to_be_bytes());
+    }
+
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        io::{Cursor, Read, Write},
+        sync::{Arc, Mutex},
+    };
+
+    use futures::{Async, Poll};
+
+    /// A connection that reads from `input` and throws away what's written.
+    struct TestConnection {
+        input: Cursor<Vec<u8>>,
+    }
+
+    impl Read for TestConnection {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buffer)
+        }
+    }
+
+    impl AsyncRead for TestConnection {}
+
+    impl Write for TestConnection {
+        fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+            Ok(buffer.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl AsyncWrite for TestConnection {
+        fn shutdown(&mut self) -> Poll<(), io::Error> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    /// Makes a masked frame, which is how clients send them.
+    fn masked_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xFA, 0x21, 0x3D];
+
+        let mut frame = encode_frame(opcode, payload);
+        if !fin {
+            frame[0] &= 0x7F;
+        }
+        frame[1] |= 0x80;
+
+        let payload_start = frame.len() - payload.len();
+        frame.truncate(payload_start);
+        frame.extend_from_slice(&mask);
+        frame.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(index, byte)| byte ^ mask[index % 4]),
+        );
+
+        frame
+    }
+
+    fn read_one(bytes: Vec<u8>) -> Result<Frame, io::Error> {
+        read_frame(Cursor::new(bytes))
+            .wait()
+            .map(|(_, frame)| frame)
+    }
+
+    #[test]
+    fn accept_key_matches_rfc() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn sha1_matches_fips() {
+        let digest: String = sha1(b"abc")
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        assert_eq!(digest, "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+
+    #[test]
+    fn frames_of_every_length_size() {
+        // Lengths that fit in 7 bits, 16 bits, and 64 bits.
+        for length in [125, 126, 0xFFFF, 0x10000] {
+            let payload: Vec<u8> = (0..length).map(|index| index as u8).collect();
+
+            let frame = read_one(masked_frame(true, OPCODE_TEXT, &payload)).unwrap();
+            assert!(frame.fin);
+            assert_eq!(frame.opcode, OPCODE_TEXT);
+            assert_eq!(frame.payload, payload, "masked frame of {} bytes", length);
+
+            let frame = read_one(encode_frame(OPCODE_BINARY, &payload)).unwrap();
+            assert_eq!(frame.opcode, OPCODE_BINARY);
+            assert_eq!(frame.payload, payload, "unmasked frame of {} bytes", length);
+        }
+    }
+
+    #[test]
+    fn continuation_frames_are_joined() {
+        let mut input = masked_frame(false, OPCODE_TEXT, b"Hel");
+        input.extend(masked_frame(false, OPCODE_CONTINUATION, b"lo, "));
+        input.extend(masked_frame(true, OPCODE_PING, b"ping"));
+        input.extend(masked_frame(true, OPCODE_CONTINUATION, b"world"));
+        input.extend(masked_frame(true, OPCODE_TEXT, b"again"));
+        input.extend(masked_frame(true, OPCODE_CLOSE, b""));
+
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let received = Arc::clone(&messages);
+
+        // Keeping the sender means only the other end can end the connection.
+        let (_outgoing_tx, outgoing_rx) = mpsc::unbounded();
+        let connection = TestConnection {
+            input: Cursor::new(input),
+        };
+
+        serve(connection, outgoing_rx, move |message| {
+            received.lock().unwrap().push(message)
+        })
+        .wait()
+        .unwrap();
+
+        assert_eq!(*messages.lock().unwrap(), ["Hello, world", "again"]);
+    }
+
+    #[test]
+    fn oversize_frame_is_rejected() {
+        // A 64-bit length of 2^40 bytes, with no payload behind it.
+        let mut frame = vec![0x80 | OPCODE_TEXT, 0x80 | 127];
+        frame.extend_from_slice(&(1u64 << 40).to_be_bytes());
+        frame.extend_from_slice(&[0; 4]);
+
+        let err = read_one(frame).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}